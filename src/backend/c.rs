@@ -0,0 +1,1315 @@
+//! Emits portable C11 for checked programs.
+//!
+//! User types become C types with the same layout, which the output checks
+//! with static assertions. Function bodies turn into statements with every
+//! local declared up front so labeled `break`s and `continue`s can be
+//! `goto`s, and block expressions leave their value in a temporary.
+//! Values of zero sized types, like `void`, don't exist in the output.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    ir::{
+        lower::{function_symbol, mangle, static_symbol},
+        opt::OptLevel,
+    },
+    parser::ast::{BinOpKind, FloatType, GlobalKind, IntSize, Path, UnaryOpKind},
+    stage::{
+        constant_eval::Value,
+        tree::{self, Body, Coercion, Expr, ExprKind, LabelId, Stmt},
+        types::Type,
+        Context, FunctionId, FunctionKind, Global, Resolvable, UserType,
+    },
+};
+
+const PRELUDE: &str = "\
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/* pointers to strings and slices along with their length */
+typedef struct { void *ptr; size_t len; } bc_slice;
+
+double fmod(double, double);
+float fmodf(float, float);
+";
+
+/// Names C or the prelude already use
+const RESERVED: &[&str] = &[
+    "auto",
+    "break",
+    "case",
+    "char",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extern",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "register",
+    "restrict",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "typedef",
+    "union",
+    "unsigned",
+    "void",
+    "volatile",
+    "while",
+    "bool",
+    "true",
+    "false",
+    "NULL",
+    "offsetof",
+    "size_t",
+    "ptrdiff_t",
+    "int8_t",
+    "int16_t",
+    "int32_t",
+    "int64_t",
+    "uint8_t",
+    "uint16_t",
+    "uint32_t",
+    "uint64_t",
+    "fmod",
+    "fmodf",
+];
+
+/// Emits the C translation unit for a program that checked without errors
+pub fn emit(context: &mut Context) -> String {
+    let mut c = Emitter {
+        context,
+        types: String::new(),
+        names: HashMap::new(),
+        defined: HashSet::new(),
+    };
+
+    let mut paths: Vec<Path> = c
+        .context
+        .user_types()
+        .map(|(path, _)| path.clone())
+        .collect();
+    paths.sort_by_key(|path| path.to_string());
+    for path in &paths {
+        let name = type_name(path);
+        let size = c.context.layout(path).size_bytes();
+        let decl = match c.context.user_type(path) {
+            Some(UserType::Struct(_)) => format!("typedef struct {name} {name};"),
+            Some(UserType::Union(_)) => format!("typedef union {name} {name};"),
+            Some(UserType::Enum(_)) => format!("typedef {} {name};", uint(size.max(1))),
+            _ => continue,
+        };
+        writeln!(c.types, "{decl}").unwrap();
+    }
+    for path in &paths {
+        c.define(&Type::Nammed(path.clone()));
+    }
+
+    let mut globals: Vec<_> = c
+        .context
+        .globals()
+        .map(|(path, var)| (path.clone(), var.clone()))
+        .collect();
+    globals.sort_by_key(|(path, _)| path.to_string());
+    let mut statics = String::new();
+    let mut inits = String::new();
+    for (path, var) in globals {
+        if matches!(var.kind, GlobalKind::Const) || c.zst(&var.ty) {
+            continue;
+        }
+        let ty = c.ty(&var.ty);
+        let name = c.static_name(&path);
+        writeln!(statics, "extern {};", decl(&ty, &name)).unwrap();
+        if let (GlobalKind::Static, Some(value)) = (&var.kind, &var.value) {
+            let init = c.init(value, &var.ty).unwrap();
+            writeln!(inits, "{} = {init};", decl(&ty, &name)).unwrap();
+        }
+    }
+
+    let mut prototypes = String::new();
+    let mut bodies = String::new();
+    let ids: Vec<FunctionId> = c.context.functions().map(|(id, _)| id).collect();
+    for id in ids {
+        let function = c.context.function(id);
+        let sig = function.sig.clone();
+        let body = match &function.kind {
+            FunctionKind::Definition {
+                code: Resolvable::Resolved(body),
+                ..
+            } => Some(body.clone()),
+            FunctionKind::Definition { .. } => continue,
+            FunctionKind::Declaration(_) => None,
+        };
+        let name = c.function_name(id);
+        let params: Vec<_> = sig.params.iter().map(|(ty, _)| (ty, None)).collect();
+        let prototype = c.signature(&name, &params, &sig.ret_ty);
+        writeln!(prototypes, "{prototype};").unwrap();
+        if let Some(body) = body {
+            bodies.push('\n');
+            bodies.push_str(&FunctionEmitter::new(&mut c, &body).finish(&name, &body));
+        }
+    }
+
+    let mut out = PRELUDE.to_string();
+    for part in [&c.types, &statics, &inits, &prototypes] {
+        if !part.is_empty() {
+            out.push('\n');
+            out.push_str(part);
+        }
+    }
+    out.push_str(&bodies);
+    out
+}
+
+/// Compiles a program into an executable with the system C compiler, `$CC`
+/// or else `cc`
+pub fn build(
+    context: &mut Context,
+    output: &std::path::Path,
+    level: OptLevel,
+) -> Result<(), String> {
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+    let id = BUILDS.fetch_add(1, Ordering::Relaxed);
+    let source = std::env::temp_dir().join(format!("bc-{}-{id}.c", std::process::id()));
+    std::fs::write(&source, emit(context))
+        .map_err(|err| format!("could not write {}: {err}", source.display()))?;
+    let level = match level {
+        OptLevel::O0 => "-O0",
+        OptLevel::O1 => "-O1",
+        OptLevel::O2 => "-O2",
+    };
+    let cc = std::env::var_os("CC").unwrap_or("cc".into());
+    let result = super::run(
+        Command::new(cc)
+            .args(["-std=c11", level, "-o"])
+            .arg(output)
+            .arg(&source)
+            // the prototypes of C functions don't always match libc's
+            .args(["-fno-builtin", "-lm"]),
+    );
+    let _ = std::fs::remove_file(&source);
+    result
+}
+
+/// Appends an underscore to names C would misunderstand
+fn ident(name: &str) -> String {
+    match RESERVED.contains(&name) || name.starts_with("bc_") {
+        true => format!("{name}_"),
+        false => name.to_string(),
+    }
+}
+
+fn type_name(path: &Path) -> String {
+    ident(&mangle(path))
+}
+
+/// Declares `name` with the C type `ty`
+fn decl(ty: &str, name: &str) -> String {
+    match ty.ends_with('*') {
+        true => format!("{ty}{name}"),
+        false => format!("{ty} {name}"),
+    }
+}
+
+/// Drops the parentheses around a whole expression
+fn bare(code: &str) -> &str {
+    let Some(inner) = code
+        .strip_prefix('(')
+        .and_then(|code| code.strip_suffix(')'))
+    else {
+        return code;
+    };
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => return code,
+            (None, ')') => depth -= 1,
+            _ => {}
+        }
+    }
+    inner
+}
+
+fn uint(size: usize) -> &'static str {
+    match size {
+        1 => "uint8_t",
+        2 => "uint16_t",
+        4 => "uint32_t",
+        _ => "uint64_t",
+    }
+}
+
+/// A C string literal, anything but printable ASCII is escaped
+fn string_literal(value: &str) -> String {
+    let mut out = String::from("\"");
+    for byte in value.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => write!(out, "\\{}", byte as char).unwrap(),
+            b' '..=b'~' => out.push(byte as char),
+            _ => write!(out, "\\{byte:03o}").unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn int_literal(value: i128, ty: &Type) -> String {
+    let Type::Int(size, signed) = ty else {
+        return value.to_string();
+    };
+    let bits = match size {
+        IntSize::U8 => 8,
+        IntSize::U16 => 16,
+        IntSize::U32 => 32,
+        IntSize::U64 | IntSize::Usize => 64,
+    };
+    match signed {
+        // the most negative value can't be written as a negated literal
+        true if value == -(1 << (bits - 1)) => format!("({} - 1)", value + 1),
+        true if value < 0 => format!("({value})"),
+        true => value.to_string(),
+        false => format!("{value}u"),
+    }
+}
+
+/// `text` is the value written out as a literal of its type
+fn float_literal(value: f64, text: String) -> String {
+    if value.is_nan() {
+        "(0.0 / 0.0)".to_string()
+    } else if value.is_infinite() {
+        let sign = if value < 0.0 { "-" } else { "" };
+        format!("({sign}1.0 / 0.0)")
+    } else if value < 0.0 {
+        format!("({text})")
+    } else {
+        text
+    }
+}
+
+fn operator(op: BinOpKind) -> &'static str {
+    match op {
+        BinOpKind::Times => "*",
+        BinOpKind::Divide => "/",
+        BinOpKind::Modulo => "%",
+        BinOpKind::Plus => "+",
+        BinOpKind::Minus => "-",
+        BinOpKind::ShiftLeft => "<<",
+        BinOpKind::ShiftRight => ">>",
+        BinOpKind::BitAnd => "&",
+        BinOpKind::BitXor => "^",
+        BinOpKind::BitOr => "|",
+        BinOpKind::Eq => "==",
+        BinOpKind::Neq => "!=",
+        BinOpKind::Gt => ">",
+        BinOpKind::Lt => "<",
+        BinOpKind::Gteq => ">=",
+        BinOpKind::Lteq => "<=",
+        BinOpKind::LogicalAnd => "&&",
+        BinOpKind::LogicalOr => "||",
+    }
+}
+
+/// Whether evaluating the expression can't change anything another
+/// expression could observe
+fn pure(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Local(_) | ExprKind::Static(_) | ExprKind::Function(_) | ExprKind::Value(_) => {
+            true
+        }
+        ExprKind::Field(inner, _)
+        | ExprKind::Unary(_, inner)
+        | ExprKind::Coerce(_, inner)
+        | ExprKind::UnionCon(_, inner) => pure(inner),
+        ExprKind::Index(l, r) | ExprKind::Binary(l, _, r) => pure(l) && pure(r),
+        ExprKind::StructCon(values) | ExprKind::ArrayCon(values) => values.iter().all(pure),
+        ExprKind::Block(..)
+        | ExprKind::Call(..)
+        | ExprKind::Assign(..)
+        | ExprKind::CompoundAssign(..)
+        | ExprKind::Break(..)
+        | ExprKind::Continue(_)
+        | ExprKind::Return(_) => false,
+    }
+}
+
+fn is_ident(code: &str) -> bool {
+    code.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+struct Emitter<'a> {
+    context: &'a mut Context,
+    /// Type declarations and definitions, each after everything it needs
+    types: String,
+    /// The names given to array and function pointer types
+    names: HashMap<Type, String>,
+    defined: HashSet<Type>,
+}
+
+impl Emitter<'_> {
+    fn zst(&mut self, ty: &Type) -> bool {
+        let layout = ty.layout(self.context);
+        layout.is_sized() && layout.size_bytes() == 0
+    }
+
+    fn function_name(&self, id: FunctionId) -> String {
+        let name = function_symbol(self.context, id);
+        match &self.context.function(id).kind {
+            FunctionKind::Definition { external: None, .. } => ident(&name),
+            _ => name,
+        }
+    }
+
+    fn static_name(&self, path: &Path) -> String {
+        let name = static_symbol(self.context, path);
+        match name == mangle(path) {
+            true => ident(&name),
+            false => name,
+        }
+    }
+
+    /// The C type of values of `ty`, defined before anything using it
+    fn ty(&mut self, ty: &Type) -> String {
+        let name = self.declared(ty);
+        self.define(ty);
+        name
+    }
+
+    /// The C name of a type which might not be defined yet, enough to point to it
+    fn declared(&mut self, ty: &Type) -> String {
+        match ty {
+            Type::Int(size, signed) => {
+                let name = match (size, signed) {
+                    (IntSize::U8, false) => "uint8_t",
+                    (IntSize::U16, false) => "uint16_t",
+                    (IntSize::U32, false) => "uint32_t",
+                    (IntSize::U64, false) => "uint64_t",
+                    (IntSize::Usize, false) => "size_t",
+                    (IntSize::U8, true) => "int8_t",
+                    (IntSize::U16, true) => "int16_t",
+                    (IntSize::U32, true) => "int32_t",
+                    (IntSize::U64, true) => "int64_t",
+                    (IntSize::Usize, true) => "ptrdiff_t",
+                };
+                name.to_string()
+            }
+            Type::Float(FloatType::F32) => "float".to_string(),
+            Type::Float(FloatType::F64) => "double".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Char => "char".to_string(),
+            Type::Void => "void".to_string(),
+            Type::Ptr(inner, _) | Type::Ref(inner, _) => match inner.is_sized(self.context) {
+                true => format!("{} *", self.declared(inner)),
+                false => "bc_slice".to_string(),
+            },
+            Type::Nammed(path) => type_name(path),
+            Type::FnPointer(params, ret) => {
+                if let Some(name) = self.names.get(ty) {
+                    return name.clone();
+                }
+                let ret = match ret {
+                    Some(ret) if !self.zst(ret) => self.declared(ret),
+                    _ => "void".to_string(),
+                };
+                let mut list = Vec::new();
+                for param in params {
+                    if !self.zst(param) {
+                        list.push(self.declared(param));
+                    }
+                }
+                if list.is_empty() {
+                    list.push("void".to_string());
+                }
+                let name = format!("bc_fn_{}", self.names.len());
+                writeln!(self.types, "typedef {ret} (*{name})({});", list.join(", ")).unwrap();
+                self.names.insert(ty.clone(), name.clone());
+                name
+            }
+            Type::ArrayStatic(..) => {
+                if let Some(name) = self.names.get(ty) {
+                    return name.clone();
+                }
+                let name = format!("bc_array_{}", self.names.len());
+                writeln!(self.types, "typedef struct {name} {name};").unwrap();
+                self.names.insert(ty.clone(), name.clone());
+                name
+            }
+            Type::Str | Type::Array(_) => unreachable!("{ty} has no C type"),
+        }
+    }
+
+    /// Writes the definition of a type and everything it contains
+    fn define(&mut self, ty: &Type) {
+        if self.defined.contains(ty) || self.zst(ty) {
+            return;
+        }
+        match ty {
+            Type::ArrayStatic(element, len) => {
+                self.defined.insert(ty.clone());
+                let name = self.declared(ty);
+                let element = self.ty(element);
+                writeln!(self.types, "struct {name} {{ {element} items[{len}]; }};").unwrap();
+            }
+            Type::Nammed(path) => {
+                self.defined.insert(ty.clone());
+                let layout = self.context.layout(path);
+                let name = type_name(path);
+                let (keyword, members): (_, Vec<_>) = match self.context.user_type(path) {
+                    Some(UserType::Struct(struc)) => (
+                        "struct",
+                        struc
+                            .members
+                            .iter()
+                            .map(|member| (member.name.clone(), member.ty.clone(), member.offset))
+                            .collect(),
+                    ),
+                    Some(UserType::Union(unio)) => (
+                        "union",
+                        unio.members
+                            .iter()
+                            .map(|member| (member.name.clone(), member.ty.clone(), 0))
+                            .collect(),
+                    ),
+                    _ => return,
+                };
+                let mut lines = Vec::new();
+                let mut checks = Vec::new();
+                if layout.is_sized() {
+                    checks.push(format!("sizeof({name}) == {}", layout.size_bytes()));
+                }
+                checks.push(format!("_Alignof({name}) == {}", layout.align()));
+                for (member, ty, offset) in members {
+                    let member = ident(&member);
+                    if !ty.is_sized(self.context) {
+                        // only reached through pointers to the start of the type
+                        lines.push(format!("    /* unsized {member} at {offset} */"));
+                    } else if !self.zst(&ty) {
+                        let ty = self.ty(&ty);
+                        lines.push(format!("    {};", decl(&ty, &member)));
+                        checks.push(format!("offsetof({name}, {member}) == {offset}"));
+                    }
+                }
+                writeln!(self.types, "{keyword} {name} {{").unwrap();
+                for line in lines {
+                    writeln!(self.types, "{line}").unwrap();
+                }
+                writeln!(self.types, "}};").unwrap();
+                writeln!(
+                    self.types,
+                    "_Static_assert({}, \"layout of {path}\");",
+                    checks.join(" && ")
+                )
+                .unwrap();
+            }
+            _ => {}
+        }
+    }
+
+    /// A function declaration, parameters without a name are left unnamed
+    fn signature(&mut self, name: &str, params: &[(&Type, Option<String>)], ret: &Type) -> String {
+        let ret = match self.zst(ret) {
+            true => "void".to_string(),
+            false => self.ty(ret),
+        };
+        let mut list = Vec::new();
+        for (ty, param) in params {
+            if self.zst(ty) {
+                continue;
+            }
+            let ty = self.ty(ty);
+            list.push(match param {
+                Some(param) => decl(&ty, param),
+                None => ty,
+            });
+        }
+        if list.is_empty() {
+            list.push("void".to_string());
+        }
+        format!("{ret} {name}({})", list.join(", "))
+    }
+
+    /// A constant in initializer form, nothing for zero sized types
+    fn init(&mut self, value: &Value, ty: &Type) -> Option<String> {
+        if self.zst(ty) {
+            return None;
+        }
+        Some(match value {
+            Value::F32(value) => float_literal(*value as f64, format!("{value:?}f")),
+            Value::F64(value) => float_literal(*value, format!("{value:?}")),
+            Value::Bool(value) => value.to_string(),
+            Value::Char(c) if c.is_ascii_graphic() && !matches!(c, '\'' | '\\') => {
+                format!("'{c}'")
+            }
+            Value::Str(value) => {
+                format!("{{ (void *){}, {} }}", string_literal(value), value.len())
+            }
+            Value::Ref(path) | Value::Ptr(path) => {
+                let ty = match self.context.global(path) {
+                    Some(Global::Variable(Resolvable::Resolved(var))) => var.ty.clone(),
+                    _ => Type::Void,
+                };
+                match self.zst(&ty) {
+                    true => "NULL".to_string(),
+                    false => format!("&{}", self.static_name(path)),
+                }
+            }
+            Value::Array(values) => {
+                let element = ty.element().unwrap();
+                let values: Vec<_> = values
+                    .iter()
+                    .filter_map(|value| self.init(value, element))
+                    .collect();
+                format!("{{ {{ {} }} }}", values.join(", "))
+            }
+            Value::Struct(values) => {
+                let members = self.context.members(ty);
+                let mut inits = Vec::new();
+                for (value, member) in values.iter().zip(members) {
+                    if let Some(value) = self.init(value, &member.ty) {
+                        inits.push(format!(".{} = {value}", ident(&member.name)));
+                    }
+                }
+                format!("{{ {} }}", inits.join(", "))
+            }
+            Value::Union(member, value) => {
+                let member = self.context.members(ty).swap_remove(*member);
+                let (name, ty) = (ident(&member.name), member.ty);
+                match self.init(value, &ty) {
+                    Some(value) => format!("{{ .{name} = {value} }}"),
+                    None => "{ 0 }".to_string(),
+                }
+            }
+            value => int_literal(value.as_int().unwrap(), ty),
+        })
+    }
+
+    /// A constant usable as an expression
+    fn constant(&mut self, value: &Value, ty: &Type) -> Option<String> {
+        let init = self.init(value, ty)?;
+        match value {
+            Value::Str(_) | Value::Array(_) | Value::Struct(_) | Value::Union(..) => {
+                Some(format!("(({}){init})", self.ty(ty)))
+            }
+            _ => Some(init),
+        }
+    }
+}
+
+/// A place in memory, unsized ones are the address of their start and
+/// their length
+enum Place {
+    Sized(String),
+    Unsized(String, String),
+}
+
+struct Label {
+    /// Where the value of the block goes
+    result: Option<String>,
+    broken: bool,
+    continued: bool,
+}
+
+struct FunctionEmitter<'e, 'c> {
+    c: &'e mut Emitter<'c>,
+    /// The C name of every local, zero sized ones have none
+    locals: Vec<Option<String>>,
+    /// Declarations of the locals and temporaries
+    decls: String,
+    out: String,
+    indent: usize,
+    temps: usize,
+    labels: Vec<Option<Label>>,
+}
+
+impl<'e, 'c> FunctionEmitter<'e, 'c> {
+    fn new(c: &'e mut Emitter<'c>, body: &Body) -> Self {
+        let mut emitter = FunctionEmitter {
+            c,
+            locals: Vec::new(),
+            decls: String::new(),
+            out: String::new(),
+            indent: 1,
+            temps: 0,
+            labels: (0..body.labels).map(|_| None).collect(),
+        };
+        for (id, local) in body.locals.iter().enumerate() {
+            if emitter.c.zst(&local.ty) {
+                emitter.locals.push(None);
+                continue;
+            }
+            let name = format!("{}_{id}", local.name);
+            if id >= body.params {
+                let ty = emitter.c.ty(&local.ty);
+                writeln!(emitter.decls, "    {};", decl(&ty, &name)).unwrap();
+            }
+            emitter.locals.push(Some(name));
+        }
+        emitter.stmts(&body.stmts);
+        emitter
+    }
+
+    fn finish(self, name: &str, body: &Body) -> String {
+        let params: Vec<_> = body.locals[..body.params]
+            .iter()
+            .zip(&self.locals)
+            .map(|(local, name)| (&local.ty, name.clone()))
+            .collect();
+        let signature = self.c.signature(name, &params, &body.ret_ty);
+        let mut out = format!("{signature} {{\n");
+        out.push_str(&self.decls);
+        if !self.decls.is_empty() && !self.out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&self.out);
+        out.push_str("}\n");
+        out
+    }
+
+    fn line(&mut self, line: impl AsRef<str>) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(line.as_ref());
+        self.out.push('\n');
+    }
+
+    /// Labels need a statement after them, even at the end of a block
+    fn label(&mut self, label: String) {
+        self.indent -= 1;
+        self.line(format!("{label}: ;"));
+        self.indent += 1;
+    }
+
+    fn temp(&mut self, ty: &str) -> String {
+        let name = format!("bc_t{}", self.temps);
+        self.temps += 1;
+        writeln!(self.decls, "    {};", decl(ty, &name)).unwrap();
+        name
+    }
+
+    /// Keeps a value in a temporary so it is evaluated right now
+    fn spill(&mut self, code: String, ty: &Type) -> String {
+        if is_ident(&code) {
+            return code;
+        }
+        let ty = self.c.ty(ty);
+        let temp = self.temp(&ty);
+        self.line(format!("{temp} = {};", bare(&code)));
+        temp
+    }
+
+    /// Keeps the address of a place in a temporary so evaluating it again
+    /// gives the same place
+    fn hold(&mut self, place: Place, ty: &Type) -> Place {
+        match place {
+            Place::Sized(code) if !is_ident(&code) => {
+                let ty = self.c.ty(ty);
+                let temp = self.temp(&format!("{ty} *"));
+                self.line(format!("{temp} = &{code};"));
+                Place::Sized(format!("(*{temp})"))
+            }
+            place => place,
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Expr(expr) => self.effect(expr),
+                Stmt::Let(id, Some(value)) => {
+                    let value = self.expr(value);
+                    if let (Some(value), Some(local)) = (value, self.locals[id.0].clone()) {
+                        self.line(format!("{local} = {};", bare(&value)));
+                    }
+                }
+                Stmt::Let(_, None) => {}
+            }
+        }
+    }
+
+    /// Evaluates an expression only for what it does
+    fn effect(&mut self, expr: &Expr) {
+        match self.expr(expr) {
+            Some(code) if !pure(expr) && !is_ident(&code) => {
+                match matches!(expr.kind, ExprKind::Call(..)) {
+                    true => self.line(format!("{code};")),
+                    false => self.line(format!("(void)({code});")),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Evaluates expressions from left to right, values followed by
+    /// expressions with side effects are kept in temporaries
+    fn exprs(&mut self, exprs: &[&Expr]) -> Vec<Option<String>> {
+        let mut codes = Vec::new();
+        for (i, expr) in exprs.iter().enumerate() {
+            let code = self.expr(expr);
+            let constant = matches!(expr.kind, ExprKind::Value(_) | ExprKind::Function(_));
+            codes.push(match code {
+                Some(code) if !constant && !exprs[i + 1..].iter().all(|e| pure(e)) => {
+                    Some(self.spill(code, &expr.ty))
+                }
+                code => code,
+            });
+        }
+        codes
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Option<String> {
+        let effects_only = !matches!(
+            expr.kind,
+            ExprKind::Call(..)
+                | ExprKind::Block(..)
+                | ExprKind::Assign(..)
+                | ExprKind::CompoundAssign(..)
+                | ExprKind::Break(..)
+                | ExprKind::Continue(_)
+                | ExprKind::Return(_)
+        );
+        if effects_only && self.c.zst(&expr.ty) {
+            match &expr.kind {
+                ExprKind::Field(inner, _)
+                | ExprKind::Unary(_, inner)
+                | ExprKind::Coerce(_, inner)
+                | ExprKind::UnionCon(_, inner) => self.effect(inner),
+                ExprKind::Index(l, r) => {
+                    self.effect(l);
+                    self.effect(r);
+                }
+                ExprKind::StructCon(values) | ExprKind::ArrayCon(values) => {
+                    values.iter().for_each(|value| self.effect(value))
+                }
+                _ => {}
+            }
+            return None;
+        }
+
+        match &expr.kind {
+            ExprKind::Local(_)
+            | ExprKind::Static(_)
+            | ExprKind::Field(..)
+            | ExprKind::Index(..)
+            | ExprKind::Unary(UnaryOpKind::Deref, _) => match self.place(expr) {
+                Place::Sized(code) => Some(code),
+                Place::Unsized(..) => unreachable!("{} is unsized", expr.ty),
+            },
+            ExprKind::Function(id) => Some(self.c.function_name(*id)),
+            ExprKind::Value(value) => self.c.constant(value, &expr.ty),
+            ExprKind::Block(id, block) => self.block(*id, block, &expr.ty),
+            ExprKind::Call(callee, args) => {
+                let callee = match &callee.kind {
+                    ExprKind::Function(id) => self.c.function_name(*id),
+                    _ => {
+                        let code = self.expr(callee).unwrap();
+                        match args.iter().all(pure) {
+                            true => format!("({code})"),
+                            false => self.spill(code, &callee.ty),
+                        }
+                    }
+                };
+                let args: Vec<_> = args.iter().collect();
+                let args: Vec<_> = self.exprs(&args).into_iter().flatten().collect();
+                let call = format!("{callee}({})", args.join(", "));
+                if self.c.zst(&expr.ty) {
+                    self.line(format!("{call};"));
+                    return None;
+                }
+                Some(call)
+            }
+
+            ExprKind::Unary(UnaryOpKind::Ref | UnaryOpKind::RefMut, inner) => {
+                if self.c.zst(&inner.ty) {
+                    self.effect(inner);
+                    return Some(format!("(({})NULL)", self.c.ty(&expr.ty)));
+                }
+                match self.place(inner) {
+                    Place::Sized(code) => Some(format!("&{code}")),
+                    Place::Unsized(ptr, len) => Some(format!("((bc_slice){{ {ptr}, {len} }})")),
+                }
+            }
+            ExprKind::Unary(op, inner) => {
+                let value = self.expr(inner).unwrap();
+                let ty = self.c.ty(&expr.ty);
+                Some(match (op, &expr.ty) {
+                    (UnaryOpKind::Negate, Type::Float(_)) => format!("(-{value})"),
+                    (UnaryOpKind::Negate, _) => {
+                        let wide = uint(expr.ty.layout(self.c.context).size_bytes().max(4));
+                        format!("(({ty})-({wide}){value})")
+                    }
+                    (_, Type::Bool) => format!("(!{value})"),
+                    _ => format!("(({ty})~{value})"),
+                })
+            }
+
+            ExprKind::Binary(l, op @ (BinOpKind::LogicalAnd | BinOpKind::LogicalOr), r) => {
+                let left = self.expr(l).unwrap();
+                if pure(r) {
+                    let right = self.expr(r).unwrap();
+                    return Some(format!("({left} {} {right})", operator(*op)));
+                }
+                let temp = self.temp("bool");
+                self.line(format!("{temp} = {};", bare(&left)));
+                match op {
+                    BinOpKind::LogicalAnd => self.line(format!("if ({temp}) {{")),
+                    _ => self.line(format!("if (!{temp}) {{")),
+                }
+                self.indent += 1;
+                let right = self.expr(r).unwrap();
+                self.line(format!("{temp} = {};", bare(&right)));
+                self.indent -= 1;
+                self.line("}");
+                Some(temp)
+            }
+            ExprKind::Binary(l, op @ (BinOpKind::Eq | BinOpKind::Neq), r)
+                if l.ty
+                    .pointee()
+                    .is_some_and(|inner| !inner.is_sized(self.c.context)) =>
+            {
+                // fat pointers are equal when both the address and length are
+                let [a, b] = self.exprs(&[l, r]).try_into().unwrap();
+                let a = self.spill(a.unwrap(), &l.ty);
+                let b = self.spill(b.unwrap(), &r.ty);
+                Some(match op {
+                    BinOpKind::Eq => format!("({a}.ptr == {b}.ptr && {a}.len == {b}.len)"),
+                    _ => format!("({a}.ptr != {b}.ptr || {a}.len != {b}.len)"),
+                })
+            }
+            ExprKind::Binary(l, op, r) => {
+                let [a, b] = self.exprs(&[l, r]).try_into().unwrap();
+                Some(self.arith(*op, &a.unwrap(), &b.unwrap(), &l.ty))
+            }
+            ExprKind::Assign(place, value) => {
+                let mut target = self.place(place);
+                if !pure(value) {
+                    target = self.hold(target, &place.ty);
+                }
+                if let (Some(value), Place::Sized(target)) = (self.expr(value), target) {
+                    self.line(format!("{target} = {};", bare(&value)));
+                }
+                None
+            }
+            ExprKind::CompoundAssign(place, op, value) => {
+                let mut target = self.place(place);
+                if !pure(place) || !pure(value) {
+                    target = self.hold(target, &place.ty);
+                }
+                let value = self.expr(value).unwrap();
+                let Place::Sized(target) = target else {
+                    unreachable!("{} is unsized", place.ty)
+                };
+                let result = self.arith(*op, &target, &value, &place.ty);
+                self.line(format!("{target} = {};", bare(&result)));
+                None
+            }
+            ExprKind::Coerce(coercion, inner) => {
+                let value = self.expr(inner).unwrap();
+                match coercion {
+                    Coercion::RefToPtr | Coercion::Immutable => Some(value),
+                    Coercion::Unsize(len) => {
+                        Some(format!("((bc_slice){{ (void *){value}, {len} }})"))
+                    }
+                    Coercion::DataPtr => {
+                        let ty = self.c.ty(&expr.ty);
+                        match inner.ty.pointee().unwrap().is_sized(self.c.context) {
+                            true => Some(format!("(({ty}){value})")),
+                            false => Some(format!("(({ty}){value}.ptr)")),
+                        }
+                    }
+                }
+            }
+
+            ExprKind::StructCon(values) => {
+                let members = self.c.context.members(&expr.ty);
+                let values: Vec<_> = values.iter().collect();
+                let mut inits = Vec::new();
+                for (value, member) in self.exprs(&values).into_iter().zip(members) {
+                    if let Some(value) = value {
+                        inits.push(format!(".{} = {value}", ident(&member.name)));
+                    }
+                }
+                let ty = self.c.ty(&expr.ty);
+                Some(format!("(({ty}){{ {} }})", inits.join(", ")))
+            }
+            ExprKind::UnionCon(member, value) => {
+                let name = ident(&self.c.context.members(&expr.ty)[*member].name);
+                let ty = self.c.ty(&expr.ty);
+                match self.expr(value) {
+                    Some(value) => Some(format!("(({ty}){{ .{name} = {value} }})")),
+                    None => Some(format!("(({ty}){{ 0 }})")),
+                }
+            }
+            ExprKind::ArrayCon(values) => {
+                let values: Vec<_> = values.iter().collect();
+                let values: Vec<_> = self.exprs(&values).into_iter().flatten().collect();
+                let ty = self.c.ty(&expr.ty);
+                Some(format!("(({ty}){{ {{ {} }} }})", values.join(", ")))
+            }
+
+            ExprKind::Break(id, value) => {
+                let value = value.as_ref().and_then(|value| self.expr(value));
+                let label = self.labels[id.0].as_mut().unwrap();
+                label.broken = true;
+                if let (Some(value), Some(result)) = (value, label.result.clone()) {
+                    self.line(format!("{result} = {};", bare(&value)));
+                }
+                self.line(format!("goto bc_break_{};", id.0));
+                None
+            }
+            ExprKind::Continue(id) => {
+                self.labels[id.0].as_mut().unwrap().continued = true;
+                self.line(format!("goto bc_continue_{};", id.0));
+                None
+            }
+            ExprKind::Return(value) => {
+                match value.as_ref().and_then(|value| self.expr(value)) {
+                    Some(value) => self.line(format!("return {};", bare(&value))),
+                    None => self.line("return;"),
+                }
+                None
+            }
+        }
+    }
+
+    /// Applies a binary operator to values of `ty`, integers wrap around
+    /// and shift amounts are taken modulo the width like in constants
+    fn arith(&mut self, op: BinOpKind, a: &str, b: &str, ty: &Type) -> String {
+        let sym = operator(op);
+        let compare = matches!(
+            op,
+            BinOpKind::Eq
+                | BinOpKind::Neq
+                | BinOpKind::Gt
+                | BinOpKind::Lt
+                | BinOpKind::Gteq
+                | BinOpKind::Lteq
+        );
+        match ty {
+            Type::Float(float) if op == BinOpKind::Modulo => match float {
+                FloatType::F32 => format!("fmodf({a}, {b})"),
+                FloatType::F64 => format!("fmod({a}, {b})"),
+            },
+            Type::Int(..) if !compare => {
+                let c = self.c.ty(ty);
+                let size = ty.layout(self.c.context).size_bytes();
+                // narrower types would be promoted to a signed int
+                let wide = uint(size.max(4));
+                match op {
+                    BinOpKind::Plus | BinOpKind::Minus | BinOpKind::Times => {
+                        format!("(({c})(({wide}){a} {sym} ({wide}){b}))")
+                    }
+                    BinOpKind::ShiftLeft => {
+                        format!("(({c})(({wide}){a} << ({b} & {})))", size * 8 - 1)
+                    }
+                    BinOpKind::ShiftRight => format!("(({c})({a} >> ({b} & {})))", size * 8 - 1),
+                    _ => format!("(({c})({a} {sym} {b}))"),
+                }
+            }
+            _ => format!("({a} {sym} {b})"),
+        }
+    }
+
+    fn place(&mut self, expr: &Expr) -> Place {
+        match &expr.kind {
+            ExprKind::Local(id) => Place::Sized(self.locals[id.0].clone().unwrap()),
+            ExprKind::Static(path) => Place::Sized(self.c.static_name(path)),
+            ExprKind::Unary(UnaryOpKind::Deref, pointer) => {
+                let code = self.expr(pointer).unwrap();
+                match expr.ty.is_sized(self.c.context) {
+                    true => Place::Sized(format!("(*{code})")),
+                    false => {
+                        let fat = self.spill(code, &pointer.ty);
+                        Place::Unsized(format!("{fat}.ptr"), format!("{fat}.len"))
+                    }
+                }
+            }
+            ExprKind::Field(base, member) => {
+                let member = self.c.context.members(&base.ty).swap_remove(*member);
+                let (name, ty, offset) = (ident(&member.name), member.ty, member.offset);
+                match self.place(base) {
+                    Place::Sized(code) => Place::Sized(format!("{code}.{name}")),
+                    Place::Unsized(ptr, len) if !ty.is_sized(self.c.context) => {
+                        Place::Unsized(format!("((char *){ptr} + {offset})"), len)
+                    }
+                    Place::Unsized(ptr, _) => {
+                        let base = self.c.ty(&base.ty);
+                        Place::Sized(format!("(({base} *){ptr})->{name}"))
+                    }
+                }
+            }
+            ExprKind::Index(base, index) => {
+                let mut place = self.place(base);
+                if !pure(index) {
+                    place = self.hold(place, &base.ty);
+                }
+                let index = self.expr(index).unwrap();
+                match place {
+                    Place::Sized(code) => Place::Sized(format!("{code}.items[{index}]")),
+                    Place::Unsized(ptr, _) => {
+                        let element = self.c.ty(base.ty.element().unwrap());
+                        Place::Sized(format!("(({element} *){ptr})[{index}]"))
+                    }
+                }
+            }
+            _ => {
+                let code = self.expr(expr).unwrap();
+                let ty = self.c.ty(&expr.ty);
+                let temp = self.temp(&ty);
+                self.line(format!("{temp} = {};", bare(&code)));
+                Place::Sized(temp)
+            }
+        }
+    }
+
+    fn block(&mut self, id: LabelId, block: &tree::Block, ty: &Type) -> Option<String> {
+        let result = match self.c.zst(ty) {
+            true => None,
+            false => {
+                let ty = self.c.ty(ty);
+                Some(self.temp(&ty))
+            }
+        };
+        self.labels[id.0] = Some(Label {
+            result: result.clone(),
+            broken: false,
+            continued: false,
+        });
+
+        match block {
+            tree::Block::Scope(stmts) => {
+                self.line("{");
+                self.indent += 1;
+                self.stmts(stmts);
+                self.indent -= 1;
+                self.line("}");
+            }
+            tree::Block::If(arms, els) => {
+                let mut depth = 0;
+                for (i, (cond, stmts)) in arms.iter().enumerate() {
+                    let cond = self.expr(cond).unwrap();
+                    self.line(format!("if ({}) {{", bare(&cond)));
+                    self.indent += 1;
+                    self.stmts(stmts);
+                    self.indent -= 1;
+                    if i + 1 < arms.len() || els.is_some() {
+                        self.line("} else {");
+                        self.indent += 1;
+                        depth += 1;
+                    } else {
+                        self.line("}");
+                    }
+                }
+                if let Some(els) = els {
+                    self.stmts(els);
+                }
+                for _ in 0..depth {
+                    self.indent -= 1;
+                    self.line("}");
+                }
+            }
+            tree::Block::While(cond, stmts) => {
+                self.line("for (;;) {");
+                self.indent += 1;
+                let cond = self.expr(cond).unwrap();
+                self.line(format!("if (!{cond}) break;"));
+                self.stmts(stmts);
+                self.continue_label(id);
+                self.indent -= 1;
+                self.line("}");
+            }
+            tree::Block::Loop(stmts) => {
+                self.line("for (;;) {");
+                self.indent += 1;
+                self.stmts(stmts);
+                self.continue_label(id);
+                self.indent -= 1;
+                self.line("}");
+            }
+            tree::Block::Range(local, start, end, inclusive, stmts) => {
+                let [first, last] = self.exprs(&[start, end]).try_into().unwrap();
+                let end_ty = &end.ty;
+                let last = self.spill(last.unwrap(), end_ty);
+                let counter = self.locals[local.0].clone().unwrap();
+                self.line(format!("{counter} = {};", first.unwrap()));
+                let op = if *inclusive { "<=" } else { "<" };
+                self.line(format!("if ({counter} {op} {last}) {{"));
+                self.indent += 1;
+                self.line("for (;;) {");
+                self.indent += 1;
+                self.stmts(stmts);
+                self.continue_label(id);
+                let next = self.arith(BinOpKind::Plus, &counter, "1", &start.ty);
+                let stop = Context::range_last(*inclusive, &counter, &next);
+                self.line(format!("if ({stop} == {last}) break;"));
+                self.line(format!("{counter} = {};", bare(&next)));
+                self.indent -= 1;
+                self.line("}");
+                self.indent -= 1;
+                self.line("}");
+            }
+            tree::Block::Each(local, iter, stmts) => {
+                let (base, len, element) = match &iter.ty {
+                    Type::Ref(inner, _) | Type::Ptr(inner, _) => {
+                        let code = self.expr(iter).unwrap();
+                        match &**inner {
+                            Type::ArrayStatic(element, len) => {
+                                (format!("{code}->items"), len.to_string(), element)
+                            }
+                            Type::Array(element) => {
+                                let fat = self.spill(code, &iter.ty);
+                                let ty = self.c.ty(element);
+                                (format!("({ty} *){fat}.ptr"), format!("{fat}.len"), element)
+                            }
+                            ty => unreachable!("{ty} is not an array"),
+                        }
+                    }
+                    Type::ArrayStatic(element, len) => {
+                        let Place::Sized(code) = self.place(iter) else {
+                            unreachable!("arrays by value are sized")
+                        };
+                        (format!("{code}.items"), len.to_string(), element)
+                    }
+                    ty => unreachable!("{ty} is not an array"),
+                };
+                let element_ty = self.c.ty(element);
+                let items = self.temp(&format!("{element_ty} *"));
+                self.line(format!("{items} = {base};"));
+                let len = self.spill(len, &Type::USIZE);
+                let index = self.temp("size_t");
+                self.line(format!("for ({index} = 0; {index} < {len}; {index}++) {{"));
+                self.indent += 1;
+                if let Some(local) = self.locals[local.0].clone() {
+                    match iter.ty.pointee().is_some() {
+                        true => self.line(format!("{local} = &{items}[{index}];")),
+                        false => self.line(format!("{local} = {items}[{index}];")),
+                    }
+                }
+                self.stmts(stmts);
+                self.continue_label(id);
+                self.indent -= 1;
+                self.line("}");
+            }
+        }
+
+        if self.labels[id.0].as_ref().unwrap().broken {
+            self.label(format!("bc_break_{}", id.0));
+        }
+        result
+    }
+
+    fn continue_label(&mut self, id: LabelId) {
+        if self.labels[id.0].as_ref().unwrap().continued {
+            self.label(format!("bc_continue_{}", id.0));
+        }
+    }
+}
+
+#[cfg(test)]
+fn emit_source(src: &str) -> String {
+    let mut program = crate::stage::check::checked_source(src);
+    emit(&mut program.context)
+}
+
+#[cfg(test)]
+const PROGRAM: &str = r#"
+    struct Point { i32 x, i64 y, }
+    union Bits { u32 int, f32 float, }
+    enum Color { Red, Green, Blue, }
+
+    static Point ORIGIN = Point { x = 0, y = 0 };
+    static &str GREETING = "hello\n";
+    static u8 COUNTER = 0;
+
+    extern "C" fn write(i32 fd, *u8 data, usize len) isize;
+    extern "C" fn strlen(*u8 str) usize;
+
+    impl Point {
+        fn scaled(&Self self, i32 by) i32 { return self.x * by; }
+    }
+
+    fn first_even(&[i32] values) i32 {
+        i32 found = 'search {
+            for (value in values) {
+                if (*value % 2 == 0) { break 'search *value; }
+            }
+            break 'search -1;
+        };
+        return found;
+    }
+
+    fn main() i32 {
+        write(1, GREETING, strlen(GREETING));
+        mut Point p = Point { x = 3, y = -4 };
+        p.x += 10;
+        mut i32 total = 0;
+        for (i in 0..=4) {
+            if (i == 2) { continue; }
+            total += i;
+        }
+        COUNTER += 200u8;
+        COUNTER += 100u8;
+        Bits bits = Bits { float = 1.0 };
+        Color color = Color::Blue;
+        if (color != Color::Blue || bits.int != 1065353216u32) { return 1; }
+        if (COUNTER != 44u8) { return 2; }
+        return total + p.scaled(0);
+    }
+"#;
+
+#[test]
+fn emit_program() {
+    crate::ir::assert_snapshot("c_program.c", &emit_source(PROGRAM));
+}
+
+#[test]
+fn compile_and_run() {
+    let dir = std::env::temp_dir().join(format!("bc_c_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.c");
+    let binary = dir.join("program");
+    std::fs::write(&source, emit_source(PROGRAM)).unwrap();
+    let Ok(status) = std::process::Command::new("cc")
+        .args(["-std=c11", "-o"])
+        .args([&binary, &source])
+        // the prototypes of C functions don't always match libc's
+        .args(["-fno-builtin", "-lm"])
+        .status()
+    else {
+        // no C compiler to check the output with
+        return;
+    };
+    assert!(status.success());
+    let output = std::process::Command::new(&binary).output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(output.stdout, b"hello\n");
+    // 0 + 1 + 3 + 4
+    assert_eq!(output.status.code(), Some(8));
+}
@@ -0,0 +1,365 @@
+//! Writing ELF64 relocatable object files for x86-64.
+//!
+//! Objects have a fixed set of sections, symbols are local unless they are
+//! global or weak and relocations name the symbol they refer to. Symbols
+//! nothing defines end up undefined for the linker to find.
+
+use crate::parser::ast::Vis;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Text,
+    Data,
+    Rodata,
+    Bss,
+}
+
+impl SectionKind {
+    pub const ALL: [SectionKind; 4] = [
+        SectionKind::Text,
+        SectionKind::Data,
+        SectionKind::Rodata,
+        SectionKind::Bss,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            SectionKind::Text => ".text",
+            SectionKind::Data => ".data",
+            SectionKind::Rodata => ".rodata",
+            SectionKind::Bss => ".bss",
+        }
+    }
+
+    fn flags(self) -> u64 {
+        match self {
+            SectionKind::Text => SHF_ALLOC | SHF_EXECINSTR,
+            SectionKind::Data | SectionKind::Bss => SHF_ALLOC | SHF_WRITE,
+            SectionKind::Rodata => SHF_ALLOC,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Section {
+    /// The contents, only the length matters for `.bss`
+    pub bytes: Vec<u8>,
+    pub align: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Object,
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub vis: Vis,
+    /// Global symbols that something else can define instead
+    pub weak: bool,
+    pub kind: SymbolKind,
+    /// Where the symbol is defined, `None` if it's defined elsewhere
+    pub section: Option<SectionKind>,
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl Symbol {
+    /// Whether only this object can see the symbol
+    fn is_local(&self) -> bool {
+        self.vis == Vis::Priv && !self.weak && self.section.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+    /// The 8 byte address of the symbol
+    Abs64,
+    /// The 4 byte distance from the relocated bytes to the symbol
+    Pc32,
+    /// Like `Pc32` but may go through the procedure linkage table
+    Plt32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub section: SectionKind,
+    pub offset: usize,
+    pub symbol: String,
+    pub kind: RelocationKind,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Object {
+    pub sections: [Section; 4],
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+const R_X86_64_64: u64 = 1;
+const R_X86_64_PC32: u64 = 2;
+const R_X86_64_PLT32: u64 = 4;
+
+const HEADER_SIZE: usize = 64;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+/// A table of nul terminated names
+#[derive(Default)]
+struct Strings {
+    bytes: Vec<u8>,
+}
+
+impl Strings {
+    fn new() -> Self {
+        Self { bytes: vec![0] }
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: usize,
+    size: usize,
+    link: u32,
+    info: u32,
+    align: usize,
+    entry_size: usize,
+}
+
+impl Object {
+    pub fn section(&self, kind: SectionKind) -> &Section {
+        &self.sections[kind as usize]
+    }
+
+    pub fn section_mut(&mut self, kind: SectionKind) -> &mut Section {
+        &mut self.sections[kind as usize]
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// Lays the object out as an ELF file
+    pub fn write(&self) -> Vec<u8> {
+        // locals come first in the symbol table
+        let mut symbols: Vec<&Symbol> = self.symbols.iter().collect();
+        symbols.sort_by_key(|symbol| !symbol.is_local());
+        let locals = 1 + symbols.iter().filter(|symbol| symbol.is_local()).count();
+        let index = |name: &str| {
+            1 + symbols
+                .iter()
+                .position(|symbol| symbol.name == name)
+                .expect("relocation against a symbol that doesn't exist") as u64
+        };
+
+        let mut out = vec![0; HEADER_SIZE];
+        let mut headers = Vec::new();
+        let mut names = Strings::new();
+        // the section indices of `.text` to `.bss` are 1 to 4
+        for kind in SectionKind::ALL {
+            let section = self.section(kind);
+            let offset = align(&mut out, section.align.max(1));
+            if kind != SectionKind::Bss {
+                out.extend_from_slice(&section.bytes);
+            }
+            headers.push(SectionHeader {
+                name: names.add(kind.name()),
+                kind: match kind {
+                    SectionKind::Bss => SHT_NOBITS,
+                    _ => SHT_PROGBITS,
+                },
+                flags: kind.flags(),
+                offset,
+                size: section.bytes.len(),
+                link: 0,
+                info: 0,
+                align: section.align.max(1),
+                entry_size: 0,
+            });
+        }
+
+        let symtab = SectionKind::ALL.len() + 1 + SectionKind::ALL.len();
+        let strtab = symtab + 1;
+        for kind in SectionKind::ALL {
+            let offset = align(&mut out, 8);
+            let mut count = 0;
+            for relocation in self.relocations.iter().filter(|r| r.section == kind) {
+                let kind = match relocation.kind {
+                    RelocationKind::Abs64 => R_X86_64_64,
+                    RelocationKind::Pc32 => R_X86_64_PC32,
+                    RelocationKind::Plt32 => R_X86_64_PLT32,
+                };
+                out.extend_from_slice(&(relocation.offset as u64).to_le_bytes());
+                out.extend_from_slice(&(index(&relocation.symbol) << 32 | kind).to_le_bytes());
+                out.extend_from_slice(&relocation.addend.to_le_bytes());
+                count += 1;
+            }
+            headers.push(SectionHeader {
+                name: names.add(&format!(".rela{}", kind.name())),
+                kind: SHT_RELA,
+                flags: SHF_INFO_LINK,
+                offset,
+                size: count * RELA_SIZE,
+                link: symtab as u32,
+                info: kind as u32 + 1,
+                align: 8,
+                entry_size: RELA_SIZE,
+            });
+        }
+
+        let mut strings = Strings::new();
+        let offset = align(&mut out, 8);
+        out.extend_from_slice(&[0; SYMBOL_SIZE]);
+        for symbol in &symbols {
+            let bind = if symbol.weak {
+                STB_WEAK
+            } else if symbol.is_local() {
+                STB_LOCAL
+            } else {
+                STB_GLOBAL
+            };
+            let kind = match symbol.kind {
+                SymbolKind::Function => STT_FUNC,
+                SymbolKind::Object => STT_OBJECT,
+                SymbolKind::Unknown => STT_NOTYPE,
+            };
+            let section = symbol.section.map_or(0, |kind| kind as u16 + 1);
+            out.extend_from_slice(&strings.add(&symbol.name).to_le_bytes());
+            out.push(bind << 4 | kind);
+            out.push(0);
+            out.extend_from_slice(&section.to_le_bytes());
+            out.extend_from_slice(&(symbol.offset as u64).to_le_bytes());
+            out.extend_from_slice(&(symbol.size as u64).to_le_bytes());
+        }
+        headers.push(SectionHeader {
+            name: names.add(".symtab"),
+            kind: SHT_SYMTAB,
+            flags: 0,
+            offset,
+            size: (symbols.len() + 1) * SYMBOL_SIZE,
+            link: strtab as u32,
+            info: locals as u32,
+            align: 8,
+            entry_size: SYMBOL_SIZE,
+        });
+
+        let offset = out.len();
+        out.extend_from_slice(&strings.bytes);
+        headers.push(SectionHeader {
+            name: names.add(".strtab"),
+            kind: SHT_STRTAB,
+            flags: 0,
+            offset,
+            size: strings.bytes.len(),
+            link: 0,
+            info: 0,
+            align: 1,
+            entry_size: 0,
+        });
+
+        // without it linkers assume the stack has to be executable
+        headers.push(SectionHeader {
+            name: names.add(".note.GNU-stack"),
+            kind: SHT_PROGBITS,
+            flags: 0,
+            offset: out.len(),
+            size: 0,
+            link: 0,
+            info: 0,
+            align: 1,
+            entry_size: 0,
+        });
+
+        let shstrtab = headers.len() + 1;
+        let name = names.add(".shstrtab");
+        let offset = out.len();
+        out.extend_from_slice(&names.bytes);
+        headers.push(SectionHeader {
+            name,
+            kind: SHT_STRTAB,
+            flags: 0,
+            offset,
+            size: names.bytes.len(),
+            link: 0,
+            info: 0,
+            align: 1,
+            entry_size: 0,
+        });
+
+        let section_headers = align(&mut out, 8);
+        out.extend_from_slice(&[0; SECTION_HEADER_SIZE]);
+        for header in &headers {
+            out.extend_from_slice(&header.name.to_le_bytes());
+            out.extend_from_slice(&header.kind.to_le_bytes());
+            out.extend_from_slice(&header.flags.to_le_bytes());
+            out.extend_from_slice(&0u64.to_le_bytes());
+            out.extend_from_slice(&(header.offset as u64).to_le_bytes());
+            out.extend_from_slice(&(header.size as u64).to_le_bytes());
+            out.extend_from_slice(&header.link.to_le_bytes());
+            out.extend_from_slice(&header.info.to_le_bytes());
+            out.extend_from_slice(&(header.align as u64).to_le_bytes());
+            out.extend_from_slice(&(header.entry_size as u64).to_le_bytes());
+        }
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        // 64 bit, little endian, version 1, System V
+        header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        header.extend_from_slice(&[0; 8]);
+        // relocatable, x86-64, version 1
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&62u16.to_le_bytes());
+        header.extend_from_slice(&1u32.to_le_bytes());
+        // no entry point or program headers
+        header.extend_from_slice(&0u64.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes());
+        header.extend_from_slice(&(section_headers as u64).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&(headers.len() as u16 + 1).to_le_bytes());
+        header.extend_from_slice(&(shstrtab as u16).to_le_bytes());
+        out[..HEADER_SIZE].copy_from_slice(&header);
+        out
+    }
+}
+
+/// Pads `out` to a multiple of `align` and gives the new length
+fn align(out: &mut Vec<u8>, align: usize) -> usize {
+    out.resize(out.len().next_multiple_of(align), 0);
+    out.len()
+}
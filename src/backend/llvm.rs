@@ -0,0 +1,736 @@
+//! Textual LLVM IR for `llc`, `opt` or `clang`, written without LLVM itself.
+//!
+//! Struct and union types become named packed types with explicit padding,
+//! so their layout is the one the checker gave them on any target, and
+//! statics get initializers of those types. Function bodies come from the
+//! IR with registers as SSA values, block parameters as phis and every slot
+//! an `alloca` of bytes that memory is reached through with byte offsets.
+//! Branch edges that pass arguments get a block of their own so each phi
+//! can tell them apart.
+
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    ir::{
+        lower::{mangle, static_symbol},
+        BinOp, CastOp, CmpOp, DataPart, Function, Inst, Module, Operand, Target, Terminator, Ty,
+        UnOp,
+    },
+    parser::ast::{FloatType, GlobalKind, IntSize, Path, Vis},
+    stage::{constant_eval::Value, types::Type, Context, UserType},
+};
+
+const MEMCPY: &str = "llvm.memcpy.p0.p0.i64";
+
+fn ty(ty: Ty) -> &'static str {
+    match ty {
+        Ty::Bool => "i1",
+        Ty::I8 => "i8",
+        Ty::I16 => "i16",
+        Ty::I32 => "i32",
+        Ty::I64 => "i64",
+        Ty::F32 => "float",
+        Ty::F64 => "double",
+        Ty::Ptr => "ptr",
+    }
+}
+
+fn ret_ty(ret: Option<Ty>) -> &'static str {
+    ret.map_or("void", ty)
+}
+
+/// LLVM wants floats of either width as the hex bits of a double
+fn float(value: f64) -> String {
+    format!("0x{:016X}", value.to_bits())
+}
+
+fn int(bits: u64, size: usize) -> String {
+    let shift = 64 - size * 8;
+    (((bits << shift) as i64) >> shift).to_string()
+}
+
+fn linkage(vis: Vis) -> &'static str {
+    match vis {
+        Vis::Pub => "",
+        Vis::Priv => "internal ",
+    }
+}
+
+/// A byte string constant
+fn bytes(bytes: &[u8]) -> String {
+    let mut out = format!("[{} x i8] c\"", bytes.len());
+    for byte in bytes {
+        match byte {
+            b' '..=b'~' if *byte != b'"' && *byte != b'\\' => out.push(*byte as char),
+            _ => write!(out, "\\{byte:02X}").unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn type_name(path: &Path, ty: &UserType) -> String {
+    match ty {
+        UserType::Union(_) => format!("%union.{}", mangle(path)),
+        _ => format!("%struct.{}", mangle(path)),
+    }
+}
+
+pub fn emit(context: &mut Context, module: &Module) -> String {
+    let mut emitter = Emitter {
+        context,
+        out: String::new(),
+        strings: module
+            .data
+            .iter()
+            .filter(|data| data.name.starts_with("__str_"))
+            .filter_map(|data| match data.init.as_deref() {
+                Some([DataPart::Bytes(bytes)]) => {
+                    let value = String::from_utf8(bytes[..bytes.len() - 1].to_vec()).ok()?;
+                    Some((value, data.name.clone()))
+                }
+                _ => None,
+            })
+            .collect(),
+    };
+    emitter.types();
+
+    let mut statics = HashMap::new();
+    for (path, var) in emitter.context.globals() {
+        if !matches!(var.kind, GlobalKind::Const) {
+            statics.insert(static_symbol(emitter.context, path), var.clone());
+        }
+    }
+    for data in &module.data {
+        let var = statics.get(&data.name);
+        let init = match (&data.init, var) {
+            (None, Some(var)) => {
+                let ty = emitter.ty(&var.ty);
+                writeln!(emitter.out, "@{} = external global {ty}", data.name).unwrap();
+                continue;
+            }
+            (None, None) => {
+                writeln!(emitter.out, "@{} = external global i8", data.name).unwrap();
+                continue;
+            }
+            (_, Some(var)) if !emitter.has_union(&var.ty) => {
+                emitter.constant(var.value.as_ref().unwrap(), &var.ty)
+            }
+            (Some(parts), _) => data_parts(parts),
+        };
+        let kind = if data.mutable { "global" } else { "constant" };
+        writeln!(
+            emitter.out,
+            "@{} = {}{kind} {init}, align {}",
+            data.name,
+            linkage(data.vis),
+            data.align
+        )
+        .unwrap();
+    }
+    if !module.data.is_empty() {
+        emitter.out.push('\n');
+    }
+
+    for ext in &module.externs {
+        let params: Vec<_> = ext.params.iter().map(|param| ty(*param)).collect();
+        let ret = ret_ty(ext.ret);
+        writeln!(
+            emitter.out,
+            "declare {ret} @{}({})",
+            ext.name,
+            params.join(", ")
+        )
+        .unwrap();
+    }
+    if !module.externs.is_empty() {
+        emitter.out.push('\n');
+    }
+
+    let rets: HashMap<&str, Option<Ty>> = module
+        .externs
+        .iter()
+        .map(|ext| (ext.name.as_str(), ext.ret))
+        .chain(
+            module
+                .functions
+                .iter()
+                .map(|function| (function.name.as_str(), function.ret)),
+        )
+        .collect();
+    let mut memcpy = false;
+    for function in &module.functions {
+        let mut emitter = FunctionEmitter {
+            out: &mut emitter.out,
+            function,
+            rets: &rets,
+            temps: 0,
+            memcpy: false,
+        };
+        emitter.emit();
+        memcpy |= emitter.memcpy;
+        emitter.out.push('\n');
+    }
+    if memcpy {
+        writeln!(emitter.out, "declare void @{MEMCPY}(ptr, ptr, i64, i1)").unwrap();
+    }
+    while emitter.out.ends_with("\n\n") {
+        emitter.out.pop();
+    }
+    emitter.out
+}
+
+/// Data the IR only knows the bytes of, a packed struct when it holds
+/// addresses of other symbols
+fn data_parts(parts: &[DataPart]) -> String {
+    let parts: Vec<_> = parts
+        .iter()
+        .map(|part| match part {
+            DataPart::Bytes(data) => bytes(data),
+            DataPart::Addr(name) => format!("ptr @{name}"),
+        })
+        .collect();
+    match &parts[..] {
+        [part] if part.starts_with('[') => part.clone(),
+        _ => {
+            let types: Vec<_> = parts
+                .iter()
+                .map(|part| match part.split_once(" c\"") {
+                    Some((ty, _)) => ty,
+                    None => "ptr",
+                })
+                .collect();
+            format!("<{{ {} }}> <{{ {} }}>", types.join(", "), parts.join(", "))
+        }
+    }
+}
+
+struct Emitter<'a> {
+    context: &'a mut Context,
+    out: String,
+    /// The data symbol of every string literal
+    strings: HashMap<String, String>,
+}
+
+impl Emitter<'_> {
+    /// Defines every struct and union as a packed type with its padding
+    fn types(&mut self) {
+        let mut paths: Vec<Path> = self
+            .context
+            .user_types()
+            .filter(|(_, ty)| matches!(ty, UserType::Struct(_) | UserType::Union(_)))
+            .map(|(path, _)| path.clone())
+            .collect();
+        paths.sort_by_key(|path| path.to_string());
+        for path in &paths {
+            let layout = self.context.layout(path);
+            let fields = match self.context.user_type(path).unwrap() {
+                UserType::Struct(struc) => {
+                    let members: Vec<_> = struc
+                        .members
+                        .iter()
+                        .map(|member| (member.offset, member.ty.clone()))
+                        .collect();
+                    let mut fields = Vec::new();
+                    let mut end = 0;
+                    for (offset, ty) in members {
+                        if offset > end {
+                            fields.push(format!("[{} x i8]", offset - end));
+                        }
+                        end = offset + ty.layout(self.context).size_bytes();
+                        fields.push(self.ty(&ty));
+                    }
+                    if layout.size_bytes() > end {
+                        fields.push(format!("[{} x i8]", layout.size_bytes() - end));
+                    }
+                    fields
+                }
+                _ => vec![format!("[{} x i8]", layout.size_bytes())],
+            };
+            let name = type_name(path, self.context.user_type(path).unwrap());
+            writeln!(self.out, "{name} = type <{{ {} }}>", fields.join(", ")).unwrap();
+        }
+        if !paths.is_empty() {
+            self.out.push('\n');
+        }
+    }
+
+    /// The type of values of a checked type in memory
+    fn ty(&mut self, ty: &Type) -> String {
+        match ty {
+            Type::Int(IntSize::U8, _) | Type::Bool | Type::Char => "i8".to_string(),
+            Type::Int(IntSize::U16, _) => "i16".to_string(),
+            Type::Int(IntSize::U32, _) => "i32".to_string(),
+            Type::Int(IntSize::U64 | IntSize::Usize, _) => "i64".to_string(),
+            Type::Float(FloatType::F32) => "float".to_string(),
+            Type::Float(FloatType::F64) => "double".to_string(),
+            Type::Void => "{}".to_string(),
+            Type::FnPointer(..) => "ptr".to_string(),
+            Type::Ptr(inner, _) | Type::Ref(inner, _) => match inner.is_sized(self.context) {
+                true => "ptr".to_string(),
+                false => "{ ptr, i64 }".to_string(),
+            },
+            Type::ArrayStatic(element, len) => format!("[{len} x {}]", self.ty(element)),
+            Type::Nammed(path) => match self.context.user_type(path) {
+                Some(UserType::Enum(_)) => match self.context.layout(path).size_bytes() {
+                    0 => "{}".to_string(),
+                    size => format!("i{}", size * 8),
+                },
+                Some(user) => type_name(path, user),
+                None => unreachable!("{path} is not a type"),
+            },
+            Type::Str | Type::Array(_) => unreachable!("{ty} is unsized"),
+        }
+    }
+
+    /// Whether a value of the type holds a union, which LLVM has no
+    /// constants for
+    fn has_union(&mut self, ty: &Type) -> bool {
+        match ty {
+            Type::ArrayStatic(element, _) => self.has_union(element),
+            Type::Nammed(path) => match self.context.user_type(path) {
+                Some(UserType::Union(_)) => true,
+                Some(UserType::Struct(struc)) => {
+                    let members: Vec<_> = struc.members.iter().map(|m| m.ty.clone()).collect();
+                    members.iter().any(|member| self.has_union(member))
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// A typed constant holding a value
+    fn constant(&mut self, value: &Value, ty: &Type) -> String {
+        let llvm = self.ty(ty);
+        let size = ty.layout(self.context).size_bytes();
+        match value {
+            Value::Void(()) => format!("{llvm} zeroinitializer"),
+            Value::F32(value) => format!("float {}", float(*value as f64)),
+            Value::F64(value) => format!("double {}", float(*value)),
+            Value::Str(value) => {
+                let name = &self.strings[value];
+                format!("{llvm} {{ ptr @{name}, i64 {} }}", value.len())
+            }
+            Value::Ref(path) | Value::Ptr(path) => {
+                format!("ptr @{}", static_symbol(self.context, path))
+            }
+            Value::Array(values) => {
+                let element = ty.element().unwrap();
+                let values: Vec<_> = values
+                    .iter()
+                    .map(|value| self.constant(value, element))
+                    .collect();
+                format!("{llvm} [{}]", values.join(", "))
+            }
+            Value::Struct(values) => {
+                let Type::Nammed(path) = ty else {
+                    unreachable!("{ty} is not a struct")
+                };
+                let Some(UserType::Struct(struc)) = self.context.user_type(path) else {
+                    unreachable!("{ty} is not a struct")
+                };
+                let members: Vec<_> = struc
+                    .members
+                    .iter()
+                    .map(|member| (member.offset, member.ty.clone()))
+                    .collect();
+                let mut fields = Vec::new();
+                let mut end = 0;
+                for (value, (offset, ty)) in values.iter().zip(members) {
+                    if offset > end {
+                        fields.push(format!("[{} x i8] zeroinitializer", offset - end));
+                    }
+                    end = offset + ty.layout(self.context).size_bytes();
+                    fields.push(self.constant(value, &ty));
+                }
+                if size > end {
+                    fields.push(format!("[{} x i8] zeroinitializer", size - end));
+                }
+                format!("{llvm} <{{ {} }}>", fields.join(", "))
+            }
+            Value::Union(..) => unreachable!("unions are written as bytes"),
+            _ if size == 0 => format!("{llvm} zeroinitializer"),
+            _ => format!("{llvm} {}", int(value.as_int().unwrap() as u64, size)),
+        }
+    }
+}
+
+struct FunctionEmitter<'a> {
+    out: &'a mut String,
+    function: &'a Function,
+    /// What every function and extern returns
+    rets: &'a HashMap<&'a str, Option<Ty>>,
+    temps: usize,
+    memcpy: bool,
+}
+
+impl FunctionEmitter<'_> {
+    fn value(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Reg(reg) => format!("%r{}", reg.0),
+            Operand::Int(Ty::Bool, bits) => (*bits != 0).to_string(),
+            Operand::Int(Ty::Ptr, 0) => "null".to_string(),
+            Operand::Int(Ty::Ptr, bits) => format!("inttoptr (i64 {bits} to ptr)"),
+            Operand::Int(ty, bits) => int(*bits, ty.size()),
+            Operand::F32(value) => float(*value as f64),
+            Operand::F64(value) => float(*value),
+            Operand::Global(name) => format!("@{name}"),
+        }
+    }
+
+    fn typed(&self, operand: &Operand) -> String {
+        let ty = ty(self.function.operand_ty(operand));
+        format!("{ty} {}", self.value(operand))
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("%t{}", self.temps)
+    }
+
+    fn emit(&mut self) {
+        let function = self.function;
+        let mut incoming: Vec<Vec<(String, &[Operand])>> = vec![Vec::new(); function.blocks.len()];
+        for (i, block) in function.blocks.iter().enumerate() {
+            match &block.term {
+                Terminator::Jump(target) => {
+                    incoming[target.block.0].push((format!("%b{i}"), &target.args));
+                }
+                Terminator::Branch(_, a, b) => {
+                    for (edge, target) in [a, b].into_iter().enumerate() {
+                        let pred = match target.args.is_empty() {
+                            true => format!("%b{i}"),
+                            false => format!("%b{i}.{edge}"),
+                        };
+                        incoming[target.block.0].push((pred, &target.args));
+                    }
+                }
+                Terminator::Return(_) | Terminator::Unreachable => {}
+            }
+        }
+        // the entry block can't be jumped to, loops back to it need another
+        let separate_entry = !incoming[0].is_empty();
+        let params: Vec<_> = function
+            .params()
+            .iter()
+            .map(|reg| {
+                let ty = ty(function.reg_ty(*reg));
+                match separate_entry {
+                    true => format!("{ty} %a{}", reg.0),
+                    false => format!("{ty} %r{}", reg.0),
+                }
+            })
+            .collect();
+
+        writeln!(
+            self.out,
+            "define {}{} @{}({}) {{",
+            linkage(function.vis),
+            ret_ty(function.ret),
+            function.name,
+            params.join(", ")
+        )
+        .unwrap();
+        let allocas: Vec<_> = function
+            .slots
+            .iter()
+            .enumerate()
+            .map(|(i, slot)| {
+                format!(
+                    "  %s{i} = alloca [{} x i8], align {}",
+                    slot.size, slot.align
+                )
+            })
+            .collect();
+        if separate_entry {
+            writeln!(self.out, "entry:").unwrap();
+            for alloca in &allocas {
+                writeln!(self.out, "{alloca}").unwrap();
+            }
+            writeln!(self.out, "  br label %b0").unwrap();
+        }
+
+        for (i, block) in function.blocks.iter().enumerate() {
+            writeln!(self.out, "b{i}:").unwrap();
+            if i == 0 && !separate_entry {
+                for alloca in &allocas {
+                    writeln!(self.out, "{alloca}").unwrap();
+                }
+            } else {
+                for (index, param) in block.params.iter().enumerate() {
+                    let mut edges: Vec<_> = incoming[i]
+                        .iter()
+                        .map(|(pred, args)| format!("[ {}, {pred} ]", self.value(&args[index])))
+                        .collect();
+                    if i == 0 {
+                        edges.push(format!("[ %a{}, %entry ]", param.0));
+                    }
+                    let ty = ty(function.reg_ty(*param));
+                    writeln!(self.out, "  %r{} = phi {ty} {}", param.0, edges.join(", ")).unwrap();
+                }
+            }
+            for inst in &block.insts {
+                self.inst(inst);
+            }
+            self.terminator(i, &block.term);
+        }
+        writeln!(self.out, "}}").unwrap();
+    }
+
+    fn inst(&mut self, inst: &Inst) {
+        match inst {
+            Inst::Binary(reg, op, a, b) => {
+                let ty = ty(self.function.operand_ty(a));
+                let name = match op {
+                    BinOp::Add => "add",
+                    BinOp::Sub => "sub",
+                    BinOp::Mul => "mul",
+                    BinOp::SDiv => "sdiv",
+                    BinOp::UDiv => "udiv",
+                    BinOp::SRem => "srem",
+                    BinOp::URem => "urem",
+                    BinOp::FAdd => "fadd",
+                    BinOp::FSub => "fsub",
+                    BinOp::FMul => "fmul",
+                    BinOp::FDiv => "fdiv",
+                    BinOp::FRem => "frem",
+                    BinOp::And => "and",
+                    BinOp::Or => "or",
+                    BinOp::Xor => "xor",
+                    BinOp::Shl => "shl",
+                    BinOp::LShr => "lshr",
+                    BinOp::AShr => "ashr",
+                };
+                let mut b = self.value(b);
+                // shifting by the width or more is poison in LLVM, the IR
+                // only uses the low bits of the amount
+                if matches!(op, BinOp::Shl | BinOp::LShr | BinOp::AShr) {
+                    let bits = self.function.operand_ty(a).size() * 8;
+                    let amount = self.temp();
+                    writeln!(self.out, "  {amount} = and {ty} {b}, {}", bits - 1).unwrap();
+                    b = amount;
+                }
+                let a = self.value(a);
+                writeln!(self.out, "  %r{} = {name} {ty} {a}, {b}", reg.0).unwrap();
+            }
+            Inst::Unary(reg, op, value) => {
+                let ty = ty(self.function.operand_ty(value));
+                let value = self.value(value);
+                let line = match op {
+                    UnOp::Neg => format!("sub {ty} 0, {value}"),
+                    UnOp::FNeg => format!("fneg {ty} {value}"),
+                    UnOp::Not if ty == "i1" => format!("xor i1 {value}, true"),
+                    UnOp::Not => format!("xor {ty} {value}, -1"),
+                };
+                writeln!(self.out, "  %r{} = {line}", reg.0).unwrap();
+            }
+            Inst::Cmp(reg, op, a, b) => {
+                let (inst, cond) = match op {
+                    CmpOp::Eq => ("icmp", "eq"),
+                    CmpOp::Ne => ("icmp", "ne"),
+                    CmpOp::SLt => ("icmp", "slt"),
+                    CmpOp::SLe => ("icmp", "sle"),
+                    CmpOp::SGt => ("icmp", "sgt"),
+                    CmpOp::SGe => ("icmp", "sge"),
+                    CmpOp::ULt => ("icmp", "ult"),
+                    CmpOp::ULe => ("icmp", "ule"),
+                    CmpOp::UGt => ("icmp", "ugt"),
+                    CmpOp::UGe => ("icmp", "uge"),
+                    CmpOp::FEq => ("fcmp", "oeq"),
+                    CmpOp::FNe => ("fcmp", "une"),
+                    CmpOp::FLt => ("fcmp", "olt"),
+                    CmpOp::FLe => ("fcmp", "ole"),
+                    CmpOp::FGt => ("fcmp", "ogt"),
+                    CmpOp::FGe => ("fcmp", "oge"),
+                };
+                let a = self.typed(a);
+                let b = self.value(b);
+                writeln!(self.out, "  %r{} = {inst} {cond} {a}, {b}", reg.0).unwrap();
+            }
+            Inst::Cast(reg, op, value) => {
+                let from = self.function.operand_ty(value);
+                let to = self.function.reg_ty(*reg);
+                let name = match (op, from, to) {
+                    (_, Ty::Ptr, _) => "ptrtoint",
+                    (_, _, Ty::Ptr) => "inttoptr",
+                    (CastOp::ZExt, ..) => "zext",
+                    (CastOp::SExt, ..) => "sext",
+                    (CastOp::Trunc, ..) => "trunc",
+                };
+                let value = self.typed(value);
+                writeln!(self.out, "  %r{} = {name} {value} to {}", reg.0, ty(to)).unwrap();
+            }
+            Inst::SlotAddr(reg, slot) => {
+                writeln!(
+                    self.out,
+                    "  %r{} = getelementptr i8, ptr %s{}, i64 0",
+                    reg.0, slot.0
+                )
+                .unwrap();
+            }
+            Inst::Load(reg, addr) => {
+                let ty = ty(self.function.reg_ty(*reg));
+                let addr = self.value(addr);
+                writeln!(self.out, "  %r{} = load {ty}, ptr {addr}", reg.0).unwrap();
+            }
+            Inst::Store(addr, value) => {
+                let value = self.typed(value);
+                let addr = self.value(addr);
+                writeln!(self.out, "  store {value}, ptr {addr}").unwrap();
+            }
+            Inst::PtrAdd(reg, addr, offset) => {
+                let addr = self.value(addr);
+                let offset = self.typed(offset);
+                writeln!(
+                    self.out,
+                    "  %r{} = getelementptr i8, ptr {addr}, {offset}",
+                    reg.0
+                )
+                .unwrap();
+            }
+            Inst::Copy(to, from, size) => {
+                self.memcpy = true;
+                let (to, from) = (self.value(to), self.value(from));
+                writeln!(
+                    self.out,
+                    "  call void @{MEMCPY}(ptr {to}, ptr {from}, i64 {size}, i1 false)"
+                )
+                .unwrap();
+            }
+            Inst::Call(reg, callee, args) => {
+                let ret = match (callee, reg) {
+                    (Operand::Global(name), _) if self.rets.contains_key(name.as_str()) => {
+                        self.rets[name.as_str()]
+                    }
+                    (_, reg) => reg.map(|reg| self.function.reg_ty(reg)),
+                };
+                let args: Vec<_> = args.iter().map(|arg| self.typed(arg)).collect();
+                let call = format!(
+                    "call {} {}({})",
+                    ret_ty(ret),
+                    self.value(callee),
+                    args.join(", ")
+                );
+                match reg {
+                    Some(reg) => writeln!(self.out, "  %r{} = {call}", reg.0).unwrap(),
+                    None => writeln!(self.out, "  {call}").unwrap(),
+                }
+            }
+        }
+    }
+
+    fn terminator(&mut self, block: usize, term: &Terminator) {
+        match term {
+            Terminator::Jump(target) => {
+                writeln!(self.out, "  br label %b{}", target.block.0).unwrap();
+            }
+            Terminator::Branch(cond, a, b) => {
+                let label = |edge: usize, target: &Target| match target.args.is_empty() {
+                    true => format!("%b{}", target.block.0),
+                    false => format!("%b{block}.{edge}"),
+                };
+                let cond = self.value(cond);
+                writeln!(
+                    self.out,
+                    "  br i1 {cond}, label {}, label {}",
+                    label(0, a),
+                    label(1, b)
+                )
+                .unwrap();
+                for (edge, target) in [a, b].into_iter().enumerate() {
+                    if !target.args.is_empty() {
+                        writeln!(self.out, "b{block}.{edge}:").unwrap();
+                        writeln!(self.out, "  br label %b{}", target.block.0).unwrap();
+                    }
+                }
+            }
+            Terminator::Return(Some(value)) => {
+                let value = self.typed(value);
+                writeln!(self.out, "  ret {value}").unwrap();
+            }
+            Terminator::Return(None) => writeln!(self.out, "  ret void").unwrap(),
+            Terminator::Unreachable => writeln!(self.out, "  unreachable").unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+fn emit_source(src: &str, level: crate::ir::opt::OptLevel) -> String {
+    let mut program = crate::stage::check::checked_source(src);
+    let mut module = crate::ir::lower(&mut program.context);
+    crate::ir::opt::optimize(&mut module, level);
+    emit(&mut program.context, &module)
+}
+
+#[test]
+fn emit_module() {
+    let src = r#"
+        struct Point { u8 tag, i64 y, i16 z, }
+        struct Line { Point from, Point to, }
+        union Bits { u32 int, f32 float, }
+
+        const Point UNIT = Point { tag = 1, y = -1, z = 2 };
+        static Line LINE = Line { from = UNIT, to = Point { tag = 2, y = 5, z = -3 } };
+        static Bits BITS = Bits { float = 1.0 };
+        static &str NAME = "line\n";
+        static &Line CURRENT = &LINE;
+        static f64 SCALE = 2.5;
+        extern static u32 FLAG;
+
+        extern "C" fn write(i32 fd, *u8 data, usize len) isize;
+        extern "C" fn strlen(*u8 str) usize;
+
+        fn length(&Line line) i64 {
+            return line.to.y - line.from.y;
+        }
+
+        pub fn main() i32 {
+            write(1, NAME, strlen(NAME));
+            mut i64 total = 0;
+            for (i in 0..10) {
+                total += length(CURRENT) << i;
+            }
+            if (BITS.int != 1065353216u32 || FLAG == 0u32) { return 1; }
+            if (total != 4092i64) { return 2; }
+            return 0;
+        }
+    "#;
+    crate::ir::assert_snapshot(
+        "llvm_module.ll",
+        &emit_source(src, crate::ir::opt::OptLevel::O1),
+    );
+}
+
+#[test]
+fn run_with_lli() {
+    use crate::ir::opt::OptLevel;
+
+    let Ok(version) = std::process::Command::new("lli").arg("--version").output() else {
+        // no LLVM to check the output with
+        return;
+    };
+    let version = String::from_utf8_lossy(&version.stdout);
+    let major = version
+        .split("version ")
+        .nth(1)
+        .and_then(|version| version.split('.').next()?.parse::<u32>().ok())
+        .unwrap_or(u32::MAX);
+    for level in [OptLevel::O0, OptLevel::O2] {
+        let source = emit_source(crate::backend::x86_64::PROGRAM, level);
+        let mut lli = std::process::Command::new("lli");
+        // opaque pointers only became the default in LLVM 15
+        if major < 15 {
+            lli.arg("-opaque-pointers");
+        }
+        let mut child = lli
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::io::Write::write_all(&mut child.stdin.take().unwrap(), source.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(output.stdout, b"hello\n", "{source}");
+        assert_eq!(output.status.code(), Some(8), "{source}");
+    }
+}
@@ -0,0 +1,22 @@
+//! Turns checked programs into something a machine can run.
+
+use std::process::Command;
+
+pub mod c;
+pub mod elf;
+pub mod llvm;
+pub mod vm;
+pub mod wasm;
+pub mod x86_64;
+
+/// Runs a tool like the linker or C compiler, failing unless it succeeds
+fn run(command: &mut Command) -> Result<(), String> {
+    let name = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .status()
+        .map_err(|err| format!("could not run `{name}`: {err}"))?;
+    if !status.success() {
+        return Err(format!("`{name}` failed with {status}"));
+    }
+    Ok(())
+}
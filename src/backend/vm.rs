@@ -0,0 +1,900 @@
+//! Bytecode for the [`Run`] stack machine, so programs run without a native
+//! toolchain.
+//!
+//! Registers become locals of a function's frame and blocks become runs of
+//! operations, a jump stores the block arguments into the target's parameter
+//! locals before going there. Memory is a byte array with the layout every
+//! other backend uses: statics from [`DATA_START`], then a stack growing down
+//! from the end that holds the slots. Values narrower than a byte or wider
+//! than the type are never on the stack, an `i8` is a `u8`, a pointer a
+//! `u64`, and function pointers are one more than the function's index.
+//!
+//! `write` and `strlen` are the only `extern "C"` functions there is, every
+//! other one is an error when compiling.
+
+use std::{any::TypeId, collections::HashMap, io::Write};
+
+use crate::{
+    bruh2::{self, Checked, HorribleVec, Local, MachineOperation, Operator, Run, TypeError},
+    ir::{
+        BinOp, CastOp, CmpOp, DataPart, Function, Inst, Module, Operand, Reg, Target, Terminator,
+        Ty, UnOp,
+    },
+};
+
+/// Where statics start, nothing valid is at null
+const DATA_START: usize = 16;
+const STACK_SIZE: usize = 1024 * 1024;
+
+/// The type on the stack for each [`Ty`], in the order of [`index`]
+static TYPES: [TypeId; 7] = [
+    TypeId::of::<bool>(),
+    TypeId::of::<u8>(),
+    TypeId::of::<u16>(),
+    TypeId::of::<u32>(),
+    TypeId::of::<u64>(),
+    TypeId::of::<f32>(),
+    TypeId::of::<f64>(),
+];
+static PAIRS: [[TypeId; 2]; 7] = [
+    [TYPES[0], TYPES[0]],
+    [TYPES[1], TYPES[1]],
+    [TYPES[2], TYPES[2]],
+    [TYPES[3], TYPES[3]],
+    [TYPES[4], TYPES[4]],
+    [TYPES[5], TYPES[5]],
+    [TYPES[6], TYPES[6]],
+];
+/// An address and a value to store there
+static STORES: [[TypeId; 2]; 7] = [
+    [TYPES[4], TYPES[0]],
+    [TYPES[4], TYPES[1]],
+    [TYPES[4], TYPES[2]],
+    [TYPES[4], TYPES[3]],
+    [TYPES[4], TYPES[4]],
+    [TYPES[4], TYPES[5]],
+    [TYPES[4], TYPES[6]],
+];
+static WRITE: [TypeId; 3] = [TYPES[3], TYPES[4], TYPES[4]];
+
+fn index(ty: Ty) -> usize {
+    match ty {
+        Ty::Bool => 0,
+        Ty::I8 => 1,
+        Ty::I16 => 2,
+        Ty::I32 => 3,
+        Ty::I64 | Ty::Ptr => 4,
+        Ty::F32 => 5,
+        Ty::F64 => 6,
+    }
+}
+
+/// A local holding a value of the type on the stack for `ty`
+fn local(ty: Ty, offset: usize) -> Local {
+    match ty {
+        Ty::Bool => Local::new::<bool>(offset),
+        Ty::I8 => Local::new::<u8>(offset),
+        Ty::I16 => Local::new::<u16>(offset),
+        Ty::I32 => Local::new::<u32>(offset),
+        Ty::I64 | Ty::Ptr => Local::new::<u64>(offset),
+        Ty::F32 => Local::new::<f32>(offset),
+        Ty::F64 => Local::new::<f64>(offset),
+    }
+}
+
+fn one(ty: Ty) -> &'static [TypeId] {
+    std::slice::from_ref(&TYPES[index(ty)])
+}
+
+/// The memory and output of a running program
+#[derive(Debug)]
+pub struct Machine {
+    pub memory: Vec<u8>,
+    /// The lowest address of the stack's live part
+    sp: usize,
+    /// The lowest address the stack may reach
+    limit: usize,
+    /// Where writes to standard output go, straight through when `None`
+    pub stdout: Option<Vec<u8>>,
+}
+
+impl Machine {
+    fn bytes(&mut self, address: u64, size: usize) -> Result<&mut [u8], String> {
+        let start = address as usize;
+        match self.memory.get_mut(start..start.saturating_add(size)) {
+            Some(bytes) if start >= DATA_START => Ok(bytes),
+            _ => Err(format!(
+                "out of bounds memory access of {size} bytes at {address:#x}"
+            )),
+        }
+    }
+
+    fn write(&mut self, fd: u32, address: u64, len: u64) -> Result<u64, String> {
+        let bytes = self.bytes(address, len as usize)?.to_vec();
+        let written = match (fd, &mut self.stdout) {
+            (1, Some(stdout)) => {
+                stdout.extend_from_slice(&bytes);
+                Ok(())
+            }
+            (1, None) => std::io::stdout().write_all(&bytes),
+            (2, _) => std::io::stderr().write_all(&bytes),
+            _ => return Ok(-1i64 as u64),
+        };
+        match written {
+            Ok(()) => Ok(len),
+            Err(_) => Ok(-1i64 as u64),
+        }
+    }
+
+    fn strlen(&mut self, address: u64) -> Result<u64, String> {
+        let mut len = 0;
+        while self.bytes(address + len, 1)?[0] != 0 {
+            len += 1;
+        }
+        Ok(len)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    Binary(BinOp, Ty),
+    Unary(UnOp, Ty),
+    /// Compares two values of the type
+    Cmp(CmpOp, Ty),
+    /// Turns a value of the first type into one of the second
+    Cast(CastOp, Ty, Ty),
+    /// Pops an address and pushes the value there
+    Load(Ty),
+    /// Pops a value and then an address to store it at
+    Store(Ty),
+    PtrAdd,
+    /// Pops the source and then the destination of a copy of this many bytes
+    Copy(usize),
+    /// Allocates this many bytes of stack for a function's slots and pushes
+    /// their address
+    Enter(usize),
+    /// Frees what the matching [`Instruction::Enter`] allocated
+    Leave(usize),
+    /// Pops a value nothing uses
+    Drop(Ty),
+    Write,
+    Strlen,
+    Unreachable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Value {
+    /// The bits of an integer value, zero extended
+    pub fn bits(self) -> Option<u64> {
+        match self {
+            Value::Bool(value) => Some(value as u64),
+            Value::U8(value) => Some(value as u64),
+            Value::U16(value) => Some(value as u64),
+            Value::U32(value) => Some(value as u64),
+            Value::U64(value) => Some(value),
+            Value::F32(_) | Value::F64(_) => None,
+        }
+    }
+}
+
+/// # Safety
+///
+/// The top of `stack` must hold a value of the type `ty` is on the stack as.
+unsafe fn pop(stack: &mut HorribleVec, ty: Ty) -> u64 {
+    match ty {
+        Ty::Bool => stack.pop::<bool>() as u64,
+        Ty::I8 => stack.pop::<u8>() as u64,
+        Ty::I16 => stack.pop::<u16>() as u64,
+        Ty::I32 => stack.pop::<u32>() as u64,
+        Ty::I64 | Ty::Ptr => stack.pop::<u64>(),
+        Ty::F32 => stack.pop::<f32>().to_bits() as u64,
+        Ty::F64 => stack.pop::<f64>().to_bits(),
+    }
+}
+
+/// Pushes the bits of a value of the type, floats as their IEEE bits
+fn push(stack: &mut HorribleVec, ty: Ty, bits: u64) {
+    match ty {
+        Ty::Bool => stack.push(bits & 1 != 0),
+        Ty::I8 => stack.push(bits as u8),
+        Ty::I16 => stack.push(bits as u16),
+        Ty::I32 => stack.push(bits as u32),
+        Ty::I64 | Ty::Ptr => stack.push(bits),
+        Ty::F32 => stack.push(f32::from_bits(bits as u32)),
+        Ty::F64 => stack.push(f64::from_bits(bits)),
+    }
+}
+
+/// A float of the type as an `f64`, which holds every `f32` exactly
+fn float(ty: Ty, bits: u64) -> f64 {
+    match ty {
+        Ty::F32 => f32::from_bits(bits as u32) as f64,
+        _ => f64::from_bits(bits),
+    }
+}
+
+/// The bits of `value` rounded to the type
+fn float_bits(ty: Ty, value: f64) -> u64 {
+    match ty {
+        Ty::F32 => (value as f32).to_bits() as u64,
+        _ => value.to_bits(),
+    }
+}
+
+fn binary(op: BinOp, ty: Ty, a: u64, b: u64) -> Result<u64, String> {
+    let (sa, sb) = (ty.sign_extend(a), ty.sign_extend(b));
+    let shift = (b & (ty.size() as u64 * 8 - 1)) as u32;
+    let float_op = |f: fn(f64, f64) -> f64| float_bits(ty, f(float(ty, a), float(ty, b)));
+    let bits = match op {
+        BinOp::Add => a.wrapping_add(b),
+        BinOp::Sub => a.wrapping_sub(b),
+        BinOp::Mul => a.wrapping_mul(b),
+        BinOp::SDiv | BinOp::SRem | BinOp::UDiv | BinOp::URem if b == 0 => {
+            return Err("division by zero".to_string())
+        }
+        BinOp::SDiv => sa.wrapping_div(sb) as u64,
+        BinOp::UDiv => a / b,
+        BinOp::SRem => sa.wrapping_rem(sb) as u64,
+        BinOp::URem => a % b,
+        BinOp::FAdd => return Ok(float_op(|a, b| a + b)),
+        BinOp::FSub => return Ok(float_op(|a, b| a - b)),
+        BinOp::FMul => return Ok(float_op(|a, b| a * b)),
+        BinOp::FDiv => return Ok(float_op(|a, b| a / b)),
+        BinOp::FRem => return Ok(float_op(|a, b| a % b)),
+        BinOp::And => a & b,
+        BinOp::Or => a | b,
+        BinOp::Xor => a ^ b,
+        BinOp::Shl => a << shift,
+        BinOp::LShr => a >> shift,
+        BinOp::AShr => (sa >> shift) as u64,
+    };
+    Ok(ty.truncate(bits))
+}
+
+fn cmp(op: CmpOp, ty: Ty, a: u64, b: u64) -> bool {
+    let (sa, sb) = (ty.sign_extend(a), ty.sign_extend(b));
+    let (fa, fb) = (float(ty, a), float(ty, b));
+    match op {
+        CmpOp::Eq => a == b,
+        CmpOp::Ne => a != b,
+        CmpOp::SLt => sa < sb,
+        CmpOp::SLe => sa <= sb,
+        CmpOp::SGt => sa > sb,
+        CmpOp::SGe => sa >= sb,
+        CmpOp::ULt => a < b,
+        CmpOp::ULe => a <= b,
+        CmpOp::UGt => a > b,
+        CmpOp::UGe => a >= b,
+        CmpOp::FEq => fa == fb,
+        CmpOp::FNe => fa != fb,
+        CmpOp::FLt => fa < fb,
+        CmpOp::FLe => fa <= fb,
+        CmpOp::FGt => fa > fb,
+        CmpOp::FGe => fa >= fb,
+    }
+}
+
+unsafe impl Operator for Instruction {
+    type AnyValue = Value;
+    type Context = Machine;
+
+    fn output(&self) -> &[TypeId] {
+        match self {
+            Instruction::Binary(_, ty) | Instruction::Unary(_, ty) | Instruction::Load(ty) => {
+                one(*ty)
+            }
+            Instruction::Cmp(..) => one(Ty::Bool),
+            Instruction::Cast(_, _, to) => one(*to),
+            Instruction::PtrAdd
+            | Instruction::Enter(_)
+            | Instruction::Write
+            | Instruction::Strlen => one(Ty::Ptr),
+            Instruction::Store(_)
+            | Instruction::Copy(_)
+            | Instruction::Leave(_)
+            | Instruction::Drop(_)
+            | Instruction::Unreachable => &[],
+        }
+    }
+
+    fn input(&self) -> &[TypeId] {
+        match self {
+            Instruction::Binary(_, ty) | Instruction::Cmp(_, ty) => &PAIRS[index(*ty)],
+            Instruction::Unary(_, ty) | Instruction::Cast(_, ty, _) | Instruction::Drop(ty) => {
+                one(*ty)
+            }
+            Instruction::Load(_) | Instruction::Strlen => one(Ty::Ptr),
+            Instruction::Store(ty) => &STORES[index(*ty)],
+            Instruction::PtrAdd | Instruction::Copy(_) => &PAIRS[index(Ty::Ptr)],
+            Instruction::Write => &WRITE,
+            Instruction::Enter(_) | Instruction::Leave(_) | Instruction::Unreachable => &[],
+        }
+    }
+
+    unsafe fn run(&self, machine: &mut Machine, stack: &mut HorribleVec) -> Result<(), String> {
+        match *self {
+            Instruction::Binary(op, ty) => {
+                let b = pop(stack, ty);
+                let a = pop(stack, ty);
+                push(stack, ty, binary(op, ty, a, b)?);
+            }
+            Instruction::Unary(op, ty) => {
+                let value = pop(stack, ty);
+                let bits = match op {
+                    UnOp::Neg => value.wrapping_neg(),
+                    UnOp::FNeg => float_bits(ty, -float(ty, value)),
+                    UnOp::Not => !value,
+                };
+                push(stack, ty, ty.truncate(bits));
+            }
+            Instruction::Cmp(op, ty) => {
+                let b = pop(stack, ty);
+                let a = pop(stack, ty);
+                stack.push(cmp(op, ty, a, b));
+            }
+            Instruction::Cast(op, from, to) => {
+                let value = pop(stack, from);
+                let bits = match op {
+                    CastOp::SExt => from.sign_extend(value) as u64,
+                    CastOp::ZExt | CastOp::Trunc => value,
+                };
+                push(stack, to, to.truncate(bits));
+            }
+            Instruction::Load(ty) => {
+                let address = stack.pop::<u64>();
+                let mut bytes = [0; 8];
+                bytes[..ty.size()].copy_from_slice(machine.bytes(address, ty.size())?);
+                push(stack, ty, u64::from_le_bytes(bytes));
+            }
+            Instruction::Store(ty) => {
+                let value = pop(stack, ty);
+                let address = stack.pop::<u64>();
+                machine
+                    .bytes(address, ty.size())?
+                    .copy_from_slice(&value.to_le_bytes()[..ty.size()]);
+            }
+            Instruction::PtrAdd => {
+                let offset = stack.pop::<u64>();
+                let address = stack.pop::<u64>();
+                stack.push(address.wrapping_add(offset));
+            }
+            Instruction::Copy(size) => {
+                let from = stack.pop::<u64>();
+                let to = stack.pop::<u64>();
+                let bytes = machine.bytes(from, size)?.to_vec();
+                machine.bytes(to, size)?.copy_from_slice(&bytes);
+            }
+            Instruction::Enter(size) => {
+                let size = size.next_multiple_of(16);
+                if machine.sp - machine.limit < size {
+                    return Err("stack overflow".to_string());
+                }
+                machine.sp -= size;
+                stack.push(machine.sp as u64);
+            }
+            Instruction::Leave(size) => machine.sp += size.next_multiple_of(16),
+            Instruction::Drop(ty) => {
+                pop(stack, ty);
+            }
+            Instruction::Write => {
+                let len = stack.pop::<u64>();
+                let address = stack.pop::<u64>();
+                let fd = stack.pop::<u32>();
+                stack.push(machine.write(fd, address, len)?);
+            }
+            Instruction::Strlen => {
+                let address = stack.pop::<u64>();
+                stack.push(machine.strlen(address)?);
+            }
+            Instruction::Unreachable => return Err("reached unreachable code".to_string()),
+        }
+        Ok(())
+    }
+
+    fn decodes(ty: TypeId) -> bool {
+        TYPES.contains(&ty)
+    }
+
+    unsafe fn get_value(ty: TypeId, stack: &mut HorribleVec) -> Value {
+        match TYPES.iter().position(|other| *other == ty) {
+            Some(0) => Value::Bool(stack.pop()),
+            Some(1) => Value::U8(stack.pop()),
+            Some(2) => Value::U16(stack.pop()),
+            Some(3) => Value::U32(stack.pop()),
+            Some(4) => Value::U64(stack.pop()),
+            Some(5) => Value::F32(stack.pop()),
+            Some(6) => Value::F64(stack.pop()),
+            _ => unreachable!("no value is of type {ty:?}"),
+        }
+    }
+}
+
+/// A module compiled to bytecode, with memory holding its statics
+#[derive(Debug)]
+pub struct Program {
+    pub run: Checked<Instruction>,
+    pub machine: Machine,
+    functions: HashMap<String, usize>,
+}
+
+impl Program {
+    /// Calls a function without parameters, giving its result
+    pub fn call(&mut self, name: &str) -> Result<Option<Value>, String> {
+        let function = *self
+            .functions
+            .get(name)
+            .ok_or_else(|| format!("there is no function `{name}`"))?;
+        if !self.run.program().functions[function].params.is_empty() {
+            return Err(format!("`{name}` can't be called without arguments"));
+        }
+        let sp = self.machine.sp;
+        let mut results = match self.run.call(function, &mut self.machine) {
+            Ok(results) => results,
+            // the program's own faults read like the interpreter's
+            Err(TypeError::Trap { reason, .. }) => {
+                self.machine.sp = sp;
+                return Err(reason);
+            }
+            Err(err) => return Err(err.to_string()),
+        };
+        Ok(results.pop())
+    }
+}
+
+/// Where everything a module refers to by name is
+struct Symbols {
+    /// One more than the index of each function, like function pointers
+    functions: HashMap<String, u64>,
+    /// What each function returns
+    rets: Vec<Option<Ty>>,
+    addresses: HashMap<String, u64>,
+    externs: HashMap<String, Instruction>,
+}
+
+pub fn compile(module: &Module) -> Result<Program, String> {
+    let mut externs = HashMap::new();
+    for ext in &module.externs {
+        let instruction = match ext.name.as_str() {
+            "write" => Instruction::Write,
+            "strlen" => Instruction::Strlen,
+            _ => {
+                return Err(format!(
+                    "`{}` is defined outside of the program, which the bytecode can't call",
+                    ext.name
+                ))
+            }
+        };
+        externs.insert(ext.name.clone(), instruction);
+    }
+    let functions = module
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(index, function)| (function.name.clone(), index as u64 + 1))
+        .collect();
+
+    let mut addresses = HashMap::new();
+    let mut end = DATA_START;
+    for data in &module.data {
+        let Some(init) = &data.init else {
+            return Err(format!(
+                "`{}` is defined outside of the program, which the bytecode can't refer to",
+                data.name
+            ));
+        };
+        end = end.next_multiple_of(data.align);
+        addresses.insert(data.name.clone(), end as u64);
+        end += init
+            .iter()
+            .map(|part| match part {
+                DataPart::Bytes(bytes) => bytes.len(),
+                DataPart::Addr(_) => 8,
+            })
+            .sum::<usize>();
+    }
+    let symbols = Symbols {
+        functions,
+        rets: module
+            .functions
+            .iter()
+            .map(|function| function.ret)
+            .collect(),
+        addresses,
+        externs,
+    };
+
+    let limit = end.next_multiple_of(16);
+    let mut memory = vec![0; limit + STACK_SIZE];
+    for data in &module.data {
+        let mut at = symbols.addresses[&data.name] as usize;
+        for part in data.init.iter().flatten() {
+            let bytes = match part {
+                DataPart::Bytes(bytes) => bytes.clone(),
+                DataPart::Addr(name) => symbols.address(name)?.to_le_bytes().to_vec(),
+            };
+            memory[at..at + bytes.len()].copy_from_slice(&bytes);
+            at += bytes.len();
+        }
+    }
+
+    let mut run = Run::new();
+    for function in &module.functions {
+        let compiled = FunctionCompiler::new(&mut run, &symbols, function).compile()?;
+        run.functions.push(compiled);
+    }
+    let run = run
+        .check()
+        .map_err(|err| format!("compiled to invalid bytecode: {err}"))?;
+    Ok(Program {
+        run,
+        machine: Machine {
+            sp: memory.len(),
+            limit,
+            memory,
+            stdout: None,
+        },
+        functions: symbols
+            .functions
+            .into_iter()
+            .map(|(name, index)| (name, index as usize - 1))
+            .collect(),
+    })
+}
+
+impl Symbols {
+    fn address(&self, name: &str) -> Result<u64, String> {
+        if let Some(index) = self.functions.get(name) {
+            Ok(*index)
+        } else if let Some(address) = self.addresses.get(name) {
+            Ok(*address)
+        } else {
+            Err(format!("`{name}` can't be referred to by address"))
+        }
+    }
+}
+
+struct FunctionCompiler<'a> {
+    run: &'a mut Run<Instruction>,
+    symbols: &'a Symbols,
+    function: &'a Function,
+    locals: Vec<Local>,
+    /// The local holding the address of the slots
+    frame: Local,
+    /// The offset of each slot from the frame's address
+    slots: Vec<usize>,
+    slots_size: usize,
+    /// Where each block's operations start
+    starts: Vec<usize>,
+    /// Jumps to blocks, by their operation
+    fixups: Vec<(usize, usize)>,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    fn new(run: &'a mut Run<Instruction>, symbols: &'a Symbols, function: &'a Function) -> Self {
+        // the parameters come first, so calls can move them into place
+        let params = function.params();
+        let order = params.iter().map(|reg| reg.0);
+        let order =
+            order.chain((0..function.regs.len()).filter(|reg| !params.contains(&Reg(*reg))));
+        let mut locals = vec![None; function.regs.len()];
+        let mut offset = 0;
+        for reg in order {
+            let ty = function.regs[reg];
+            locals[reg] = Some(local(ty, offset));
+            offset += ty.size();
+        }
+        let locals = locals.into_iter().map(Option::unwrap).collect();
+        let frame = local(Ty::Ptr, offset);
+
+        let mut slots = Vec::new();
+        let mut slots_size: usize = 0;
+        for slot in &function.slots {
+            slots_size = slots_size.next_multiple_of(slot.align);
+            slots.push(slots_size);
+            slots_size += slot.size;
+        }
+        FunctionCompiler {
+            run,
+            symbols,
+            function,
+            locals,
+            frame,
+            slots,
+            slots_size,
+            starts: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    fn compile(mut self) -> Result<bruh2::Function, String> {
+        let entry = self.run.operator.len();
+        if self.slots_size > 0 {
+            self.op(Instruction::Enter(self.slots_size));
+            self.emit(MachineOperation::SetLocal(self.frame));
+        }
+        for block in &self.function.blocks {
+            self.starts.push(self.run.operator.len());
+            for inst in &block.insts {
+                self.inst(inst)?;
+            }
+            self.terminator(&block.term)?;
+        }
+        for (at, block) in std::mem::take(&mut self.fixups) {
+            let to = self.starts[block];
+            match &mut self.run.operator[at] {
+                MachineOperation::Jump(target) | MachineOperation::JumpUnless(target) => {
+                    *target = to
+                }
+                _ => unreachable!("only jumps are fixed up"),
+            }
+        }
+        Ok(bruh2::Function {
+            entry,
+            params: self
+                .function
+                .params()
+                .iter()
+                .map(|reg| self.locals[reg.0])
+                .collect(),
+            results: self
+                .function
+                .ret
+                .map(|ty| TYPES[index(ty)])
+                .into_iter()
+                .collect(),
+            frame_size: self.frame.offset() + self.frame.size(),
+        })
+    }
+
+    fn emit(&mut self, op: MachineOperation<Instruction>) {
+        self.run.operator.push(op);
+    }
+
+    fn op(&mut self, instruction: Instruction) {
+        self.run.add_operator(instruction);
+    }
+
+    fn set(&mut self, reg: Reg) {
+        self.emit(MachineOperation::SetLocal(self.locals[reg.0]));
+    }
+
+    fn ty(&self, operand: &Operand) -> Ty {
+        self.function.operand_ty(operand)
+    }
+
+    fn operand(&mut self, operand: &Operand) -> Result<(), String> {
+        match operand {
+            Operand::Reg(reg) => self.emit(MachineOperation::Local(self.locals[reg.0])),
+            Operand::Int(ty, bits) => match ty {
+                Ty::Bool => self.run.push_val(*bits != 0),
+                Ty::I8 => self.run.push_val(*bits as u8),
+                Ty::I16 => self.run.push_val(*bits as u16),
+                Ty::I32 => self.run.push_val(*bits as u32),
+                _ => self.run.push_val(*bits),
+            },
+            Operand::F32(value) => self.run.push_val(*value),
+            Operand::F64(value) => self.run.push_val(*value),
+            Operand::Global(name) => {
+                let address = self.symbols.address(name)?;
+                self.run.push_val(address);
+            }
+        }
+        Ok(())
+    }
+
+    fn inst(&mut self, inst: &Inst) -> Result<(), String> {
+        match inst {
+            Inst::Binary(reg, op, a, b) => {
+                self.operand(a)?;
+                self.operand(b)?;
+                self.op(Instruction::Binary(*op, self.ty(a)));
+                self.set(*reg);
+            }
+            Inst::Unary(reg, op, value) => {
+                self.operand(value)?;
+                self.op(Instruction::Unary(*op, self.ty(value)));
+                self.set(*reg);
+            }
+            Inst::Cmp(reg, op, a, b) => {
+                self.operand(a)?;
+                self.operand(b)?;
+                self.op(Instruction::Cmp(*op, self.ty(a)));
+                self.set(*reg);
+            }
+            Inst::Cast(reg, op, value) => {
+                self.operand(value)?;
+                let to = self.function.reg_ty(*reg);
+                self.op(Instruction::Cast(*op, self.ty(value), to));
+                self.set(*reg);
+            }
+            Inst::SlotAddr(reg, slot) => {
+                self.emit(MachineOperation::Local(self.frame));
+                self.run.push_val(self.slots[slot.0] as u64);
+                self.op(Instruction::PtrAdd);
+                self.set(*reg);
+            }
+            Inst::Load(reg, address) => {
+                self.operand(address)?;
+                self.op(Instruction::Load(self.function.reg_ty(*reg)));
+                self.set(*reg);
+            }
+            Inst::Store(address, value) => {
+                self.operand(address)?;
+                self.operand(value)?;
+                self.op(Instruction::Store(self.ty(value)));
+            }
+            Inst::PtrAdd(reg, address, offset) => {
+                self.operand(address)?;
+                self.operand(offset)?;
+                self.op(Instruction::PtrAdd);
+                self.set(*reg);
+            }
+            Inst::Copy(to, from, size) => {
+                self.operand(to)?;
+                self.operand(from)?;
+                self.op(Instruction::Copy(*size));
+            }
+            Inst::Call(reg, callee, args) => self.call(*reg, callee, args)?,
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, reg: Option<Reg>, callee: &Operand, args: &[Operand]) -> Result<(), String> {
+        for arg in args {
+            self.operand(arg)?;
+        }
+        let name = match callee {
+            Operand::Global(name) => Some(name),
+            _ => None,
+        };
+        let ret = if let Some(instruction) = name.and_then(|name| self.symbols.externs.get(name)) {
+            self.op(*instruction);
+            Some(Ty::Ptr)
+        } else if let Some(index) = name.and_then(|name| self.symbols.functions.get(name)) {
+            let index = *index as usize - 1;
+            self.emit(MachineOperation::Call(index));
+            self.symbols.rets[index]
+        } else {
+            self.operand(callee)?;
+            let params = args.iter().map(|arg| TYPES[index(self.ty(arg))]).collect();
+            let results = reg
+                .iter()
+                .map(|reg| TYPES[index(self.function.reg_ty(*reg))]);
+            self.emit(MachineOperation::CallIndirect {
+                params,
+                results: results.collect(),
+            });
+            reg.map(|reg| self.function.reg_ty(reg))
+        };
+        match (reg, ret) {
+            (Some(reg), _) => self.set(reg),
+            (None, Some(ty)) => self.op(Instruction::Drop(ty)),
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    fn jump(&mut self, target: &Target) -> Result<(), String> {
+        for arg in &target.args {
+            self.operand(arg)?;
+        }
+        let params = &self.function.blocks[target.block.0].params;
+        for param in params.iter().rev() {
+            self.set(*param);
+        }
+        self.fixups.push((self.run.operator.len(), target.block.0));
+        self.emit(MachineOperation::Jump(usize::MAX));
+        Ok(())
+    }
+
+    fn terminator(&mut self, term: &Terminator) -> Result<(), String> {
+        match term {
+            Terminator::Jump(target) => self.jump(target)?,
+            Terminator::Branch(cond, then, otherwise) => {
+                self.operand(cond)?;
+                let branch = self.run.operator.len();
+                self.emit(MachineOperation::JumpUnless(usize::MAX));
+                self.jump(then)?;
+                let at = self.run.operator.len();
+                self.run.operator[branch] = MachineOperation::JumpUnless(at);
+                self.jump(otherwise)?;
+            }
+            Terminator::Return(value) => {
+                if let Some(value) = value {
+                    self.operand(value)?;
+                }
+                if self.slots_size > 0 {
+                    self.op(Instruction::Leave(self.slots_size));
+                }
+                self.emit(MachineOperation::Return);
+            }
+            Terminator::Unreachable => self.op(Instruction::Unreachable),
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn run_program() {
+    for level in [crate::ir::opt::OptLevel::O0, crate::ir::opt::OptLevel::O2] {
+        let mut module = crate::ir::lower::lower_source(crate::backend::x86_64::PROGRAM);
+        crate::ir::opt::optimize(&mut module, level);
+        let mut program = compile(&module).unwrap();
+        program.machine.stdout = Some(Vec::new());
+        let result = program.call("main").unwrap();
+        assert_eq!(result, Some(Value::U32(8)), "at {level:?}");
+        assert_eq!(program.machine.stdout.as_deref(), Some(&b"hello\n"[..]));
+    }
+}
+
+#[test]
+fn function_pointers_and_externs() {
+    let module = crate::ir::lower::lower_source(
+        r#"
+        extern "C" fn write(i32 fd, *u8 data, usize len) isize;
+        fn twice(u64 value) u64 { return value * 2u64; }
+        fn apply(fn(u64) u64 f, u64 value) u64 { return f(value); }
+        fn main() u64 { return apply(twice, 21u64); }
+        "#,
+    );
+    let mut program = compile(&module).unwrap();
+    assert_eq!(program.call("main").unwrap(), Some(Value::U64(42)));
+    assert_eq!(
+        program.call("missing").unwrap_err(),
+        "there is no function `missing`"
+    );
+
+    let module = crate::ir::lower::lower_source(r#"extern "C" fn exit(i32 code);"#);
+    assert_eq!(
+        compile(&module).unwrap_err(),
+        "`exit` is defined outside of the program, which the bytecode can't call"
+    );
+}
+
+#[test]
+fn runtime_faults() {
+    let run = |src: &str, level| {
+        let mut module = crate::ir::lower::lower_source(src);
+        crate::ir::opt::optimize(&mut module, level);
+        let mut program = compile(&module).unwrap();
+        program.machine.stdout = Some(Vec::new());
+        let result = program.call("main");
+        (result, program)
+    };
+    for level in [crate::ir::opt::OptLevel::O0, crate::ir::opt::OptLevel::O2] {
+        let (result, _) = run(
+            "fn main() i32 { mut i32 zero = 0; return 1 / zero; }",
+            level,
+        );
+        assert_eq!(result, Err("division by zero".to_string()), "at {level:?}");
+
+        let (result, mut program) = run(
+            "fn down(i32 n) i32 { return down(n + 1); } fn main() i32 { return down(0); }",
+            level,
+        );
+        assert_eq!(result, Err("stack overflow".to_string()), "at {level:?}");
+        // a trap leaves the machine usable
+        assert_eq!(program.call("main"), Err("stack overflow".to_string()));
+    }
+
+    let (result, _) = run(
+        r#"
+        extern "C" fn write(i32 fd, *u8 data, usize len) isize;
+        fn main() isize { return write(1, "hi", 1000000000); }
+        "#,
+        crate::ir::opt::OptLevel::O0,
+    );
+    assert!(result
+        .unwrap_err()
+        .starts_with("out of bounds memory access"));
+}
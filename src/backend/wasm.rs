@@ -0,0 +1,1001 @@
+//! Binary WebAssembly modules for sandboxed hosts.
+//!
+//! Memory keeps the layout the rest of the compiler gives every type, so
+//! pointers stay 8 bytes and are `i64` values wrapped to 32 bits whenever
+//! memory is accessed. Statics sit at fixed addresses with the stack after
+//! them, slots are allocated on it through a stack pointer global, and
+//! function pointers are indices into a table holding every function.
+//!
+//! Integers narrower than 32 bits live in `i32`s with the bits above their
+//! type cleared. A function's blocks turn into a loop around a `br_table`
+//! on the number of the next block, jumps set the number and go around the
+//! loop unless they go to the block right after.
+
+use std::collections::HashMap;
+
+use crate::{
+    ir::{
+        BinOp, CastOp, CmpOp, DataPart, Function, Inst, Module, Operand, Reg, Target, Terminator,
+        Ty, UnOp,
+    },
+    parser::ast::Vis,
+};
+
+/// Where statics start, nothing valid is at null
+const DATA_START: u64 = 16;
+const STACK_SIZE: u64 = 64 * 1024;
+const PAGE_SIZE: u64 = 64 * 1024;
+
+/// Where `extern "C"` functions are imported from
+const IMPORT_MODULE: &str = "env";
+/// The name the linear memory is exported under
+const MEMORY: &str = "memory";
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_TABLE: u8 = 4;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_GLOBAL: u8 = 6;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_ELEMENT: u8 = 9;
+const SECTION_CODE: u8 = 10;
+const SECTION_DATA: u8 = 11;
+
+const UNREACHABLE: u8 = 0x00;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_TABLE: u8 = 0x0e;
+const RETURN: u8 = 0x0f;
+const CALL: u8 = 0x10;
+const CALL_INDIRECT: u8 = 0x11;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const GLOBAL_GET: u8 = 0x23;
+const GLOBAL_SET: u8 = 0x24;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const F32_CONST: u8 = 0x43;
+const F64_CONST: u8 = 0x44;
+const I32_EQZ: u8 = 0x45;
+const I32_EQ: u8 = 0x46;
+const I64_EQ: u8 = 0x51;
+const F32_EQ: u8 = 0x5b;
+const F64_EQ: u8 = 0x61;
+const I32_ADD: u8 = 0x6a;
+const I32_SUB: u8 = 0x6b;
+const I32_AND: u8 = 0x71;
+const I32_XOR: u8 = 0x73;
+const I64_ADD: u8 = 0x7c;
+const I64_SUB: u8 = 0x7d;
+const I64_XOR: u8 = 0x85;
+const F32_NEG: u8 = 0x8c;
+const F32_TRUNC: u8 = 0x8f;
+const F32_ADD: u8 = 0x92;
+const F64_NEG: u8 = 0x9a;
+const F64_TRUNC: u8 = 0x9d;
+const F64_ADD: u8 = 0xa0;
+const I32_WRAP_I64: u8 = 0xa7;
+const I64_EXTEND_I32_S: u8 = 0xac;
+const I64_EXTEND_I32_U: u8 = 0xad;
+const I32_EXTEND8_S: u8 = 0xc0;
+const I32_EXTEND16_S: u8 = 0xc1;
+const PREFIX_FC: u8 = 0xfc;
+const MEMORY_COPY: u64 = 10;
+
+/// The block type of blocks without results
+const EMPTY: u8 = 0x40;
+const FUNCREF: u8 = 0x70;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    fn code(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+            ValType::F32 => 0x7d,
+            ValType::F64 => 0x7c,
+        }
+    }
+}
+
+fn val_type(ty: Ty) -> ValType {
+    match ty {
+        Ty::Bool | Ty::I8 | Ty::I16 | Ty::I32 => ValType::I32,
+        Ty::I64 | Ty::Ptr => ValType::I64,
+        Ty::F32 => ValType::F32,
+        Ty::F64 => ValType::F64,
+    }
+}
+
+/// The mask keeping the bits of a type narrower than its value type
+fn mask(ty: Ty) -> Option<i64> {
+    match ty {
+        Ty::Bool => Some(1),
+        Ty::I8 => Some(0xff),
+        Ty::I16 => Some(0xffff),
+        _ => None,
+    }
+}
+
+fn uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let sign = byte & 0x40 != 0;
+        if (value == 0 && !sign) || (value == -1 && sign) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    uleb(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn section(out: &mut Vec<u8>, id: u8, count: usize, contents: &[u8]) {
+    let mut body = Vec::new();
+    uleb(&mut body, count as u64);
+    body.extend_from_slice(contents);
+    out.push(id);
+    uleb(out, body.len() as u64);
+    out.extend_from_slice(&body);
+}
+
+/// The parameters and result of a function
+type Signature = (Vec<ValType>, Option<ValType>);
+
+#[derive(Default)]
+struct Types {
+    signatures: Vec<Signature>,
+}
+
+impl Types {
+    fn index(&mut self, params: Vec<ValType>, ret: Option<ValType>) -> u64 {
+        let signature = (params, ret);
+        let index = match self.signatures.iter().position(|s| *s == signature) {
+            Some(index) => index,
+            None => {
+                self.signatures.push(signature);
+                self.signatures.len() - 1
+            }
+        };
+        index as u64
+    }
+}
+
+/// Where functions and statics are
+struct Symbols {
+    /// Function indices, imports come first
+    functions: HashMap<String, u64>,
+    addresses: HashMap<String, u64>,
+}
+
+impl Symbols {
+    /// The value of a pointer to a symbol, functions are their index in the
+    /// table which starts at 1
+    fn address(&self, symbol: &str) -> u64 {
+        match self.functions.get(symbol) {
+            Some(index) => index + 1,
+            None => self.addresses[symbol],
+        }
+    }
+}
+
+/// Writes a program as a WebAssembly module importing `extern "C"`
+/// functions from `env` and exporting public functions and its memory
+pub fn emit(module: &Module) -> Result<Vec<u8>, String> {
+    if let Some(data) = module.data.iter().find(|data| data.init.is_none()) {
+        return Err(format!(
+            "`{}` is defined outside of the program, which WebAssembly can't refer to",
+            data.name
+        ));
+    }
+    let exports: Vec<_> = module
+        .functions
+        .iter()
+        .enumerate()
+        .filter(|(_, function)| function.vis == Vis::Pub)
+        .collect();
+    if exports.iter().any(|(_, function)| function.name == MEMORY) {
+        return Err(format!(
+            "`{MEMORY}` can't be public, the memory is exported under that name"
+        ));
+    }
+
+    let names = module.externs.iter().map(|ext| &ext.name);
+    let names = names.chain(module.functions.iter().map(|function| &function.name));
+    let functions: HashMap<String, u64> = names
+        .enumerate()
+        .map(|(index, name)| (name.clone(), index as u64))
+        .collect();
+    let mut addresses = HashMap::new();
+    let mut segments = Vec::new();
+    let mut end = DATA_START;
+    for data in &module.data {
+        end = end.next_multiple_of(data.align as u64);
+        addresses.insert(data.name.clone(), end);
+        let size: usize = data
+            .init
+            .iter()
+            .flatten()
+            .map(|part| match part {
+                DataPart::Bytes(bytes) => bytes.len(),
+                DataPart::Addr(_) => 8,
+            })
+            .sum();
+        segments.push((end, data));
+        end += size as u64;
+    }
+    let stack = end.next_multiple_of(16) + STACK_SIZE;
+    let symbols = Symbols {
+        functions,
+        addresses,
+    };
+
+    let mut types = Types::default();
+    let mut imports = Vec::new();
+    for ext in &module.externs {
+        let params = ext.params.iter().map(|ty| val_type(*ty)).collect();
+        let ty = types.index(params, ext.ret.map(val_type));
+        name(&mut imports, IMPORT_MODULE);
+        name(&mut imports, &ext.name);
+        imports.push(0x00);
+        uleb(&mut imports, ty);
+    }
+
+    let mut declarations = Vec::new();
+    let mut code = Vec::new();
+    for function in &module.functions {
+        let params = function.params().iter();
+        let params = params.map(|reg| val_type(function.reg_ty(*reg))).collect();
+        uleb(
+            &mut declarations,
+            types.index(params, function.ret.map(val_type)),
+        );
+        let body = FunctionEmitter::new(function, &symbols, &mut types).emit();
+        uleb(&mut code, body.len() as u64);
+        code.extend_from_slice(&body);
+    }
+
+    let mut out = b"\0asm".to_vec();
+    out.extend_from_slice(&1u32.to_le_bytes());
+
+    let mut type_section = Vec::new();
+    for (params, ret) in &types.signatures {
+        type_section.push(0x60);
+        uleb(&mut type_section, params.len() as u64);
+        type_section.extend(params.iter().map(|ty| ty.code()));
+        uleb(&mut type_section, ret.is_some() as u64);
+        type_section.extend(ret.iter().map(|ty| ty.code()));
+    }
+    section(
+        &mut out,
+        SECTION_TYPE,
+        types.signatures.len(),
+        &type_section,
+    );
+    section(&mut out, SECTION_IMPORT, module.externs.len(), &imports);
+    section(
+        &mut out,
+        SECTION_FUNCTION,
+        module.functions.len(),
+        &declarations,
+    );
+
+    let count = (module.externs.len() + module.functions.len()) as u64;
+    let mut table = vec![FUNCREF, 0x01];
+    uleb(&mut table, count + 1);
+    uleb(&mut table, count + 1);
+    section(&mut out, SECTION_TABLE, 1, &table);
+
+    let mut memory = vec![0x00];
+    uleb(&mut memory, stack.div_ceil(PAGE_SIZE));
+    section(&mut out, SECTION_MEMORY, 1, &memory);
+
+    // the stack pointer
+    let mut global = vec![ValType::I64.code(), 0x01, I64_CONST];
+    sleb(&mut global, stack as i64);
+    global.push(END);
+    section(&mut out, SECTION_GLOBAL, 1, &global);
+
+    let mut export_section = Vec::new();
+    for (index, function) in &exports {
+        name(&mut export_section, &function.name);
+        export_section.push(0x00);
+        uleb(&mut export_section, (module.externs.len() + index) as u64);
+    }
+    name(&mut export_section, MEMORY);
+    export_section.extend_from_slice(&[0x02, 0x00]);
+    section(&mut out, SECTION_EXPORT, exports.len() + 1, &export_section);
+
+    // every function is in the table so pointers to them can be called
+    let mut elements = vec![0x00, I32_CONST, 1, END];
+    uleb(&mut elements, count);
+    for index in 0..count {
+        uleb(&mut elements, index);
+    }
+    section(&mut out, SECTION_ELEMENT, 1, &elements);
+
+    section(&mut out, SECTION_CODE, module.functions.len(), &code);
+
+    let mut data_section = Vec::new();
+    let mut data_count = 0;
+    for (address, data) in segments {
+        let mut bytes = Vec::new();
+        for part in data.init.iter().flatten() {
+            match part {
+                DataPart::Bytes(part) => bytes.extend_from_slice(part),
+                DataPart::Addr(symbol) => {
+                    bytes.extend_from_slice(&symbols.address(symbol).to_le_bytes())
+                }
+            }
+        }
+        // memory starts out zeroed
+        if bytes.iter().all(|byte| *byte == 0) {
+            continue;
+        }
+        data_section.extend_from_slice(&[0x00, I32_CONST]);
+        sleb(&mut data_section, address as i64);
+        data_section.push(END);
+        uleb(&mut data_section, bytes.len() as u64);
+        data_section.extend_from_slice(&bytes);
+        data_count += 1;
+    }
+    section(&mut out, SECTION_DATA, data_count, &data_section);
+    Ok(out)
+}
+
+struct FunctionEmitter<'a> {
+    function: &'a Function,
+    symbols: &'a Symbols,
+    types: &'a mut Types,
+    code: Vec<u8>,
+    /// The local every register lives in
+    locals: Vec<u64>,
+    /// The local holding the number of the block to go to
+    next: u64,
+    /// The local holding the address of the slots
+    frame: u64,
+    /// Where every slot is in the frame
+    offsets: Vec<u64>,
+    frame_size: u64,
+}
+
+impl<'a> FunctionEmitter<'a> {
+    fn new(function: &'a Function, symbols: &'a Symbols, types: &'a mut Types) -> Self {
+        let params = function.params();
+        let mut locals = vec![0; function.regs.len()];
+        for (local, param) in params.iter().enumerate() {
+            locals[param.0] = local as u64;
+        }
+        let mut declared = Vec::new();
+        for (reg, ty) in function.regs.iter().enumerate() {
+            if !params.iter().any(|param| param.0 == reg) {
+                locals[reg] = (params.len() + declared.len()) as u64;
+                declared.push(val_type(*ty));
+            }
+        }
+        let next = (params.len() + declared.len()) as u64;
+        declared.extend([ValType::I32, ValType::I64]);
+
+        let mut offsets = Vec::new();
+        let mut frame_size: u64 = 0;
+        for slot in &function.slots {
+            let offset = frame_size.next_multiple_of(slot.align as u64);
+            offsets.push(offset);
+            frame_size = offset + slot.size as u64;
+        }
+
+        // locals are declared in runs of the same type
+        let mut runs: Vec<(u64, ValType)> = Vec::new();
+        for ty in declared {
+            match runs.last_mut() {
+                Some((count, last)) if *last == ty => *count += 1,
+                _ => runs.push((1, ty)),
+            }
+        }
+        let mut code = Vec::new();
+        uleb(&mut code, runs.len() as u64);
+        for (count, ty) in runs {
+            uleb(&mut code, count);
+            code.push(ty.code());
+        }
+
+        Self {
+            function,
+            symbols,
+            types,
+            code,
+            locals,
+            next,
+            frame: next + 1,
+            offsets,
+            frame_size: frame_size.next_multiple_of(16),
+        }
+    }
+
+    fn op(&mut self, op: u8) {
+        self.code.push(op);
+    }
+
+    fn op_index(&mut self, op: u8, index: u64) {
+        self.code.push(op);
+        uleb(&mut self.code, index);
+    }
+
+    fn i32_const(&mut self, value: i64) {
+        self.code.push(I32_CONST);
+        sleb(&mut self.code, value as i32 as i64);
+    }
+
+    fn i64_const(&mut self, value: i64) {
+        self.code.push(I64_CONST);
+        sleb(&mut self.code, value);
+    }
+
+    fn operand(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Reg(reg) => self.op_index(LOCAL_GET, self.locals[reg.0]),
+            Operand::Int(ty, bits) => match val_type(*ty) {
+                ValType::I32 => self.i32_const(*bits as i64),
+                _ => self.i64_const(*bits as i64),
+            },
+            Operand::F32(value) => {
+                self.op(F32_CONST);
+                self.code.extend_from_slice(&value.to_le_bytes());
+            }
+            Operand::F64(value) => {
+                self.op(F64_CONST);
+                self.code.extend_from_slice(&value.to_le_bytes());
+            }
+            Operand::Global(symbol) => self.i64_const(self.symbols.address(symbol) as i64),
+        }
+    }
+
+    /// Pushes an operand sign extended to its value type
+    fn signed(&mut self, operand: &Operand) {
+        self.operand(operand);
+        match self.function.operand_ty(operand) {
+            Ty::I8 => self.op(I32_EXTEND8_S),
+            Ty::I16 => self.op(I32_EXTEND16_S),
+            _ => {}
+        }
+    }
+
+    /// Clears the bits above a narrow type
+    fn normalize(&mut self, ty: Ty) {
+        if let Some(mask) = mask(ty) {
+            self.i32_const(mask);
+            self.op(I32_AND);
+        }
+    }
+
+    /// Pushes an address as an offset into memory
+    fn address(&mut self, operand: &Operand) {
+        self.operand(operand);
+        self.op(I32_WRAP_I64);
+    }
+
+    fn memarg(&mut self, ty: Ty) {
+        uleb(&mut self.code, ty.size().trailing_zeros() as u64);
+        uleb(&mut self.code, 0);
+    }
+
+    fn set(&mut self, reg: Reg) {
+        self.op_index(LOCAL_SET, self.locals[reg.0]);
+    }
+
+    fn emit(mut self) -> Vec<u8> {
+        if self.frame_size > 0 {
+            self.op_index(GLOBAL_GET, 0);
+            self.i64_const(self.frame_size as i64);
+            self.op(I64_SUB);
+            self.op_index(LOCAL_TEE, self.frame);
+            self.op_index(GLOBAL_SET, 0);
+        }
+        let count = self.function.blocks.len();
+        self.code.extend_from_slice(&[LOOP, EMPTY]);
+        for _ in 0..count {
+            self.code.extend_from_slice(&[BLOCK, EMPTY]);
+        }
+        self.op_index(LOCAL_GET, self.next);
+        self.op(BR_TABLE);
+        uleb(&mut self.code, count as u64);
+        for block in 0..count {
+            uleb(&mut self.code, block as u64);
+        }
+        uleb(&mut self.code, 0);
+        for (id, block) in self.function.blocks.iter().enumerate() {
+            // the code of a block comes after the end of its block
+            self.op(END);
+            for inst in &block.insts {
+                self.inst(inst);
+            }
+            self.term(id, &block.term);
+        }
+        self.op(END);
+        self.op(UNREACHABLE);
+        self.op(END);
+        self.code
+    }
+
+    fn inst(&mut self, inst: &Inst) {
+        match inst {
+            Inst::Binary(reg, op, a, b) => {
+                let ty = self.function.reg_ty(*reg);
+                self.binary(ty, *op, a, b);
+                self.set(*reg);
+            }
+            Inst::Unary(reg, op, value) => {
+                let ty = self.function.reg_ty(*reg);
+                match op {
+                    UnOp::FNeg if ty == Ty::F32 => {
+                        self.operand(value);
+                        self.op(F32_NEG);
+                    }
+                    UnOp::FNeg => {
+                        self.operand(value);
+                        self.op(F64_NEG);
+                    }
+                    UnOp::Not if ty == Ty::Bool => {
+                        self.operand(value);
+                        self.op(I32_EQZ);
+                    }
+                    UnOp::Not if val_type(ty) == ValType::I32 => {
+                        self.operand(value);
+                        self.i32_const(-1);
+                        self.op(I32_XOR);
+                        self.normalize(ty);
+                    }
+                    UnOp::Not => {
+                        self.operand(value);
+                        self.i64_const(-1);
+                        self.op(I64_XOR);
+                    }
+                    UnOp::Neg if val_type(ty) == ValType::I32 => {
+                        self.i32_const(0);
+                        self.operand(value);
+                        self.op(I32_SUB);
+                        self.normalize(ty);
+                    }
+                    UnOp::Neg => {
+                        self.i64_const(0);
+                        self.operand(value);
+                        self.op(I64_SUB);
+                    }
+                }
+                self.set(*reg);
+            }
+            Inst::Cmp(reg, op, a, b) => {
+                self.cmp(*op, a, b);
+                self.set(*reg);
+            }
+            Inst::Cast(reg, op, value) => {
+                let to = self.function.reg_ty(*reg);
+                let from = self.function.operand_ty(value);
+                match (val_type(from), val_type(to), op) {
+                    (ValType::I32, ValType::I64, CastOp::SExt) => {
+                        self.signed(value);
+                        self.op(I64_EXTEND_I32_S);
+                    }
+                    (ValType::I32, ValType::I64, _) => {
+                        self.operand(value);
+                        self.op(I64_EXTEND_I32_U);
+                    }
+                    (ValType::I64, ValType::I32, _) => {
+                        self.operand(value);
+                        self.op(I32_WRAP_I64);
+                        self.normalize(to);
+                    }
+                    (_, _, CastOp::SExt) => {
+                        self.signed(value);
+                        self.normalize(to);
+                    }
+                    (_, _, CastOp::Trunc) => {
+                        self.operand(value);
+                        self.normalize(to);
+                    }
+                    (_, _, CastOp::ZExt) => self.operand(value),
+                }
+                self.set(*reg);
+            }
+            Inst::SlotAddr(reg, slot) => {
+                self.op_index(LOCAL_GET, self.frame);
+                self.i64_const(self.offsets[slot.0] as i64);
+                self.op(I64_ADD);
+                self.set(*reg);
+            }
+            Inst::Load(reg, addr) => {
+                let ty = self.function.reg_ty(*reg);
+                self.address(addr);
+                self.op(match ty {
+                    Ty::Bool | Ty::I8 => 0x2d,
+                    Ty::I16 => 0x2f,
+                    Ty::I32 => 0x28,
+                    Ty::I64 | Ty::Ptr => 0x29,
+                    Ty::F32 => 0x2a,
+                    Ty::F64 => 0x2b,
+                });
+                self.memarg(ty);
+                self.set(*reg);
+            }
+            Inst::Store(addr, value) => {
+                let ty = self.function.operand_ty(value);
+                self.address(addr);
+                self.operand(value);
+                self.op(match ty {
+                    Ty::Bool | Ty::I8 => 0x3a,
+                    Ty::I16 => 0x3b,
+                    Ty::I32 => 0x36,
+                    Ty::I64 | Ty::Ptr => 0x37,
+                    Ty::F32 => 0x38,
+                    Ty::F64 => 0x39,
+                });
+                self.memarg(ty);
+            }
+            Inst::PtrAdd(reg, base, offset) => {
+                self.operand(base);
+                self.operand(offset);
+                self.op(I64_ADD);
+                self.set(*reg);
+            }
+            Inst::Copy(to, from, size) => {
+                self.address(to);
+                self.address(from);
+                self.i32_const(*size as i64);
+                self.op(PREFIX_FC);
+                uleb(&mut self.code, MEMORY_COPY);
+                self.code.extend_from_slice(&[0x00, 0x00]);
+            }
+            Inst::Call(reg, callee, args) => {
+                for arg in args {
+                    self.operand(arg);
+                }
+                let direct = match callee {
+                    Operand::Global(symbol) => self.symbols.functions.get(symbol).copied(),
+                    _ => None,
+                };
+                match direct {
+                    Some(index) => self.op_index(CALL, index),
+                    None => {
+                        let params = args.iter().map(|arg| self.function.operand_ty(arg));
+                        let params = params.map(val_type).collect();
+                        let ret = reg.map(|reg| val_type(self.function.reg_ty(reg)));
+                        let ty = self.types.index(params, ret);
+                        self.address(callee);
+                        self.op_index(CALL_INDIRECT, ty);
+                        uleb(&mut self.code, 0);
+                    }
+                }
+                if let Some(reg) = reg {
+                    self.set(*reg);
+                }
+            }
+        }
+    }
+
+    fn binary(&mut self, ty: Ty, op: BinOp, a: &Operand, b: &Operand) {
+        let (add, trunc) = match ty {
+            Ty::F32 => (F32_ADD, F32_TRUNC),
+            _ => (F64_ADD, F64_TRUNC),
+        };
+        // sub, mul and div follow add for floats
+        let float = match op {
+            BinOp::FAdd => Some(add),
+            BinOp::FSub => Some(add + 1),
+            BinOp::FMul => Some(add + 2),
+            BinOp::FDiv => Some(add + 3),
+            _ => None,
+        };
+        if let Some(float) = float {
+            self.operand(a);
+            self.operand(b);
+            self.op(float);
+            return;
+        }
+        if op == BinOp::FRem {
+            // a - b * trunc(a / b), without the precision of C's `fmod`
+            self.operand(a);
+            self.operand(b);
+            self.operand(a);
+            self.operand(b);
+            self.op(add + 3);
+            self.op(trunc);
+            self.op(add + 2);
+            self.op(add + 1);
+            return;
+        }
+
+        // integer operations come in the same order for both widths
+        let offset = match op {
+            BinOp::Add => 0,
+            BinOp::Sub => 1,
+            BinOp::Mul => 2,
+            BinOp::SDiv => 3,
+            BinOp::UDiv => 4,
+            BinOp::SRem => 5,
+            BinOp::URem => 6,
+            BinOp::And => 7,
+            BinOp::Or => 8,
+            BinOp::Xor => 9,
+            BinOp::Shl => 10,
+            BinOp::AShr => 11,
+            BinOp::LShr => 12,
+            _ => unreachable!("{op} is a float operation"),
+        };
+        let base = match val_type(ty) {
+            ValType::I32 => I32_ADD,
+            _ => I64_ADD,
+        };
+        match op {
+            BinOp::SDiv | BinOp::SRem => {
+                self.signed(a);
+                self.signed(b);
+            }
+            BinOp::AShr => self.signed(a),
+            _ => self.operand(a),
+        }
+        match op {
+            BinOp::SDiv | BinOp::SRem => {}
+            BinOp::Shl | BinOp::AShr | BinOp::LShr if mask(ty).is_some() => {
+                // shifts only use the bits that can count up to the width
+                self.operand(b);
+                self.i32_const(ty.size() as i64 * 8 - 1);
+                self.op(I32_AND);
+            }
+            _ => self.operand(b),
+        }
+        self.op(base + offset);
+        self.normalize(ty);
+    }
+
+    fn cmp(&mut self, op: CmpOp, a: &Operand, b: &Operand) {
+        let ty = self.function.operand_ty(a);
+        let (base, offset) = match (val_type(ty), op) {
+            (ValType::F32 | ValType::F64, _) => {
+                let base = if ty == Ty::F32 { F32_EQ } else { F64_EQ };
+                let offset = match op {
+                    CmpOp::FEq => 0,
+                    CmpOp::FNe => 1,
+                    CmpOp::FLt => 2,
+                    CmpOp::FGt => 3,
+                    CmpOp::FLe => 4,
+                    _ => 5,
+                };
+                (base, offset)
+            }
+            (value, _) => {
+                let base = if value == ValType::I32 {
+                    I32_EQ
+                } else {
+                    I64_EQ
+                };
+                let offset = match op {
+                    CmpOp::Eq => 0,
+                    CmpOp::Ne => 1,
+                    CmpOp::SLt => 2,
+                    CmpOp::ULt => 3,
+                    CmpOp::SGt => 4,
+                    CmpOp::UGt => 5,
+                    CmpOp::SLe => 6,
+                    CmpOp::ULe => 7,
+                    CmpOp::SGe => 8,
+                    _ => 9,
+                };
+                (base, offset)
+            }
+        };
+        if matches!(op, CmpOp::SLt | CmpOp::SLe | CmpOp::SGt | CmpOp::SGe) {
+            self.signed(a);
+            self.signed(b);
+        } else {
+            self.operand(a);
+            self.operand(b);
+        }
+        self.op(base + offset);
+    }
+
+    /// Passes the arguments of a jump from block `from`, `depth` is how
+    /// many blocks the jump is nested in inside of the block's code
+    fn jump(&mut self, from: usize, target: &Target, depth: usize) {
+        for arg in &target.args {
+            self.operand(arg);
+        }
+        let params = &self.function.blocks[target.block.0].params;
+        for param in params.iter().rev() {
+            self.set(*param);
+        }
+        if target.block.0 != from + 1 {
+            self.i32_const(target.block.0 as i64);
+            self.op_index(LOCAL_SET, self.next);
+            let depth = self.function.blocks.len() - 1 - from + depth;
+            self.op_index(BR, depth as u64);
+        }
+    }
+
+    fn term(&mut self, id: usize, term: &Terminator) {
+        match term {
+            Terminator::Jump(target) => self.jump(id, target, 0),
+            Terminator::Branch(cond, then, other) => {
+                self.operand(cond);
+                self.code.extend_from_slice(&[IF, EMPTY]);
+                self.jump(id, then, 1);
+                self.op(ELSE);
+                self.jump(id, other, 1);
+                self.op(END);
+            }
+            Terminator::Return(value) => {
+                if self.frame_size > 0 {
+                    self.op_index(LOCAL_GET, self.frame);
+                    self.i64_const(self.frame_size as i64);
+                    self.op(I64_ADD);
+                    self.op_index(GLOBAL_SET, 0);
+                }
+                if let Some(value) = value {
+                    self.operand(value);
+                }
+                self.op(RETURN);
+            }
+            Terminator::Unreachable => self.op(UNREACHABLE),
+        }
+    }
+}
+
+#[cfg(test)]
+const PROGRAM: &str = r#"
+    struct Point { i32 x, i64 y, }
+
+    static Point ORIGIN = Point { x = 0, y = 0 };
+    static &str GREETING = "hello\n";
+    static u8 COUNTER = 0;
+
+    extern "C" fn record(u32 value);
+    extern "C" fn note(u8 value);
+
+    impl Point {
+        fn scaled(&Self self, i64 by) i64 { return self.y * by; }
+    }
+
+    fn fib(u32 n) u32 {
+        if (n < 2u32) { return n; }
+        return fib(n - 1u32) + fib(n - 2u32);
+    }
+
+    pub fn greets(&str text) bool {
+        return text == "hello\n";
+    }
+
+    fn main() i32 {
+        if (!greets(GREETING) || greets("hello")) { return 6; }
+        mut Point p = Point { x = 3, y = -4 };
+        p.x += 10;
+        if (p.x != 13 || p.scaled(2i64) != -8i64 || ORIGIN.y != 0i64) { return 1; }
+        mut i32 seven = -7;
+        mut u32 big = 4000000000u32;
+        if (seven / 2 != -3 || seven % 2 != -1 || big / 3u32 != 1333333333u32) { return 2; }
+        mut f64 rest = 7.5;
+        rest %= 2.0;
+        mut f32 quarter = 1.0;
+        quarter /= 4.0;
+        if (rest != 1.5 || -rest > 0.0 || quarter != 0.25) { return 3; }
+        mut i32 shift = 33;
+        mut i8 small = -128i8;
+        small -= 1i8;
+        if (1 << shift != 2 || seven >> 1 != -4 || small != 127i8) { return 4; }
+        COUNTER += 200u8;
+        COUNTER += 100u8;
+        if (COUNTER != 44u8) { return 5; }
+        note(COUNTER);
+        mut u32 i = 0u32;
+        while (i < 5u32) {
+            record(fib(i));
+            i += 1u32;
+        }
+        return 8;
+    }
+"#;
+
+#[cfg(test)]
+fn validate(bytes: &[u8]) {
+    wasmparser::Validator::new()
+        .validate_all(bytes)
+        .unwrap_or_else(|err| panic!("invalid module: {err}"));
+}
+
+#[test]
+fn imports_and_exports() {
+    let module = crate::ir::lower::lower_source(PROGRAM);
+    let bytes = emit(&module).unwrap();
+    validate(&bytes);
+    let mut imports = Vec::new();
+    let mut exports = Vec::new();
+    for payload in wasmparser::Parser::new(0).parse_all(&bytes) {
+        match payload.unwrap() {
+            wasmparser::Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import.unwrap();
+                    imports.push(format!("{}.{}", import.module, import.name));
+                }
+            }
+            wasmparser::Payload::ExportSection(reader) => {
+                for export in reader {
+                    exports.push(export.unwrap().name.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(imports, ["env.record", "env.note"]);
+    assert_eq!(exports, ["greets", "main", "memory"]);
+
+    let module =
+        crate::ir::lower::lower_source("extern static u8 FLAG; fn main() u8 { return FLAG; }");
+    assert!(emit(&module).is_err());
+}
+
+#[test]
+fn validate_and_run() {
+    for level in [crate::ir::opt::OptLevel::O0, crate::ir::opt::OptLevel::O2] {
+        let mut module = crate::ir::lower::lower_source(PROGRAM);
+        crate::ir::opt::optimize(&mut module, level);
+        let bytes = emit(&module).unwrap();
+        validate(&bytes);
+
+        let Ok(node) = std::process::Command::new("node").arg("--version").output() else {
+            // nothing to run the module with
+            continue;
+        };
+        if !node.status.success() {
+            continue;
+        }
+        let path = std::env::temp_dir().join(format!("bc_wasm_{}.wasm", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let script = "
+            const bytes = require('fs').readFileSync(process.argv[1]);
+            const recorded = [];
+            const record = (value) => { recorded.push(value); };
+            const env = { record, note: record };
+            WebAssembly.instantiate(bytes, { env }).then(({ instance }) => {
+                const code = instance.exports.main();
+                console.log(code + ' ' + recorded.join(' '));
+            });
+        ";
+        let output = std::process::Command::new("node")
+            .arg("-e")
+            .arg(script)
+            .arg(&path)
+            .output()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(stdout.trim(), "8 44 0 1 1 2 3", "at {level:?}: {output:?}");
+    }
+}
@@ -0,0 +1,725 @@
+//! Assembling the text this backend emits into an object file.
+//!
+//! Only the instructions, operands and directives code generation produces
+//! are understood. Jumps and calls always take 4 byte displacements, the
+//! ones to something defined in `.text` are filled in here and the rest
+//! become relocations.
+
+use std::collections::HashMap;
+
+use crate::{
+    backend::elf::{Object, Relocation, RelocationKind, SectionKind, Symbol, SymbolKind},
+    parser::ast::Vis,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Arg {
+    /// The number and size of a general purpose register
+    Gpr(u8, usize),
+    Xmm(u8),
+    St(u8),
+    Imm(i64),
+    /// A base register and displacement
+    Mem(u8, i32),
+    /// A symbol relative to the instruction pointer
+    Rip(String),
+    Sym(String),
+    /// A call through a register
+    Indirect(u8),
+}
+
+/// The `r/m` operand of an instruction
+enum Rm<'a> {
+    Reg(u8),
+    Mem(u8, i32),
+    Rip(&'a str),
+}
+
+/// The `r/m` operand an argument can be, registers have to be `size` if
+/// it's given
+fn rm(arg: &Arg, size: Option<usize>) -> Option<Rm<'_>> {
+    match arg {
+        Arg::Gpr(num, arg_size) if size.is_none_or(|size| size == *arg_size) => Some(Rm::Reg(*num)),
+        Arg::Xmm(num) if size.is_none() => Some(Rm::Reg(*num)),
+        Arg::Mem(base, disp) => Some(Rm::Mem(*base, *disp)),
+        Arg::Rip(symbol) => Some(Rm::Rip(symbol)),
+        _ => None,
+    }
+}
+
+const GPRS: [[&str; 4]; 16] = [
+    ["al", "ax", "eax", "rax"],
+    ["cl", "cx", "ecx", "rcx"],
+    ["dl", "dx", "edx", "rdx"],
+    ["bl", "bx", "ebx", "rbx"],
+    ["spl", "sp", "esp", "rsp"],
+    ["bpl", "bp", "ebp", "rbp"],
+    ["sil", "si", "esi", "rsi"],
+    ["dil", "di", "edi", "rdi"],
+    ["r8b", "r8w", "r8d", "r8"],
+    ["r9b", "r9w", "r9d", "r9"],
+    ["r10b", "r10w", "r10d", "r10"],
+    ["r11b", "r11w", "r11d", "r11"],
+    ["r12b", "r12w", "r12d", "r12"],
+    ["r13b", "r13w", "r13d", "r13"],
+    ["r14b", "r14w", "r14d", "r14"],
+    ["r15b", "r15w", "r15d", "r15"],
+];
+
+const CONDITIONS: [&str; 16] = [
+    "o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g",
+];
+
+fn condition(name: &str) -> Option<u8> {
+    let name = match name {
+        "z" => "e",
+        "nz" => "ne",
+        _ => name,
+    };
+    CONDITIONS
+        .iter()
+        .position(|cc| *cc == name)
+        .map(|cc| cc as u8)
+}
+
+/// The operand size of an instruction suffix
+fn size(suffix: char) -> Option<usize> {
+    match suffix {
+        'b' => Some(1),
+        'w' => Some(2),
+        'l' => Some(4),
+        'q' => Some(8),
+        _ => None,
+    }
+}
+
+fn parse_int(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<u64>().ok()?,
+    } as i64;
+    Some(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+fn parse_gpr(name: &str) -> Option<(u8, usize)> {
+    GPRS.iter().enumerate().find_map(|(num, names)| {
+        let size = names.iter().position(|other| *other == name)?;
+        Some((num as u8, 1 << size))
+    })
+}
+
+fn parse_arg(text: &str) -> Result<Arg, String> {
+    let bad = || format!("unknown operand `{text}`");
+    if let Some(imm) = text.strip_prefix('$') {
+        return parse_int(imm).map(Arg::Imm).ok_or_else(bad);
+    }
+    if let Some(reg) = text.strip_prefix("*%") {
+        return parse_gpr(reg)
+            .map(|(num, _)| Arg::Indirect(num))
+            .ok_or_else(bad);
+    }
+    if let Some(reg) = text.strip_prefix('%') {
+        if let Some(num) = reg.strip_prefix("xmm") {
+            return num.parse().map(Arg::Xmm).map_err(|_| bad());
+        }
+        if let Some(num) = reg
+            .strip_prefix("st(")
+            .and_then(|num| num.strip_suffix(')'))
+        {
+            return num.parse().map(Arg::St).map_err(|_| bad());
+        }
+        return parse_gpr(reg)
+            .map(|(num, size)| Arg::Gpr(num, size))
+            .ok_or_else(bad);
+    }
+    if let Some((disp, base)) = text.split_once('(') {
+        let base = base.strip_suffix(')').ok_or_else(bad)?;
+        if base == "%rip" {
+            return Ok(Arg::Rip(disp.to_string()));
+        }
+        let (base, _) = base.strip_prefix('%').and_then(parse_gpr).ok_or_else(bad)?;
+        let disp = match disp {
+            "" => 0,
+            _ => parse_int(disp).ok_or_else(bad)?,
+        };
+        return Ok(Arg::Mem(base, i32::try_from(disp).map_err(|_| bad())?));
+    }
+    Ok(Arg::Sym(text.to_string()))
+}
+
+/// Splits operands at commas outside of parentheses
+fn split_args(text: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !text[start..].trim().is_empty() {
+        args.push(text[start..].trim());
+    }
+    args
+}
+
+struct Assembler {
+    object: Object,
+    section: SectionKind,
+    /// Labels starting with `.L`, which only `.text` has
+    labels: HashMap<String, usize>,
+    /// 4 byte displacements in `.text` to fill in once every label is known
+    fixups: Vec<(usize, String)>,
+}
+
+/// Turns assembly text into an object file
+pub fn assemble(text: &str) -> Result<Object, String> {
+    let mut asm = Assembler {
+        object: Object::default(),
+        section: SectionKind::Text,
+        labels: HashMap::new(),
+        fixups: Vec::new(),
+    };
+    for (number, line) in text.lines().enumerate() {
+        asm.line(line.trim())
+            .map_err(|err| format!("line {}: {err}", number + 1))?;
+    }
+    asm.finish()
+}
+
+impl Assembler {
+    fn bytes(&mut self) -> &mut Vec<u8> {
+        &mut self.object.section_mut(self.section).bytes
+    }
+
+    fn offset(&self) -> usize {
+        self.object.section(self.section).bytes.len()
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.bytes().extend_from_slice(bytes);
+    }
+
+    fn symbol(&mut self, name: &str) -> &mut Symbol {
+        let index = match self.object.symbols.iter().position(|s| s.name == name) {
+            Some(index) => index,
+            None => {
+                self.object.symbols.push(Symbol {
+                    name: name.to_string(),
+                    vis: Vis::Priv,
+                    weak: false,
+                    kind: SymbolKind::Unknown,
+                    section: None,
+                    offset: 0,
+                    size: 0,
+                });
+                self.object.symbols.len() - 1
+            }
+        };
+        &mut self.object.symbols[index]
+    }
+
+    fn relocate(&mut self, symbol: &str, kind: RelocationKind, addend: i64) {
+        let relocation = Relocation {
+            section: self.section,
+            offset: self.offset(),
+            symbol: symbol.to_string(),
+            kind,
+            addend,
+        };
+        self.object.relocations.push(relocation);
+    }
+
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        if line.is_empty() {
+            return Ok(());
+        }
+        if let Some(label) = line.strip_suffix(':') {
+            let offset = self.offset();
+            if label.starts_with(".L") {
+                self.labels.insert(label.to_string(), offset);
+            } else {
+                let section = self.section;
+                let symbol = self.symbol(label);
+                symbol.section = Some(section);
+                symbol.offset = offset;
+            }
+            return Ok(());
+        }
+        let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let args = split_args(rest);
+        if name.starts_with('.') {
+            self.directive(name, &args)
+        } else {
+            let args = args
+                .iter()
+                .map(|arg| parse_arg(arg))
+                .collect::<Result<Vec<_>, _>>()?;
+            self.instruction(name, &args)
+                .ok_or_else(|| format!("cannot assemble `{line}`"))
+        }
+    }
+
+    fn directive(&mut self, name: &str, args: &[&str]) -> Result<(), String> {
+        match (name, args) {
+            (".text", []) => self.section = SectionKind::Text,
+            (".data", []) => self.section = SectionKind::Data,
+            (".bss", []) => self.section = SectionKind::Bss,
+            (".section", [".rodata"]) => self.section = SectionKind::Rodata,
+            (".globl", [name]) => self.symbol(name).vis = Vis::Pub,
+            (".weak", [name]) => {
+                let symbol = self.symbol(name);
+                symbol.vis = Vis::Pub;
+                symbol.weak = true;
+            }
+            (".type", [name, "@function"]) => self.symbol(name).kind = SymbolKind::Function,
+            (".type", [name, "@object"]) => self.symbol(name).kind = SymbolKind::Object,
+            (".size", [name, size]) if size.strip_prefix(".-") == Some(name) => {
+                let end = self.offset();
+                let symbol = self.symbol(name);
+                symbol.size = end - symbol.offset;
+            }
+            (".balign", [align]) => {
+                let align: usize = align.parse().map_err(|_| "bad alignment")?;
+                let section = self.object.section_mut(self.section);
+                section.align = section.align.max(align);
+                let len = section.bytes.len().next_multiple_of(align);
+                section.bytes.resize(len, 0);
+            }
+            (".zero", [len]) => {
+                let len: usize = len.parse().map_err(|_| "bad length")?;
+                let end = self.offset() + len;
+                self.bytes().resize(end, 0);
+            }
+            (".byte", bytes) => {
+                for byte in bytes {
+                    let byte = byte.parse::<u8>().map_err(|_| "bad byte")?;
+                    self.emit(&[byte]);
+                }
+            }
+            (".quad", [symbol]) => {
+                self.relocate(symbol, RelocationKind::Abs64, 0);
+                self.emit(&[0; 8]);
+            }
+            _ => return Err(format!("unknown directive `{name}`")),
+        }
+        Ok(())
+    }
+
+    /// Encodes an instruction with a ModRM byte, `imm` comes last
+    #[allow(clippy::too_many_arguments)]
+    fn encode(
+        &mut self,
+        prefix: Option<u8>,
+        wide: bool,
+        opcode: &[u8],
+        reg: u8,
+        rm: Rm,
+        byte_regs: bool,
+        imm: &[u8],
+    ) {
+        let mut rex = 0x40;
+        if wide {
+            rex |= 8;
+        }
+        if reg & 8 != 0 {
+            rex |= 4;
+        }
+        if let Rm::Reg(base) | Rm::Mem(base, _) = rm {
+            if base & 8 != 0 {
+                rex |= 1;
+            }
+        }
+        // `spl`, `bpl`, `sil` and `dil` only exist with a REX prefix
+        let uniform = byte_regs
+            && ((4..8).contains(&reg) || matches!(rm, Rm::Reg(base) if (4..8).contains(&base)));
+        if let Some(prefix) = prefix {
+            self.emit(&[prefix]);
+        }
+        if rex != 0x40 || uniform {
+            self.emit(&[rex]);
+        }
+        self.emit(opcode);
+        let reg = (reg & 7) << 3;
+        match rm {
+            Rm::Reg(base) => self.emit(&[0xc0 | reg | (base & 7)]),
+            Rm::Mem(base, disp) => {
+                let mode = if disp == 0 && base & 7 != 5 {
+                    0x00
+                } else if i8::try_from(disp).is_ok() {
+                    0x40
+                } else {
+                    0x80
+                };
+                self.emit(&[mode | reg | (base & 7)]);
+                if base & 7 == 4 {
+                    // a SIB byte with no index
+                    self.emit(&[0x24]);
+                }
+                match mode {
+                    0x40 => self.emit(&[disp as u8]),
+                    0x80 => self.emit(&disp.to_le_bytes()),
+                    _ => {}
+                }
+            }
+            Rm::Rip(symbol) => {
+                self.emit(&[reg | 5]);
+                self.relocate(symbol, RelocationKind::Pc32, -4 - imm.len() as i64);
+                self.emit(&[0; 4]);
+            }
+        }
+        self.emit(imm);
+    }
+
+    /// Encodes an instruction whose opcode holds the register
+    fn encode_plus(&mut self, wide: bool, opcode: u8, reg: u8, imm: &[u8]) {
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3);
+        if rex != 0x40 {
+            self.emit(&[rex]);
+        }
+        self.emit(&[opcode + (reg & 7)]);
+        self.emit(imm);
+    }
+
+    /// A jump or call to a symbol or label
+    fn branch(&mut self, opcode: &[u8], target: &str) {
+        self.emit(opcode);
+        let offset = self.offset();
+        self.fixups.push((offset, target.to_string()));
+        self.emit(&[0; 4]);
+    }
+
+    fn instruction(&mut self, name: &str, args: &[Arg]) -> Option<()> {
+        let rm = |arg| rm(arg, None);
+        let byte_regs = args.iter().any(|arg| matches!(arg, Arg::Gpr(_, 1)));
+
+        match (name, args) {
+            ("cqto", []) => self.emit(&[0x48, 0x99]),
+            ("leave", []) => self.emit(&[0xc9]),
+            ("ret", []) => self.emit(&[0xc3]),
+            ("ud2", []) => self.emit(&[0x0f, 0x0b]),
+            ("syscall", []) => self.emit(&[0x0f, 0x05]),
+            ("rep", [Arg::Sym(op)]) if op == "movsb" => self.emit(&[0xf3, 0xa4]),
+            ("fprem", []) => self.emit(&[0xd9, 0xf8]),
+            ("fnstsw", [Arg::Gpr(0, 2)]) => self.emit(&[0xdf, 0xe0]),
+            ("fstp", [Arg::St(n)]) => self.emit(&[0xdd, 0xd8 + n]),
+            ("flds" | "fldl" | "fstps" | "fstpl", [mem @ Arg::Mem(..)]) => {
+                let opcode = if name.ends_with('s') { 0xd9 } else { 0xdd };
+                let reg = if name.starts_with("fld") { 0 } else { 3 };
+                self.encode(None, false, &[opcode], reg, rm(mem)?, false, &[]);
+            }
+            ("pushq", [Arg::Gpr(num, 8)]) => self.encode_plus(false, 0x50, *num, &[]),
+            ("popq", [Arg::Gpr(num, 8)]) => self.encode_plus(false, 0x58, *num, &[]),
+            ("call", [Arg::Indirect(num)]) => {
+                self.encode(None, false, &[0xff], 2, Rm::Reg(*num), false, &[])
+            }
+            ("call", [Arg::Sym(target)]) => self.branch(&[0xe8], target),
+            ("jmp", [Arg::Sym(target)]) => self.branch(&[0xe9], target),
+            ("movabsq", [Arg::Imm(imm), Arg::Gpr(num, 8)]) => {
+                self.encode_plus(true, 0xb8, *num, &imm.to_le_bytes())
+            }
+            ("leaq", [src @ (Arg::Mem(..) | Arg::Rip(_)), Arg::Gpr(dst, 8)]) => {
+                self.encode(None, true, &[0x8d], *dst, rm(src)?, false, &[])
+            }
+            ("movzbq" | "movsbq" | "movzwq" | "movswq", [src, Arg::Gpr(dst, 8)]) => {
+                let opcode = match name {
+                    "movzbq" => 0xb6,
+                    "movzwq" => 0xb7,
+                    "movsbq" => 0xbe,
+                    _ => 0xbf,
+                };
+                self.encode(None, true, &[0x0f, opcode], *dst, rm(src)?, false, &[]);
+            }
+            ("movslq", [src, Arg::Gpr(dst, 8)]) => {
+                self.encode(None, true, &[0x63], *dst, rm(src)?, false, &[])
+            }
+            // moves between general purpose and vector registers
+            ("movd" | "movq", [Arg::Gpr(gpr, _), Arg::Xmm(xmm)]) => {
+                let wide = name == "movq";
+                self.encode(
+                    Some(0x66),
+                    wide,
+                    &[0x0f, 0x6e],
+                    *xmm,
+                    Rm::Reg(*gpr),
+                    false,
+                    &[],
+                )
+            }
+            ("movd" | "movq", [Arg::Xmm(xmm), Arg::Gpr(gpr, _)]) => {
+                let wide = name == "movq";
+                self.encode(
+                    Some(0x66),
+                    wide,
+                    &[0x0f, 0x7e],
+                    *xmm,
+                    Rm::Reg(*gpr),
+                    false,
+                    &[],
+                )
+            }
+            ("ucomiss" | "ucomisd", [src @ Arg::Xmm(_), Arg::Xmm(dst)]) => {
+                let prefix = (name == "ucomisd").then_some(0x66);
+                self.encode(prefix, false, &[0x0f, 0x2e], *dst, rm(src)?, false, &[]);
+            }
+            (
+                "addss" | "subss" | "mulss" | "divss" | "addsd" | "subsd" | "mulsd" | "divsd",
+                [src @ Arg::Xmm(_), Arg::Xmm(dst)],
+            ) => {
+                let prefix = if name.ends_with("ss") { 0xf3 } else { 0xf2 };
+                let opcode = match &name[..3] {
+                    "add" => 0x58,
+                    "mul" => 0x59,
+                    "sub" => 0x5c,
+                    _ => 0x5e,
+                };
+                self.encode(
+                    Some(prefix),
+                    false,
+                    &[0x0f, opcode],
+                    *dst,
+                    rm(src)?,
+                    false,
+                    &[],
+                );
+            }
+            ("imulq", [src, Arg::Gpr(dst, 8)]) => {
+                self.encode(None, true, &[0x0f, 0xaf], *dst, rm(src)?, false, &[])
+            }
+            ("btcl" | "btcq", [Arg::Imm(bit), dst]) => {
+                let wide = name == "btcq";
+                self.encode(None, wide, &[0x0f, 0xba], 7, rm(dst)?, false, &[*bit as u8]);
+            }
+            _ if name.starts_with("set") && args.len() == 1 => {
+                let cc = condition(&name[3..])?;
+                self.encode(None, false, &[0x0f, 0x90 + cc], 0, rm(&args[0])?, true, &[]);
+            }
+            _ if name.starts_with('j') && args.len() == 1 => {
+                let cc = condition(&name[1..])?;
+                let Arg::Sym(target) = &args[0] else {
+                    return None;
+                };
+                self.branch(&[0x0f, 0x80 + cc], target);
+            }
+            _ => {
+                let (base, suffix) = name.split_at(name.len().checked_sub(1)?);
+                let size = size(suffix.chars().next()?)?;
+                self.sized(base, size, args, byte_regs)?;
+            }
+        }
+        Some(())
+    }
+
+    /// Instructions taking an operand size suffix
+    fn sized(&mut self, name: &str, size: usize, args: &[Arg], byte_regs: bool) -> Option<()> {
+        let prefix = (size == 2).then_some(0x66);
+        let wide = size == 8;
+        let byte = size == 1;
+        let rm = |arg| rm(arg, Some(size));
+        let imm = |value: i64| -> Vec<u8> {
+            match size {
+                1 => vec![value as u8],
+                2 => (value as u16).to_le_bytes().to_vec(),
+                _ => (value as u32).to_le_bytes().to_vec(),
+            }
+        };
+        let alu = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"]
+            .iter()
+            .position(|op| *op == name)
+            .map(|op| op as u8);
+        let unary = match name {
+            "not" => Some(2),
+            "neg" => Some(3),
+            "div" => Some(6),
+            "idiv" => Some(7),
+            _ => None,
+        };
+        let shift = match name {
+            "shl" => Some(4),
+            "shr" => Some(5),
+            "sar" => Some(7),
+            _ => None,
+        };
+
+        match (args, alu, unary, shift) {
+            ([Arg::Gpr(src, s), dst], Some(op), ..) if *s == size => {
+                let opcode = op * 8 + if byte { 0 } else { 1 };
+                self.encode(prefix, wide, &[opcode], *src, rm(dst)?, byte_regs, &[]);
+            }
+            ([src @ (Arg::Mem(..) | Arg::Rip(_)), Arg::Gpr(dst, _)], Some(op), ..) => {
+                let opcode = op * 8 + if byte { 2 } else { 3 };
+                self.encode(prefix, wide, &[opcode], *dst, rm(src)?, byte_regs, &[]);
+            }
+            ([Arg::Imm(value), dst], Some(op), ..) => {
+                if byte {
+                    self.encode(prefix, wide, &[0x80], op, rm(dst)?, byte_regs, &imm(*value));
+                } else if i8::try_from(*value).is_ok() {
+                    let imm = [*value as u8];
+                    self.encode(prefix, wide, &[0x83], op, rm(dst)?, byte_regs, &imm);
+                } else {
+                    self.encode(prefix, wide, &[0x81], op, rm(dst)?, byte_regs, &imm(*value));
+                }
+            }
+            ([dst], _, Some(op), _) => {
+                let opcode = if byte { 0xf6 } else { 0xf7 };
+                self.encode(prefix, wide, &[opcode], op, rm(dst)?, byte_regs, &[]);
+            }
+            ([Arg::Gpr(1, 1), dst], _, _, Some(op)) => {
+                let opcode = if byte { 0xd2 } else { 0xd3 };
+                self.encode(prefix, wide, &[opcode], op, rm(dst)?, byte_regs, &[]);
+            }
+            _ => match (name, args) {
+                ("mov", [Arg::Gpr(src, s), dst]) if *s == size => {
+                    let opcode = if byte { 0x88 } else { 0x89 };
+                    self.encode(prefix, wide, &[opcode], *src, rm(dst)?, byte_regs, &[]);
+                }
+                ("mov", [src @ (Arg::Mem(..) | Arg::Rip(_)), Arg::Gpr(dst, _)]) => {
+                    let opcode = if byte { 0x8a } else { 0x8b };
+                    self.encode(prefix, wide, &[opcode], *dst, rm(src)?, byte_regs, &[]);
+                }
+                ("mov", [Arg::Imm(value), Arg::Gpr(dst, _)]) if size == 4 => {
+                    self.encode_plus(false, 0xb8, *dst, &imm(*value));
+                }
+                ("mov", [Arg::Imm(value), dst]) => {
+                    if wide && i32::try_from(*value).is_err() {
+                        return None;
+                    }
+                    let opcode = if byte { 0xc6 } else { 0xc7 };
+                    self.encode(
+                        prefix,
+                        wide,
+                        &[opcode],
+                        0,
+                        rm(dst)?,
+                        byte_regs,
+                        &imm(*value),
+                    );
+                }
+                ("test", [Arg::Gpr(src, s), dst]) if *s == size => {
+                    let opcode = if byte { 0x84 } else { 0x85 };
+                    self.encode(prefix, wide, &[opcode], *src, rm(dst)?, byte_regs, &[]);
+                }
+                ("test", [Arg::Imm(value), dst]) => {
+                    let opcode = if byte { 0xf6 } else { 0xf7 };
+                    self.encode(
+                        prefix,
+                        wide,
+                        &[opcode],
+                        0,
+                        rm(dst)?,
+                        byte_regs,
+                        &imm(*value),
+                    );
+                }
+                _ => return None,
+            },
+        }
+        Some(())
+    }
+
+    fn finish(mut self) -> Result<Object, String> {
+        self.section = SectionKind::Text;
+        for (offset, target) in std::mem::take(&mut self.fixups) {
+            let defined = match self.labels.get(&target) {
+                Some(offset) => Some(*offset),
+                None if target.starts_with(".L") => {
+                    return Err(format!("undefined label `{target}`"));
+                }
+                None => self
+                    .object
+                    .symbol(&target)
+                    .filter(|symbol| symbol.section == Some(SectionKind::Text))
+                    .map(|symbol| symbol.offset),
+            };
+            match defined {
+                Some(to) => {
+                    let distance = to as i64 - (offset as i64 + 4);
+                    let bytes = (distance as i32).to_le_bytes();
+                    self.bytes()[offset..offset + 4].copy_from_slice(&bytes);
+                }
+                None => self.object.relocations.push(Relocation {
+                    section: SectionKind::Text,
+                    offset,
+                    symbol: target,
+                    kind: RelocationKind::Plt32,
+                    addend: -4,
+                }),
+            }
+        }
+        // whatever is left is defined somewhere else
+        let relocations = std::mem::take(&mut self.object.relocations);
+        for relocation in &relocations {
+            self.symbol(&relocation.symbol);
+        }
+        self.object.relocations = relocations;
+        Ok(self.object)
+    }
+}
+
+#[test]
+fn encode() {
+    let object = assemble(
+        "
+    .text
+    .globl f
+f:
+    movq %rsp, %rbp
+    movl $-1, %r9d
+    addq $200, -8(%rbp)
+    movb %sil, (%r12)
+    sete %dil
+    leaq data(%rip), %rax
+    jne .L0
+    call g
+.L0:
+    ret
+    .data
+data:
+    .quad f
+",
+    )
+    .unwrap();
+    assert_eq!(
+        object.section(SectionKind::Text).bytes,
+        [
+            0x48, 0x89, 0xe5, // movq %rsp, %rbp
+            0x41, 0xb9, 0xff, 0xff, 0xff, 0xff, // movl $-1, %r9d
+            0x48, 0x81, 0x45, 0xf8, 0xc8, 0x00, 0x00, 0x00, // addq $200, -8(%rbp)
+            0x41, 0x88, 0x34, 0x24, // movb %sil, (%r12)
+            0x40, 0x0f, 0x94, 0xc7, // sete %dil
+            0x48, 0x8d, 0x05, 0x00, 0x00, 0x00, 0x00, // leaq data(%rip), %rax
+            0x0f, 0x85, 0x05, 0x00, 0x00, 0x00, // jne .L0
+            0xe8, 0x00, 0x00, 0x00, 0x00, // call g
+            0xc3, // ret
+        ]
+    );
+    let kinds: Vec<_> = object
+        .relocations
+        .iter()
+        .map(|r| (r.symbol.as_str(), r.kind, r.addend))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            ("data", RelocationKind::Pc32, -4),
+            ("f", RelocationKind::Abs64, 0),
+            ("g", RelocationKind::Plt32, -4),
+        ]
+    );
+    assert_eq!(object.symbol("f").unwrap().vis, Vis::Pub);
+    assert_eq!(object.symbol("g").unwrap().section, None);
+}
@@ -0,0 +1,1038 @@
+//! x86-64 code generation for the System V ABI as GNU assembler text.
+//!
+//! IR registers live wherever [`regalloc`] puts them, instructions load
+//! their operands into fixed scratch registers, compute and write the result
+//! back. Moves that would overwrite each other's sources, like block
+//! arguments passing a block's own parameters around, go through the stack.
+
+use std::{
+    fmt::Write as _,
+    path::Path,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    ir::{
+        BinOp, CastOp, CmpOp, Data, DataPart, Function, Inst, Module, Operand, Reg, Target,
+        Terminator, Ty, UnOp,
+    },
+    parser::ast::Vis,
+};
+
+use self::regalloc::{Allocation, Location};
+
+use super::run;
+
+pub mod asm;
+pub mod regalloc;
+
+/// Calls `main` with the argument count and vector and exits with what it
+/// returns, it's weak so C's startup files can take over when they're linked
+const START: &str = "_start";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Gpr {
+    Rax,
+    Rbx,
+    Rcx,
+    Rdx,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+/// Integer arguments in the order they are passed
+const ARGS: [Gpr; 6] = [Gpr::Rdi, Gpr::Rsi, Gpr::Rdx, Gpr::Rcx, Gpr::R8, Gpr::R9];
+
+/// How many arguments go in `xmm` registers
+const FLOAT_ARGS: usize = 8;
+
+impl Gpr {
+    pub fn name(self, size: usize) -> &'static str {
+        let names = match self {
+            Gpr::Rax => ["%al", "%ax", "%eax", "%rax"],
+            Gpr::Rbx => ["%bl", "%bx", "%ebx", "%rbx"],
+            Gpr::Rcx => ["%cl", "%cx", "%ecx", "%rcx"],
+            Gpr::Rdx => ["%dl", "%dx", "%edx", "%rdx"],
+            Gpr::Rsi => ["%sil", "%si", "%esi", "%rsi"],
+            Gpr::Rdi => ["%dil", "%di", "%edi", "%rdi"],
+            Gpr::R8 => ["%r8b", "%r8w", "%r8d", "%r8"],
+            Gpr::R9 => ["%r9b", "%r9w", "%r9d", "%r9"],
+            Gpr::R10 => ["%r10b", "%r10w", "%r10d", "%r10"],
+            Gpr::R11 => ["%r11b", "%r11w", "%r11d", "%r11"],
+            Gpr::R12 => ["%r12b", "%r12w", "%r12d", "%r12"],
+            Gpr::R13 => ["%r13b", "%r13w", "%r13d", "%r13"],
+            Gpr::R14 => ["%r14b", "%r14w", "%r14d", "%r14"],
+            Gpr::R15 => ["%r15b", "%r15w", "%r15d", "%r15"],
+        };
+        match size {
+            1 => names[0],
+            2 => names[1],
+            4 => names[2],
+            _ => names[3],
+        }
+    }
+}
+
+/// The instruction suffix for an operand size
+fn suffix(size: usize) -> char {
+    match size {
+        1 => 'b',
+        2 => 'w',
+        4 => 'l',
+        _ => 'q',
+    }
+}
+
+/// How narrow values fill a whole register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ext {
+    Zero,
+    Sign,
+}
+
+/// Where a value is during code generation
+#[derive(Debug, Clone, PartialEq)]
+enum Loc {
+    Gpr(Gpr),
+    Xmm(usize),
+    Mem(String),
+}
+
+/// What a move reads
+enum Source {
+    Operand(Operand),
+    Loc(Loc),
+}
+
+/// Where the arguments of a call or the parameters of a function go
+enum ArgLoc {
+    Gpr(Gpr),
+    Xmm(usize),
+    /// The index among the arguments passed on the stack
+    Stack(usize),
+}
+
+fn classify<'a>(tys: impl IntoIterator<Item = &'a Ty>) -> (Vec<ArgLoc>, usize) {
+    let (mut ints, mut floats, mut stack) = (0, 0, 0);
+    let locs = tys
+        .into_iter()
+        .map(|ty| {
+            if ty.is_float() && floats < FLOAT_ARGS {
+                floats += 1;
+                ArgLoc::Xmm(floats - 1)
+            } else if !ty.is_float() && ints < ARGS.len() {
+                ints += 1;
+                ArgLoc::Gpr(ARGS[ints - 1])
+            } else {
+                stack += 1;
+                ArgLoc::Stack(stack - 1)
+            }
+        })
+        .collect();
+    (locs, stack)
+}
+
+fn align(offset: usize, align: usize) -> usize {
+    offset.next_multiple_of(align)
+}
+
+/// Shows where the registers of every function live
+pub fn allocations(module: &Module) -> String {
+    let mut out = String::new();
+    for function in &module.functions {
+        let _ = writeln!(out, "fn {}:", function.name);
+        let _ = write!(out, "{}", regalloc::allocate(function));
+    }
+    out
+}
+
+/// Writes a program as assembly, a function named `main` gets a `_start`
+/// calling it
+pub fn emit(module: &Module) -> String {
+    let mut out = String::new();
+    out.push_str("    .text\n");
+    if let Some(main) = module.functions.iter().find(|f| f.name == "main") {
+        emit_start(&mut out, main, links_libc(module));
+    }
+    for function in &module.functions {
+        let allocation = regalloc::allocate(function);
+        FunctionEmitter::new(function, &allocation, &mut out).emit();
+    }
+    for data in &module.data {
+        emit_data(&mut out, data);
+    }
+    out
+}
+
+fn emit_start(out: &mut String, main: &Function, libc: bool) {
+    let _ = writeln!(out, "    .weak {START}");
+    let _ = writeln!(out, "{START}:");
+    out.push_str("    xorl %ebp, %ebp\n");
+    out.push_str("    movq (%rsp), %rdi\n");
+    out.push_str("    leaq 8(%rsp), %rsi\n");
+    out.push_str("    andq $-16, %rsp\n");
+    let _ = writeln!(out, "    call {}", main.name);
+    match main.ret {
+        Some(ty) if !ty.is_float() => out.push_str("    movl %eax, %edi\n"),
+        _ => out.push_str("    xorl %edi, %edi\n"),
+    }
+    if libc {
+        // flushes what stdio still has buffered
+        out.push_str("    call exit\n");
+    } else {
+        // exit_group
+        out.push_str("    movl $231, %eax\n");
+        out.push_str("    syscall\n");
+    }
+}
+
+/// Whether a program uses anything defined outside of it, which libc has to
+/// provide
+fn links_libc(module: &Module) -> bool {
+    !module.externs.is_empty() || module.data.iter().any(|data| data.init.is_none())
+}
+
+fn emit_data(out: &mut String, data: &Data) {
+    let Some(init) = &data.init else {
+        return;
+    };
+    let zero = init.iter().all(|part| match part {
+        DataPart::Bytes(bytes) => bytes.iter().all(|b| *b == 0),
+        DataPart::Addr(_) => false,
+    });
+    let section = match (data.mutable, zero) {
+        (true, true) => ".bss",
+        (true, false) => ".data",
+        (false, _) => ".section .rodata",
+    };
+    let _ = writeln!(out, "    {section}");
+    let _ = writeln!(out, "    .balign {}", data.align);
+    if data.vis == Vis::Pub {
+        let _ = writeln!(out, "    .globl {}", data.name);
+    }
+    let _ = writeln!(out, "    .type {}, @object", data.name);
+    let _ = writeln!(out, "{}:", data.name);
+    for part in init {
+        match part {
+            DataPart::Bytes(bytes) if zero => {
+                if !bytes.is_empty() {
+                    let _ = writeln!(out, "    .zero {}", bytes.len());
+                }
+            }
+            DataPart::Bytes(bytes) => {
+                for chunk in bytes.chunks(16) {
+                    let bytes: Vec<_> = chunk.iter().map(|b| b.to_string()).collect();
+                    let _ = writeln!(out, "    .byte {}", bytes.join(", "));
+                }
+            }
+            DataPart::Addr(name) => {
+                let _ = writeln!(out, "    .quad {name}");
+            }
+        }
+    }
+    let _ = writeln!(out, "    .size {0}, .-{0}", data.name);
+}
+
+struct FunctionEmitter<'a> {
+    function: &'a Function,
+    out: &'a mut String,
+    /// Where every register lives
+    locations: Vec<Option<Loc>>,
+    slots: Vec<usize>,
+    /// The callee saved registers along with where they are kept
+    saved: Vec<(Gpr, usize)>,
+    /// 16 bytes of memory for going through the x87 unit
+    scratch: usize,
+    frame: usize,
+    labels: usize,
+}
+
+impl<'a> FunctionEmitter<'a> {
+    fn new(function: &'a Function, allocation: &Allocation, out: &'a mut String) -> Self {
+        let mut frame = 0;
+        let slots = function
+            .slots
+            .iter()
+            .map(|slot| {
+                // the frame itself is only 16 byte aligned
+                frame = align(frame + slot.size, slot.align.min(16));
+                frame
+            })
+            .collect();
+        let spills: Vec<_> = (0..allocation.spills)
+            .map(|_| {
+                frame += 8;
+                frame
+            })
+            .collect();
+        let saved = allocation
+            .saved
+            .iter()
+            .map(|gpr| {
+                frame += 8;
+                (*gpr, frame)
+            })
+            .collect();
+        let locations = allocation
+            .locations
+            .iter()
+            .map(|location| {
+                Some(match (*location)? {
+                    Location::Gpr(gpr) => Loc::Gpr(gpr),
+                    Location::Xmm(xmm) => Loc::Xmm(xmm),
+                    Location::Spill(slot) => Loc::Mem(format!("-{}(%rbp)", spills[slot])),
+                })
+            })
+            .collect();
+        let scratch = align(frame + 16, 16);
+        Self {
+            function,
+            out,
+            locations,
+            slots,
+            saved,
+            scratch,
+            frame: scratch,
+            labels: 0,
+        }
+    }
+
+    fn line(&mut self, text: impl AsRef<str>) {
+        self.out.push_str("    ");
+        self.out.push_str(text.as_ref());
+        self.out.push('\n');
+    }
+
+    fn block_label(&self, block: usize) -> String {
+        format!(".L{}_{block}", self.function.name)
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}_l{}", self.function.name, self.labels)
+    }
+
+    fn loc(&self, reg: Reg) -> Loc {
+        self.locations[reg.0].clone().unwrap()
+    }
+
+    fn ty(&self, operand: &Operand) -> Ty {
+        self.function.operand_ty(operand)
+    }
+
+    fn emit(mut self) {
+        let name = &self.function.name;
+        if self.function.vis == Vis::Pub {
+            let _ = writeln!(self.out, "    .globl {name}");
+        }
+        let _ = writeln!(self.out, "    .type {name}, @function");
+        let _ = writeln!(self.out, "{name}:");
+        self.line("pushq %rbp");
+        self.line("movq %rsp, %rbp");
+        self.line(format!("subq ${}, %rsp", self.frame));
+
+        for (gpr, offset) in self.saved.clone() {
+            self.line(format!("movq {}, -{offset}(%rbp)", gpr.name(8)));
+        }
+
+        let params = self.function.params().to_vec();
+        let (locs, _) = classify(params.iter().map(|reg| &self.function.regs[reg.0]));
+        let moves = params
+            .into_iter()
+            .zip(locs)
+            .map(|(param, loc)| {
+                let source = match loc {
+                    ArgLoc::Gpr(gpr) => Loc::Gpr(gpr),
+                    ArgLoc::Xmm(xmm) => Loc::Xmm(xmm),
+                    ArgLoc::Stack(i) => Loc::Mem(format!("{}(%rbp)", 16 + 8 * i)),
+                };
+                (
+                    Source::Loc(source),
+                    self.loc(param),
+                    self.function.reg_ty(param),
+                )
+            })
+            .collect();
+        self.moves(moves);
+
+        for (i, block) in self.function.blocks.iter().enumerate() {
+            let _ = writeln!(self.out, "{}:", self.block_label(i));
+            for inst in &block.insts {
+                self.inst(inst);
+            }
+            self.terminator(i, &block.term);
+        }
+        let _ = writeln!(self.out, "    .size {name}, .-{name}");
+    }
+
+    /// Loads `size` bytes from memory or a register extending them to 64 bits
+    fn mov_from(&mut self, mem: &str, gpr: Gpr, size: usize, ext: Ext) {
+        let op = match (size, ext) {
+            (1, Ext::Zero) => "movzbq",
+            (1, Ext::Sign) => "movsbq",
+            (2, Ext::Zero) => "movzwq",
+            (2, Ext::Sign) => "movswq",
+            (4, Ext::Zero) => {
+                // writing the low half clears the rest
+                self.line(format!("movl {mem}, {}", gpr.name(4)));
+                return;
+            }
+            (4, Ext::Sign) => "movslq",
+            _ if mem == gpr.name(8) => return,
+            _ => "movq",
+        };
+        self.line(format!("{op} {mem}, {}", gpr.name(8)));
+    }
+
+    /// Puts the value of a location in a register extended to 64 bits
+    fn read(&mut self, loc: &Loc, ty: Ty, gpr: Gpr, ext: Ext) {
+        match loc {
+            Loc::Gpr(reg) => self.mov_from(reg.name(ty.size()), gpr, ty.size(), ext),
+            Loc::Xmm(xmm) if ty == Ty::F32 => self.line(format!("movd %xmm{xmm}, {}", gpr.name(4))),
+            Loc::Xmm(xmm) => self.line(format!("movq %xmm{xmm}, {}", gpr.name(8))),
+            Loc::Mem(mem) => self.mov_from(mem, gpr, ty.size(), ext),
+        }
+    }
+
+    /// Writes the low bytes of a register to a location
+    fn write(&mut self, gpr: Gpr, loc: &Loc, ty: Ty) {
+        let size = ty.size();
+        match loc {
+            Loc::Gpr(reg) if *reg == gpr => {}
+            Loc::Gpr(reg) => self.line(format!("movq {}, {}", gpr.name(8), reg.name(8))),
+            Loc::Xmm(xmm) if ty == Ty::F32 => self.line(format!("movd {}, %xmm{xmm}", gpr.name(4))),
+            Loc::Xmm(xmm) => self.line(format!("movq {}, %xmm{xmm}", gpr.name(8))),
+            Loc::Mem(mem) => self.line(format!("mov{} {}, {mem}", suffix(size), gpr.name(size))),
+        }
+    }
+
+    fn source_loc(&self, source: &Source) -> Option<Loc> {
+        match source {
+            Source::Operand(Operand::Reg(reg)) => Some(self.loc(*reg)),
+            Source::Operand(_) => None,
+            Source::Loc(loc) => Some(loc.clone()),
+        }
+    }
+
+    fn read_source(&mut self, source: &Source, ty: Ty, gpr: Gpr) {
+        match source {
+            Source::Operand(operand) => self.load(operand, gpr, Ext::Zero),
+            Source::Loc(loc) => self.read(loc, ty, gpr, Ext::Zero),
+        }
+    }
+
+    /// Does several moves at once, going through the stack if one of them
+    /// would overwrite what another one reads
+    fn moves(&mut self, moves: Vec<(Source, Loc, Ty)>) {
+        let moves: Vec<_> = moves
+            .into_iter()
+            .filter(|(source, dst, _)| self.source_loc(source).as_ref() != Some(dst))
+            .collect();
+        let sources: Vec<_> = moves
+            .iter()
+            .map(|(source, ..)| self.source_loc(source))
+            .collect();
+        let overlap = moves.iter().enumerate().any(|(i, (_, dst, _))| {
+            sources
+                .iter()
+                .enumerate()
+                .any(|(j, source)| i != j && source.as_ref() == Some(dst))
+        });
+        if overlap {
+            for (source, _, ty) in &moves {
+                self.read_source(source, *ty, Gpr::Rax);
+                self.line("pushq %rax");
+            }
+            for (_, dst, ty) in moves.iter().rev() {
+                self.line("popq %rax");
+                self.write(Gpr::Rax, dst, *ty);
+            }
+        } else {
+            for (source, dst, ty) in &moves {
+                match dst {
+                    Loc::Gpr(gpr) => self.read_source(source, *ty, *gpr),
+                    _ => {
+                        self.read_source(source, *ty, Gpr::Rax);
+                        self.write(Gpr::Rax, dst, *ty);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Puts an operand in a register extended to 64 bits, floats as their bits
+    fn load(&mut self, operand: &Operand, gpr: Gpr, ext: Ext) {
+        let bits = match operand {
+            Operand::Reg(reg) => {
+                let ty = self.function.reg_ty(*reg);
+                self.read(&self.loc(*reg), ty, gpr, ext);
+                return;
+            }
+            Operand::Global(name) => {
+                self.line(format!("leaq {name}(%rip), {}", gpr.name(8)));
+                return;
+            }
+            Operand::Int(ty, value) if ext == Ext::Sign => ty.sign_extend(*value) as u64,
+            Operand::Int(_, value) => *value,
+            Operand::F32(value) => value.to_bits() as u64,
+            Operand::F64(value) => value.to_bits(),
+        };
+        if i32::try_from(bits as i64).is_ok() {
+            self.line(format!("movq ${}, {}", bits as i64, gpr.name(8)));
+        } else if u32::try_from(bits).is_ok() {
+            self.line(format!("movl ${bits}, {}", gpr.name(4)));
+        } else {
+            self.line(format!("movabsq ${}, {}", bits as i64, gpr.name(8)));
+        }
+    }
+
+    fn store(&mut self, gpr: Gpr, reg: Reg) {
+        let ty = self.function.reg_ty(reg);
+        self.write(gpr, &self.loc(reg), ty);
+    }
+
+    /// Moves the bits in `rax` to `xmm` or back
+    fn xmm(&mut self, ty: Ty, xmm: usize, to_xmm: bool) {
+        let (op, gpr) = match ty {
+            Ty::F32 => ("movd", "%eax"),
+            _ => ("movq", "%rax"),
+        };
+        if to_xmm {
+            self.line(format!("{op} {gpr}, %xmm{xmm}"));
+        } else {
+            self.line(format!("{op} %xmm{xmm}, {gpr}"));
+        }
+    }
+
+    fn inst(&mut self, inst: &Inst) {
+        match inst {
+            Inst::Binary(dst, op, a, b) => self.binary(*dst, *op, a, b),
+            Inst::Unary(dst, op, a) => {
+                let ty = self.function.reg_ty(*dst);
+                self.load(a, Gpr::Rax, Ext::Zero);
+                match op {
+                    UnOp::Neg => self.line("negq %rax"),
+                    UnOp::FNeg if ty == Ty::F32 => self.line("btcl $31, %eax"),
+                    UnOp::FNeg => self.line("btcq $63, %rax"),
+                    UnOp::Not if ty == Ty::Bool => self.line("xorq $1, %rax"),
+                    UnOp::Not => self.line("notq %rax"),
+                }
+                self.store(Gpr::Rax, *dst);
+            }
+            Inst::Cmp(dst, op, a, b) => self.cmp(*dst, *op, a, b),
+            Inst::Cast(dst, op, a) => {
+                let ext = match op {
+                    CastOp::SExt => Ext::Sign,
+                    CastOp::ZExt | CastOp::Trunc => Ext::Zero,
+                };
+                self.load(a, Gpr::Rax, ext);
+                self.store(Gpr::Rax, *dst);
+            }
+            Inst::SlotAddr(dst, slot) => {
+                self.line(format!("leaq -{}(%rbp), %rax", self.slots[slot.0]));
+                self.store(Gpr::Rax, *dst);
+            }
+            Inst::Load(dst, addr) => {
+                self.load(addr, Gpr::Rax, Ext::Zero);
+                let size = self.function.reg_ty(*dst).size();
+                self.mov_from("(%rax)", Gpr::Rax, size, Ext::Zero);
+                self.store(Gpr::Rax, *dst);
+            }
+            Inst::Store(addr, value) => {
+                let size = self.ty(value).size();
+                self.load(value, Gpr::Rcx, Ext::Zero);
+                self.load(addr, Gpr::Rax, Ext::Zero);
+                self.line(format!(
+                    "mov{} {}, (%rax)",
+                    suffix(size),
+                    Gpr::Rcx.name(size)
+                ));
+            }
+            Inst::PtrAdd(dst, base, offset) => {
+                self.load(base, Gpr::Rax, Ext::Zero);
+                self.load(offset, Gpr::Rcx, Ext::Sign);
+                self.line("addq %rcx, %rax");
+                self.store(Gpr::Rax, *dst);
+            }
+            Inst::Copy(to, from, size) => {
+                if *size != 0 {
+                    self.load(to, Gpr::Rdi, Ext::Zero);
+                    self.load(from, Gpr::Rsi, Ext::Zero);
+                    self.line(format!("movq ${size}, %rcx"));
+                    self.line("rep movsb");
+                }
+            }
+            Inst::Call(dst, callee, args) => self.call(*dst, callee, args),
+        }
+    }
+
+    fn binary(&mut self, dst: Reg, op: BinOp, a: &Operand, b: &Operand) {
+        let ty = self.function.reg_ty(dst);
+        let float = match op {
+            BinOp::FAdd => Some("add"),
+            BinOp::FSub => Some("sub"),
+            BinOp::FMul => Some("mul"),
+            BinOp::FDiv => Some("div"),
+            BinOp::FRem => return self.frem(dst, a, b),
+            _ => None,
+        };
+        if let Some(op) = float {
+            let sfx = if ty == Ty::F32 { "ss" } else { "sd" };
+            self.load(a, Gpr::Rax, Ext::Zero);
+            self.xmm(ty, 0, true);
+            self.load(b, Gpr::Rax, Ext::Zero);
+            self.xmm(ty, 1, true);
+            self.line(format!("{op}{sfx} %xmm1, %xmm0"));
+            self.xmm(ty, 0, false);
+            self.store(Gpr::Rax, dst);
+            return;
+        }
+
+        let ext = match op {
+            BinOp::SDiv | BinOp::SRem | BinOp::AShr => Ext::Sign,
+            _ => Ext::Zero,
+        };
+        self.load(a, Gpr::Rax, ext);
+        self.load(b, Gpr::Rcx, ext);
+        match op {
+            BinOp::Add => self.line("addq %rcx, %rax"),
+            BinOp::Sub => self.line("subq %rcx, %rax"),
+            BinOp::Mul => self.line("imulq %rcx, %rax"),
+            BinOp::And => self.line("andq %rcx, %rax"),
+            BinOp::Or => self.line("orq %rcx, %rax"),
+            BinOp::Xor => self.line("xorq %rcx, %rax"),
+            BinOp::SDiv | BinOp::SRem => {
+                self.line("cqto");
+                self.line("idivq %rcx");
+            }
+            BinOp::UDiv | BinOp::URem => {
+                self.line("xorl %edx, %edx");
+                self.line("divq %rcx");
+            }
+            BinOp::Shl | BinOp::LShr | BinOp::AShr => {
+                // shifting by the width or more wraps around
+                self.line(format!("andl ${}, %ecx", ty.size() * 8 - 1));
+                let op = match op {
+                    BinOp::Shl => "shlq",
+                    BinOp::LShr => "shrq",
+                    _ => "sarq",
+                };
+                self.line(format!("{op} %cl, %rax"));
+            }
+            _ => unreachable!(),
+        }
+        let result = match op {
+            BinOp::SRem | BinOp::URem => Gpr::Rdx,
+            _ => Gpr::Rax,
+        };
+        self.store(result, dst);
+    }
+
+    /// There is no SSE instruction for the remainder but x87 has one
+    fn frem(&mut self, dst: Reg, a: &Operand, b: &Operand) {
+        let ty = self.function.reg_ty(dst);
+        let sfx = if ty == Ty::F32 { 's' } else { 'l' };
+        let (first, second) = (self.scratch, self.scratch - 8);
+        self.load(a, Gpr::Rax, Ext::Zero);
+        self.line(format!("movq %rax, -{first}(%rbp)"));
+        self.load(b, Gpr::Rax, Ext::Zero);
+        self.line(format!("movq %rax, -{second}(%rbp)"));
+        self.line(format!("fld{sfx} -{second}(%rbp)"));
+        self.line(format!("fld{sfx} -{first}(%rbp)"));
+        let again = self.label();
+        let _ = writeln!(self.out, "{again}:");
+        self.line("fprem");
+        self.line("fnstsw %ax");
+        self.line("testw $0x400, %ax");
+        self.line(format!("jnz {again}"));
+        self.line("fstp %st(1)");
+        self.line(format!("fstp{sfx} -{first}(%rbp)"));
+        self.mov_from(&format!("-{first}(%rbp)"), Gpr::Rax, ty.size(), Ext::Zero);
+        self.store(Gpr::Rax, dst);
+    }
+
+    fn cmp(&mut self, dst: Reg, op: CmpOp, a: &Operand, b: &Operand) {
+        let ty = self.ty(a);
+        if ty.is_float() {
+            let sfx = if ty == Ty::F32 { "ss" } else { "sd" };
+            self.load(a, Gpr::Rax, Ext::Zero);
+            self.xmm(ty, 0, true);
+            self.load(b, Gpr::Rax, Ext::Zero);
+            self.xmm(ty, 1, true);
+            // unordered sets every flag, `a` and `ae` are false for NaN
+            match op {
+                CmpOp::FLt | CmpOp::FLe => self.line(format!("ucomi{sfx} %xmm0, %xmm1")),
+                _ => self.line(format!("ucomi{sfx} %xmm1, %xmm0")),
+            }
+            match op {
+                CmpOp::FEq => {
+                    self.line("sete %al");
+                    self.line("setnp %cl");
+                    self.line("andb %cl, %al");
+                }
+                CmpOp::FNe => {
+                    self.line("setne %al");
+                    self.line("setp %cl");
+                    self.line("orb %cl, %al");
+                }
+                CmpOp::FLt | CmpOp::FGt => self.line("seta %al"),
+                _ => self.line("setae %al"),
+            }
+            self.store(Gpr::Rax, dst);
+            return;
+        }
+
+        let (ext, cc) = match op {
+            CmpOp::Eq => (Ext::Zero, "e"),
+            CmpOp::Ne => (Ext::Zero, "ne"),
+            CmpOp::SLt => (Ext::Sign, "l"),
+            CmpOp::SLe => (Ext::Sign, "le"),
+            CmpOp::SGt => (Ext::Sign, "g"),
+            CmpOp::SGe => (Ext::Sign, "ge"),
+            CmpOp::ULt => (Ext::Zero, "b"),
+            CmpOp::ULe => (Ext::Zero, "be"),
+            CmpOp::UGt => (Ext::Zero, "a"),
+            CmpOp::UGe => (Ext::Zero, "ae"),
+            _ => unreachable!("float comparison of integers"),
+        };
+        self.load(a, Gpr::Rax, ext);
+        self.load(b, Gpr::Rcx, ext);
+        self.line("cmpq %rcx, %rax");
+        self.line(format!("set{cc} %al"));
+        self.store(Gpr::Rax, dst);
+    }
+
+    fn call(&mut self, dst: Option<Reg>, callee: &Operand, args: &[Operand]) {
+        let tys: Vec<_> = args.iter().map(|arg| self.ty(arg)).collect();
+        let (locs, stack) = classify(&tys);
+        // the stack has to stay 16 byte aligned at the call
+        let pad = stack % 2 == 1;
+        if pad {
+            self.line("subq $8, %rsp");
+        }
+        for (arg, loc) in args.iter().zip(&locs).rev() {
+            if let ArgLoc::Stack(_) = loc {
+                self.load(arg, Gpr::Rax, Ext::Zero);
+                self.line("pushq %rax");
+            }
+        }
+        if !matches!(callee, Operand::Global(_)) {
+            self.load(callee, Gpr::R11, Ext::Zero);
+        }
+        let mut floats = 0;
+        let mut moves = Vec::new();
+        for ((arg, loc), ty) in args.iter().zip(&locs).zip(&tys) {
+            let dst = match loc {
+                ArgLoc::Gpr(gpr) => Loc::Gpr(*gpr),
+                ArgLoc::Xmm(xmm) => {
+                    floats += 1;
+                    Loc::Xmm(*xmm)
+                }
+                ArgLoc::Stack(_) => continue,
+            };
+            moves.push((Source::Operand(arg.clone()), dst, *ty));
+        }
+        self.moves(moves);
+        // variadic functions want the number of vector registers used
+        self.line(format!("movl ${floats}, %eax"));
+        match callee {
+            Operand::Global(name) => self.line(format!("call {name}")),
+            _ => self.line("call *%r11"),
+        }
+        let popped = 8 * (stack + pad as usize);
+        if popped != 0 {
+            self.line(format!("addq ${popped}, %rsp"));
+        }
+        if let Some(dst) = dst {
+            let ty = self.function.reg_ty(dst);
+            if ty.is_float() {
+                self.xmm(ty, 0, false);
+            }
+            self.store(Gpr::Rax, dst);
+        }
+    }
+
+    /// Passes the arguments of a jump to the parameters of its target and
+    /// goes there unless it's `next`, the block right after
+    fn jump(&mut self, target: &Target, next: Option<usize>) {
+        let params = &self.function.blocks[target.block.0].params;
+        let moves = target
+            .args
+            .iter()
+            .zip(params)
+            .map(|(arg, param)| {
+                let ty = self.function.reg_ty(*param);
+                (Source::Operand(arg.clone()), self.loc(*param), ty)
+            })
+            .collect();
+        self.moves(moves);
+        if Some(target.block.0) != next {
+            self.line(format!("jmp {}", self.block_label(target.block.0)));
+        }
+    }
+
+    fn terminator(&mut self, block: usize, term: &Terminator) {
+        match term {
+            Terminator::Jump(target) => self.jump(target, Some(block + 1)),
+            Terminator::Branch(cond, a, b) => {
+                let other = self.label();
+                self.load(cond, Gpr::Rax, Ext::Zero);
+                self.line("testb %al, %al");
+                self.line(format!("jz {other}"));
+                self.jump(a, None);
+                let _ = writeln!(self.out, "{other}:");
+                self.jump(b, Some(block + 1));
+            }
+            Terminator::Return(value) => {
+                if let Some(value) = value {
+                    let ty = self.ty(value);
+                    self.load(value, Gpr::Rax, Ext::Zero);
+                    if ty.is_float() {
+                        self.xmm(ty, 0, true);
+                    }
+                }
+                for (gpr, offset) in self.saved.clone() {
+                    self.line(format!("movq -{offset}(%rbp), {}", gpr.name(8)));
+                }
+                self.line("leave");
+                self.line("ret");
+            }
+            Terminator::Unreachable => self.line("ud2"),
+        }
+    }
+}
+
+/// Assembles and links a program into an executable. When the program uses
+/// anything defined outside of it, linking goes through the system C
+/// compiler, `$CC` or else `cc`, which knows where the dynamic linker, libc
+/// and libm are
+pub fn build(module: &Module, output: &Path) -> Result<(), String> {
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+    let id = BUILDS.fetch_add(1, Ordering::Relaxed);
+    let object = std::env::temp_dir().join(format!("bc-{}-{id}.o", std::process::id()));
+    let result = write_object(module, &object).and_then(|()| {
+        if !links_libc(module) {
+            return run(Command::new("ld").arg("-o").arg(output).arg(&object));
+        }
+        let cc = std::env::var_os("CC").unwrap_or("cc".into());
+        run(Command::new(cc)
+            // `_start` stays ours, the code isn't position independent
+            .args(["-nostartfiles", "-no-pie", "-o"])
+            .arg(output)
+            .arg(&object)
+            .arg("-lm"))
+    });
+    let _ = std::fs::remove_file(&object);
+    result
+}
+
+/// Writes a program as an ELF relocatable object file
+pub fn write_object(module: &Module, object: &Path) -> Result<(), String> {
+    let bytes = asm::assemble(&emit(module))?.write();
+    std::fs::write(object, bytes)
+        .map_err(|err| format!("could not write {}: {err}", object.display()))
+}
+
+#[cfg(test)]
+pub(crate) const PROGRAM: &str = r#"
+    struct Point { i32 x, i64 y, }
+
+    static Point ORIGIN = Point { x = 0, y = 0 };
+    static &str GREETING = "hello\n";
+    static u8 COUNTER = 0;
+
+    extern "C" fn write(i32 fd, *u8 data, usize len) isize;
+    extern "C" fn strlen(*u8 str) usize;
+
+    impl Point {
+        fn scaled(&Self self, i64 by) i64 { return self.y * by; }
+    }
+
+    fn many(i32 a, i32 b, i32 c, i32 d, i32 e, i32 f, i32 g, f64 h, i32 i) i32 {
+        if (h != 0.5) { return 0; }
+        return a - b + c - d + e - f + g * i;
+    }
+
+    fn pressure(i64 a, f64 x) i64 {
+        i64 b = a + 1i64;
+        i64 c = a * 3i64;
+        i64 d = b - c;
+        i64 e = a ^ 5i64;
+        i64 f = c + d;
+        i64 g = e * f;
+        mut i64 h = 0i64;
+        if (fib(5u32) == 5u32) { h = 1i64; }
+        f64 y = x * 2.0;
+        f64 z = y + x;
+        i64 i = g - h;
+        i64 j = a + b + c + d + e + f + g + h + i;
+        if (z != 4.5) { return 0i64; }
+        return j + b + c + d + e + f + g + h + i;
+    }
+
+    fn fib(u32 n) u32 {
+        if (n < 2u32) { return n; }
+        return fib(n - 1u32) + fib(n - 2u32);
+    }
+
+    fn main() i32 {
+        write(1, GREETING, strlen(GREETING));
+        mut Point p = Point { x = 3, y = -4 };
+        p.x += 10;
+        if (p.x != 13 || p.scaled(2i64) != -8i64 || ORIGIN.y != 0i64) { return 1; }
+        mut i32 seven = -7;
+        mut u32 big = 4000000000u32;
+        if (seven / 2 != -3 || seven % 2 != -1 || big / 3u32 != 1333333333u32) { return 2; }
+        mut f64 rest = 7.5;
+        rest %= 2.0;
+        mut f32 quarter = 1.0;
+        quarter /= 4.0;
+        if (rest != 1.5 || -rest > 0.0 || quarter != 0.25) { return 3; }
+        mut i32 shift = 33;
+        if (1 << shift != 2 || seven >> 1 != -4) { return 4; }
+        COUNTER += 200u8;
+        COUNTER += 100u8;
+        if (COUNTER != 44u8) { return 5; }
+        if (fib(10u32) != 55u32 || many(1, 2, 3, 4, 5, 6, 7, 0.5, 8) != 53) { return 6; }
+        if (pressure(2i64, 1.5) != 118i64) { return 7; }
+        return 8;
+    }
+"#;
+
+#[test]
+fn emit_functions() {
+    let mut module = crate::ir::lower::lower_source(
+        r#"
+        fn sum(u32 n) u32 {
+            mut u32 total = 0;
+            for (i in 0..n) { total += i; }
+            return total;
+        }
+        fn half(f64 value) f64 { return value / 2.0; }
+        "#,
+    );
+    crate::ir::opt::optimize(&mut module, crate::ir::opt::OptLevel::O1);
+    crate::ir::assert_snapshot("x86_64_functions.s", &emit(&module));
+}
+
+#[test]
+fn build_and_run() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        // no linker to check the output with
+        return;
+    }
+    for level in [crate::ir::opt::OptLevel::O0, crate::ir::opt::OptLevel::O2] {
+        let mut module = crate::ir::lower::lower_source(PROGRAM);
+        crate::ir::opt::optimize(&mut module, level);
+        let binary = std::env::temp_dir().join(format!("bc_x86_64_{}", std::process::id()));
+        build(&module, &binary).unwrap();
+        let output = Command::new(&binary).output().unwrap();
+        std::fs::remove_file(&binary).unwrap();
+        assert_eq!(output.stdout, b"hello\n");
+        assert_eq!(output.status.code(), Some(8), "at {level:?}");
+    }
+}
+
+#[test]
+fn flush_stdio_on_exit() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        return;
+    }
+    let module = crate::ir::lower::lower_source(
+        r#"
+        extern "C" fn putchar(i32 c) i32;
+        fn main() i32 {
+            putchar(104);
+            putchar(105);
+            putchar(10);
+            return 3;
+        }
+        "#,
+    );
+    let binary = std::env::temp_dir().join(format!("bc_x86_64_stdio_{}", std::process::id()));
+    build(&module, &binary).unwrap();
+    // a pipe makes stdout fully buffered, so nothing shows up unless exiting
+    // flushes it
+    let output = Command::new(&binary).output().unwrap();
+    std::fs::remove_file(&binary).unwrap();
+    assert_eq!(output.stdout, b"hi\n");
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn link_libm() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        return;
+    }
+    let module = crate::ir::lower::lower_source(
+        r#"
+        extern "C" fn sqrt(f64 value) f64;
+        fn main() i32 {
+            if (sqrt(16.0) == 4.0) { return 5; }
+            return 1;
+        }
+        "#,
+    );
+    let binary = std::env::temp_dir().join(format!("bc_x86_64_libm_{}", std::process::id()));
+    build(&module, &binary).unwrap();
+    let status = Command::new(&binary).status().unwrap();
+    std::fs::remove_file(&binary).unwrap();
+    assert_eq!(status.code(), Some(5));
+}
+
+#[test]
+fn link_with_c() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        return;
+    }
+    let module = crate::ir::lower::lower_source(
+        r#"
+        pub static i64 TOTAL = 40;
+        static i64 HIDDEN = 1;
+
+        fn step(i64 value) i64 { return value + HIDDEN; }
+        pub fn add(i64 a, i64 b) i64 { return step(a) + b - HIDDEN; }
+        "#,
+    );
+    let dir = std::env::temp_dir().join(format!("bc_link_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let object = dir.join("lib.o");
+    write_object(&module, &object).unwrap();
+    let c = dir.join("main.c");
+    std::fs::write(
+        &c,
+        "long add(long, long);\nextern long TOTAL;\nint main(void) { return add(TOTAL, 2); }\n",
+    )
+    .unwrap();
+    let binary = dir.join("main");
+    run(Command::new("cc")
+        .arg("-o")
+        .arg(&binary)
+        .arg(&c)
+        .arg(&object))
+    .unwrap();
+    let status = Command::new(&binary).status().unwrap();
+    // private functions stay out of the way of C's
+    std::fs::write(
+        &c,
+        "long step(long v) { return v; }\nint main(void) { return step(3); }\n",
+    )
+    .unwrap();
+    let hidden = run(Command::new("cc")
+        .arg("-o")
+        .arg(&binary)
+        .arg(&c)
+        .arg(&object));
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(status.code(), Some(42));
+    hidden.unwrap();
+}
@@ -0,0 +1,305 @@
+//! Linear scan register allocation.
+//!
+//! Every IR register gets a single live interval covering everything from
+//! its first to its last appearance in block order, so it lives in the same
+//! place for its whole life. Values live across a call only get callee saved
+//! registers, the scratch registers code generation uses are never handed
+//! out and neither are the ones a copy uses to values live during it.
+
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+};
+
+use crate::ir::{Function, Inst, Operand, Reg};
+
+use super::Gpr;
+
+/// Registers nothing is preserved in across calls, tried first
+const CALLER_SAVED: [Gpr; 5] = [Gpr::R10, Gpr::R8, Gpr::R9, Gpr::Rsi, Gpr::Rdi];
+
+/// Registers copies of memory go through
+const COPY: [Gpr; 2] = [Gpr::Rsi, Gpr::Rdi];
+
+const CALLEE_SAVED: [Gpr; 5] = [Gpr::Rbx, Gpr::R12, Gpr::R13, Gpr::R14, Gpr::R15];
+
+/// `xmm0` to `xmm7` pass arguments and every `xmm` register is caller saved
+const XMMS: [usize; 8] = [8, 9, 10, 11, 12, 13, 14, 15];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    Gpr(Gpr),
+    Xmm(usize),
+    /// An 8 byte stack slot shared with registers that are never live at
+    /// the same time
+    Spill(usize),
+}
+
+pub struct Allocation {
+    /// Where every register lives, `None` for registers that don't appear
+    pub locations: Vec<Option<Location>>,
+    pub spills: usize,
+    /// The callee saved registers the function has to restore
+    pub saved: Vec<Gpr>,
+}
+
+/// The first and last position a register is live at
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    reg: Reg,
+    start: usize,
+    end: usize,
+}
+
+impl Interval {
+    fn overlaps(&self, other: &Interval) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+}
+
+/// Numbers the program points of a function and finds the intervals along
+/// with the positions of calls and copies
+fn intervals(function: &Function) -> (Vec<Interval>, Vec<usize>, Vec<usize>) {
+    let blocks = &function.blocks;
+    let mut starts = Vec::new();
+    let mut ends = Vec::new();
+    let mut pos = 0;
+    for block in blocks {
+        starts.push(pos);
+        pos += block.insts.len() + 1;
+        ends.push(pos);
+        pos += 1;
+    }
+
+    let mut succs = vec![Vec::new(); blocks.len()];
+    let mut uses = vec![HashSet::new(); blocks.len()];
+    let mut defs = vec![HashSet::new(); blocks.len()];
+    for (id, block) in blocks.iter().enumerate() {
+        defs[id].extend(block.params.iter().copied());
+        let operands = block.insts.iter().flat_map(|inst| inst.operands());
+        for operand in operands.chain(block.term.operands()) {
+            if let Operand::Reg(reg) = operand {
+                uses[id].insert(*reg);
+            }
+        }
+        defs[id].extend(block.insts.iter().filter_map(Inst::def));
+        succs[id] = block.term.targets().iter().map(|t| t.block.0).collect();
+    }
+    for id in 0..blocks.len() {
+        let defined = defs[id].clone();
+        uses[id].retain(|reg| !defined.contains(reg));
+    }
+
+    let mut live_in: Vec<HashSet<Reg>> = vec![HashSet::new(); blocks.len()];
+    let mut live_out: Vec<HashSet<Reg>> = vec![HashSet::new(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in (0..blocks.len()).rev() {
+            let out: HashSet<Reg> = succs[id]
+                .iter()
+                .flat_map(|succ| live_in[*succ].iter().copied())
+                .collect();
+            let mut live: HashSet<Reg> = out.difference(&defs[id]).copied().collect();
+            live.extend(uses[id].iter().copied());
+            if live != live_in[id] || out != live_out[id] {
+                live_in[id] = live;
+                live_out[id] = out;
+                changed = true;
+            }
+        }
+    }
+
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; function.regs.len()];
+    let mut touch = |reg: Reg, pos: usize| {
+        let range = ranges[reg.0].get_or_insert((pos, pos));
+        range.0 = range.0.min(pos);
+        range.1 = range.1.max(pos);
+    };
+    let mut calls = Vec::new();
+    let mut copies = Vec::new();
+    for (id, block) in blocks.iter().enumerate() {
+        for param in &block.params {
+            touch(*param, starts[id]);
+        }
+        for (i, inst) in block.insts.iter().enumerate() {
+            let pos = starts[id] + 1 + i;
+            match inst {
+                Inst::Call(..) => calls.push(pos),
+                Inst::Copy(..) => copies.push(pos),
+                _ => {}
+            }
+            inst.def().into_iter().for_each(|reg| touch(reg, pos));
+            for operand in inst.operands() {
+                if let Operand::Reg(reg) = operand {
+                    touch(*reg, pos);
+                }
+            }
+        }
+        for operand in block.term.operands() {
+            if let Operand::Reg(reg) = operand {
+                touch(*reg, ends[id]);
+            }
+        }
+        // jumps write the parameters of where they go
+        for target in block.term.targets() {
+            for param in &blocks[target.block.0].params {
+                touch(*param, ends[id]);
+            }
+        }
+        live_in[id].iter().for_each(|reg| touch(*reg, starts[id]));
+        live_out[id].iter().for_each(|reg| touch(*reg, ends[id]));
+    }
+
+    let mut intervals: Vec<_> = ranges
+        .into_iter()
+        .enumerate()
+        .filter_map(|(reg, range)| {
+            let (start, end) = range?;
+            Some(Interval {
+                reg: Reg(reg),
+                start,
+                end,
+            })
+        })
+        .collect();
+    intervals.sort_by_key(|interval| (interval.start, interval.reg));
+    (intervals, calls, copies)
+}
+
+pub fn allocate(function: &Function) -> Allocation {
+    let (intervals, calls, copies) = intervals(function);
+    let mut locations = vec![None; function.regs.len()];
+    let mut active: Vec<Interval> = Vec::new();
+    let mut spills: Vec<Vec<Interval>> = Vec::new();
+    let mut saved = Vec::new();
+
+    let mut spill = |locations: &mut Vec<Option<Location>>, interval: Interval| {
+        let slot = spills
+            .iter()
+            .position(|slot| slot.iter().all(|other| !other.overlaps(&interval)))
+            .unwrap_or_else(|| {
+                spills.push(Vec::new());
+                spills.len() - 1
+            });
+        spills[slot].push(interval);
+        locations[interval.reg.0] = Some(Location::Spill(slot));
+    };
+
+    for interval in intervals {
+        active.retain(|other| other.end >= interval.start);
+        let float = function.reg_ty(interval.reg).is_float();
+        let crosses = calls
+            .iter()
+            .any(|call| interval.start < *call && *call < interval.end);
+        let copied = copies
+            .iter()
+            .any(|copy| (interval.start..=interval.end).contains(copy));
+        let candidates: Vec<Location> = match (float, crosses) {
+            (true, true) => Vec::new(),
+            (true, false) => XMMS.iter().map(|xmm| Location::Xmm(*xmm)).collect(),
+            (false, true) => CALLEE_SAVED.iter().map(|gpr| Location::Gpr(*gpr)).collect(),
+            (false, false) => CALLER_SAVED
+                .iter()
+                .filter(|gpr| !copied || !COPY.contains(gpr))
+                .chain(&CALLEE_SAVED)
+                .map(|gpr| Location::Gpr(*gpr))
+                .collect(),
+        };
+        let taken: HashSet<Location> = active
+            .iter()
+            .filter_map(|other| locations[other.reg.0])
+            .collect();
+        let free = candidates.iter().find(|loc| !taken.contains(loc)).copied();
+        let location = match free {
+            Some(location) => Some(location),
+            None => {
+                // the register living the longest makes room for this one
+                let victim = active
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, other)| {
+                        candidates.contains(&locations[other.reg.0].unwrap())
+                            && other.end > interval.end
+                    })
+                    .max_by_key(|(_, other)| other.end)
+                    .map(|(i, _)| i);
+                victim.map(|i| {
+                    let victim = active.remove(i);
+                    let location = locations[victim.reg.0].unwrap();
+                    spill(&mut locations, victim);
+                    location
+                })
+            }
+        };
+        match location {
+            Some(location) => {
+                if let Location::Gpr(gpr) = location {
+                    if CALLEE_SAVED.contains(&gpr) && !saved.contains(&gpr) {
+                        saved.push(gpr);
+                    }
+                }
+                locations[interval.reg.0] = Some(location);
+                active.push(interval);
+            }
+            None => spill(&mut locations, interval),
+        }
+    }
+    saved.sort_by_key(|gpr| CALLEE_SAVED.iter().position(|other| other == gpr));
+    Allocation {
+        locations,
+        spills: spills.len(),
+        saved,
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Gpr(gpr) => write!(f, "{}", gpr.name(8)),
+            Location::Xmm(xmm) => write!(f, "%xmm{xmm}"),
+            Location::Spill(slot) => write!(f, "spill{slot}"),
+        }
+    }
+}
+
+impl Display for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (reg, location) in self.locations.iter().enumerate() {
+            if let Some(location) = location {
+                writeln!(f, "    %{reg}: {location}")?;
+            }
+        }
+        if !self.saved.is_empty() {
+            let saved: Vec<_> = self.saved.iter().map(|gpr| gpr.name(8)).collect();
+            writeln!(f, "    saves {}", saved.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn allocate_pressure() {
+    let mut module = crate::ir::lower::lower_source(
+        r#"
+        extern "C" fn next(i64 value) i64;
+
+        fn spread(i64 a, f64 x) i64 {
+            i64 b = next(a);
+            i64 c = a * 3i64;
+            i64 d = b - c;
+            i64 e = next(d);
+            i64 f = c + d;
+            i64 g = e * f;
+            i64 h = a ^ g;
+            i64 i = next(h);
+            f64 y = x * 2.0;
+            i64 j = a + b + c + d + e + f + g + h + i;
+            if (y > 1.0) { return j; }
+            return j + b + c + d + e + f + g + h + i;
+        }
+        "#,
+    );
+    crate::ir::opt::optimize(&mut module, crate::ir::opt::OptLevel::O1);
+    crate::ir::assert_snapshot("x86_64_regalloc.txt", &super::allocations(&module));
+}
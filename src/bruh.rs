@@ -1,7 +1,7 @@
+#![allow(dead_code, clippy::single_match)]
+
 use std::{
     collections::VecDeque,
-    default,
-    marker::PhantomData,
     mem::{discriminant, Discriminant},
 };
 
@@ -171,7 +171,7 @@ impl Grammar {
                         cursor_event = CursorEvent::Prev;
                     }
                     b'1'..=b'9' => {
-                        *c = *c - 1;
+                        *c -= 1;
                         self.state = State::ReturnToNum;
                     }
                     _ => return false,
@@ -393,7 +393,7 @@ impl<V, O: Operator<V>> Run<V, O> {
     }
 }
 
-lalrpop_mod!(calc);
+lalrpop_mod!(#[allow(clippy::empty_line_after_outer_attr)] calc);
 
 #[test]
 #[allow(enum_intrinsics_non_enums)]
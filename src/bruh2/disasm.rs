@@ -0,0 +1,350 @@
+//! Ways of looking at a [`Run`]: a listing of its operations, and the
+//! expression trees they build on the stack.
+//!
+//! Trees are put back together from the operations in order, so they only
+//! follow straight line code. Values still on the stack at a jump, or where
+//! one lands, become trees of their own, and operands that were pushed
+//! before then show up as `_`.
+
+use std::{any::TypeId, collections::HashSet, fmt::Debug};
+
+use super::{format::TAGS, Local, MachineOperation, Operator, Run};
+
+static NAMES: [&str; 11] = [
+    "bool", "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64", "f32", "f64",
+];
+
+/// The name of a type, or its id when it isn't one a file can hold
+pub fn type_name(ty: TypeId) -> String {
+    match TAGS.iter().position(|other| *other == ty) {
+        Some(tag) => NAMES[tag].to_string(),
+        None => format!("{ty:?}"),
+    }
+}
+
+fn types(types: &[TypeId]) -> String {
+    let names: Vec<_> = types.iter().map(|ty| type_name(*ty)).collect();
+    names.join(" ")
+}
+
+fn local(local: &Local) -> String {
+    format!("l{}", local.offset())
+}
+
+/// The value of a literal, when it's of a type there's a name for
+fn literal<O: Operator>(run: &Run<O>, literal: usize) -> String {
+    let Some((ty, bytes)) = run.literals.get(literal) else {
+        return format!("<missing literal {literal}>");
+    };
+    let Some(tag) = TAGS.iter().position(|other| other == ty) else {
+        return format!("<{} bytes of {ty:?}>", bytes.len());
+    };
+    // none of these types have padding, all their bytes are set
+    let bytes: Vec<u8> = run.values.inner[bytes.clone()]
+        .iter()
+        .map(|byte| unsafe { byte.assume_init() })
+        .collect();
+    match tag {
+        0 => (bytes[0] != 0).to_string(),
+        1 => bytes[0].to_string(),
+        2 => u16::from_ne_bytes(bytes[..].try_into().unwrap()).to_string(),
+        3 => u32::from_ne_bytes(bytes[..].try_into().unwrap()).to_string(),
+        4 => u64::from_ne_bytes(bytes[..].try_into().unwrap()).to_string(),
+        5 => i8::from_ne_bytes(bytes[..].try_into().unwrap()).to_string(),
+        6 => i16::from_ne_bytes(bytes[..].try_into().unwrap()).to_string(),
+        7 => i32::from_ne_bytes(bytes[..].try_into().unwrap()).to_string(),
+        8 => i64::from_ne_bytes(bytes[..].try_into().unwrap()).to_string(),
+        9 => format!("{:?}", f32::from_ne_bytes(bytes[..].try_into().unwrap())),
+        _ => format!("{:?}", f64::from_ne_bytes(bytes[..].try_into().unwrap())),
+    }
+}
+
+/// Lists every operation with what it takes and gives, and where each
+/// function starts
+pub fn disassemble<O: Operator + Debug>(run: &Run<O>) -> String {
+    let mut out = String::new();
+    if run.frame_size > 0 {
+        out += &format!("; {} bytes of locals\n", run.frame_size);
+    }
+    for (at, op) in run.operator.iter().enumerate() {
+        for (index, function) in run.functions.iter().enumerate() {
+            if function.entry == at {
+                let params: Vec<_> = function.params.iter().map(Local::ty).collect();
+                out += &format!(
+                    "fn {index}({}) -> ({}), {} bytes of locals:\n",
+                    types(&params),
+                    types(&function.results),
+                    function.frame_size
+                );
+            }
+        }
+        let line = match op {
+            MachineOperation::Lit(index) => {
+                let ty = run
+                    .literals
+                    .get(*index)
+                    .map_or("?".to_string(), |(ty, _)| type_name(*ty));
+                format!("lit {} -> {ty}", literal(run, *index))
+            }
+            MachineOperation::Op(op) => {
+                format!("{op:?} {} -> {}", types(op.input()), types(op.output()))
+            }
+            MachineOperation::Local(l) => format!("local {} -> {}", local(l), type_name(l.ty())),
+            MachineOperation::SetLocal(l) => {
+                format!("set_local {} {} ->", local(l), type_name(l.ty()))
+            }
+            MachineOperation::Jump(to) => format!("jump {to}"),
+            MachineOperation::JumpUnless(to) => format!("jump_unless {to} bool ->"),
+            MachineOperation::Call(index) => match run.functions.get(*index) {
+                Some(function) => {
+                    let params: Vec<_> = function.params.iter().map(Local::ty).collect();
+                    let results = types(&function.results);
+                    format!("call {index} {} -> {results}", types(&params))
+                }
+                None => format!("call {index}"),
+            },
+            MachineOperation::CallIndirect { params, results } => {
+                let (params, results) = (types(params), types(results));
+                format!("call_indirect {params} u64 -> {results}")
+            }
+            MachineOperation::Return => "return".to_string(),
+        };
+        out += &format!("{at:>4}  {}\n", line.trim_end());
+    }
+    out
+}
+
+/// What a value on the stack was made from
+#[derive(Debug)]
+pub enum Tree<'a, O> {
+    /// A literal, local or value from before the tree started
+    Value(String),
+    Op(&'a O, Vec<Tree<'a, O>>),
+    /// A call, to a function by its index or `call_indirect`
+    Call(String, Vec<Tree<'a, O>>),
+    Set(String, Box<Tree<'a, O>>),
+    /// A jump or return, with what it takes off the stack
+    Control(String, Vec<Tree<'a, O>>),
+}
+
+/// Takes operands off the stack, making up the ones pushed before it started
+fn pop<'a, O>(stack: &mut Vec<(usize, Tree<'a, O>)>, count: usize) -> Vec<Tree<'a, O>> {
+    let mut values: Vec<_> = (0..count)
+        .map(|_| match stack.pop() {
+            Some((_, tree)) => tree,
+            None => Tree::Value("_".to_string()),
+        })
+        .collect();
+    values.reverse();
+    values
+}
+
+/// Puts the operations back together into trees, each with the index of the
+/// operation it ends at
+pub fn trees<O: Operator>(run: &Run<O>) -> Vec<(usize, Tree<'_, O>)> {
+    let targets: HashSet<usize> = run
+        .operator
+        .iter()
+        .filter_map(|op| match op {
+            MachineOperation::Jump(to) | MachineOperation::JumpUnless(to) => Some(*to),
+            _ => None,
+        })
+        .chain(run.functions.iter().map(|function| function.entry))
+        .collect();
+    let mut roots = Vec::new();
+    let mut stack: Vec<(usize, Tree<O>)> = Vec::new();
+    for (at, op) in run.operator.iter().enumerate() {
+        if targets.contains(&at) {
+            roots.append(&mut stack);
+        }
+        let tree = match op {
+            MachineOperation::Lit(index) => Tree::Value(literal(run, *index)),
+            MachineOperation::Op(op) => {
+                let args = pop(&mut stack, op.input().len());
+                if op.output().is_empty() {
+                    roots.push((at, Tree::Op(op, args)));
+                    continue;
+                }
+                Tree::Op(op, args)
+            }
+            MachineOperation::Local(l) => Tree::Value(local(l)),
+            MachineOperation::SetLocal(l) => {
+                let value = pop(&mut stack, 1).remove(0);
+                roots.push((at, Tree::Set(local(l), Box::new(value))));
+                continue;
+            }
+            MachineOperation::Call(index) => {
+                let (params, results) = match run.functions.get(*index) {
+                    Some(function) => (function.params.len(), function.results.len()),
+                    None => (0, 0),
+                };
+                let call = Tree::Call(format!("f{index}"), pop(&mut stack, params));
+                if results == 0 {
+                    roots.push((at, call));
+                    continue;
+                }
+                call
+            }
+            MachineOperation::CallIndirect { params, results } => {
+                let call = Tree::Call(
+                    "call_indirect".to_string(),
+                    pop(&mut stack, params.len() + 1),
+                );
+                if results.is_empty() {
+                    roots.push((at, call));
+                    continue;
+                }
+                call
+            }
+            MachineOperation::Jump(to) => {
+                roots.append(&mut stack);
+                roots.push((at, Tree::Control(format!("jump {to}"), Vec::new())));
+                continue;
+            }
+            MachineOperation::JumpUnless(to) => {
+                let condition = pop(&mut stack, 1);
+                roots.append(&mut stack);
+                roots.push((at, Tree::Control(format!("jump_unless {to}"), condition)));
+                continue;
+            }
+            MachineOperation::Return => {
+                let values = stack.drain(..).map(|(_, tree)| tree).collect();
+                roots.push((at, Tree::Control("return".to_string(), values)));
+                continue;
+            }
+        };
+        stack.push((at, tree));
+    }
+    roots.append(&mut stack);
+    roots
+}
+
+impl<O: Debug> Tree<'_, O> {
+    pub fn sexpr(&self) -> String {
+        let list = |head: String, args: &[Tree<O>]| {
+            let mut out = format!("({head}");
+            for arg in args {
+                out += " ";
+                out += &arg.sexpr();
+            }
+            out + ")"
+        };
+        match self {
+            Tree::Value(value) => value.clone(),
+            Tree::Op(op, args) => list(format!("{op:?}"), args),
+            Tree::Call(name, args) | Tree::Control(name, args) => list(name.clone(), args),
+            Tree::Set(name, value) => format!("(set {name} {})", value.sexpr()),
+        }
+    }
+
+    /// Writes binary operators `symbol` has a symbol for between their
+    /// operands, in parentheses when they're nested
+    pub fn infix(&self, symbol: impl Fn(&O) -> Option<&'static str> + Copy) -> String {
+        let call = |name: String, args: &[Tree<O>]| {
+            let args: Vec<_> = args.iter().map(|arg| arg.infix(symbol)).collect();
+            format!("{name}({})", args.join(", "))
+        };
+        let operand = |tree: &Tree<O>| match tree {
+            Tree::Op(op, args) if args.len() == 2 && symbol(op).is_some() => {
+                format!("({})", tree.infix(symbol))
+            }
+            _ => tree.infix(symbol),
+        };
+        match self {
+            Tree::Value(value) => value.clone(),
+            Tree::Op(op, args) => match (symbol(op), &args[..]) {
+                (Some(symbol), [left, right]) => {
+                    format!("{} {symbol} {}", operand(left), operand(right))
+                }
+                _ => call(format!("{op:?}"), args),
+            },
+            Tree::Call(name, args) => call(name.clone(), args),
+            Tree::Set(name, value) => format!("{name} = {}", value.infix(symbol)),
+            Tree::Control(name, args) => {
+                let args: Vec<_> = args.iter().map(|arg| arg.infix(symbol)).collect();
+                match args.is_empty() {
+                    true => name.clone(),
+                    false => format!("{name} {}", args.join(", ")),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn parse(src: &str) -> Run<super::BasicOperator> {
+    let mut calc = super::Calc::new();
+    super::calc2::FinishedParser::new()
+        .parse(&mut calc, src)
+        .unwrap();
+    calc.run
+}
+
+#[test]
+fn print_trees() {
+    use super::BasicOperator;
+
+    let symbol = |op: &BasicOperator| Some(op.symbol());
+    let run = parse("(2+3*4) + 12");
+    let printed: Vec<_> = trees(&run)
+        .iter()
+        .map(|(at, tree)| (*at, tree.sexpr(), tree.infix(symbol)))
+        .collect();
+    assert_eq!(
+        printed,
+        [
+            (0, "(jump 1)".to_string(), "jump 1".to_string()),
+            (
+                7,
+                "(Add (Add 2.0 (Times 3.0 4.0)) 12.0)".to_string(),
+                "(2.0 + (3.0 * 4.0)) + 12.0".to_string()
+            ),
+        ]
+    );
+
+    let run = parse("fn fib(n) = if n < 2 { n } else { fib(n - 1) + fib(n - 2) }; fib(10)");
+    let printed: Vec<_> = trees(&run)
+        .iter()
+        .map(|(at, tree)| format!("{at}: {}", tree.infix(symbol)))
+        .collect();
+    assert_eq!(
+        printed,
+        [
+            "0: jump 17",
+            "4: jump_unless 7 l0 < 2.0",
+            "5: l0",
+            "6: jump 16",
+            "15: f0(l0 - 1.0) + f0(l0 - 2.0)",
+            "16: return",
+            "18: f0(10.0)",
+        ]
+    );
+}
+
+#[test]
+fn disassemble_calc() {
+    let run = parse("fn twice(n) = n * 2; let x = 1.5; while x < 10 { x = twice(x); } x == 12");
+    let listing = "\
+; 8 bytes of locals
+   0  jump 5
+fn 0(f64) -> (f64), 8 bytes of locals:
+   1  local l0 -> f64
+   2  lit 2.0 -> f64
+   3  Times f64 f64 -> f64
+   4  return
+   5  lit 1.5 -> f64
+   6  set_local l0 f64 ->
+   7  local l0 -> f64
+   8  lit 10.0 -> f64
+   9  Lt f64 f64 -> bool
+  10  jump_unless 15 bool ->
+  11  local l0 -> f64
+  12  call 0 f64 -> f64
+  13  set_local l0 f64 ->
+  14  jump 7
+  15  local l0 -> f64
+  16  lit 12.0 -> f64
+  17  Eq f64 f64 -> bool
+";
+    assert_eq!(disassemble(&run), listing);
+}
@@ -0,0 +1,397 @@
+//! A file format for [`Run`] programs, so they don't have to be compiled
+//! again every time they run.
+//!
+//! Everything is little endian, counts are `u32` and offsets and indices
+//! `u64`. A file starts with [`MAGIC`], the [`VERSION`] as a `u16` and the
+//! [`Encode::NAME`] of its operators, then holds the frame size of the code
+//! from the first operation, the literal pool, the functions and the
+//! operations. Types are one byte tags instead of [`TypeId`]s, which differ
+//! between builds, so only the primitive types of [`TAGS`] can be in a file.
+//!
+//! Loading checks the program again, a file that was tampered with or cut
+//! off is an error like any other.
+
+use std::any::TypeId;
+
+use super::{BasicOperator, Checked, Function, Local, MachineOperation, Operator, Run};
+
+pub const MAGIC: &[u8; 4] = b"BRUH";
+pub const VERSION: u16 = 1;
+
+/// The largest frame a file may ask for, so a broken one can't exhaust memory
+const MAX_FRAME_SIZE: usize = 1 << 24;
+
+/// The types a file can hold values of, a type's tag is its index
+pub static TAGS: [TypeId; 11] = [
+    TypeId::of::<bool>(),
+    TypeId::of::<u8>(),
+    TypeId::of::<u16>(),
+    TypeId::of::<u32>(),
+    TypeId::of::<u64>(),
+    TypeId::of::<i8>(),
+    TypeId::of::<i16>(),
+    TypeId::of::<i32>(),
+    TypeId::of::<i64>(),
+    TypeId::of::<f32>(),
+    TypeId::of::<f64>(),
+];
+
+/// Operators that can be in a file
+pub trait Encode: Operator + Sized {
+    /// Tells files of different operators apart
+    const NAME: &'static str;
+
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(input: &mut Reader) -> Result<Self, String>;
+}
+
+impl Encode for BasicOperator {
+    const NAME: &'static str = "basic";
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let index = BasicOperator::ALL.iter().position(|op| op == self).unwrap();
+        out.push(index as u8);
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        let index = input.u8()?;
+        BasicOperator::ALL
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| format!("there's no operator {index}"))
+    }
+}
+
+/// Reads the parts of a file, erring when it runs out
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .at
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.at..end))
+            .ok_or_else(|| format!("the file ends at byte {}", self.bytes.len()))?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    pub fn usize(&mut self) -> Result<usize, String> {
+        let value = u64::from_le_bytes(self.array()?);
+        usize::try_from(value).map_err(|_| format!("{value} is too big"))
+    }
+
+    fn frame_size(&mut self) -> Result<usize, String> {
+        match self.usize()? {
+            size if size > MAX_FRAME_SIZE => Err(format!("a frame of {size} bytes is too big")),
+            size => Ok(size),
+        }
+    }
+
+    /// Reads a count and then that many things
+    fn many<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        let count = self.u32()?;
+        (0..count).map(|_| read(self)).collect()
+    }
+
+    fn ty(&mut self) -> Result<TypeId, String> {
+        let tag = self.u8()?;
+        TAGS.get(tag as usize)
+            .copied()
+            .ok_or_else(|| format!("there's no type {tag}"))
+    }
+
+    fn local(&mut self) -> Result<Local, String> {
+        let tag = self.u8()?;
+        let offset = self.usize()?;
+        Ok(match tag {
+            0 => Local::new::<bool>(offset),
+            1 => Local::new::<u8>(offset),
+            2 => Local::new::<u16>(offset),
+            3 => Local::new::<u32>(offset),
+            4 => Local::new::<u64>(offset),
+            5 => Local::new::<i8>(offset),
+            6 => Local::new::<i16>(offset),
+            7 => Local::new::<i32>(offset),
+            8 => Local::new::<i64>(offset),
+            9 => Local::new::<f32>(offset),
+            10 => Local::new::<f64>(offset),
+            _ => return Err(format!("there's no type {tag}")),
+        })
+    }
+
+    /// Reads a literal into the pool of a program
+    fn literal<O: Operator>(&mut self, run: &mut Run<O>) -> Result<(), String> {
+        let tag = self.u8()?;
+        match tag {
+            0 => match self.u8()? {
+                byte @ (0 | 1) => run.literal(byte == 1),
+                byte => return Err(format!("{byte} isn't a boolean")),
+            },
+            1 => run.literal(self.u8()?),
+            2 => run.literal(u16::from_le_bytes(self.array()?)),
+            3 => run.literal(u32::from_le_bytes(self.array()?)),
+            4 => run.literal(u64::from_le_bytes(self.array()?)),
+            5 => run.literal(i8::from_le_bytes(self.array()?)),
+            6 => run.literal(i16::from_le_bytes(self.array()?)),
+            7 => run.literal(i32::from_le_bytes(self.array()?)),
+            8 => run.literal(i64::from_le_bytes(self.array()?)),
+            9 => run.literal(f32::from_le_bytes(self.array()?)),
+            10 => run.literal(f64::from_le_bytes(self.array()?)),
+            _ => return Err(format!("there's no type {tag}")),
+        };
+        Ok(())
+    }
+}
+
+fn tag(ty: TypeId) -> Result<u8, String> {
+    match TAGS.iter().position(|other| *other == ty) {
+        Some(tag) => Ok(tag as u8),
+        None => Err(format!("values of {ty:?} can't be in a file")),
+    }
+}
+
+fn write_usize(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u64).to_le_bytes());
+}
+
+fn write_count(out: &mut Vec<u8>, count: usize) {
+    out.extend_from_slice(&(count as u32).to_le_bytes());
+}
+
+fn write_types(out: &mut Vec<u8>, types: &[TypeId]) -> Result<(), String> {
+    write_count(out, types.len());
+    for ty in types {
+        out.push(tag(*ty)?);
+    }
+    Ok(())
+}
+
+fn write_local(out: &mut Vec<u8>, local: &Local) -> Result<(), String> {
+    out.push(tag(local.ty())?);
+    write_usize(out, local.offset());
+    Ok(())
+}
+
+/// Writes a program to bytes, which fails if it has values of types that
+/// aren't in [`TAGS`]
+pub fn write<O: Encode>(run: &Run<O>) -> Result<Vec<u8>, String> {
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    write_count(&mut out, O::NAME.len());
+    out.extend_from_slice(O::NAME.as_bytes());
+    write_usize(&mut out, run.frame_size);
+
+    write_count(&mut out, run.literals.len());
+    for (ty, bytes) in &run.literals {
+        let tag = tag(*ty)?;
+        out.push(tag);
+        // the pool holds values the way they are in memory, and none of the
+        // types in a file have padding
+        let bytes = &run.values.inner[bytes.clone()];
+        let bytes: Vec<u8> = bytes
+            .iter()
+            .map(|byte| unsafe { byte.assume_init() })
+            .collect();
+        match cfg!(target_endian = "little") {
+            true => out.extend_from_slice(&bytes),
+            false => out.extend(bytes.iter().rev()),
+        }
+    }
+
+    write_count(&mut out, run.functions.len());
+    for function in &run.functions {
+        write_usize(&mut out, function.entry);
+        write_count(&mut out, function.params.len());
+        for param in &function.params {
+            write_local(&mut out, param)?;
+        }
+        write_types(&mut out, &function.results)?;
+        write_usize(&mut out, function.frame_size);
+    }
+
+    write_count(&mut out, run.operator.len());
+    for op in &run.operator {
+        match op {
+            MachineOperation::Lit(literal) => {
+                out.push(0);
+                write_usize(&mut out, *literal);
+            }
+            MachineOperation::Op(op) => {
+                out.push(1);
+                op.encode(&mut out);
+            }
+            MachineOperation::Local(local) => {
+                out.push(2);
+                write_local(&mut out, local)?;
+            }
+            MachineOperation::SetLocal(local) => {
+                out.push(3);
+                write_local(&mut out, local)?;
+            }
+            MachineOperation::Jump(to) => {
+                out.push(4);
+                write_usize(&mut out, *to);
+            }
+            MachineOperation::JumpUnless(to) => {
+                out.push(5);
+                write_usize(&mut out, *to);
+            }
+            MachineOperation::Call(function) => {
+                out.push(6);
+                write_usize(&mut out, *function);
+            }
+            MachineOperation::CallIndirect { params, results } => {
+                out.push(7);
+                write_types(&mut out, params)?;
+                write_types(&mut out, results)?;
+            }
+            MachineOperation::Return => out.push(8),
+        }
+    }
+    Ok(out)
+}
+
+/// Loads a program written by [`write`] and checks it can run
+pub fn read<O: Encode>(bytes: &[u8]) -> Result<Checked<O>, String> {
+    let mut input = Reader { bytes, at: 0 };
+    if input.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err("not a program file".to_string());
+    }
+    let version = u16::from_le_bytes(input.array()?);
+    if version != VERSION {
+        return Err(format!(
+            "the file is of version {version}, only version {VERSION} can be read"
+        ));
+    }
+    let len = input.u32()? as usize;
+    let name = input.bytes(len)?;
+    if name != O::NAME.as_bytes() {
+        return Err(format!(
+            "the file is for `{}` operators instead of `{}`",
+            String::from_utf8_lossy(name),
+            O::NAME
+        ));
+    }
+
+    let mut run = Run::new();
+    run.frame_size = input.frame_size()?;
+    let literals = input.u32()?;
+    for _ in 0..literals {
+        input.literal(&mut run)?;
+    }
+    run.functions = input.many(|input| {
+        Ok(Function {
+            entry: input.usize()?,
+            params: input.many(Reader::local)?,
+            results: input.many(Reader::ty)?,
+            frame_size: input.frame_size()?,
+        })
+    })?;
+    run.operator = input.many(|input| {
+        Ok(match input.u8()? {
+            0 => MachineOperation::Lit(input.usize()?),
+            1 => MachineOperation::Op(O::decode(input)?),
+            2 => MachineOperation::Local(input.local()?),
+            3 => MachineOperation::SetLocal(input.local()?),
+            4 => MachineOperation::Jump(input.usize()?),
+            5 => MachineOperation::JumpUnless(input.usize()?),
+            6 => MachineOperation::Call(input.usize()?),
+            7 => MachineOperation::CallIndirect {
+                params: input.many(Reader::ty)?,
+                results: input.many(Reader::ty)?,
+            },
+            8 => MachineOperation::Return,
+            op => return Err(format!("there's no operation {op}")),
+        })
+    })?;
+    if input.at != bytes.len() {
+        return Err(format!("the program ends at byte {}", input.at));
+    }
+    run.check()
+        .map_err(|err| format!("the program is invalid: {err}"))
+}
+
+#[cfg(test)]
+fn fib() -> Run<BasicOperator> {
+    let mut calc = super::Calc::new();
+    let src =
+        "fn fib(n) = if n < 2 { n } else { fib(n - 1) + fib(n - 2) }; let x = 10; fib(x) > 50";
+    super::calc2::FinishedParser::new()
+        .parse(&mut calc, src)
+        .unwrap();
+    calc.run
+}
+
+#[test]
+fn round_trip() {
+    let bytes = write(&fib()).unwrap();
+    let mut program = read::<BasicOperator>(&bytes).unwrap();
+    assert!(matches!(
+        program.run(&mut ()).unwrap()[..],
+        [super::Type::Boolean(true)]
+    ));
+    assert_eq!(write(program.program()).unwrap(), bytes);
+    program.push(20.0);
+    let results = program.call(0, &mut ()).unwrap();
+    assert!(matches!(results[..], [super::Type::Number(n)] if n == 6765.0));
+}
+
+#[test]
+fn corrupt_files() {
+    let bytes = write(&fib()).unwrap();
+    for len in 0..bytes.len() {
+        assert!(
+            read::<BasicOperator>(&bytes[..len]).is_err(),
+            "cut at {len}"
+        );
+    }
+    // any change has to be caught or give a program that checks
+    for at in 0..bytes.len() {
+        for flip in [1, 0x80, 0xff] {
+            let mut bytes = bytes.clone();
+            bytes[at] ^= flip;
+            let _ = read::<BasicOperator>(&bytes);
+        }
+    }
+
+    let mut bytes = write(&fib()).unwrap();
+    bytes[4] = 2;
+    assert_eq!(
+        read::<BasicOperator>(&bytes).unwrap_err(),
+        "the file is of version 2, only version 1 can be read"
+    );
+    assert_eq!(
+        read::<BasicOperator>(b"\x7fELF").unwrap_err(),
+        "not a program file"
+    );
+
+    // adding numbers to booleans
+    let mut run = Run::<BasicOperator>::new();
+    run.push_val(true);
+    run.push_val(1.0);
+    run.add_operator(BasicOperator::Add);
+    let err = read::<BasicOperator>(&write(&run).unwrap()).unwrap_err();
+    assert!(
+        err.starts_with("the program is invalid: operation 2 expected"),
+        "{err}"
+    );
+}
@@ -0,0 +1,1375 @@
+pub mod disasm;
+pub mod format;
+pub mod repl;
+
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    mem::{size_of, MaybeUninit},
+    ops::Range,
+};
+
+use lalrpop_util::lalrpop_mod;
+
+macro_rules! operator {
+    (reverseME $output:ident => $par:ident: $par_ty:ty, $(
+        $par_r:ident: $par_ty_r:ty,
+    ),* $(,)?) => {
+        operator!(reverseME $output => $($par_r: $par_ty_r,)*);
+        let $par: $par_ty = unsafe{$output.pop()};
+    };
+    (reverseME $output:ident => ) => {
+    };
+
+    (TUP($ident:ident) $ret:ty $(,)?) => {
+        ($ident,)
+    };
+    (TUP($ident:ident) $ret:ty,$($ret_r:ty,)*) => {
+        $ident
+    };
+
+    (0 $output:ident($ident:ident) $ret:ty,$($ret_r:ty,)*) => {
+        $output.push($ident.0);
+        operator!(1 $output($ident) $($ret_r,)*);
+    };
+    (1 $output:ident($ident:ident) $ret:ty,$($ret_r:ty,)*) => {
+        $output.push($ident.1);
+        operator!(2 $output($ident) $($ret_r,)*);
+    };
+    (2 $output:ident($ident:ident) $ret:ty,$($ret_r:ty,)*) => {
+        $output.push($ident.2);
+        operator!(3 $output($ident) $($ret_r,)*);
+    };
+    (3 $output:ident($ident:ident) $ret:ty,$($ret_r:ty,)*) => {
+        $output.push($ident.3);
+        operator!(4 $output($ident) $($ret_r,)*);
+    };
+    (4 $output:ident($ident:ident) $ret:ty,$($ret_r:ty,)*) => {
+        $output.push($ident.4);
+        operator!(5 $output($ident) $($ret_r,)*);
+    };
+    ($lit:literal $output:ident($ident:ident)) => {
+    };
+
+    (   $context:ident: $context_ty:ty;
+
+        $(#[$meta:meta])* $vis:vis enum $name:ident {
+        $(
+            $op:ident(
+                $(
+                    $par:ident: $par_ty:ty
+                ),* $(,)?
+            ) -> $($ret:ty),* $(,)? $expr:block
+        )*
+    }
+    $(#[$meta_o:meta])* $vis_o:vis enum $name_o:ident {
+        $(
+            $ty_e_name:ident($ty:ty)
+        ),* $(,)?
+    }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name{
+            $(
+                $op,
+            )*
+        }
+
+        impl $name {
+            /// Every operator, in the order they're declared
+            $vis const ALL: &'static [$name] = &[$($name::$op),*];
+        }
+
+
+        $(#[$meta_o])* $vis_o enum $name_o {
+            $(
+                $ty_e_name($ty)
+            ),*
+        }
+
+        unsafe impl Operator for $name{
+            type Context = $context_ty;
+            type AnyValue = $name_o;
+
+            unsafe fn run(
+                &self,
+                $context: &mut Self::Context,
+                output: &mut HorribleVec,
+            ) -> Result<(), String> {
+                match self{
+                    $(
+                        Self::$op => {
+                            operator!(reverseME output => $($par: $par_ty,)*);
+
+                            #[allow(unused_parens)]
+                            let tup: ($($ret),*);
+                            tup = $expr;
+                            let tup: ($($ret,)*) = operator!(TUP(tup)$($ret,)*);
+                            operator!(0 output(tup) $($ret,)*);
+                        }
+                    )*
+                };
+                Ok(())
+            }
+
+            fn output(&self) -> &'static [TypeId] {
+                match self{
+                    $(
+                        Self::$op => {
+                            const V: &[TypeId] = &[$(TypeId::of::<$ret>(),)*];
+                            V
+                        }
+                    )*
+                }
+            }
+
+            fn input(&self) -> &'static [TypeId] {
+                match self{
+                    $(
+                        Self::$op => {
+                            const V: &[TypeId] = &[$(TypeId::of::<$par_ty>(),)*];
+                            V
+                        }
+                    )*
+                }
+            }
+
+            fn decodes(ty: TypeId) -> bool {
+                $(ty == TypeId::of::<$ty>())||*
+            }
+
+            unsafe fn get_value(ty: TypeId, output: &mut HorribleVec) -> $name_o{
+                $(
+                    #[allow(non_upper_case_globals)]
+                    const $ty_e_name: TypeId = TypeId::of::<$ty>();
+                )*
+                match ty{
+                    $(
+                        val if val == $ty_e_name => $name_o::$ty_e_name(output.pop::<$ty>()),
+                    )*
+                    _ => unreachable!("`get_value` of a type `decodes` rejects")
+                }
+            }
+        }
+    };
+}
+
+/// # Safety
+///
+/// [`Operator::input`] and [`Operator::output`] must exactly describe the
+/// values [`Operator::run`] pops from and pushes to the stack.
+pub unsafe trait Operator {
+    type AnyValue;
+    type Context;
+
+    fn output(&self) -> &[TypeId];
+    fn input(&self) -> &[TypeId];
+    /// Gives why the program can't go on, like a division by zero, leaving
+    /// the stack however it was
+    ///
+    /// # Safety
+    ///
+    /// The top of `output` must hold values of the types in [`Operator::input`].
+    unsafe fn run(
+        &self,
+        context: &mut Self::Context,
+        output: &mut HorribleVec,
+    ) -> Result<(), String>;
+    /// Whether [`Operator::get_value`] can read a value of this type
+    fn decodes(ty: TypeId) -> bool;
+    /// # Safety
+    ///
+    /// The top of `output` must hold a value of type `ty`, which
+    /// [`Operator::decodes`].
+    unsafe fn get_value(ty: TypeId, output: &mut HorribleVec) -> Self::AnyValue;
+}
+
+operator! {
+    _context: ();
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum BasicOperator {
+        Times(left: f64, right: f64) -> f64{
+            left * right
+        }
+        Div(left: f64, right: f64) -> f64{
+            left / right
+        }
+        Add(left: f64, right: f64) -> f64{
+            left + right
+        }
+        Minus(left: f64, right: f64) -> f64{
+            left - right
+        }
+
+        Eq(left: f64, right: f64) -> bool{
+            left == right
+        }
+        Gt(left: f64, right: f64) -> bool{
+            left > right
+        }
+        Lt(left: f64, right: f64) -> bool{
+            left < right
+        }
+
+        Or(left: bool, right: bool) -> bool{
+            left || right
+        }
+        And(left: bool, right: bool) -> bool{
+            left && right
+        }
+    }
+    #[derive(Debug, Clone, Copy)]
+    pub enum Type{
+        Number(f64),
+        Boolean(bool),
+    }
+}
+
+impl BasicOperator {
+    /// How the operator is written in calc
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BasicOperator::Times => "*",
+            BasicOperator::Div => "/",
+            BasicOperator::Add => "+",
+            BasicOperator::Minus => "-",
+            BasicOperator::Eq => "==",
+            BasicOperator::Gt => ">",
+            BasicOperator::Lt => "<",
+            BasicOperator::Or => "||",
+            BasicOperator::And => "&&",
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct HorribleVec {
+    inner: Vec<MaybeUninit<u8>>,
+}
+
+impl HorribleVec {
+    pub const fn new() -> Self {
+        Self { inner: Vec::new() }
+    }
+
+    pub fn push<T>(&mut self, value: T) {
+        self.inner.reserve(size_of::<T>());
+        unsafe {
+            self.inner
+                .as_mut_ptr()
+                .add(self.inner.len())
+                .cast::<T>()
+                .write_unaligned(value);
+            self.inner.set_len(self.inner.len() + size_of::<T>());
+        }
+    }
+
+    pub fn push_front<T>(&mut self, value: T) {
+        self.inner.reserve(size_of::<T>());
+        unsafe {
+            std::ptr::copy(
+                self.inner.as_ptr(),
+                self.inner.as_mut_ptr().add(size_of::<T>()),
+                self.inner.len(),
+            );
+
+            self.inner.as_mut_ptr().cast::<T>().write_unaligned(value);
+
+            self.inner.set_len(self.inner.len() + size_of::<T>());
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The last `size_of::<T>()` bytes must have been pushed as a `T`.
+    pub unsafe fn pop<T>(&mut self) -> T {
+        let data = self.inner[self.inner.len() - size_of::<T>()..self.inner.len()].as_ptr();
+        let value = data.cast::<T>().read_unaligned();
+        for _ in 0..size_of::<T>() {
+            self.inner.pop().unwrap();
+        }
+        value
+    }
+
+    /// # Safety
+    ///
+    /// The last `size` bytes of `values` must form whole values.
+    pub unsafe fn push_n_from(&mut self, values: &mut HorribleVec, size: usize) {
+        self.inner.reserve(size);
+        assert!(values.inner.len() >= size);
+        unsafe {
+            let src = values.inner.as_mut_ptr().add(values.inner.len() - size);
+            let dst = self.inner.as_mut_ptr().add(self.inner.len());
+            std::ptr::copy_nonoverlapping(src, dst, size);
+
+            values.inner.set_len(values.inner.len() - size);
+            self.inner.set_len(self.inner.len() + size);
+        }
+    }
+}
+
+#[test]
+fn bruh() {
+    let mut thing = HorribleVec::new();
+
+    thing.push_front(12i32);
+    thing.push_front("Hiiii!");
+    thing.push_front("asldkasd".to_owned());
+
+    unsafe {
+        println!("{}", thing.pop::<i32>());
+        println!("{}", thing.pop::<&str>());
+        println!("{}", thing.pop::<String>());
+    }
+}
+
+#[derive(Debug)]
+pub enum MachineOperation<O> {
+    /// Pushes one of the literals of [`Run::push_val`], by index, which has
+    /// to be of a type the operator [decodes](Operator::decodes) or a `u64`
+    /// for [`MachineOperation::CallIndirect`]
+    Lit(usize),
+    Op(O),
+    /// Pushes a copy of a local of the current frame
+    Local(Local),
+    /// Pops a value into a local of the current frame
+    SetLocal(Local),
+    /// Continues at another operation, which every path to has to have
+    /// values of the same types on the stack
+    Jump(usize),
+    /// Pops a `bool` and continues at another operation if it's false
+    JumpUnless(usize),
+    /// Calls one of [`Run::functions`], popping its parameters
+    Call(usize),
+    /// Pops a `u64` holding one more than the index of a function in
+    /// [`Run::functions`] and calls it, which stops the program with an
+    /// error unless the function takes and gives these types
+    CallIndirect {
+        params: Vec<TypeId>,
+        results: Vec<TypeId>,
+    },
+    /// Leaves the current function, its results stay on the stack
+    Return,
+}
+
+/// Where a value lives in a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Local {
+    ty: TypeId,
+    offset: usize,
+    size: usize,
+}
+
+impl Local {
+    /// A local holding a `T` this many bytes into the frame
+    pub fn new<T: Copy + 'static>(offset: usize) -> Self {
+        Self {
+            ty: TypeId::of::<T>(),
+            offset,
+            size: size_of::<T>(),
+        }
+    }
+
+    pub fn ty(&self) -> TypeId {
+        self.ty
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn overlaps(&self, other: &Local) -> bool {
+        self.offset < other.offset + other.size && other.offset < self.offset + self.size
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Function {
+    /// The operation the function starts at
+    pub entry: usize,
+    /// The first locals, in the order they're pushed before a call
+    pub params: Vec<Local>,
+    pub results: Vec<TypeId>,
+    /// The size of all locals together
+    pub frame_size: usize,
+}
+
+#[derive(Debug)]
+struct Frame {
+    /// Where to continue after returning
+    ret: usize,
+    /// Where the frame's locals start
+    base: usize,
+}
+
+/// Why [`Run::check`] refused a program, or a [`Checked`] one couldn't run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    /// The operation at `at` needs a value of type `expected` where there's
+    /// one of type `found`, `None` being no value at all
+    Mismatch {
+        at: usize,
+        expected: Option<TypeId>,
+        found: Option<TypeId>,
+    },
+    /// The operation at `at` can't run for a reason other than types
+    Invalid { at: usize, reason: String },
+    /// [`Checked::call`] was given a function that doesn't exist
+    NoFunction(usize),
+    /// The operation at `at` stopped the program while it ran, like an
+    /// operator dividing by zero or a call too deep
+    Trap { at: usize, reason: String },
+}
+
+impl TypeError {
+    /// What's wrong, without which operation it's at
+    pub fn message(&self) -> String {
+        let describe = |ty: &Option<TypeId>| match ty {
+            Some(ty) => disasm::type_name(*ty),
+            None => "nothing".to_string(),
+        };
+        match self {
+            TypeError::Mismatch {
+                expected, found, ..
+            } => format!(
+                "expected {} but found {}",
+                describe(expected),
+                describe(found)
+            ),
+            TypeError::Invalid { reason, .. } | TypeError::Trap { reason, .. } => reason.clone(),
+            TypeError::NoFunction(function) => format!("there's no function {function}"),
+        }
+    }
+
+    /// The index of the operation that's wrong
+    pub fn at(&self) -> Option<usize> {
+        match self {
+            TypeError::Mismatch { at, .. }
+            | TypeError::Invalid { at, .. }
+            | TypeError::Trap { at, .. } => Some(*at),
+            TypeError::NoFunction(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.at() {
+            Some(at) => write!(f, "operation {at} {}", self.message()),
+            None => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+/// Checks that the top of the stack holds values of these types, popping them
+fn pop(at: usize, stack: &mut Vec<TypeId>, types: &[TypeId]) -> Result<(), TypeError> {
+    for ty in types.iter().rev() {
+        match stack.pop() {
+            Some(found) if found == *ty => {}
+            found => {
+                return Err(TypeError::Mismatch {
+                    at,
+                    expected: Some(*ty),
+                    found,
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that two stacks hold the same types
+fn compare(at: usize, expected: &[TypeId], found: &[TypeId]) -> Result<(), TypeError> {
+    match (0..expected.len().max(found.len())).find(|i| expected.get(*i) != found.get(*i)) {
+        Some(i) => Err(TypeError::Mismatch {
+            at,
+            expected: expected.get(i).copied(),
+            found: found.get(i).copied(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the operator can read back values of these types
+fn decodable<O: Operator>(at: usize, types: &[TypeId]) -> Result<(), TypeError> {
+    match types.iter().find(|ty| !O::decodes(**ty)) {
+        Some(ty) => Err(TypeError::Invalid {
+            at,
+            reason: format!("has a {}, which can't be read back", disasm::type_name(*ty)),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// What's known before an operation runs, on every path to it
+#[derive(Debug, Clone)]
+struct State {
+    stack: Vec<TypeId>,
+    /// The locals that are set
+    locals: Vec<Local>,
+}
+
+/// What the operations being checked run as part of
+#[derive(Debug, Clone, Copy)]
+enum Scope<'a> {
+    /// The code from the first operation, with a frame of [`Run::frame_size`]
+    Main,
+    Function(&'a Function),
+}
+
+/// A program being built, [`Run::check`] turns it into one that can run
+#[derive(Default, Debug)]
+pub struct Run<O: Operator> {
+    /// The literal pool
+    values: HorribleVec,
+    /// The type and bytes in `values` of each literal
+    literals: Vec<(TypeId, Range<usize>)>,
+    pub operator: Vec<MachineOperation<O>>,
+    pub functions: Vec<Function>,
+    /// The size of the locals of the code from the first operation
+    pub frame_size: usize,
+}
+
+impl<O: Operator> Run<O> {
+    pub fn new() -> Self {
+        Self {
+            operator: Default::default(),
+            values: Default::default(),
+            literals: Default::default(),
+            functions: Default::default(),
+            frame_size: 0,
+        }
+    }
+
+    pub fn add_operator(&mut self, op: O) {
+        self.operator.push(MachineOperation::Op(op));
+    }
+
+    pub fn push_val<T: Copy + 'static>(&mut self, value: T) {
+        let literal = self.literal(value);
+        self.operator.push(MachineOperation::Lit(literal));
+    }
+
+    /// Adds a value to the literal pool, giving its index
+    fn literal<T: Copy + 'static>(&mut self, value: T) -> usize {
+        let start = self.values.inner.len();
+        self.values.push(value);
+        let bytes = start..self.values.inner.len();
+        self.literals.push((TypeId::of::<T>(), bytes));
+        self.literals.len() - 1
+    }
+
+    /// Checks that every operation gets the types it takes and only reads
+    /// locals that are set, whichever way the program goes
+    pub fn type_check(&self) -> Result<(), TypeError> {
+        self.verify().map(|_| ())
+    }
+
+    /// Type checks the program so it can run
+    pub fn check(self) -> Result<Checked<O>, TypeError> {
+        let main = self.verify()?;
+        Ok(Checked {
+            program: self,
+            main,
+            stack: HorribleVec::new(),
+            args: Vec::new(),
+            locals: Vec::new(),
+        })
+    }
+
+    /// Checks every function, and the code from the first operation unless
+    /// a function starts there, giving what that code leaves on the stack
+    fn verify(&self) -> Result<Option<Vec<TypeId>>, TypeError> {
+        for function in &self.functions {
+            let mut offset = 0;
+            for param in &function.params {
+                if param.offset != offset {
+                    return Err(TypeError::Invalid {
+                        at: function.entry,
+                        reason: "starts a function whose parameters aren't its first locals"
+                            .to_string(),
+                    });
+                }
+                offset += param.size;
+            }
+            if offset > function.frame_size {
+                return Err(TypeError::Invalid {
+                    at: function.entry,
+                    reason: "starts a function whose parameters don't fit its frame".to_string(),
+                });
+            }
+            self.verify_from(function.entry, Scope::Function(function))?;
+        }
+        if self.functions.iter().any(|function| function.entry == 0) {
+            return Ok(None);
+        }
+        self.verify_from(0, Scope::Main).map(Some)
+    }
+
+    /// Follows every path from an operation, like the JVM's verifier: the
+    /// types on the stack have to agree wherever paths join, and a local is
+    /// only set after a join if it's set on every path into it
+    fn verify_from(&self, entry: usize, scope: Scope) -> Result<Vec<TypeId>, TypeError> {
+        let end = self.operator.len();
+        if entry > end {
+            return Err(TypeError::Invalid {
+                at: entry,
+                reason: "is past the last operation".to_string(),
+            });
+        }
+        let locals = match scope {
+            Scope::Main => Vec::new(),
+            Scope::Function(function) => function.params.clone(),
+        };
+        let mut states: Vec<Option<State>> = vec![None; end + 1];
+        states[entry] = Some(State {
+            stack: Vec::new(),
+            locals,
+        });
+        let mut work = vec![entry];
+        let mut results = None;
+        let frame_size = match scope {
+            Scope::Main => self.frame_size,
+            Scope::Function(function) => function.frame_size,
+        };
+        let in_frame = |at: usize, local: &Local| match local.offset.checked_add(local.size) {
+            Some(end) if end <= frame_size => Ok(()),
+            _ => Err(TypeError::Invalid {
+                at,
+                reason: "uses a local outside of the frame".to_string(),
+            }),
+        };
+
+        while let Some(at) = work.pop() {
+            let mut state = states[at].clone().unwrap();
+            let Some(op) = self.operator.get(at) else {
+                match scope {
+                    // running past the last operation ends the program
+                    Scope::Main => {
+                        let expected = results.get_or_insert_with(|| state.stack.clone());
+                        compare(at, expected, &state.stack)?;
+                        decodable::<O>(at, &state.stack)?;
+                    }
+                    Scope::Function(_) => {
+                        return Err(TypeError::Invalid {
+                            at,
+                            reason: "is reached by a function without returning".to_string(),
+                        })
+                    }
+                }
+                continue;
+            };
+            let mut next = vec![at + 1];
+            match op {
+                MachineOperation::Lit(literal) => {
+                    let Some((ty, _)) = self.literals.get(*literal) else {
+                        return Err(TypeError::Invalid {
+                            at,
+                            reason: format!("pushes literal {literal}, which doesn't exist"),
+                        });
+                    };
+                    if *ty != TypeId::of::<u64>() {
+                        decodable::<O>(at, std::slice::from_ref(ty))?;
+                    }
+                    state.stack.push(*ty);
+                }
+                MachineOperation::Op(op) => {
+                    pop(at, &mut state.stack, op.input())?;
+                    state.stack.extend_from_slice(op.output());
+                }
+                MachineOperation::Local(local) => {
+                    in_frame(at, local)?;
+                    if !state.locals.contains(local) {
+                        return Err(TypeError::Invalid {
+                            at,
+                            reason: "reads a local that might not be set".to_string(),
+                        });
+                    }
+                    state.stack.push(local.ty);
+                }
+                MachineOperation::SetLocal(local) => {
+                    in_frame(at, local)?;
+                    pop(at, &mut state.stack, &[local.ty])?;
+                    state.locals.retain(|other| !other.overlaps(local));
+                    state.locals.push(*local);
+                }
+                MachineOperation::Jump(to) => next = vec![*to],
+                MachineOperation::JumpUnless(to) => {
+                    pop(at, &mut state.stack, &[TypeId::of::<bool>()])?;
+                    next.push(*to);
+                }
+                MachineOperation::Call(function) => {
+                    let Some(function) = self.functions.get(*function) else {
+                        return Err(TypeError::Invalid {
+                            at,
+                            reason: format!("calls function {function}, which doesn't exist"),
+                        });
+                    };
+                    let params: Vec<_> = function.params.iter().map(Local::ty).collect();
+                    pop(at, &mut state.stack, &params)?;
+                    state.stack.extend_from_slice(&function.results);
+                }
+                MachineOperation::CallIndirect { params, results } => {
+                    pop(at, &mut state.stack, &[TypeId::of::<u64>()])?;
+                    pop(at, &mut state.stack, params)?;
+                    state.stack.extend_from_slice(results);
+                }
+                MachineOperation::Return => {
+                    match scope {
+                        Scope::Main => {
+                            let expected = results.get_or_insert_with(|| state.stack.clone());
+                            compare(at, expected, &state.stack)?;
+                            decodable::<O>(at, &state.stack)?;
+                        }
+                        Scope::Function(function) => {
+                            compare(at, &function.results, &state.stack)?;
+                        }
+                    }
+                    next.clear();
+                }
+            }
+            for to in next {
+                if to > end {
+                    return Err(TypeError::Invalid {
+                        at,
+                        reason: "continues past the last operation".to_string(),
+                    });
+                }
+                match &mut states[to] {
+                    Some(old) => {
+                        compare(to, &old.stack, &state.stack)?;
+                        let set = old.locals.len();
+                        old.locals.retain(|local| state.locals.contains(local));
+                        if old.locals.len() != set {
+                            work.push(to);
+                        }
+                    }
+                    slot => {
+                        *slot = Some(state.clone());
+                        work.push(to);
+                    }
+                }
+            }
+        }
+        Ok(results.unwrap_or_default())
+    }
+}
+
+/// A program that passed [`Run::check`], the only kind that runs
+#[derive(Debug)]
+pub struct Checked<O: Operator> {
+    program: Run<O>,
+    /// What the code from the first operation gives, when it isn't a function
+    main: Option<Vec<TypeId>>,
+    stack: HorribleVec,
+    /// The types of the arguments pushed for the next call
+    args: Vec<TypeId>,
+    locals: Vec<MaybeUninit<u8>>,
+}
+
+/// How many calls deep a [`Checked`] program can go
+const MAX_DEPTH: usize = 4096;
+
+impl<O: Operator> Checked<O> {
+    pub fn program(&self) -> &Run<O> {
+        &self.program
+    }
+
+    /// Pushes an argument for [`Checked::call`]
+    pub fn push<T: Copy + 'static>(&mut self, value: T) {
+        self.stack.push(value);
+        self.args.push(TypeId::of::<T>());
+    }
+
+    /// Runs the code from the first operation and gives what it leaves on
+    /// the stack, which can't be done when a function starts there
+    pub fn run(&mut self, context: &mut O::Context) -> Result<Vec<O::AnyValue>, TypeError> {
+        self.stack = HorribleVec::new();
+        self.args.clear();
+        let Some(main) = self.main.clone() else {
+            return Err(TypeError::Invalid {
+                at: 0,
+                reason: "starts a function, which has to be called".to_string(),
+            });
+        };
+        self.locals = vec![MaybeUninit::uninit(); self.program.frame_size];
+        let frame = Frame {
+            ret: self.program.operator.len(),
+            base: 0,
+        };
+        self.execute(0, vec![frame], context)?;
+        Ok(self.results(&main))
+    }
+
+    /// Runs a function with the pushed arguments and gives its results
+    pub fn call(
+        &mut self,
+        function: usize,
+        context: &mut O::Context,
+    ) -> Result<Vec<O::AnyValue>, TypeError> {
+        let args = std::mem::take(&mut self.args);
+        let checked = match self.program.functions.get(function) {
+            Some(callee) => {
+                let params: Vec<_> = callee.params.iter().map(Local::ty).collect();
+                compare(callee.entry, &params, &args)
+                    .and_then(|()| decodable::<O>(callee.entry, &callee.results))
+            }
+            None => Err(TypeError::NoFunction(function)),
+        };
+        if let Err(err) = checked {
+            self.stack = HorribleVec::new();
+            return Err(err);
+        }
+        let entry = self.program.functions[function].entry;
+        let end = self.program.operator.len();
+        let frame = self.enter(function, end);
+        self.execute(entry, vec![frame], context)?;
+        let results = self.program.functions[function].results.clone();
+        Ok(self.results(&results))
+    }
+
+    /// Pops values of these types off the stack
+    fn results(&mut self, types: &[TypeId]) -> Vec<O::AnyValue> {
+        let mut values: Vec<_> = types
+            .iter()
+            .rev()
+            .map(|ty| unsafe { O::get_value(*ty, &mut self.stack) })
+            .collect();
+        values.reverse();
+        values
+    }
+
+    /// Makes a frame for a function, moving its parameters into it
+    fn enter(&mut self, function: usize, ret: usize) -> Frame {
+        let function = &self.program.functions[function];
+        let base = self.locals.len();
+        self.locals
+            .resize(base + function.frame_size, MaybeUninit::uninit());
+        let size: usize = function.params.iter().map(|param| param.size).sum();
+        let start = self.stack.inner.len() - size;
+        self.locals[base..base + size].copy_from_slice(&self.stack.inner[start..]);
+        self.stack.inner.truncate(start);
+        Frame { ret, base }
+    }
+
+    /// Stops the program at an operation, dropping what it was working on
+    fn trap(&mut self, at: usize, reason: String) -> TypeError {
+        self.stack = HorribleVec::new();
+        self.locals.clear();
+        TypeError::Trap { at, reason }
+    }
+
+    /// Runs operations from `pc`, relying on the checks of [`Run::check`]
+    /// for every pop getting the type that was pushed
+    fn execute(
+        &mut self,
+        mut pc: usize,
+        mut frames: Vec<Frame>,
+        context: &mut O::Context,
+    ) -> Result<(), TypeError> {
+        while let Some(op) = self.program.operator.get(pc) {
+            pc += 1;
+            match op {
+                MachineOperation::Lit(literal) => {
+                    let bytes =
+                        &self.program.values.inner[self.program.literals[*literal].1.clone()];
+                    self.stack.inner.extend_from_slice(bytes);
+                }
+                MachineOperation::Op(op) => {
+                    if let Err(reason) = unsafe { op.run(context, &mut self.stack) } {
+                        return Err(self.trap(pc - 1, reason));
+                    }
+                }
+                MachineOperation::Local(local) => {
+                    let start = frames.last().unwrap().base + local.offset;
+                    let bytes = &self.locals[start..start + local.size];
+                    self.stack.inner.extend_from_slice(bytes);
+                }
+                MachineOperation::SetLocal(local) => {
+                    let start = frames.last().unwrap().base + local.offset;
+                    let from = self.stack.inner.len() - local.size;
+                    self.locals[start..start + local.size]
+                        .copy_from_slice(&self.stack.inner[from..]);
+                    self.stack.inner.truncate(from);
+                }
+                MachineOperation::Jump(to) => pc = *to,
+                MachineOperation::JumpUnless(to) => {
+                    if !unsafe { self.stack.pop::<bool>() } {
+                        pc = *to;
+                    }
+                }
+                MachineOperation::Call(function) => {
+                    let function = *function;
+                    if frames.len() == MAX_DEPTH {
+                        return Err(self.trap(pc - 1, "stack overflow".to_string()));
+                    }
+                    frames.push(self.enter(function, pc));
+                    pc = self.program.functions[function].entry;
+                }
+                MachineOperation::CallIndirect { params, results } => {
+                    let index = unsafe { self.stack.pop::<u64>() };
+                    let function = (index as usize).checked_sub(1).filter(|function| {
+                        self.program
+                            .functions
+                            .get(*function)
+                            .is_some_and(|function| {
+                                function.results == *results
+                                    && function
+                                        .params
+                                        .iter()
+                                        .map(Local::ty)
+                                        .eq(params.iter().copied())
+                            })
+                    });
+                    let Some(function) = function else {
+                        self.stack = HorribleVec::new();
+                        self.locals.clear();
+                        return Err(TypeError::Invalid {
+                            at: pc - 1,
+                            reason: format!(
+                                "called {index}, which isn't a function of the right type"
+                            ),
+                        });
+                    };
+                    if frames.len() == MAX_DEPTH {
+                        return Err(self.trap(pc - 1, "stack overflow".to_string()));
+                    }
+                    frames.push(self.enter(function, pc));
+                    pc = self.program.functions[function].entry;
+                }
+                MachineOperation::Return => {
+                    let Some(frame) = frames.pop() else {
+                        return Ok(());
+                    };
+                    self.locals.truncate(frame.base);
+                    pc = frame.ret;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+lalrpop_mod!(
+    #[allow(clippy::empty_line_after_outer_attr)]
+    calc2
+);
+
+/// What the calc grammar keeps track of while it parses into a [`Run`]
+///
+/// Variables and parameters are locals, numbers or booleans depending on
+/// what's first stored in them, and functions take numbers. A function
+/// calling itself is assumed to give a number until its body is parsed,
+/// [`Run::check`] catches it if it doesn't.
+#[derive(Debug)]
+pub struct Calc {
+    pub run: Run<BasicOperator>,
+    /// The locals of the code being parsed, by name
+    locals: HashMap<String, Local>,
+    frame_size: usize,
+    /// The index of each function, by name
+    functions: HashMap<String, usize>,
+    /// Variables the program starts with
+    defined: Vec<(String, Type)>,
+    /// Where in the source operations came from, as the first operation
+    /// of each run and the offset it's for, if it's from the source at all
+    sources: Vec<(usize, Option<usize>)>,
+}
+
+impl Default for Calc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Calc {
+    pub fn new() -> Self {
+        Self {
+            run: Run::new(),
+            locals: HashMap::new(),
+            frame_size: 0,
+            functions: HashMap::new(),
+            defined: Vec::new(),
+            sources: Vec::new(),
+        }
+    }
+
+    /// Makes a variable the program starts with
+    pub fn define(&mut self, name: String, value: Type) {
+        self.defined.push((name, value));
+    }
+
+    /// Starts the code after the functions by setting the defined variables
+    pub fn start_main(&mut self) {
+        self.patch(0);
+        self.sources.push((self.run.operator.len(), None));
+        for (name, value) in std::mem::take(&mut self.defined) {
+            let ty = match value {
+                Type::Number(number) => {
+                    self.run.push_val(number);
+                    TypeId::of::<f64>()
+                }
+                Type::Boolean(boolean) => {
+                    self.run.push_val(boolean);
+                    TypeId::of::<bool>()
+                }
+            };
+            self.declare(name, ty);
+        }
+    }
+
+    /// Marks the operations added from here on as coming from an offset in
+    /// the source
+    pub fn at(&mut self, offset: usize) {
+        self.sources.push((self.run.operator.len(), Some(offset)));
+    }
+
+    /// The offset in the source an operation came from, `None` for the ones
+    /// the program starts with
+    pub fn source(&self, op: usize) -> Option<usize> {
+        let runs = self.sources.partition_point(|(first, _)| *first <= op);
+        let (_, offset) = self.sources[..runs].last()?;
+        *offset
+    }
+
+    /// Adds an operation, giving its index
+    pub fn emit(&mut self, op: MachineOperation<BasicOperator>) -> usize {
+        self.run.operator.push(op);
+        self.run.operator.len() - 1
+    }
+
+    /// Adds an operator from an offset in the source, giving the type of its
+    /// result
+    pub fn op(&mut self, at: usize, op: BasicOperator) -> TypeId {
+        self.at(at);
+        let ty = op.output()[0];
+        self.run.add_operator(op);
+        ty
+    }
+
+    /// Makes a jump go to the next operation
+    pub fn patch(&mut self, at: usize) {
+        self.patch_to(at, self.run.operator.len());
+    }
+
+    pub fn patch_to(&mut self, at: usize, to: usize) {
+        match &mut self.run.operator[at] {
+            MachineOperation::Jump(target) | MachineOperation::JumpUnless(target) => *target = to,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+
+    fn local(&mut self, ty: TypeId) -> Local {
+        let local = match ty == TypeId::of::<bool>() {
+            true => Local::new::<bool>(self.frame_size),
+            false => Local::new::<f64>(self.frame_size),
+        };
+        self.frame_size += local.size();
+        local
+    }
+
+    /// Stores the value on the stack in a new variable
+    pub fn declare(&mut self, name: String, ty: TypeId) {
+        let local = self.local(ty);
+        self.emit(MachineOperation::SetLocal(local));
+        self.locals.insert(name, local);
+    }
+
+    pub fn assign(&mut self, name: String) -> Result<(), String> {
+        let Some(&local) = self.locals.get(&name) else {
+            return Err(format!("Unknown Variable `{name}`"));
+        };
+        self.emit(MachineOperation::SetLocal(local));
+        Ok(())
+    }
+
+    pub fn load(&mut self, name: String) -> Result<TypeId, String> {
+        let Some(&local) = self.locals.get(&name) else {
+            return Err(format!("Unknown Variable `{name}`"));
+        };
+        self.emit(MachineOperation::Local(local));
+        Ok(local.ty())
+    }
+
+    pub fn call(&mut self, name: String, args: usize) -> Result<TypeId, String> {
+        let Some(&index) = self.functions.get(&name) else {
+            return Err(format!("Unknown Function `{name}`"));
+        };
+        let function = &self.run.functions[index];
+        if function.params.len() != args {
+            return Err(format!("Wrong Number Of Arguments to `{name}`"));
+        }
+        let ty = function.results[0];
+        self.emit(MachineOperation::Call(index));
+        Ok(ty)
+    }
+
+    /// Starts a function's body, giving its index
+    pub fn start_function(&mut self, name: String, params: Vec<String>) -> usize {
+        self.locals.clear();
+        self.frame_size = 0;
+        let params = params
+            .into_iter()
+            .map(|param| {
+                let local = self.local(TypeId::of::<f64>());
+                self.locals.insert(param, local);
+                local
+            })
+            .collect();
+        let index = self.run.functions.len();
+        self.run.functions.push(Function {
+            entry: self.run.operator.len(),
+            params,
+            results: vec![TypeId::of::<f64>()],
+            frame_size: 0,
+        });
+        self.functions.insert(name, index);
+        index
+    }
+
+    /// Returns the value of a function's body
+    pub fn end_function(&mut self, index: usize, ty: TypeId) {
+        self.emit(MachineOperation::Return);
+        let function = &mut self.run.functions[index];
+        function.results = vec![ty];
+        function.frame_size = std::mem::take(&mut self.frame_size);
+        self.locals.clear();
+    }
+
+    /// Jumps back to a loop's condition, and out of the loop from it
+    pub fn end_while(&mut self, (start, exit): (usize, usize)) {
+        self.emit(MachineOperation::Jump(start));
+        self.patch(exit);
+    }
+
+    pub fn finish(&mut self, ty: TypeId) -> TypeId {
+        self.run.frame_size = self.frame_size;
+        ty
+    }
+}
+
+/// Parses and runs a calc program
+#[cfg(test)]
+fn calc(src: &str) -> Result<Vec<Type>, TypeError> {
+    let mut calc = Calc::new();
+    calc2::FinishedParser::new().parse(&mut calc, src).unwrap();
+    calc.run.check()?.run(&mut ())
+}
+
+#[test]
+fn test4() {
+    let results = calc("(2+3*4) + 12").unwrap();
+    println!("{results:#?}");
+}
+
+#[test]
+fn calc_control_flow() {
+    let number = |src| match calc(src).unwrap()[..] {
+        [Type::Number(number)] => number,
+        ref results => panic!("{results:?}"),
+    };
+    assert_eq!(number("if 1 < 2 { 3 } else { 4 } * 10"), 30.0);
+    assert_eq!(
+        number("let total = 0; let i = 1; while i < 5 { total = total + i; i = i + 1; } total"),
+        10.0
+    );
+    let fib = "fn fib(n) = if n < 2 { n } else { fib(n - 1) + fib(n - 2) }; fib(10)";
+    assert_eq!(number(fib), 55.0);
+    let max = "fn max(a, b) = if a > b { a } else { b }; fn big(a) = a > 100; max(3, 7) + 1";
+    assert_eq!(number(max), 8.0);
+    assert!(matches!(
+        calc("fn big(a) = a > 100; big(101) && true").unwrap()[..],
+        [Type::Boolean(true)]
+    ));
+
+    // the branches join with different types on the stack
+    let err = calc("1 + if true { 2 } else { false }").unwrap_err();
+    assert!(matches!(err, TypeError::Mismatch { .. }), "{err}");
+    let err = calc("let flag = true; flag = 1; flag").unwrap_err();
+    assert!(matches!(err, TypeError::Mismatch { .. }), "{err}");
+    let mut calc = Calc::new();
+    let err = calc2::FinishedParser::new().parse(&mut calc, "x + 1");
+    assert!(err.is_err());
+}
+
+#[test]
+fn calls_and_jumps() {
+    let (n, total) = (Local::new::<f64>(0), Local::new::<f64>(8));
+
+    // fn triangle(n) { total = 0; while n > 0 { total = total + n; n = n - 1 } return total }
+    let mut program = Run::<BasicOperator>::new();
+    program.functions.push(Function {
+        entry: 0,
+        params: vec![n],
+        results: vec![TypeId::of::<f64>()],
+        frame_size: 16,
+    });
+    program.push_val(0.0);
+    program.operator.push(MachineOperation::SetLocal(total));
+    program.operator.push(MachineOperation::Local(n));
+    program.push_val(0.0);
+    program.add_operator(BasicOperator::Gt);
+    program.operator.push(MachineOperation::JumpUnless(15));
+    program.operator.push(MachineOperation::Local(total));
+    program.operator.push(MachineOperation::Local(n));
+    program.add_operator(BasicOperator::Add);
+    program.operator.push(MachineOperation::SetLocal(total));
+    program.operator.push(MachineOperation::Local(n));
+    program.push_val(1.0);
+    program.add_operator(BasicOperator::Minus);
+    program.operator.push(MachineOperation::SetLocal(n));
+    program.operator.push(MachineOperation::Jump(2));
+    program.operator.push(MachineOperation::Local(total));
+    program.operator.push(MachineOperation::Return);
+    let mut program = program.check().unwrap();
+
+    for _ in 0..2 {
+        program.push(4.0);
+        let results = program.call(0, &mut ()).unwrap();
+        assert!(matches!(results[..], [Type::Number(total)] if total == 10.0));
+    }
+    program.push(true);
+    assert_eq!(
+        program.call(0, &mut ()).unwrap_err(),
+        TypeError::Mismatch {
+            at: 0,
+            expected: Some(TypeId::of::<f64>()),
+            found: Some(TypeId::of::<bool>()),
+        }
+    );
+}
+
+#[test]
+fn type_errors() {
+    let (f64, bool) = (Some(TypeId::of::<f64>()), Some(TypeId::of::<bool>()));
+    let mut program = Run::<BasicOperator>::new();
+    program.push_val(1.0);
+    program.push_val(true);
+    program.add_operator(BasicOperator::Add);
+    let err = program.check().unwrap_err();
+    let mismatch = TypeError::Mismatch {
+        at: 2,
+        expected: f64,
+        found: bool,
+    };
+    assert_eq!(err, mismatch);
+    assert!(err.to_string().starts_with("operation 2 expected"));
+
+    let mut program = Run::<BasicOperator>::new();
+    program.add_operator(BasicOperator::Or);
+    let err = program.type_check().unwrap_err();
+    let mismatch = TypeError::Mismatch {
+        at: 0,
+        expected: bool,
+        found: None,
+    };
+    assert_eq!(err, mismatch);
+
+    // the local is only set when the parameter is true
+    let (flag, value) = (Local::new::<bool>(0), Local::new::<f64>(8));
+    let mut program = Run::<BasicOperator>::new();
+    program.functions.push(Function {
+        entry: 0,
+        params: vec![flag],
+        results: vec![TypeId::of::<f64>()],
+        frame_size: 16,
+    });
+    program.operator.push(MachineOperation::Local(flag));
+    program.operator.push(MachineOperation::JumpUnless(4));
+    program.push_val(2.0);
+    program.operator.push(MachineOperation::SetLocal(value));
+    program.operator.push(MachineOperation::Local(value));
+    program.operator.push(MachineOperation::Return);
+    let err = program.type_check().unwrap_err();
+    assert_eq!(err.at(), Some(4));
+    assert_eq!(
+        err.to_string(),
+        "operation 4 reads a local that might not be set"
+    );
+
+    // paths join with a number and with nothing on the stack
+    let mut program = Run::<BasicOperator>::new();
+    program.push_val(true);
+    program.operator.push(MachineOperation::JumpUnless(3));
+    program.push_val(1.0);
+    program.add_operator(BasicOperator::Add);
+    let err = program.type_check().unwrap_err();
+    let mismatch = TypeError::Mismatch {
+        at: 3,
+        expected: f64,
+        found: None,
+    };
+    assert_eq!(err, mismatch);
+}
+
+#[test]
+fn checked_programs_dont_panic() {
+    // nothing can read back a `u64` or `u8` from a `BasicOperator` program
+    let mut program = Run::<BasicOperator>::new();
+    program.push_val(5u64);
+    let err = program.check().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "operation 1 has a u64, which can't be read back"
+    );
+    let mut program = Run::<BasicOperator>::new();
+    program.push_val(5u8);
+    assert_eq!(program.check().unwrap_err().at(), Some(0));
+
+    // main calls through a function pointer, which doesn't point at anything,
+    // then two functions giving a number and a function pointer
+    let x = Local::new::<f64>(0);
+    let mut program = Run::<BasicOperator>::new();
+    program.push_val(1.0);
+    program.push_val(7u64);
+    program.operator.push(MachineOperation::CallIndirect {
+        params: vec![TypeId::of::<f64>()],
+        results: vec![TypeId::of::<f64>()],
+    });
+    program.operator.push(MachineOperation::Return);
+    program.operator.push(MachineOperation::Local(x));
+    program.operator.push(MachineOperation::Return);
+    program.push_val(1u64);
+    program.operator.push(MachineOperation::Return);
+    program.functions.push(Function {
+        entry: 4,
+        params: vec![x],
+        results: vec![TypeId::of::<f64>()],
+        frame_size: 8,
+    });
+    program.functions.push(Function {
+        entry: 6,
+        params: vec![],
+        results: vec![TypeId::of::<u64>()],
+        frame_size: 0,
+    });
+    let mut program = program.check().unwrap();
+    let err = program.run(&mut ()).unwrap_err();
+    assert_eq!(err.at(), Some(2));
+    assert_eq!(
+        program.call(3, &mut ()).unwrap_err(),
+        TypeError::NoFunction(3)
+    );
+    assert_eq!(program.call(1, &mut ()).unwrap_err().at(), Some(6));
+    // none of it got in the way of calls that work
+    program.push(2.0);
+    let results = program.call(0, &mut ()).unwrap();
+    assert!(matches!(results[..], [Type::Number(x)] if x == 2.0));
+
+    let mut program = Run::<BasicOperator>::new();
+    program.operator.push(MachineOperation::Local(x));
+    program.operator.push(MachineOperation::Return);
+    program.functions.push(Function {
+        entry: 0,
+        params: vec![x],
+        results: vec![TypeId::of::<f64>()],
+        frame_size: 8,
+    });
+    let mut program = program.check().unwrap();
+    assert_eq!(program.run(&mut ()).unwrap_err().at(), Some(0));
+}
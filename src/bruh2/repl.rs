@@ -0,0 +1,169 @@
+//! Calc a line at a time, keeping the variables each line names.
+//!
+//! Every line is parsed into a [`Run`](super::Run) of its own, which starts
+//! by setting the variables from the lines before, and only runs once
+//! [`Run::check`](super::Run::check) has passed it.
+
+use lalrpop_util::ParseError;
+
+use super::{calc2, BasicOperator, Calc, Run, Type, TypeError};
+
+/// The variables named so far
+#[derive(Debug, Default)]
+pub struct Repl {
+    variables: Vec<(String, Type)>,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The value of a variable named by an earlier line
+    pub fn variable(&self, name: &str) -> Option<Type> {
+        let (_, value) = self.variables.iter().find(|(other, _)| other == name)?;
+        Some(*value)
+    }
+
+    /// Runs a line, giving what to show for it
+    ///
+    /// A line is calc that ends in an expression, or in `name = expression`
+    /// to keep its value around for later lines. Parse and type errors point
+    /// at where in the line they are with a `^`.
+    pub fn eval(&mut self, line: &str) -> Result<String, String> {
+        let mut calc = Calc::new();
+        for (name, value) in &self.variables {
+            calc.define(name.clone(), *value);
+        }
+        let name = calc2::LineParser::new()
+            .parse(&mut calc, line)
+            .map_err(|err| describe(line, err))?;
+        let run = std::mem::replace(&mut calc.run, Run::new());
+        let end = run.operator.len();
+        let type_error = |err: TypeError| {
+            // running off the end is where the line ends
+            let at = match err.at() {
+                Some(at) if at >= end => Some(line.len()),
+                Some(at) => calc.source(at),
+                None => None,
+            };
+            match at {
+                Some(at) => point(line, at, &format!("type error: {}", err.message())),
+                None => format!("type error: {err}"),
+            }
+        };
+        let mut program = run.check().map_err(type_error)?;
+        let values = program.run(&mut ()).map_err(type_error)?;
+        let value = match values[..] {
+            [value] => value,
+            ref values => return Err(format!("the line gave {} values", values.len())),
+        };
+        let Some(name) = name else {
+            return Ok(show(value));
+        };
+        let shown = format!("{name} = {}", show(value));
+        match self.variables.iter_mut().find(|(other, _)| *other == name) {
+            Some((_, old)) => *old = value,
+            None => self.variables.push((name, value)),
+        }
+        Ok(shown)
+    }
+}
+
+/// A value along with its type
+fn show(value: <BasicOperator as super::Operator>::AnyValue) -> String {
+    match value {
+        Type::Number(number) => format!("{number} : f64"),
+        Type::Boolean(boolean) => format!("{boolean} : bool"),
+    }
+}
+
+/// A parse error, under the part of the line it's about
+fn describe<T: std::fmt::Display>(line: &str, err: ParseError<usize, T, String>) -> String {
+    let (at, message) = match err {
+        ParseError::InvalidToken { location } => (location, "invalid token".to_string()),
+        ParseError::UnrecognizedEof { location, .. } => {
+            (location, "the line ends too soon".to_string())
+        }
+        ParseError::UnrecognizedToken {
+            token: (start, token, _),
+            ..
+        }
+        | ParseError::ExtraToken {
+            token: (start, token, _),
+        } => {
+            // tokens the lexer can't match come through empty
+            let mut token = token.to_string();
+            if token.is_empty() {
+                token = line
+                    .get(start..)
+                    .and_then(|rest| rest.chars().next())
+                    .into_iter()
+                    .collect();
+            }
+            (start, format!("unexpected `{token}`"))
+        }
+        ParseError::User { error } => return error,
+    };
+    point(line, at, &message)
+}
+
+/// A message under an offset in the line
+fn point(line: &str, at: usize, message: &str) -> String {
+    let column = line.get(..at).map_or(0, |before| before.chars().count());
+    format!("{}^ {message}", " ".repeat(column))
+}
+
+#[test]
+fn variables() {
+    let mut repl = Repl::new();
+    assert_eq!(repl.eval("1 + 2 * 3").unwrap(), "7 : f64");
+    assert_eq!(repl.eval("x = 2 + 3").unwrap(), "x = 5 : f64");
+    assert_eq!(repl.eval("big = x > 4").unwrap(), "big = true : bool");
+    assert_eq!(
+        repl.eval("if big { x * x } else { 0 }").unwrap(),
+        "25 : f64"
+    );
+    assert_eq!(repl.eval("x = x + 1").unwrap(), "x = 6 : f64");
+    // statements and functions only last for their line
+    let line = "fn sq(n) = n * n; let y = sq(x); y - 1";
+    assert_eq!(repl.eval(line).unwrap(), "35 : f64");
+    assert!(repl.eval("y").is_err());
+    // a variable can change type
+    assert_eq!(
+        repl.eval("x = !big").err(),
+        Some("    ^ unexpected `!`".to_string())
+    );
+    assert_eq!(repl.eval("x = x == 6").unwrap(), "x = true : bool");
+    assert!(matches!(repl.variable("x"), Some(Type::Boolean(true))));
+}
+
+#[test]
+fn errors() {
+    let mut repl = Repl::new();
+    assert_eq!(repl.eval("1 + * 2").unwrap_err(), "    ^ unexpected `*`");
+    assert_eq!(
+        repl.eval("(1 + 2").unwrap_err(),
+        "      ^ the line ends too soon"
+    );
+    assert_eq!(repl.eval("x + 1").unwrap_err(), "Unknown Variable `x`");
+    assert_eq!(
+        repl.eval("1 + true").unwrap_err(),
+        "  ^ type error: expected f64 but found bool"
+    );
+    // the variables set before the line don't shift where errors point
+    repl.eval("a = 1").unwrap();
+    repl.eval("b = true").unwrap();
+    assert_eq!(
+        repl.eval("a * 2 + b").unwrap_err(),
+        "      ^ type error: expected f64 but found bool"
+    );
+    assert_eq!(
+        repl.eval("if a { 1 } else { 2 }").unwrap_err(),
+        "^ type error: expected bool but found f64"
+    );
+    assert_eq!(repl.eval("b = c").unwrap_err(), "Unknown Variable `c`");
+    // nothing was kept from lines that failed
+    assert!(repl.eval("x = 1 +").is_err());
+    assert_eq!(repl.variable("x").map(|_| ()), None);
+}
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use crate::stage::scope::Scope;
 
+#[allow(dead_code)]
 struct Workspace {
     files: HashMap<Scope<'static>, String>,
 }
@@ -0,0 +1,655 @@
+//! A JSON description of a program, for tools that want to look at bc code
+//! without linking the compiler.
+//!
+//! The output is an object with three members:
+//!
+//! - `schema`: [`SCHEMA_VERSION`], bumped whenever anything below changes in
+//!   a way that could break a reader. New members can be added without a bump.
+//! - `ast`: `{ "items": [...] }`, every top level definition in the order it
+//!   was written. Items, struct and union fields, enum variants, the methods
+//!   of an `impl`, statements and expressions all have a `span`.
+//! - `model`: what the checker worked out, sorted by path. `types` have their
+//!   `size` and `align` and each struct member its `offset`, `globals` their
+//!   evaluated `value` and `functions` their resolved signatures.
+//!
+//! A span is `{ "start", "end", "line", "column" }`, byte offsets of where it
+//! starts and ends, and the line and character it starts at counting from 1.
+//!
+//! Types are objects with a `kind`: `primitive` with a `name` like `i32`,
+//! `named` with a `path`, `pointer` and `reference` with `mutable` and `to`,
+//! `array` with `of` and a `length` that is `null` for unsized arrays, and
+//! `function` with `params` and `return`. In the AST an array `length` is an
+//! expression, in the model a number.
+//!
+//! Expressions and statements are objects with a `kind` and a `span` too,
+//! their other members are named after the parts of the syntax they stand
+//! for, see [`Exporter::expression`] and [`Exporter::statement`]. An
+//! expression in parentheses spans them as well.
+
+use crate::{
+    json::Json,
+    parser::ast::{
+        self, Block, DefKind, Expression, ExpressionKind, FloatType, GlobalKind, IntSize, Literal,
+        Module, Path, Span, Statement, StatementKind, Vis,
+    },
+    stage::{constant_eval::Value, types::Type, Context, FunctionKind, UserType},
+};
+
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Describes a module parsed from `src` and the context it was checked into
+pub fn export(src: &str, module: &Module, context: &Context) -> Json {
+    Json::object([
+        ("schema", SCHEMA_VERSION.into()),
+        ("ast", ast(src, module)),
+        ("model", model(context)),
+    ])
+}
+
+pub fn ast(src: &str, module: &Module) -> Json {
+    let exporter = Exporter::new(src);
+    let mut function_def = module.function_def.iter();
+    let mut function_header = module.function_header.iter();
+    let mut struct_def = module.struct_def.iter();
+    let mut union_def = module.union_def.iter();
+    let mut enum_def = module.enum_def.iter();
+    let mut glob_def = module.glob_def.iter();
+    let mut impl_def = module.impl_def.iter();
+    let mut items = Vec::new();
+    for kind in &module.order {
+        let item = match kind {
+            DefKind::FunctionDef => function_def_json(&exporter, function_def.next().unwrap()),
+            DefKind::FunctionHeader => {
+                let function = function_header.next().unwrap();
+                let params = function.params.iter().map(|(ty, name)| {
+                    Json::object([("name", name.as_str().into()), ("type", exporter.ty(ty))])
+                });
+                Json::object([
+                    ("kind", "function_header".into()),
+                    ("name", function.name.as_str().into()),
+                    ("span", exporter.span(function.span)),
+                    ("abi", function.kind.clone().into()),
+                    ("params", Json::Array(params.collect())),
+                    (
+                        "return",
+                        function.ret.as_ref().map(|ty| exporter.ty(ty)).into(),
+                    ),
+                ])
+            }
+            DefKind::StructDef | DefKind::UnionDef => {
+                let (keyword, name, values, span) = match kind {
+                    DefKind::StructDef => {
+                        let def = struct_def.next().unwrap();
+                        ("struct", &def.name, &def.values, def.span)
+                    }
+                    _ => {
+                        let def = union_def.next().unwrap();
+                        ("union", &def.name, &def.values, def.span)
+                    }
+                };
+                let fields = values.iter().map(|(ty, name, span)| {
+                    Json::object([
+                        ("name", name.as_str().into()),
+                        ("span", exporter.span(*span)),
+                        ("type", exporter.ty(ty)),
+                    ])
+                });
+                Json::object([
+                    ("kind", keyword.into()),
+                    ("name", name.as_str().into()),
+                    ("span", exporter.span(span)),
+                    ("fields", Json::Array(fields.collect())),
+                ])
+            }
+            DefKind::EnumDef => {
+                let def = enum_def.next().unwrap();
+                let variants = def.values.iter().map(|(name, span)| {
+                    Json::object([
+                        ("name", name.as_str().into()),
+                        ("span", exporter.span(*span)),
+                    ])
+                });
+                Json::object([
+                    ("kind", "enum".into()),
+                    ("name", def.name.as_str().into()),
+                    ("span", exporter.span(def.span)),
+                    ("variants", Json::Array(variants.collect())),
+                ])
+            }
+            DefKind::GlobalDef => {
+                let def = glob_def.next().unwrap();
+                let keyword = match def.kind {
+                    GlobalKind::Const => "const",
+                    GlobalKind::Static => "static",
+                    GlobalKind::Extern => "extern_static",
+                };
+                Json::object([
+                    ("kind", keyword.into()),
+                    ("name", def.name.as_str().into()),
+                    ("span", exporter.span(def.span)),
+                    ("public", (def.vis == Vis::Pub).into()),
+                    ("type", exporter.ty(&def.ty)),
+                    (
+                        "value",
+                        def.value
+                            .as_ref()
+                            .map(|value| exporter.expression(value))
+                            .into(),
+                    ),
+                ])
+            }
+            DefKind::ImplDef => {
+                let def = impl_def.next().unwrap();
+                let functions = def
+                    .functions
+                    .iter()
+                    .map(|function| function_def_json(&exporter, function));
+                Json::object([
+                    ("kind", "impl".into()),
+                    ("span", exporter.span(def.span)),
+                    ("type", exporter.ty(&def.ty)),
+                    ("functions", Json::Array(functions.collect())),
+                ])
+            }
+        };
+        items.push(item);
+    }
+    Json::object([("items", Json::Array(items))])
+}
+
+/// Describes the nodes of a module, with their spans as lines and columns of
+/// the source it was parsed from
+struct Exporter<'a> {
+    src: &'a str,
+    /// Where each line starts
+    starts: Vec<usize>,
+}
+
+impl<'a> Exporter<'a> {
+    fn new(src: &'a str) -> Self {
+        let starts = src.match_indices('\n').map(|(i, _)| i + 1);
+        Self {
+            src,
+            starts: std::iter::once(0).chain(starts).collect(),
+        }
+    }
+
+    fn span(&self, Span { start, end }: Span) -> Json {
+        let line = self.starts.partition_point(|&line| line <= start);
+        let column = self.src[self.starts[line - 1]..start].chars().count() + 1;
+        Json::object([
+            ("start", start.into()),
+            ("end", end.into()),
+            ("line", line.into()),
+            ("column", column.into()),
+        ])
+    }
+
+    fn ty(&self, ty: &ast::Type) -> Json {
+        match ty {
+            ast::Type::Int(size, signed) => primitive(int_name(size, *signed)),
+            ast::Type::Float(FloatType::F32) => primitive("f32".into()),
+            ast::Type::Float(FloatType::F64) => primitive("f64".into()),
+            ast::Type::Bool => primitive("bool".into()),
+            ast::Type::Char => primitive("char".into()),
+            ast::Type::Void => primitive("void".into()),
+            ast::Type::Str => primitive("str".into()),
+            ast::Type::FnPointer(params, ret) => function_type(
+                params.iter().map(|ty| self.ty(ty)).collect(),
+                ret.as_deref().map(|ty| self.ty(ty)).into(),
+            ),
+            ast::Type::Nammed(path) => named(path),
+            ast::Type::Ptr(inner, mutable) => pointer("pointer", *mutable, self.ty(inner)),
+            ast::Type::Ref(inner, mutable) => pointer("reference", *mutable, self.ty(inner)),
+            ast::Type::Array(inner) => array(self.ty(inner), Json::Null),
+            ast::Type::ArrayStatic(inner, length) => array(self.ty(inner), self.expression(length)),
+        }
+    }
+
+    fn statements(&self, statements: &[Statement]) -> Json {
+        Json::Array(statements.iter().map(|stmt| self.statement(stmt)).collect())
+    }
+
+    fn statement(&self, statement: &Statement) -> Json {
+        let (kind, members): (&str, Vec<(&str, Json)>) = match &statement.kind {
+            StatementKind::Expression(expr) => {
+                ("expression", vec![("expression", self.expression(expr))])
+            }
+            StatementKind::VariableDeclaration(mutable, ty, name, value) => (
+                "declaration",
+                vec![
+                    ("mutable", (*mutable).into()),
+                    ("type", self.ty(ty)),
+                    ("name", name.as_str().into()),
+                    (
+                        "value",
+                        value.as_ref().map(|value| self.expression(value)).into(),
+                    ),
+                ],
+            ),
+        };
+        self.node(kind, statement.span, members)
+    }
+
+    /// An expression as an object with a `kind` and members for its parts
+    fn expression(&self, expr: &Expression) -> Json {
+        let boxed = |expr: &Expression| self.expression(expr);
+        let list = |exprs: &[Expression]| Json::Array(exprs.iter().map(boxed).collect());
+        let statements = |statements: &[Statement]| self.statements(statements);
+        let label = |label: &Option<String>| Json::from(label.clone());
+        let (kind, members): (&str, Vec<(&str, Json)>) = match &expr.kind {
+            ExpressionKind::Path(path) => ("path", vec![("path", path.to_string().into())]),
+            ExpressionKind::Literal(literal) => {
+                let (ty, value) = match literal {
+                    Literal::String(string) => ("string", string.as_str().into()),
+                    Literal::Char(char) => ("char", char.as_str().into()),
+                    Literal::Boolean(bool) => ("bool", (*bool).into()),
+                    // numbers keep their suffix, so they stay text
+                    Literal::Number(number) => ("number", number.as_str().into()),
+                };
+                ("literal", vec![("type", ty.into()), ("value", value)])
+            }
+            ExpressionKind::Block(block) => match block {
+                Block::Scope(name, body) => (
+                    "scope",
+                    vec![("label", label(name)), ("body", statements(body))],
+                ),
+                Block::While(name, condition, body) => (
+                    "while",
+                    vec![
+                        ("label", label(name)),
+                        ("condition", boxed(condition)),
+                        ("body", statements(body)),
+                    ],
+                ),
+                Block::Loop(name, body) => (
+                    "loop",
+                    vec![("label", label(name)), ("body", statements(body))],
+                ),
+                Block::For(name, binding, iterator, body) => (
+                    "for",
+                    vec![
+                        ("label", label(name)),
+                        ("name", binding.as_str().into()),
+                        ("iterator", boxed(iterator)),
+                        ("body", statements(body)),
+                    ],
+                ),
+                Block::If(name, condition, body, else_ifs, otherwise) => {
+                    let else_ifs = else_ifs.iter().map(|(condition, body)| {
+                        Json::object([("condition", boxed(condition)), ("body", statements(body))])
+                    });
+                    (
+                        "if",
+                        vec![
+                            ("label", label(name)),
+                            ("condition", boxed(condition)),
+                            ("body", statements(body)),
+                            ("else_ifs", Json::Array(else_ifs.collect())),
+                            ("else", otherwise.as_deref().map(statements).into()),
+                        ],
+                    )
+                }
+            },
+            ExpressionKind::FieldAccess(value, field) => (
+                "field",
+                vec![("value", boxed(value)), ("field", field.as_str().into())],
+            ),
+            ExpressionKind::MemberFunction(value, method, args) => (
+                "method_call",
+                vec![
+                    ("value", boxed(value)),
+                    ("method", method.as_str().into()),
+                    ("args", list(args)),
+                ],
+            ),
+            ExpressionKind::ArrayAccess(value, index) => (
+                "index",
+                vec![("value", boxed(value)), ("index", boxed(index))],
+            ),
+            ExpressionKind::FunctionCall(function, args) => (
+                "call",
+                vec![("function", boxed(function)), ("args", list(args))],
+            ),
+            ExpressionKind::UnaryOp(op, value) => (
+                "unary",
+                vec![("op", op.to_string().into()), ("value", boxed(value))],
+            ),
+            ExpressionKind::BinaryOp(left, op, right) => (
+                "binary",
+                vec![
+                    ("left", boxed(left)),
+                    ("op", op.to_string().into()),
+                    ("right", boxed(right)),
+                ],
+            ),
+            ExpressionKind::Assign(place, value) => (
+                "assign",
+                vec![("place", boxed(place)), ("value", boxed(value))],
+            ),
+            ExpressionKind::CompoundAssign(place, op, value) => (
+                "compound_assign",
+                vec![
+                    ("place", boxed(place)),
+                    ("op", op.to_string().into()),
+                    ("value", boxed(value)),
+                ],
+            ),
+            ExpressionKind::SizeOf(ty) => ("size_of", vec![("type", self.ty(ty))]),
+            ExpressionKind::AlignOf(ty) => ("align_of", vec![("type", self.ty(ty))]),
+            ExpressionKind::Sized(ty) => ("sized", vec![("type", self.ty(ty))]),
+            ExpressionKind::TypeName(ty) => ("type_name", vec![("type", self.ty(ty))]),
+            ExpressionKind::OffsetOf(ty, field) => (
+                "offset_of",
+                vec![("type", self.ty(ty)), ("field", field.as_str().into())],
+            ),
+            ExpressionKind::Range(start, end, inclusive) => (
+                "range",
+                vec![
+                    ("start", boxed(start)),
+                    ("end", boxed(end)),
+                    ("inclusive", (*inclusive).into()),
+                ],
+            ),
+            ExpressionKind::StructCon(path, fields) => {
+                let fields = fields.iter().map(|(name, value)| {
+                    Json::object([("name", name.as_str().into()), ("value", boxed(value))])
+                });
+                (
+                    "struct",
+                    vec![
+                        ("path", path.to_string().into()),
+                        ("fields", Json::Array(fields.collect())),
+                    ],
+                )
+            }
+            ExpressionKind::ArrayCon(values) => ("array", vec![("values", list(values))]),
+            ExpressionKind::Break(name, value) => (
+                "break",
+                vec![
+                    ("label", label(name)),
+                    ("value", value.as_deref().map(boxed).into()),
+                ],
+            ),
+            ExpressionKind::Continue(name) => ("continue", vec![("label", label(name))]),
+            ExpressionKind::Return(value) => (
+                "return",
+                vec![("value", value.as_deref().map(boxed).into())],
+            ),
+        };
+        self.node(kind, expr.span, members)
+    }
+
+    /// A statement or expression, with its `kind` and `span` before its parts
+    fn node(&self, kind: &str, span: Span, members: Vec<(&str, Json)>) -> Json {
+        let head = [("kind", kind.into()), ("span", self.span(span))];
+        Json::object(head.into_iter().chain(members))
+    }
+}
+
+fn function_def_json(exporter: &Exporter, function: &ast::FunctionDef) -> Json {
+    let params = function.params.iter().map(|(mutable, ty, name)| {
+        Json::object([
+            ("name", name.as_str().into()),
+            ("mutable", (*mutable).into()),
+            ("type", exporter.ty(ty)),
+        ])
+    });
+    Json::object([
+        ("kind", "function".into()),
+        ("name", function.name.as_str().into()),
+        ("span", exporter.span(function.span)),
+        ("public", (function.vis == Vis::Pub).into()),
+        ("abi", function.kind.clone().into()),
+        ("params", Json::Array(params.collect())),
+        (
+            "return",
+            function.ret.as_ref().map(|ty| exporter.ty(ty)).into(),
+        ),
+        ("body", exporter.statements(&function.body)),
+    ])
+}
+
+fn primitive(name: String) -> Json {
+    Json::object([("kind", "primitive".into()), ("name", name.into())])
+}
+
+fn int_name(size: &IntSize, signed: bool) -> String {
+    let bits = match size {
+        IntSize::U8 => "8",
+        IntSize::U16 => "16",
+        IntSize::U32 => "32",
+        IntSize::U64 => "64",
+        IntSize::Usize => "size",
+    };
+    format!("{}{bits}", if signed { 'i' } else { 'u' })
+}
+
+fn pointer(kind: &str, mutable: bool, to: Json) -> Json {
+    Json::object([
+        ("kind", kind.into()),
+        ("mutable", mutable.into()),
+        ("to", to),
+    ])
+}
+
+fn array(of: Json, length: Json) -> Json {
+    Json::object([("kind", "array".into()), ("of", of), ("length", length)])
+}
+
+fn function_type(params: Vec<Json>, ret: Json) -> Json {
+    Json::object([
+        ("kind", "function".into()),
+        ("params", Json::Array(params)),
+        ("return", ret),
+    ])
+}
+
+fn model_type(ty: &Type) -> Json {
+    match ty {
+        Type::Int(size, signed) => primitive(int_name(size, *signed)),
+        Type::FnPointer(params, ret) => function_type(
+            params.iter().map(model_type).collect(),
+            ret.as_deref().map(model_type).into(),
+        ),
+        Type::Nammed(path) => named(path),
+        Type::Ptr(inner, mutable) => pointer("pointer", *mutable, model_type(inner)),
+        Type::Ref(inner, mutable) => pointer("reference", *mutable, model_type(inner)),
+        Type::Array(inner) => array(model_type(inner), Json::Null),
+        Type::ArrayStatic(inner, length) => array(model_type(inner), (*length).into()),
+        primitive_type => primitive(primitive_type.to_string()),
+    }
+}
+
+fn named(path: &Path) -> Json {
+    Json::object([("kind", "named".into()), ("path", path.to_string().into())])
+}
+
+pub fn model(context: &Context) -> Json {
+    let mut types: Vec<_> = context.user_types().collect();
+    types.sort_by_key(|(path, _)| path.to_string());
+    let types = types.into_iter().filter_map(|(path, ty)| {
+        let layout = context.computed_layout(path);
+        let (kind, members) = match ty {
+            UserType::Struct(struc) => {
+                let members = struc.members.iter().map(|member| {
+                    Json::object([
+                        ("name", member.name.as_str().into()),
+                        ("type", model_type(&member.ty)),
+                        ("offset", member.offset.into()),
+                    ])
+                });
+                ("struct", ("members", Json::Array(members.collect())))
+            }
+            UserType::Union(unio) => {
+                let members = unio.members.iter().map(|member| {
+                    Json::object([
+                        ("name", member.name.as_str().into()),
+                        ("type", model_type(&member.ty)),
+                        ("offset", 0.into()),
+                    ])
+                });
+                ("union", ("members", Json::Array(members.collect())))
+            }
+            UserType::Enum(enu) => {
+                let variants = enu.members.iter().map(|variant| {
+                    Json::object([
+                        ("name", variant.name.as_str().into()),
+                        ("value", variant.value.into()),
+                    ])
+                });
+                ("enum", ("variants", Json::Array(variants.collect())))
+            }
+            UserType::Unresolved(..) | UserType::_Processing => return None,
+        };
+        Some(Json::object([
+            ("path", path.to_string().into()),
+            ("kind", kind.into()),
+            ("size", layout.map(|layout| layout.size_bytes()).into()),
+            ("align", layout.map(|layout| layout.align().get()).into()),
+            ("sized", layout.map(|layout| layout.is_sized()).into()),
+            members,
+        ]))
+    });
+
+    let mut globals: Vec<_> = context.globals().collect();
+    globals.sort_by_key(|(path, _)| path.to_string());
+    let globals = globals.into_iter().map(|(path, var)| {
+        let kind = match var.kind {
+            GlobalKind::Const => "const",
+            GlobalKind::Static => "static",
+            GlobalKind::Extern => "extern_static",
+        };
+        Json::object([
+            ("path", path.to_string().into()),
+            ("kind", kind.into()),
+            ("public", (var.vis == Vis::Pub).into()),
+            ("type", model_type(&var.ty)),
+            ("value", var.value.as_ref().map(value).into()),
+        ])
+    });
+
+    let mut functions: Vec<_> = context
+        .functions()
+        .filter_map(|(_, function)| Some((function.sig.name.as_ref()?, function)))
+        .collect();
+    functions.sort_by_key(|(path, _)| path.to_string());
+    let functions = functions.into_iter().map(|(path, function)| {
+        let sig = &function.sig;
+        let params = sig.params.iter().map(|(ty, name)| {
+            Json::object([("name", name.as_str().into()), ("type", model_type(ty))])
+        });
+        let abi = match &function.kind {
+            FunctionKind::Declaration(abi) => Some(abi.clone()),
+            FunctionKind::Definition { external, .. } => external.clone(),
+        };
+        let defined = matches!(function.kind, FunctionKind::Definition { .. });
+        Json::object([
+            ("path", path.to_string().into()),
+            ("public", (function.vis == Vis::Pub).into()),
+            ("defined", defined.into()),
+            ("abi", abi.into()),
+            ("method", sig.is_method().into()),
+            ("params", Json::Array(params.collect())),
+            ("return", model_type(&sig.ret_ty)),
+        ])
+    });
+
+    Json::object([
+        ("types", Json::Array(types.collect())),
+        ("globals", Json::Array(globals.collect())),
+        ("functions", Json::Array(functions.collect())),
+    ])
+}
+
+/// A constant value, numbers as numbers whatever their type
+fn value(value: &Value) -> Json {
+    match value {
+        Value::U8(v) => (*v).into(),
+        Value::U16(v) => (*v).into(),
+        Value::U32(v) => (*v).into(),
+        Value::U64(v) => (*v).into(),
+        Value::I8(v) => (*v).into(),
+        Value::I16(v) => (*v).into(),
+        Value::I32(v) => (*v).into(),
+        Value::I64(v) => (*v).into(),
+        // printed as an f32 so it doesn't pick up digits from widening
+        Value::F32(v) if v.is_finite() => Json::Number(format!("{v:?}")),
+        Value::F32(_) => Json::Null,
+        Value::F64(v) => (*v).into(),
+        Value::Bool(v) => (*v).into(),
+        Value::Char(v) => v.to_string().into(),
+        Value::Void(()) => Json::Null,
+        Value::Str(v) => v.as_str().into(),
+        Value::Ref(path) => Json::object([("reference", path.to_string().into())]),
+        Value::Ptr(path) => Json::object([("pointer", path.to_string().into())]),
+        Value::Array(values) | Value::Struct(values) => {
+            Json::Array(values.iter().map(self::value).collect())
+        }
+        Value::Enum(variant) => (*variant).into(),
+        Value::Union(member, inner) => {
+            Json::object([("member", (*member).into()), ("value", self::value(inner))])
+        }
+    }
+}
+
+#[test]
+fn export_test() {
+    let src = "\
+struct Point {
+    u8 tag,
+    i32 x,
+}
+struct Buffer { usize len, [u8; 16] data }
+union Bits { u32 int, f32 float }
+enum Color { Red, Green }
+pub const f32 SCALE = 0.1f32;
+static u8 COUNT = 3;
+extern \"C\" fn puts(*u8 str) i32;
+impl Point {
+    fn sum(&Self self) i32 {
+        return self.x * 2;
+    }
+}
+fn main() i32 {
+    Point p = Point { tag = 1, x = 2 };
+    for (i in 0..2) {
+        if (i == 1) { break; }
+    }
+    return p.sum();
+}
+";
+    let program = crate::stage::check::checked_source(src);
+    let module = crate::parser::def::ModuleParser::new().parse(src).unwrap();
+    let json = export(src, &module, &program.context);
+    crate::ir::assert_snapshot("export.json", &json.pretty(2));
+
+    // what tools would look for, checked against the source
+    let items = json.get("ast").and_then(|ast| ast.get("items")).unwrap();
+    let items = items.as_array().unwrap();
+    let span = |item: &Json| {
+        let span = item.get("span").unwrap();
+        let at = |key| span.get(key).and_then(Json::as_u64).unwrap() as usize;
+        &src[at("start")..at("end")]
+    };
+    assert_eq!(items.len(), 9);
+    assert_eq!(span(&items[7]).lines().next(), Some("impl Point {"));
+    let fields = items[1].get("fields").and_then(Json::as_array).unwrap();
+    assert_eq!(span(&fields[1]), "[u8; 16] data");
+    let main = &items[8];
+    assert_eq!(
+        main.get("span").and_then(|span| span.get("line")),
+        Some(&16.into())
+    );
+    let body = main.get("body").and_then(Json::as_array).unwrap();
+    assert_eq!(span(&body[0]), "Point p = Point { tag = 1, x = 2 };");
+    let value = body[0].get("value").unwrap();
+    assert_eq!(span(value), "Point { tag = 1, x = 2 }");
+    assert_eq!(span(&body[1]).lines().last(), Some("    }"));
+    let returned = body[2].get("expression").unwrap();
+    assert_eq!(span(returned), "return p.sum()");
+    let call = returned.get("value").unwrap();
+    assert_eq!(span(call), "p.sum()");
+    assert_eq!(span(call.get("value").unwrap()), "p");
+    assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+}
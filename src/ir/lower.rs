@@ -0,0 +1,1250 @@
+//! Lowers checked function bodies to the IR.
+//!
+//! Every local lives in its own stack slot and is read and written with
+//! explicit loads and stores, promoting them to registers is left to the
+//! optimization passes.
+
+use std::collections::HashMap;
+
+use crate::{
+    parser::ast::{BinOpKind, FloatType, GlobalKind, IntSize, Path, UnaryOpKind, Vis},
+    stage::{
+        constant_eval::Value,
+        tree::{self, Body, Coercion, Expr, ExprKind, LabelId, Stmt},
+        types::{Layout, Type},
+        Context, FunctionId, FunctionKind, Global, Resolvable, UserType,
+    },
+};
+
+use super::{
+    BinOp, Block, BlockId, CastOp, CmpOp, Data, DataPart, Extern, Function, Inst, Module, Operand,
+    Reg, Slot, SlotId, Target, Terminator, Ty, UnOp,
+};
+
+/// How values of a type are handled
+enum Repr {
+    Void,
+    Scalar(Ty),
+    /// Handled through their address
+    Memory(Layout),
+}
+
+/// The result of lowering an expression
+enum Val {
+    Void,
+    Scalar(Operand),
+    /// The address of an aggregate, it has to be copied before anything else
+    /// can write to it
+    Mem(Operand),
+}
+
+type Place = crate::stage::Place<Operand>;
+
+/// Where the value of a block expression ends up
+#[derive(Clone)]
+enum Dest {
+    None,
+    Param(Reg),
+    Memory(Operand),
+}
+
+struct Label {
+    exit: BlockId,
+    /// Where `continue` goes for loops
+    cont: Option<BlockId>,
+    result: Dest,
+}
+
+/// Lowers every checked function of a program that checked without errors
+pub fn lower(context: &mut Context) -> Module {
+    let mut lower = Lower {
+        context,
+        module: Module::default(),
+        strings: HashMap::new(),
+        consts: 0,
+    };
+
+    let mut globals: Vec<_> = lower
+        .context
+        .globals()
+        .map(|(path, var)| (path.clone(), var.clone()))
+        .collect();
+    globals.sort_by_key(|(path, _)| path.to_string());
+    for (path, var) in globals {
+        let init = match (&var.kind, &var.value) {
+            (GlobalKind::Const, _) => continue,
+            (GlobalKind::Static, Some(value)) => Some(lower.data_parts(value, &var.ty)),
+            _ => None,
+        };
+        let data = Data {
+            vis: var.vis,
+            name: lower.static_symbol(&path),
+            align: var.ty.layout(lower.context).align().get(),
+            mutable: true,
+            init,
+        };
+        lower.module.data.push(data);
+    }
+
+    let ids: Vec<FunctionId> = lower.context.functions().map(|(id, _)| id).collect();
+    for id in ids {
+        let function = lower.context.function(id);
+        let name = function_symbol(lower.context, id);
+        let sig = function.sig.clone();
+        match &function.kind {
+            FunctionKind::Declaration(_) => {
+                let params: Vec<_> = sig.params.iter().map(|(ty, _)| ty.clone()).collect();
+                let (params, ret) = signature(lower.context, &params, &sig.ret_ty);
+                lower.module.externs.push(Extern { name, params, ret });
+            }
+            FunctionKind::Definition {
+                code: Resolvable::Resolved(body),
+                ..
+            } => {
+                let body = body.clone();
+                let vis = match &function.kind {
+                    FunctionKind::Definition {
+                        external: Some(_), ..
+                    } => Vis::Pub,
+                    _ if sig.name == Some(Path::new_path("main")) => Vis::Pub,
+                    _ => function.vis,
+                };
+                let mut function = Builder::new(&mut lower, name, &body).finish();
+                function.vis = vis;
+                lower.module.functions.push(function);
+            }
+            FunctionKind::Definition { .. } => {}
+        }
+    }
+    lower.module
+}
+
+fn repr(context: &mut Context, ty: &Type) -> Repr {
+    match ty {
+        Type::Void => Repr::Void,
+        Type::Int(size, _) => Repr::Scalar(match size {
+            IntSize::U8 => Ty::I8,
+            IntSize::U16 => Ty::I16,
+            IntSize::U32 => Ty::I32,
+            IntSize::U64 | IntSize::Usize => Ty::I64,
+        }),
+        Type::Float(FloatType::F32) => Repr::Scalar(Ty::F32),
+        Type::Float(FloatType::F64) => Repr::Scalar(Ty::F64),
+        Type::Bool => Repr::Scalar(Ty::Bool),
+        Type::Char => Repr::Scalar(Ty::I8),
+        Type::FnPointer(..) => Repr::Scalar(Ty::Ptr),
+        Type::Ptr(inner, _) | Type::Ref(inner, _) if inner.is_sized(context) => {
+            Repr::Scalar(Ty::Ptr)
+        }
+        Type::Nammed(path) if matches!(context.user_type(path), Some(UserType::Enum(_))) => {
+            match context.layout(path).size_bytes() {
+                0 => Repr::Void,
+                1 => Repr::Scalar(Ty::I8),
+                2 => Repr::Scalar(Ty::I16),
+                4 => Repr::Scalar(Ty::I32),
+                _ => Repr::Scalar(Ty::I64),
+            }
+        }
+        _ => Repr::Memory(ty.layout(context)),
+    }
+}
+
+/// The IR parameters and return type of a function, aggregates are returned
+/// through a pointer passed first
+fn signature(context: &mut Context, params: &[Type], ret: &Type) -> (Vec<Ty>, Option<Ty>) {
+    let mut tys = Vec::new();
+    let ret = match repr(context, ret) {
+        Repr::Void => None,
+        Repr::Scalar(ty) => Some(ty),
+        Repr::Memory(_) => {
+            tys.push(Ty::Ptr);
+            None
+        }
+    };
+    for param in params {
+        match repr(context, param) {
+            Repr::Void => {}
+            Repr::Scalar(ty) => tys.push(ty),
+            Repr::Memory(_) => tys.push(Ty::Ptr),
+        }
+    }
+    (tys, ret)
+}
+
+/// Chooses the operation for floats, signed or unsigned integers, anything
+/// else compares like an unsigned integer
+fn pick<T>(ty: &Type, float: T, signed: T, unsigned: T) -> T {
+    match (ty.is_float(), ty.is_signed()) {
+        (true, _) => float,
+        (false, true) => signed,
+        (false, false) => unsigned,
+    }
+}
+
+/// Joins the parts of a path into a name assemblers and C compilers accept
+pub(crate) fn mangle(path: &Path) -> String {
+    path.parts().collect::<Vec<_>>().join("__")
+}
+
+/// Functions defined or declared with an ABI keep their plain name
+pub(crate) fn function_symbol(context: &Context, id: FunctionId) -> String {
+    let function = context.function(id);
+    let path = function.sig.name.clone().unwrap_or_default();
+    match &function.kind {
+        FunctionKind::Declaration(_)
+        | FunctionKind::Definition {
+            external: Some(_), ..
+        } => path.last().unwrap_or_default().to_string(),
+        FunctionKind::Definition { .. } => mangle(&path),
+    }
+}
+
+/// Extern statics keep their plain name like functions with an ABI
+pub(crate) fn static_symbol(context: &Context, path: &Path) -> String {
+    match context.global(path) {
+        Some(Global::Variable(Resolvable::Resolved(var)))
+            if matches!(var.kind, GlobalKind::Extern) =>
+        {
+            path.last().unwrap_or_default().to_string()
+        }
+        _ => mangle(path),
+    }
+}
+
+struct Lower<'a> {
+    context: &'a mut Context,
+    module: Module,
+    /// The data symbol of every string literal
+    strings: HashMap<String, String>,
+    consts: usize,
+}
+
+#[derive(Default)]
+struct DataWriter {
+    parts: Vec<DataPart>,
+    len: usize,
+}
+
+impl DataWriter {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.len += bytes.len();
+        match self.parts.last_mut() {
+            Some(DataPart::Bytes(last)) => last.extend_from_slice(bytes),
+            _ => self.parts.push(DataPart::Bytes(bytes.to_vec())),
+        }
+    }
+
+    fn addr(&mut self, name: String) {
+        self.len += 8;
+        self.parts.push(DataPart::Addr(name));
+    }
+
+    fn pad_to(&mut self, len: usize) {
+        if len > self.len {
+            self.bytes(&vec![0; len - self.len]);
+        }
+    }
+}
+
+impl Lower<'_> {
+    fn static_symbol(&self, path: &Path) -> String {
+        static_symbol(self.context, path)
+    }
+
+    /// The data symbol holding a string literal, followed by a nul byte
+    /// that isn't part of its length for C functions
+    fn string(&mut self, value: &str) -> String {
+        if let Some(name) = self.strings.get(value) {
+            return name.clone();
+        }
+        let name = format!("__str_{}", self.strings.len());
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        self.module.data.push(Data {
+            vis: Vis::Priv,
+            name: name.clone(),
+            align: 1,
+            mutable: false,
+            init: Some(vec![DataPart::Bytes(bytes)]),
+        });
+        self.strings.insert(value.to_string(), name.clone());
+        name
+    }
+
+    /// Read only data holding an aggregate constant
+    fn const_data(&mut self, value: &Value, ty: &Type) -> String {
+        let name = format!("__const_{}", self.consts);
+        self.consts += 1;
+        let init = self.data_parts(value, ty);
+        let align = ty.layout(self.context).align().get();
+        self.module.data.push(Data {
+            vis: Vis::Priv,
+            name: name.clone(),
+            align,
+            mutable: false,
+            init: Some(init),
+        });
+        name
+    }
+
+    fn data_parts(&mut self, value: &Value, ty: &Type) -> Vec<DataPart> {
+        let mut out = DataWriter::default();
+        self.write_value(&mut out, value, ty);
+        out.parts
+    }
+
+    fn write_value(&mut self, out: &mut DataWriter, value: &Value, ty: &Type) {
+        let start = out.len;
+        let size = ty.layout(self.context).size_bytes();
+        match value {
+            Value::Void(()) => {}
+            Value::F32(value) => out.bytes(&value.to_le_bytes()),
+            Value::F64(value) => out.bytes(&value.to_le_bytes()),
+            Value::Str(value) => {
+                let name = self.string(value);
+                out.addr(name);
+                out.bytes(&(value.len() as u64).to_le_bytes());
+            }
+            Value::Ref(path) | Value::Ptr(path) => out.addr(self.static_symbol(path)),
+            Value::Array(values) => {
+                let element = ty.element().unwrap();
+                for value in values {
+                    self.write_value(out, value, element);
+                }
+            }
+            Value::Struct(values) => {
+                let members = self.context.members(ty);
+                for (value, member) in values.iter().zip(members) {
+                    out.pad_to(start + member.offset);
+                    self.write_value(out, value, &member.ty);
+                }
+            }
+            Value::Union(member, value) => {
+                let ty = self.context.members(ty).swap_remove(*member).ty;
+                self.write_value(out, value, &ty);
+            }
+            _ => {
+                let bits = value.as_int().unwrap() as u64;
+                out.bytes(&bits.to_le_bytes()[..size]);
+            }
+        }
+        out.pad_to(start + size);
+    }
+}
+
+struct Builder<'l, 'c> {
+    lower: &'l mut Lower<'c>,
+    func: Function,
+    /// How many jumps lead to each block, blocks without any are dropped
+    preds: Vec<usize>,
+    /// Blocks in the order they were filled, exits end up after their bodies
+    order: Vec<BlockId>,
+    /// The block being filled, `None` after a terminator until the next
+    /// block that can be reached
+    current: Option<BlockId>,
+    /// The address of every local
+    locals: Vec<Operand>,
+    local_tys: Vec<Type>,
+    labels: Vec<Option<Label>>,
+    /// Where aggregate return values are written
+    sret: Option<Operand>,
+}
+
+impl<'l, 'c> Builder<'l, 'c> {
+    fn new(lower: &'l mut Lower<'c>, name: String, body: &Body) -> Self {
+        let mut builder = Builder {
+            lower,
+            func: Function {
+                vis: Vis::Priv,
+                name,
+                ret: None,
+                regs: Vec::new(),
+                slots: Vec::new(),
+                blocks: Vec::new(),
+            },
+            preds: Vec::new(),
+            order: Vec::new(),
+            current: None,
+            locals: Vec::new(),
+            local_tys: body.locals.iter().map(|local| local.ty.clone()).collect(),
+            labels: (0..body.labels).map(|_| None).collect(),
+            sret: None,
+        };
+        let entry = builder.new_block();
+        builder.preds[entry.0] = 1;
+        builder.switch_to(entry);
+
+        match builder.repr(&body.ret_ty) {
+            Repr::Void => {}
+            Repr::Scalar(ty) => builder.func.ret = Some(ty),
+            Repr::Memory(_) => {
+                let reg = builder.block_param(entry, Ty::Ptr);
+                builder.sret = Some(Operand::Reg(reg));
+            }
+        }
+        let params: Vec<_> = body.locals[..body.params]
+            .iter()
+            .map(|local| match builder.repr(&local.ty) {
+                Repr::Void => None,
+                Repr::Scalar(ty) => Some(builder.block_param(entry, ty)),
+                Repr::Memory(_) => Some(builder.block_param(entry, Ty::Ptr)),
+            })
+            .collect();
+
+        for (id, local) in body.locals.iter().enumerate() {
+            let param = params.get(id).copied().flatten();
+            let addr = match (builder.repr(&local.ty), param) {
+                // the caller already made a copy
+                (Repr::Memory(_), Some(param)) => Operand::Reg(param),
+                (_, param) => {
+                    let layout = local.ty.layout(builder.lower.context);
+                    let addr = builder.slot(layout);
+                    if let Some(param) = param {
+                        builder.store(addr.clone(), Operand::Reg(param));
+                    }
+                    addr
+                }
+            };
+            builder.locals.push(addr);
+        }
+
+        builder.stmts(&body.stmts);
+        match builder.func.ret {
+            None => builder.terminate(Terminator::Return(None)),
+            // the flow checks make sure this can't be reached
+            Some(_) => builder.terminate(Terminator::Unreachable),
+        }
+        builder
+    }
+
+    /// Puts the blocks in order and drops the ones nothing jumps to
+    fn finish(mut self) -> Function {
+        let mut map = vec![None; self.func.blocks.len()];
+        let mut old = std::mem::take(&mut self.func.blocks);
+        let mut blocks = Vec::new();
+        for id in self.order {
+            map[id.0] = Some(BlockId(blocks.len()));
+            blocks.push(std::mem::replace(
+                &mut old[id.0],
+                Block {
+                    params: Vec::new(),
+                    insts: Vec::new(),
+                    term: Terminator::Unreachable,
+                },
+            ));
+        }
+        for block in &mut blocks {
+            for target in block.term.targets_mut() {
+                target.block = map[target.block.0].unwrap();
+            }
+        }
+        self.func.blocks = blocks;
+        self.func
+    }
+
+    fn repr(&mut self, ty: &Type) -> Repr {
+        repr(self.lower.context, ty)
+    }
+
+    fn layout(&mut self, ty: &Type) -> Layout {
+        ty.layout(self.lower.context)
+    }
+
+    fn reg(&mut self, ty: Ty) -> Reg {
+        self.func.regs.push(ty);
+        Reg(self.func.regs.len() - 1)
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.func.blocks.push(Block {
+            params: Vec::new(),
+            insts: Vec::new(),
+            term: Terminator::Unreachable,
+        });
+        self.preds.push(0);
+        BlockId(self.func.blocks.len() - 1)
+    }
+
+    fn block_param(&mut self, block: BlockId, ty: Ty) -> Reg {
+        let reg = self.reg(ty);
+        self.func.blocks[block.0].params.push(reg);
+        reg
+    }
+
+    /// Continues in `block` if anything jumps to it
+    fn switch_to(&mut self, block: BlockId) {
+        self.current = (self.preds[block.0] > 0).then_some(block);
+        if self.current.is_some() {
+            self.order.push(block);
+        }
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        if let Some(block) = self.current {
+            self.func.blocks[block.0].insts.push(inst);
+        }
+    }
+
+    fn def(&mut self, ty: Ty, inst: impl FnOnce(Reg) -> Inst) -> Operand {
+        let reg = self.reg(ty);
+        self.emit(inst(reg));
+        Operand::Reg(reg)
+    }
+
+    fn terminate(&mut self, term: Terminator) {
+        let Some(block) = self.current.take() else {
+            return;
+        };
+        for target in term.targets() {
+            self.preds[target.block.0] += 1;
+        }
+        self.func.blocks[block.0].term = term;
+    }
+
+    fn jump(&mut self, block: BlockId, args: Vec<Operand>) {
+        self.terminate(Terminator::Jump(Target { block, args }));
+    }
+
+    fn branch(&mut self, cond: Operand, then: BlockId, els: BlockId) {
+        let target = |block| Target {
+            block,
+            args: Vec::new(),
+        };
+        self.terminate(Terminator::Branch(cond, target(then), target(els)));
+    }
+
+    fn slot(&mut self, layout: Layout) -> Operand {
+        self.func.slots.push(Slot {
+            size: layout.size_bytes(),
+            align: layout.align().get(),
+        });
+        let slot = SlotId(self.func.slots.len() - 1);
+        self.def(Ty::Ptr, |reg| Inst::SlotAddr(reg, slot))
+    }
+
+    fn binary(&mut self, ty: Ty, op: BinOp, a: Operand, b: Operand) -> Operand {
+        self.def(ty, |reg| Inst::Binary(reg, op, a, b))
+    }
+
+    fn cmp(&mut self, op: CmpOp, a: Operand, b: Operand) -> Operand {
+        self.def(Ty::Bool, |reg| Inst::Cmp(reg, op, a, b))
+    }
+
+    fn load(&mut self, ty: Ty, addr: Operand) -> Operand {
+        self.def(ty, |reg| Inst::Load(reg, addr))
+    }
+
+    fn store(&mut self, addr: Operand, value: Operand) {
+        self.emit(Inst::Store(addr, value));
+    }
+
+    fn offset(&mut self, addr: Operand, offset: usize) -> Operand {
+        if offset == 0 {
+            return addr;
+        }
+        let offset = Operand::Int(Ty::I64, offset as u64);
+        self.def(Ty::Ptr, |reg| Inst::PtrAdd(reg, addr, offset))
+    }
+
+    /// Converts an integer to another width
+    fn resize(&mut self, value: Operand, to: Ty, signed: bool) -> Operand {
+        let from = self.func.operand_ty(&value);
+        if from == to {
+            return value;
+        }
+        if let Operand::Int(_, bits) = value {
+            let bits = match signed {
+                true => from.sign_extend(bits) as u64,
+                false => bits,
+            };
+            return Operand::Int(to, to.truncate(bits));
+        }
+        let op = match (from.size() > to.size(), signed) {
+            (true, _) => CastOp::Trunc,
+            (false, true) => CastOp::SExt,
+            (false, false) => CastOp::ZExt,
+        };
+        self.def(to, |reg| Inst::Cast(reg, op, value))
+    }
+
+    fn read(&mut self, addr: Operand, ty: &Type) -> Val {
+        match self.repr(ty) {
+            Repr::Void => Val::Void,
+            Repr::Scalar(ty) => Val::Scalar(self.load(ty, addr)),
+            Repr::Memory(_) => Val::Mem(addr),
+        }
+    }
+
+    fn write(&mut self, addr: Operand, ty: &Type, value: Val) {
+        match value {
+            Val::Void => {}
+            Val::Scalar(value) => self.store(addr, value),
+            Val::Mem(from) if from == addr => {}
+            Val::Mem(from) => {
+                let size = self.layout(ty).size_bytes();
+                self.emit(Inst::Copy(addr, from, size));
+            }
+        }
+    }
+
+    fn scalar(&mut self, expr: &Expr) -> Operand {
+        match self.expr(expr) {
+            Val::Scalar(value) => value,
+            _ => unreachable!("{} is not a scalar", expr.ty),
+        }
+    }
+
+    fn address(&mut self, expr: &Expr) -> Operand {
+        match self.expr(expr) {
+            Val::Mem(addr) => addr,
+            _ => unreachable!("{} is not kept in memory", expr.ty),
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Expr(expr) => {
+                    self.expr(expr);
+                }
+                Stmt::Let(id, Some(value)) => {
+                    let value_ = self.expr(value);
+                    let ty = self.local_tys[id.0].clone();
+                    self.write(self.locals[id.0].clone(), &ty, value_);
+                }
+                Stmt::Let(_, None) => {}
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Val {
+        match &expr.kind {
+            ExprKind::Local(_)
+            | ExprKind::Static(_)
+            | ExprKind::Field(..)
+            | ExprKind::Index(..)
+            | ExprKind::Unary(UnaryOpKind::Deref, _) => {
+                let place = self.place(expr);
+                self.read(place.addr, &expr.ty)
+            }
+            ExprKind::Function(id) => {
+                Val::Scalar(Operand::Global(function_symbol(self.lower.context, *id)))
+            }
+            ExprKind::Value(value) => self.constant(value, &expr.ty),
+            ExprKind::Block(id, block) => self.block(*id, block, &expr.ty),
+            ExprKind::Call(callee, args) => self.call(callee, args, &expr.ty),
+
+            ExprKind::Unary(UnaryOpKind::Ref | UnaryOpKind::RefMut, inner) => {
+                let place = self.place(inner);
+                if inner.ty.is_sized(self.lower.context) {
+                    return Val::Scalar(place.addr);
+                }
+                let fat = self.slot(Layout::new(16, 8).unwrap());
+                self.store(fat.clone(), place.addr);
+                let len_addr = self.offset(fat.clone(), 8);
+                self.store(len_addr, place.len.unwrap());
+                Val::Mem(fat)
+            }
+            ExprKind::Unary(op, inner) => {
+                let value = self.scalar(inner);
+                let ty = self.func.operand_ty(&value);
+                let op = match op {
+                    UnaryOpKind::Negate if ty.is_float() => UnOp::FNeg,
+                    UnaryOpKind::Negate => UnOp::Neg,
+                    _ => UnOp::Not,
+                };
+                Val::Scalar(self.def(ty, |reg| Inst::Unary(reg, op, value)))
+            }
+
+            ExprKind::Binary(l, op @ (BinOpKind::LogicalAnd | BinOpKind::LogicalOr), r) => {
+                let l = self.scalar(l);
+                let right = self.new_block();
+                let exit = self.new_block();
+                let result = self.block_param(exit, Ty::Bool);
+                let short = Target {
+                    block: exit,
+                    args: vec![Operand::Int(Ty::Bool, (*op == BinOpKind::LogicalOr) as u64)],
+                };
+                let right_target = Target {
+                    block: right,
+                    args: Vec::new(),
+                };
+                self.terminate(match op {
+                    BinOpKind::LogicalAnd => Terminator::Branch(l, right_target, short),
+                    _ => Terminator::Branch(l, short, right_target),
+                });
+                self.switch_to(right);
+                let r = self.scalar(r);
+                self.jump(exit, vec![r]);
+                self.switch_to(exit);
+                Val::Scalar(Operand::Reg(result))
+            }
+            ExprKind::Binary(l, op @ (BinOpKind::Eq | BinOpKind::Neq), r)
+                if matches!(self.repr(&l.ty), Repr::Memory(_)) =>
+            {
+                // fat pointers are equal when both the address and length are
+                let a = self.address(l);
+                let b = self.address(r);
+                let (cmp, join) = match op {
+                    BinOpKind::Eq => (CmpOp::Eq, BinOp::And),
+                    _ => (CmpOp::Ne, BinOp::Or),
+                };
+                let mut parts = Vec::new();
+                for (offset, ty) in [(0, Ty::Ptr), (8, Ty::I64)] {
+                    let a = self.offset(a.clone(), offset);
+                    let a = self.load(ty, a);
+                    let b = self.offset(b.clone(), offset);
+                    let b = self.load(ty, b);
+                    parts.push(self.cmp(cmp, a, b));
+                }
+                let [addr, len] = parts.try_into().unwrap();
+                Val::Scalar(self.binary(Ty::Bool, join, addr, len))
+            }
+            ExprKind::Binary(l, op, r) => {
+                let a = self.scalar(l);
+                let b = self.scalar(r);
+                Val::Scalar(self.arith(*op, a, b, &l.ty))
+            }
+            ExprKind::Assign(place, value) => {
+                let place_ = self.place(place);
+                let value = self.expr(value);
+                self.write(place_.addr, &place.ty, value);
+                Val::Void
+            }
+            ExprKind::CompoundAssign(place, op, value) => {
+                let addr = self.place(place).addr;
+                let value = self.scalar(value);
+                let Repr::Scalar(ty) = self.repr(&place.ty) else {
+                    unreachable!("{} is not a scalar", place.ty)
+                };
+                let current = self.load(ty, addr.clone());
+                let result = self.arith(*op, current, value, &place.ty);
+                self.store(addr, result);
+                Val::Void
+            }
+            ExprKind::Coerce(coercion, inner) => match coercion {
+                Coercion::RefToPtr | Coercion::Immutable => self.expr(inner),
+                Coercion::Unsize(len) => {
+                    let ptr = self.scalar(inner);
+                    let fat = self.slot(Layout::new(16, 8).unwrap());
+                    self.store(fat.clone(), ptr);
+                    let len_addr = self.offset(fat.clone(), 8);
+                    self.store(len_addr, Operand::Int(Ty::I64, *len as u64));
+                    Val::Mem(fat)
+                }
+                Coercion::DataPtr => match self.expr(inner) {
+                    Val::Mem(fat) => Val::Scalar(self.load(Ty::Ptr, fat)),
+                    thin => thin,
+                },
+            },
+
+            ExprKind::StructCon(values) => {
+                let layout = self.layout(&expr.ty);
+                let addr = self.slot(layout);
+                let members = self.lower.context.members(&expr.ty);
+                for (value, member) in values.iter().zip(members) {
+                    let value = self.expr(value);
+                    let at = self.offset(addr.clone(), member.offset);
+                    self.write(at, &member.ty, value);
+                }
+                Val::Mem(addr)
+            }
+            ExprKind::UnionCon(_, value) => {
+                let layout = self.layout(&expr.ty);
+                let addr = self.slot(layout);
+                let value_ = self.expr(value);
+                self.write(addr.clone(), &value.ty, value_);
+                Val::Mem(addr)
+            }
+            ExprKind::ArrayCon(values) => {
+                let layout = self.layout(&expr.ty);
+                let addr = self.slot(layout);
+                let element = expr.ty.element().unwrap().clone();
+                let size = self.layout(&element).size_bytes();
+                for (i, value) in values.iter().enumerate() {
+                    let value = self.expr(value);
+                    let item = self.offset(addr.clone(), i * size);
+                    self.write(item, &element, value);
+                }
+                Val::Mem(addr)
+            }
+
+            ExprKind::Break(label, value) => {
+                let value_ = value.as_ref().map(|value| self.expr(value));
+                let label = self.labels[label.0].as_ref().unwrap();
+                let (exit, result) = (label.exit, label.result.clone());
+                match (result, value_) {
+                    (Dest::Param(_), Some(Val::Scalar(value))) => self.jump(exit, vec![value]),
+                    (Dest::Memory(addr), Some(value_)) => {
+                        let ty = &value.as_ref().unwrap().ty;
+                        self.write(addr, ty, value_);
+                        self.jump(exit, Vec::new());
+                    }
+                    _ => self.jump(exit, Vec::new()),
+                }
+                Val::Void
+            }
+            ExprKind::Continue(label) => {
+                let head = self.labels[label.0].as_ref().unwrap().cont.unwrap();
+                self.jump(head, Vec::new());
+                Val::Void
+            }
+            ExprKind::Return(value) => {
+                let value_ = value.as_ref().map(|value| self.expr(value));
+                match (value_, self.sret.clone()) {
+                    (Some(Val::Scalar(value)), _) => {
+                        self.terminate(Terminator::Return(Some(value)))
+                    }
+                    (Some(value_), Some(sret)) => {
+                        let ty = &value.as_ref().unwrap().ty;
+                        self.write(sret, ty, value_);
+                        self.terminate(Terminator::Return(None));
+                    }
+                    _ => self.terminate(Terminator::Return(None)),
+                }
+                Val::Void
+            }
+        }
+    }
+
+    /// The memory location of an expression, values that aren't places are
+    /// stored to a temporary first
+    fn place(&mut self, expr: &Expr) -> Place {
+        match &expr.kind {
+            ExprKind::Local(id) => Place::thin(self.locals[id.0].clone()),
+            ExprKind::Static(path) => Place::thin(Operand::Global(self.lower.static_symbol(path))),
+            ExprKind::Unary(UnaryOpKind::Deref, inner) => self.deref(inner),
+            ExprKind::Field(base, member) => {
+                let place = self.place(base);
+                let offset = self.lower.context.members(&base.ty)[*member].offset;
+                Place {
+                    addr: self.offset(place.addr, offset),
+                    len: place.len,
+                }
+            }
+            ExprKind::Index(base, index) => {
+                let place = self.place(base);
+                let index_ = self.scalar(index);
+                let index_ = self.resize(index_, Ty::I64, index.ty.is_signed());
+                let element = base.ty.element().unwrap().clone();
+                let size = self.layout(&element).size_bytes();
+                let offset = match (index_, size) {
+                    (Operand::Int(_, index), size) => {
+                        return Place::thin(self.offset(place.addr, index as usize * size));
+                    }
+                    (index, 1) => index,
+                    (index, size) => {
+                        let size = Operand::Int(Ty::I64, size as u64);
+                        self.binary(Ty::I64, BinOp::Mul, index, size)
+                    }
+                };
+                Place::thin(self.def(Ty::Ptr, |reg| Inst::PtrAdd(reg, place.addr, offset)))
+            }
+            _ => match self.expr(expr) {
+                Val::Mem(addr) => Place::thin(addr),
+                value => {
+                    let layout = self.layout(&expr.ty);
+                    let addr = self.slot(layout);
+                    self.write(addr.clone(), &expr.ty, value);
+                    Place::thin(addr)
+                }
+            },
+        }
+    }
+
+    /// The place a reference or pointer points to
+    fn deref(&mut self, pointer: &Expr) -> Place {
+        let pointee = pointer.ty.pointee().unwrap();
+        if pointee.is_sized(self.lower.context) {
+            return Place::thin(self.scalar(pointer));
+        }
+        let fat = self.address(pointer);
+        let addr = self.load(Ty::Ptr, fat.clone());
+        let len_addr = self.offset(fat, 8);
+        let len = self.load(Ty::I64, len_addr);
+        Place {
+            addr,
+            len: Some(len),
+        }
+    }
+
+    fn constant(&mut self, value: &Value, ty: &Type) -> Val {
+        match self.repr(ty) {
+            Repr::Void => Val::Void,
+            Repr::Scalar(repr) => Val::Scalar(match value {
+                Value::F32(value) => Operand::F32(*value),
+                Value::F64(value) => Operand::F64(*value),
+                Value::Ref(path) | Value::Ptr(path) => {
+                    Operand::Global(self.lower.static_symbol(path))
+                }
+                _ => Operand::Int(repr, repr.truncate(value.as_int().unwrap() as u64)),
+            }),
+            Repr::Memory(layout) => {
+                let addr = self.slot(layout);
+                if let Value::Str(value) = value {
+                    let name = self.lower.string(value);
+                    self.store(addr.clone(), Operand::Global(name));
+                    let len_addr = self.offset(addr.clone(), 8);
+                    self.store(len_addr, Operand::Int(Ty::I64, value.len() as u64));
+                } else {
+                    let name = self.lower.const_data(value, ty);
+                    let size = layout.size_bytes();
+                    self.emit(Inst::Copy(addr.clone(), Operand::Global(name), size));
+                }
+                Val::Mem(addr)
+            }
+        }
+    }
+
+    /// Applies a binary operator to scalars, `ty` is the type of the left side
+    fn arith(&mut self, op: BinOpKind, a: Operand, b: Operand, ty: &Type) -> Operand {
+        let repr = self.func.operand_ty(&a);
+        let op = match op {
+            BinOpKind::Plus => pick(ty, BinOp::FAdd, BinOp::Add, BinOp::Add),
+            BinOpKind::Minus => pick(ty, BinOp::FSub, BinOp::Sub, BinOp::Sub),
+            BinOpKind::Times => pick(ty, BinOp::FMul, BinOp::Mul, BinOp::Mul),
+            BinOpKind::Divide => pick(ty, BinOp::FDiv, BinOp::SDiv, BinOp::UDiv),
+            BinOpKind::Modulo => pick(ty, BinOp::FRem, BinOp::SRem, BinOp::URem),
+            BinOpKind::BitAnd => BinOp::And,
+            BinOpKind::BitOr => BinOp::Or,
+            BinOpKind::BitXor => BinOp::Xor,
+            BinOpKind::ShiftLeft => BinOp::Shl,
+            BinOpKind::ShiftRight => pick(ty, BinOp::LShr, BinOp::AShr, BinOp::LShr),
+            _ => {
+                let op = match op {
+                    BinOpKind::Eq => pick(ty, CmpOp::FEq, CmpOp::Eq, CmpOp::Eq),
+                    BinOpKind::Neq => pick(ty, CmpOp::FNe, CmpOp::Ne, CmpOp::Ne),
+                    BinOpKind::Lt => pick(ty, CmpOp::FLt, CmpOp::SLt, CmpOp::ULt),
+                    BinOpKind::Lteq => pick(ty, CmpOp::FLe, CmpOp::SLe, CmpOp::ULe),
+                    BinOpKind::Gt => pick(ty, CmpOp::FGt, CmpOp::SGt, CmpOp::UGt),
+                    BinOpKind::Gteq => pick(ty, CmpOp::FGe, CmpOp::SGe, CmpOp::UGe),
+                    _ => unreachable!("{op:?} short circuits"),
+                };
+                return self.cmp(op, a, b);
+            }
+        };
+        // shift amounts can have any integer type and are never negative
+        let b = match op {
+            BinOp::Shl | BinOp::LShr | BinOp::AShr => self.resize(b, repr, false),
+            _ => b,
+        };
+        self.binary(repr, op, a, b)
+    }
+
+    fn call(&mut self, callee: &Expr, args: &[Expr], ty: &Type) -> Val {
+        let callee = match &callee.kind {
+            ExprKind::Function(id) => Operand::Global(function_symbol(self.lower.context, *id)),
+            _ => self.scalar(callee),
+        };
+        let mut operands = Vec::new();
+        let ret = match self.repr(ty) {
+            Repr::Memory(layout) => {
+                let addr = self.slot(layout);
+                operands.push(addr.clone());
+                Err(addr)
+            }
+            Repr::Scalar(ty) => Ok(Some(ty)),
+            Repr::Void => Ok(None),
+        };
+        for arg in args {
+            match self.expr(arg) {
+                Val::Void => {}
+                Val::Scalar(value) => operands.push(value),
+                // the callee gets its own copy
+                Val::Mem(from) => {
+                    let layout = self.layout(&arg.ty);
+                    let addr = self.slot(layout);
+                    self.emit(Inst::Copy(addr.clone(), from, layout.size_bytes()));
+                    operands.push(addr);
+                }
+            }
+        }
+        match ret {
+            Ok(Some(ty)) => {
+                Val::Scalar(self.def(ty, |reg| Inst::Call(Some(reg), callee, operands)))
+            }
+            Ok(None) => {
+                self.emit(Inst::Call(None, callee, operands));
+                Val::Void
+            }
+            Err(addr) => {
+                self.emit(Inst::Call(None, callee, operands));
+                Val::Mem(addr)
+            }
+        }
+    }
+
+    fn label(&mut self, id: LabelId) -> &mut Label {
+        self.labels[id.0].as_mut().unwrap()
+    }
+
+    /// Leaves a block by reaching the end of its statements
+    fn fall_through(&mut self, id: LabelId) {
+        let label = self.label(id);
+        let exit = label.exit;
+        match label.result {
+            // only `break` can give a block a value
+            Dest::Param(_) => self.terminate(Terminator::Unreachable),
+            _ => self.jump(exit, Vec::new()),
+        }
+    }
+
+    fn block(&mut self, id: LabelId, block: &tree::Block, ty: &Type) -> Val {
+        let exit = self.new_block();
+        let result = match self.repr(ty) {
+            Repr::Void => Dest::None,
+            Repr::Scalar(ty) => Dest::Param(self.block_param(exit, ty)),
+            Repr::Memory(layout) => Dest::Memory(self.slot(layout)),
+        };
+        self.labels[id.0] = Some(Label {
+            exit,
+            cont: None,
+            result: result.clone(),
+        });
+
+        match block {
+            tree::Block::Scope(stmts) => {
+                self.stmts(stmts);
+                self.fall_through(id);
+            }
+            tree::Block::If(arms, els) => {
+                for (cond, stmts) in arms {
+                    let cond = self.scalar(cond);
+                    let then = self.new_block();
+                    let next = self.new_block();
+                    self.branch(cond, then, next);
+                    self.switch_to(then);
+                    self.stmts(stmts);
+                    self.fall_through(id);
+                    self.switch_to(next);
+                }
+                if let Some(els) = els {
+                    self.stmts(els);
+                }
+                self.fall_through(id);
+            }
+            tree::Block::While(cond, stmts) => {
+                let head = self.new_block();
+                self.label(id).cont = Some(head);
+                self.jump(head, Vec::new());
+                self.switch_to(head);
+                let cond = self.scalar(cond);
+                let body = self.new_block();
+                self.branch(cond, body, exit);
+                self.switch_to(body);
+                self.stmts(stmts);
+                self.jump(head, Vec::new());
+            }
+            tree::Block::Loop(stmts) => {
+                let head = self.new_block();
+                self.label(id).cont = Some(head);
+                self.jump(head, Vec::new());
+                self.switch_to(head);
+                self.stmts(stmts);
+                self.jump(head, Vec::new());
+            }
+            tree::Block::Range(local, start, end, inclusive, stmts) => {
+                let signed = start.ty.is_signed();
+                let counter = self.locals[local.0].clone();
+                let start = self.scalar(start);
+                let end = self.scalar(end);
+                let ty = self.func.operand_ty(&start);
+                self.store(counter.clone(), start.clone());
+                let op = match (inclusive, signed) {
+                    (true, true) => CmpOp::SLe,
+                    (true, false) => CmpOp::ULe,
+                    (false, true) => CmpOp::SLt,
+                    (false, false) => CmpOp::ULt,
+                };
+                let any = self.cmp(op, start, end.clone());
+                let body = self.new_block();
+                let latch = self.new_block();
+                self.label(id).cont = Some(latch);
+                self.branch(any, body, exit);
+
+                self.switch_to(body);
+                self.stmts(stmts);
+                self.jump(latch, Vec::new());
+
+                self.switch_to(latch);
+                let current = self.load(ty, counter.clone());
+                let next = self.binary(ty, BinOp::Add, current.clone(), Operand::Int(ty, 1));
+                let last = Context::range_last(*inclusive, current, next.clone());
+                let done = self.cmp(CmpOp::Eq, last, end);
+                let step = self.new_block();
+                self.branch(done, exit, step);
+                self.switch_to(step);
+                self.store(counter, next);
+                self.jump(body, Vec::new());
+            }
+            tree::Block::Each(local, iter, stmts) => {
+                let (base, len, element) = match &iter.ty {
+                    Type::Ref(inner, _) | Type::Ptr(inner, _) => {
+                        let place = self.deref(iter);
+                        self.elements(place, inner)
+                    }
+                    ty => {
+                        let place = self.place(iter);
+                        self.elements(place, ty)
+                    }
+                };
+                let by_ref = iter.ty.pointee().is_some();
+                let size = self.layout(&element).size_bytes();
+                let index = self.slot(Layout::new(8, 8).unwrap());
+                self.store(index.clone(), Operand::Int(Ty::I64, 0));
+                let head = self.new_block();
+                let body = self.new_block();
+                let latch = self.new_block();
+                self.label(id).cont = Some(latch);
+                self.jump(head, Vec::new());
+
+                self.switch_to(head);
+                let i = self.load(Ty::I64, index.clone());
+                let more = self.cmp(CmpOp::ULt, i.clone(), len);
+                self.branch(more, body, exit);
+
+                self.switch_to(body);
+                let offset =
+                    self.binary(Ty::I64, BinOp::Mul, i, Operand::Int(Ty::I64, size as u64));
+                let item = self.def(Ty::Ptr, |reg| Inst::PtrAdd(reg, base, offset));
+                let addr = self.locals[local.0].clone();
+                if by_ref {
+                    self.store(addr, item);
+                } else {
+                    let value = self.read(item, &element);
+                    self.write(addr, &element, value);
+                }
+                self.stmts(stmts);
+                self.jump(latch, Vec::new());
+
+                self.switch_to(latch);
+                let i = self.load(Ty::I64, index.clone());
+                let next = self.binary(Ty::I64, BinOp::Add, i, Operand::Int(Ty::I64, 1));
+                self.store(index, next);
+                self.jump(head, Vec::new());
+            }
+        }
+
+        self.switch_to(exit);
+        match result {
+            Dest::None => Val::Void,
+            Dest::Param(reg) => Val::Scalar(Operand::Reg(reg)),
+            Dest::Memory(addr) => Val::Mem(addr),
+        }
+    }
+
+    /// The address, length and element type of an array place
+    fn elements(&mut self, place: Place, ty: &Type) -> (Operand, Operand, Type) {
+        match ty {
+            Type::ArrayStatic(element, len) => (
+                place.addr,
+                Operand::Int(Ty::I64, *len as u64),
+                (**element).clone(),
+            ),
+            Type::Array(element) => (place.addr, place.len.unwrap(), (**element).clone()),
+            _ => unreachable!("{ty} is not an array"),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn lower_source(src: &str) -> Module {
+    let mut program = crate::stage::check::checked_source(src);
+    lower(&mut program.context)
+}
+
+#[test]
+fn lower_control_flow() {
+    let module = lower_source(
+        r#"
+        fn sign(i32 value) i32 {
+            if (value < 0) { return -1; } else if (value == 0) { return 0; }
+            return 1;
+        }
+        fn both(bool a, bool b) bool {
+            return a && b || !a;
+        }
+        fn count(u32 limit) u32 {
+            mut u32 total = 0;
+            mut u32 i = 0;
+            while (i < limit) {
+                i = i + 1;
+                if (i % 2 == 0) { continue; }
+                total = total + i;
+            }
+            return total;
+        }
+        fn find(bool cond) i64 {
+            i64 found = 'search {
+                if (cond) { break 'search 1; }
+                break 'search 2;
+            };
+            return found;
+        }
+        "#,
+    );
+    super::assert_snapshot("lower_control_flow.ir", &module.to_string());
+}
+
+#[test]
+fn lower_loops() {
+    let module = lower_source(
+        r#"
+        fn sum(&[i32] values) i32 {
+            mut i32 total = 0;
+            for (value in values) { total += *value; }
+            for (i in 0..=3) { total <<= i; }
+            return total;
+        }
+        fn spin(u8 start) u8 {
+            mut u8 n = start;
+            u8 last = loop {
+                n -= 1;
+                if (n == 0) { break n; }
+            };
+            return last;
+        }
+        fn copies([f64; 2] values) f64 {
+            mut f64 total = 0.0;
+            for (value in values) { total = total + value; }
+            return total;
+        }
+        "#,
+    );
+    super::assert_snapshot("lower_loops.ir", &module.to_string());
+}
+
+#[test]
+fn lower_aggregates() {
+    let module = lower_source(
+        r#"
+        struct Point { i32 x, i64 y, }
+        union Bits { u32 int, f32 float, }
+
+        extern fn puts(*u8 str) i32;
+
+        static Point ORIGIN = Point { x = 1, y = 2 };
+        extern static u8 FLAG;
+
+        impl Point {
+            fn new(i32 x) Self { return Point { x = x, y = 0 }; }
+            fn shift(&mut Self self, i64 by) { self.y += by; }
+        }
+
+        fn first(&[u8] bytes) u8 {
+            return bytes[0];
+        }
+
+        fn main(mut [u8; 3] bytes) i32 {
+            mut Point p = Point::new(3);
+            p.shift(4);
+            bytes[1] = 2;
+            u8 one = first(&bytes);
+            Bits bits = Bits { float = 1.5 };
+            if (bits.int == 0 && "a" == "b") { return 0; }
+            return puts("hi") + p.x + ORIGIN.x;
+        }
+        "#,
+    );
+    super::assert_snapshot("lower_aggregates.ir", &module.to_string());
+}
@@ -0,0 +1,637 @@
+//! A control flow graph IR between the checked tree and code generation.
+//!
+//! Functions are made of basic blocks of instructions over typed virtual
+//! registers, every register is assigned exactly once. Values flowing between
+//! blocks are passed as block parameters instead of phi nodes, the entry
+//! block's parameters are the function's parameters.
+//!
+//! Only scalars live in registers, locals get a stack slot each and structs,
+//! unions, arrays and fat pointers are always handled through their address.
+//! Those are passed to functions as a pointer to a copy and returned through
+//! a pointer to caller provided memory passed before every other argument.
+
+use std::fmt::{self, Display};
+
+use crate::parser::ast::Vis;
+
+pub mod lower;
+pub mod opt;
+
+pub use lower::lower;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Ptr,
+}
+
+impl Ty {
+    pub fn size(self) -> usize {
+        match self {
+            Ty::Bool | Ty::I8 => 1,
+            Ty::I16 => 2,
+            Ty::I32 | Ty::F32 => 4,
+            Ty::I64 | Ty::F64 | Ty::Ptr => 8,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Ty::F32 | Ty::F64)
+    }
+
+    /// Keeps only the bits of `value` that fit in this type
+    pub fn truncate(self, value: u64) -> u64 {
+        match self {
+            Ty::Bool => value & 1,
+            _ if self.size() == 8 => value,
+            _ => value & ((1 << (self.size() * 8)) - 1),
+        }
+    }
+
+    /// Interprets the bits of `value` as a signed number of this type
+    pub fn sign_extend(self, value: u64) -> i64 {
+        let shift = 64 - self.size() * 8;
+        ((value << shift) as i64) >> shift
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Reg(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SlotId(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Reg(Reg),
+    /// An integer, bool or pointer constant with the bits above its type cleared
+    Int(Ty, u64),
+    F32(f32),
+    F64(f64),
+    /// The address of a function or data symbol
+    Global(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    SDiv,
+    UDiv,
+    SRem,
+    URem,
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FRem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    LShr,
+    AShr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    SLt,
+    SLe,
+    SGt,
+    SGe,
+    ULt,
+    ULe,
+    UGt,
+    UGe,
+    FEq,
+    FNe,
+    FLt,
+    FLe,
+    FGt,
+    FGe,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnOp {
+    Neg,
+    FNeg,
+    /// Bitwise not, logical not for `bool`
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CastOp {
+    ZExt,
+    SExt,
+    Trunc,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    /// Both operands and the result have the type of the register
+    Binary(Reg, BinOp, Operand, Operand),
+    Unary(Reg, UnOp, Operand),
+    /// Compares two operands of the same type giving a `bool`
+    Cmp(Reg, CmpOp, Operand, Operand),
+    /// Changes the width of an integer to that of the register
+    Cast(Reg, CastOp, Operand),
+    /// The address of a stack slot
+    SlotAddr(Reg, SlotId),
+    /// Loads a value of the register's type from an address
+    Load(Reg, Operand),
+    /// Stores the value to the address
+    Store(Operand, Operand),
+    /// Offsets a pointer by an `i64` number of bytes
+    PtrAdd(Reg, Operand, Operand),
+    /// Copies bytes from the second address to the first, the regions are
+    /// either the same or don't overlap
+    Copy(Operand, Operand, usize),
+    /// Calls a function address, the register's type is the return type
+    Call(Option<Reg>, Operand, Vec<Operand>),
+}
+
+/// A jump to a block along with the values of its parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub block: BlockId,
+    pub args: Vec<Operand>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(Target),
+    /// Goes to the first target if the `bool` is true and the second otherwise
+    Branch(Operand, Target, Target),
+    Return(Option<Operand>),
+    Unreachable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub params: Vec<Reg>,
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub size: usize,
+    pub align: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub vis: Vis,
+    pub name: String,
+    pub ret: Option<Ty>,
+    /// The type of every register
+    pub regs: Vec<Ty>,
+    pub slots: Vec<Slot>,
+    /// The first block is the entry
+    pub blocks: Vec<Block>,
+}
+
+/// A function defined outside of the program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extern {
+    pub name: String,
+    pub params: Vec<Ty>,
+    pub ret: Option<Ty>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataPart {
+    Bytes(Vec<u8>),
+    /// The 8 byte address of a symbol
+    Addr(String),
+}
+
+/// Statics, string literals and constants too big for an operand
+#[derive(Debug, Clone, PartialEq)]
+pub struct Data {
+    pub vis: Vis,
+    pub name: String,
+    pub align: usize,
+    pub mutable: bool,
+    /// The initial contents, `None` for data defined outside of the program
+    pub init: Option<Vec<DataPart>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Module {
+    pub data: Vec<Data>,
+    pub externs: Vec<Extern>,
+    pub functions: Vec<Function>,
+}
+
+impl Function {
+    pub fn params(&self) -> &[Reg] {
+        &self.blocks[0].params
+    }
+
+    pub fn reg_ty(&self, reg: Reg) -> Ty {
+        self.regs[reg.0]
+    }
+
+    pub fn operand_ty(&self, operand: &Operand) -> Ty {
+        match operand {
+            Operand::Reg(reg) => self.reg_ty(*reg),
+            Operand::Int(ty, _) => *ty,
+            Operand::F32(_) => Ty::F32,
+            Operand::F64(_) => Ty::F64,
+            Operand::Global(_) => Ty::Ptr,
+        }
+    }
+}
+
+impl Inst {
+    /// The register this instruction assigns
+    pub fn def(&self) -> Option<Reg> {
+        match self {
+            Inst::Binary(reg, ..)
+            | Inst::Unary(reg, ..)
+            | Inst::Cmp(reg, ..)
+            | Inst::Cast(reg, ..)
+            | Inst::SlotAddr(reg, _)
+            | Inst::Load(reg, _)
+            | Inst::PtrAdd(reg, ..) => Some(*reg),
+            Inst::Call(reg, ..) => *reg,
+            Inst::Store(..) | Inst::Copy(..) => None,
+        }
+    }
+
+    /// Every operand this instruction reads
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Inst::Binary(_, _, a, b)
+            | Inst::Cmp(_, _, a, b)
+            | Inst::Store(a, b)
+            | Inst::PtrAdd(_, a, b)
+            | Inst::Copy(a, b, _) => vec![a, b],
+            Inst::Unary(_, _, a) | Inst::Cast(_, _, a) | Inst::Load(_, a) => vec![a],
+            Inst::SlotAddr(..) => vec![],
+            Inst::Call(_, callee, args) => std::iter::once(callee).chain(args).collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Inst::Binary(_, _, a, b)
+            | Inst::Cmp(_, _, a, b)
+            | Inst::Store(a, b)
+            | Inst::PtrAdd(_, a, b)
+            | Inst::Copy(a, b, _) => vec![a, b],
+            Inst::Unary(_, _, a) | Inst::Cast(_, _, a) | Inst::Load(_, a) => vec![a],
+            Inst::SlotAddr(..) => vec![],
+            Inst::Call(_, callee, args) => std::iter::once(callee).chain(args).collect(),
+        }
+    }
+
+    /// Whether removing this instruction could change what the program does
+    /// even if its result is never used
+    pub fn has_side_effects(&self) -> bool {
+        matches!(self, Inst::Store(..) | Inst::Copy(..) | Inst::Call(..))
+    }
+}
+
+impl Terminator {
+    /// Every operand this terminator reads, including the block arguments
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Terminator::Branch(cond, a, b) => std::iter::once(cond)
+                .chain(&a.args)
+                .chain(&b.args)
+                .collect(),
+            Terminator::Jump(target) => target.args.iter().collect(),
+            Terminator::Return(value) => value.iter().collect(),
+            Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Terminator::Branch(cond, a, b) => std::iter::once(cond)
+                .chain(&mut a.args)
+                .chain(&mut b.args)
+                .collect(),
+            Terminator::Jump(target) => target.args.iter_mut().collect(),
+            Terminator::Return(value) => value.iter_mut().collect(),
+            Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn targets(&self) -> Vec<&Target> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch(_, a, b) => vec![a, b],
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn targets_mut(&mut self) -> Vec<&mut Target> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch(_, a, b) => vec![a, b],
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+}
+
+impl Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Ty::Bool => "bool",
+            Ty::I8 => "i8",
+            Ty::I16 => "i16",
+            Ty::I32 => "i32",
+            Ty::I64 => "i64",
+            Ty::F32 => "f32",
+            Ty::F64 => "f64",
+            Ty::Ptr => "ptr",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Reg(reg) => write!(f, "%{}", reg.0),
+            Operand::Int(Ty::Bool, value) => write!(f, "{}", *value != 0),
+            Operand::Int(Ty::Ptr, 0) => write!(f, "null"),
+            Operand::Int(ty, value) => write!(f, "{}", ty.sign_extend(*value)),
+            Operand::F32(value) => write!(f, "{value:?}"),
+            Operand::F64(value) => write!(f, "{value:?}"),
+            Operand::Global(name) => write!(f, "@{name}"),
+        }
+    }
+}
+
+impl Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::SDiv => "sdiv",
+            BinOp::UDiv => "udiv",
+            BinOp::SRem => "srem",
+            BinOp::URem => "urem",
+            BinOp::FAdd => "fadd",
+            BinOp::FSub => "fsub",
+            BinOp::FMul => "fmul",
+            BinOp::FDiv => "fdiv",
+            BinOp::FRem => "frem",
+            BinOp::And => "and",
+            BinOp::Or => "or",
+            BinOp::Xor => "xor",
+            BinOp::Shl => "shl",
+            BinOp::LShr => "lshr",
+            BinOp::AShr => "ashr",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CmpOp::Eq => "eq",
+            CmpOp::Ne => "ne",
+            CmpOp::SLt => "slt",
+            CmpOp::SLe => "sle",
+            CmpOp::SGt => "sgt",
+            CmpOp::SGe => "sge",
+            CmpOp::ULt => "ult",
+            CmpOp::ULe => "ule",
+            CmpOp::UGt => "ugt",
+            CmpOp::UGe => "uge",
+            CmpOp::FEq => "feq",
+            CmpOp::FNe => "fne",
+            CmpOp::FLt => "flt",
+            CmpOp::FLe => "fle",
+            CmpOp::FGt => "fgt",
+            CmpOp::FGe => "fge",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for UnOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            UnOp::Neg => "neg",
+            UnOp::FNeg => "fneg",
+            UnOp::Not => "not",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for CastOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CastOp::ZExt => "zext",
+            CastOp::SExt => "sext",
+            CastOp::Trunc => "trunc",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.block.0)?;
+        if !self.args.is_empty() {
+            write!(f, "(")?;
+            for (i, arg) in self.args.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{arg}")?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Jump(target) => write!(f, "jump {target}"),
+            Terminator::Branch(cond, a, b) => write!(f, "branch {cond}, {a}, {b}"),
+            Terminator::Return(Some(value)) => write!(f, "ret {value}"),
+            Terminator::Return(None) => write!(f, "ret"),
+            Terminator::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+impl Function {
+    fn fmt_inst(&self, f: &mut fmt::Formatter<'_>, inst: &Inst) -> fmt::Result {
+        let ty = |reg: &Reg| self.reg_ty(*reg);
+        match inst {
+            Inst::Binary(reg, op, a, b) => write!(f, "%{} = {op} {} {a}, {b}", reg.0, ty(reg)),
+            Inst::Unary(reg, op, a) => write!(f, "%{} = {op} {} {a}", reg.0, ty(reg)),
+            Inst::Cmp(reg, op, a, b) => {
+                write!(f, "%{} = cmp {op} {} {a}, {b}", reg.0, self.operand_ty(a))
+            }
+            Inst::Cast(reg, op, a) => write!(
+                f,
+                "%{} = {op} {} {a} to {}",
+                reg.0,
+                self.operand_ty(a),
+                ty(reg)
+            ),
+            Inst::SlotAddr(reg, slot) => write!(f, "%{} = addr slot{}", reg.0, slot.0),
+            Inst::Load(reg, addr) => write!(f, "%{} = load {} {addr}", reg.0, ty(reg)),
+            Inst::Store(addr, value) => {
+                write!(f, "store {} {value}, {addr}", self.operand_ty(value))
+            }
+            Inst::PtrAdd(reg, base, offset) => write!(f, "%{} = ptradd {base}, {offset}", reg.0),
+            Inst::Copy(to, from, size) => write!(f, "copy {to}, {from}, {size}"),
+            Inst::Call(reg, callee, args) => {
+                match reg {
+                    Some(reg) => write!(f, "%{} = call {} {callee}(", reg.0, ty(reg))?,
+                    None => write!(f, "call {callee}(")?,
+                }
+                for (i, arg) in args.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} {arg}", self.operand_ty(arg))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.vis == Vis::Pub {
+            write!(f, "pub ")?;
+        }
+        write!(f, "fn @{}(", self.name)?;
+        for (i, param) in self.params().iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", self.reg_ty(*param))?;
+        }
+        write!(f, ")")?;
+        if let Some(ret) = self.ret {
+            write!(f, " -> {ret}")?;
+        }
+        writeln!(f, " {{")?;
+        for (i, slot) in self.slots.iter().enumerate() {
+            writeln!(f, "    slot{i}: size {}, align {}", slot.size, slot.align)?;
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            write!(f, "bb{i}")?;
+            if !block.params.is_empty() {
+                write!(f, "(")?;
+                for (i, param) in block.params.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "%{}: {}", param.0, self.reg_ty(*param))?;
+                }
+                write!(f, ")")?;
+            }
+            writeln!(f, ":")?;
+            for inst in &block.insts {
+                write!(f, "    ")?;
+                self.fmt_inst(f, inst)?;
+                writeln!(f)?;
+            }
+            writeln!(f, "    {}", block.term)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl Display for Extern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "extern fn @{}(", self.name)?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{param}")?;
+        }
+        write!(f, ")")?;
+        if let Some(ret) = self.ret {
+            write!(f, " -> {ret}")?;
+        }
+        writeln!(f)
+    }
+}
+
+impl Display for Data {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(init) = &self.init else {
+            return writeln!(f, "extern data @{}", self.name);
+        };
+        if self.vis == Vis::Pub {
+            write!(f, "pub ")?;
+        }
+        let kind = if self.mutable { "data" } else { "const" };
+        write!(f, "{kind} @{}: align {} =", self.name, self.align)?;
+        for part in init {
+            match part {
+                DataPart::Bytes(bytes) => write!(f, " \"{}\"", bytes.escape_ascii())?,
+                DataPart::Addr(name) => write!(f, " @{name}")?,
+            }
+        }
+        writeln!(f)
+    }
+}
+
+impl Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for data in &self.data {
+            write!(f, "{data}")?;
+        }
+        for ext in &self.externs {
+            write!(f, "{ext}")?;
+        }
+        for function in &self.functions {
+            writeln!(f)?;
+            write!(f, "{function}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares `actual` to `test/snapshots/<name>`, setting `BLESS` writes it instead
+#[cfg(test)]
+pub(crate) fn assert_snapshot(name: &str, actual: &str) {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test/snapshots")
+        .join(name);
+    if std::env::var_os("BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("missing snapshot {name}, run with BLESS=1 to create it"));
+    if expected != actual {
+        println!("{actual}");
+        panic!("snapshot {name} changed, run with BLESS=1 to update it");
+    }
+}
@@ -0,0 +1,105 @@
+//! Dead code elimination.
+//!
+//! Marks everything stores, calls, branches and returns depend on and drops
+//! the instructions and block parameters that were never reached.
+
+use super::super::{BlockId, Function, Operand, Terminator};
+
+enum Def {
+    Param(BlockId, usize),
+    Inst(BlockId, usize),
+}
+
+pub(super) fn run(function: &mut Function) {
+    let mut defs: Vec<Option<Def>> = (0..function.regs.len()).map(|_| None).collect();
+    let mut incoming = vec![Vec::new(); function.blocks.len()];
+    for (id, block) in function.blocks.iter().enumerate() {
+        for (index, param) in block.params.iter().enumerate() {
+            defs[param.0] = Some(Def::Param(BlockId(id), index));
+        }
+        for (index, inst) in block.insts.iter().enumerate() {
+            if let Some(reg) = inst.def() {
+                defs[reg.0] = Some(Def::Inst(BlockId(id), index));
+            }
+        }
+        for target in block.term.targets() {
+            incoming[target.block.0].push(target.args.clone());
+        }
+    }
+
+    let mut live = vec![false; function.regs.len()];
+    let mut work = Vec::new();
+    let mut mark = |operand: &Operand, work: &mut Vec<_>| {
+        if let Operand::Reg(reg) = operand {
+            if !live[reg.0] {
+                live[reg.0] = true;
+                work.push(*reg);
+            }
+        }
+    };
+    for block in &function.blocks {
+        for inst in &block.insts {
+            if inst.has_side_effects() {
+                for operand in inst.operands() {
+                    mark(operand, &mut work);
+                }
+            }
+        }
+        match &block.term {
+            Terminator::Branch(cond, ..) | Terminator::Return(Some(cond)) => mark(cond, &mut work),
+            _ => {}
+        }
+    }
+    while let Some(reg) = work.pop() {
+        match defs[reg.0] {
+            Some(Def::Inst(block, index)) => {
+                for operand in function.blocks[block.0].insts[index].operands() {
+                    mark(operand, &mut work);
+                }
+            }
+            Some(Def::Param(block, index)) => {
+                for args in &incoming[block.0] {
+                    mark(&args[index], &mut work);
+                }
+            }
+            None => {}
+        }
+    }
+
+    for block in &mut function.blocks {
+        block
+            .insts
+            .retain(|inst| inst.has_side_effects() || inst.def().is_some_and(|reg| live[reg.0]));
+    }
+    // the entry block's parameters are the function's
+    for id in 1..function.blocks.len() {
+        for index in (0..function.blocks[id].params.len()).rev() {
+            if !live[function.blocks[id].params[index].0] {
+                super::remove_param(function, BlockId(id), index);
+            }
+        }
+    }
+}
+
+#[test]
+fn dce_unused() {
+    let ir = super::optimized(
+        r#"
+        fn unused(i32 a) i32 {
+            i32 b = a * 2;
+            a + 1;
+            return a;
+        }
+        fn loop_carried(u32 n) {
+            mut u32 i = 0;
+            mut u32 dead = 0;
+            while (i < n) {
+                dead = dead + i;
+                i = i + 1;
+            }
+        }
+        "#,
+        &[super::Pass::Mem2Reg, super::Pass::Dce],
+    );
+    super::super::assert_snapshot("opt_dce.ir", &ir);
+}
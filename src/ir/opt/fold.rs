@@ -0,0 +1,166 @@
+//! Constant folding and propagation.
+//!
+//! Instructions on constants are evaluated with the same functions the
+//! checker uses for constant expressions so both agree on the results.
+
+use std::collections::HashMap;
+
+use crate::{
+    parser::ast::{BinOpKind, UnaryOpKind},
+    stage::constant_eval::{apply_binop_op, apply_unary_op, Value},
+};
+
+use super::super::{BinOp, CastOp, CmpOp, Function, Inst, Operand, Terminator, Ty, UnOp};
+
+pub(super) fn run(function: &mut Function) {
+    loop {
+        let mut map = HashMap::new();
+        for block in &mut function.blocks {
+            for inst in &mut block.insts {
+                for operand in inst.operands_mut() {
+                    resolve(&map, operand);
+                }
+            }
+            let regs = &function.regs;
+            block.insts.retain(|inst| match fold(regs, inst) {
+                Some(value) => {
+                    map.insert(inst.def().unwrap(), value);
+                    false
+                }
+                None => true,
+            });
+            for operand in block.term.operands_mut() {
+                resolve(&map, operand);
+            }
+            if let Terminator::Branch(Operand::Int(Ty::Bool, cond), a, b) = &block.term {
+                let target = if *cond != 0 { a } else { b };
+                block.term = Terminator::Jump(target.clone());
+            }
+        }
+        super::substitute(function, &map);
+        if !super::remove_trivial_params(function) && map.is_empty() {
+            break;
+        }
+    }
+}
+
+fn resolve(map: &HashMap<super::super::Reg, Operand>, operand: &mut Operand) {
+    while let Operand::Reg(reg) = operand {
+        match map.get(reg) {
+            Some(value) => *operand = value.clone(),
+            None => break,
+        }
+    }
+}
+
+/// The constant an instruction evaluates to if all its operands are constant
+fn fold(regs: &[Ty], inst: &Inst) -> Option<Operand> {
+    match inst {
+        Inst::Binary(reg, op, a, b) => {
+            let (kind, signed) = match op {
+                BinOp::Add | BinOp::FAdd => (BinOpKind::Plus, false),
+                BinOp::Sub | BinOp::FSub => (BinOpKind::Minus, false),
+                BinOp::Mul | BinOp::FMul => (BinOpKind::Times, false),
+                BinOp::SDiv => (BinOpKind::Divide, true),
+                BinOp::UDiv | BinOp::FDiv => (BinOpKind::Divide, false),
+                BinOp::SRem => (BinOpKind::Modulo, true),
+                BinOp::URem | BinOp::FRem => (BinOpKind::Modulo, false),
+                BinOp::And => (BinOpKind::BitAnd, false),
+                BinOp::Or => (BinOpKind::BitOr, false),
+                BinOp::Xor => (BinOpKind::BitXor, false),
+                BinOp::Shl => (BinOpKind::ShiftLeft, false),
+                BinOp::LShr => (BinOpKind::ShiftRight, false),
+                BinOp::AShr => (BinOpKind::ShiftRight, true),
+            };
+            let value = apply_binop_op(value(a, signed)?, kind, value(b, signed)?).ok()?;
+            Some(operand(value, regs[reg.0]))
+        }
+        Inst::Cmp(_, op, a, b) => {
+            let (kind, signed) = match op {
+                CmpOp::Eq | CmpOp::FEq => (BinOpKind::Eq, false),
+                CmpOp::Ne | CmpOp::FNe => (BinOpKind::Neq, false),
+                CmpOp::SLt => (BinOpKind::Lt, true),
+                CmpOp::SLe => (BinOpKind::Lteq, true),
+                CmpOp::SGt => (BinOpKind::Gt, true),
+                CmpOp::SGe => (BinOpKind::Gteq, true),
+                CmpOp::ULt | CmpOp::FLt => (BinOpKind::Lt, false),
+                CmpOp::ULe | CmpOp::FLe => (BinOpKind::Lteq, false),
+                CmpOp::UGt | CmpOp::FGt => (BinOpKind::Gt, false),
+                CmpOp::UGe | CmpOp::FGe => (BinOpKind::Gteq, false),
+            };
+            let value = apply_binop_op(value(a, signed)?, kind, value(b, signed)?).ok()?;
+            Some(operand(value, Ty::Bool))
+        }
+        Inst::Unary(reg, op, a) => {
+            let signed = *op == UnOp::Neg;
+            let kind = match op {
+                UnOp::Neg | UnOp::FNeg => UnaryOpKind::Negate,
+                UnOp::Not => UnaryOpKind::Not,
+            };
+            let value = apply_unary_op(value(a, signed)?, kind).ok()?;
+            Some(operand(value, regs[reg.0]))
+        }
+        Inst::Cast(reg, op, Operand::Int(from, bits)) => {
+            let to = regs[reg.0];
+            let bits = match op {
+                CastOp::SExt => from.sign_extend(*bits) as u64,
+                CastOp::ZExt | CastOp::Trunc => *bits,
+            };
+            Some(Operand::Int(to, to.truncate(bits)))
+        }
+        _ => None,
+    }
+}
+
+/// A constant operand as a value of the given signedness
+fn value(operand: &Operand, signed: bool) -> Option<Value> {
+    Some(match *operand {
+        Operand::Int(Ty::Bool, bits) => Value::Bool(bits != 0),
+        Operand::Int(ty, bits) => match (ty.size(), signed) {
+            (1, false) => Value::U8(bits as u8),
+            (2, false) => Value::U16(bits as u16),
+            (4, false) => Value::U32(bits as u32),
+            (_, false) => Value::U64(bits),
+            (1, true) => Value::I8(bits as i8),
+            (2, true) => Value::I16(bits as i16),
+            (4, true) => Value::I32(bits as i32),
+            (_, true) => Value::I64(bits as i64),
+        },
+        Operand::F32(value) => Value::F32(value),
+        Operand::F64(value) => Value::F64(value),
+        Operand::Reg(_) | Operand::Global(_) => return None,
+    })
+}
+
+fn operand(value: Value, ty: Ty) -> Operand {
+    match value {
+        Value::F32(value) => Operand::F32(value),
+        Value::F64(value) => Operand::F64(value),
+        value => Operand::Int(ty, ty.truncate(value.as_int().unwrap() as u64)),
+    }
+}
+
+#[test]
+fn fold_constants() {
+    let ir = super::optimized(
+        r#"
+        fn consts() i32 {
+            if (3 < 4) { return -(2 * 3) + 1; }
+            return 10 / 0;
+        }
+        fn signs() bool {
+            u8 big = 200;
+            return 200u8 > 100u8 && -1i8 < 1i8;
+        }
+        fn propagated(bool cond) i64 {
+            i64 value = 'pick {
+                if (cond) { break 'pick 7; }
+                break 'pick 7;
+            };
+            return value;
+        }
+        "#,
+        &[super::Pass::Mem2Reg, super::Pass::Fold],
+    );
+    super::super::assert_snapshot("opt_fold.ir", &ir);
+}
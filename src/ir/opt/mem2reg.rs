@@ -0,0 +1,168 @@
+//! Promotion of stack slots to registers.
+//!
+//! A slot whose address is only ever loaded from and stored to, always as
+//! the same scalar covering the whole slot, is replaced by the values
+//! stored to it. Blocks with several predecessors get a parameter per
+//! promoted slot which the simplest cases of are removed again right after.
+
+use std::collections::HashMap;
+
+use super::super::{Function, Inst, Operand, Reg, Terminator, Ty};
+
+pub(super) fn run(function: &mut Function) {
+    // jumps from unreachable blocks would need arguments nobody knows
+    super::remove_unreachable(function);
+    let (vars, tys) = promotable(function);
+    if vars.is_empty() {
+        return;
+    }
+
+    let preds = super::predecessors(function);
+    let order = super::reverse_postorder(function);
+    let mut entries: Vec<Option<Vec<Operand>>> = vec![None; function.blocks.len()];
+    entries[0] = Some(tys.iter().map(|ty| super::zero(*ty)).collect());
+    for &block in &order {
+        if block.0 != 0 && preds[block.0].len() > 1 {
+            let params = tys
+                .iter()
+                .map(|ty| {
+                    let reg = Reg(function.regs.len());
+                    function.regs.push(*ty);
+                    function.blocks[block.0].params.push(reg);
+                    Operand::Reg(reg)
+                })
+                .collect();
+            entries[block.0] = Some(params);
+        }
+    }
+
+    let mut exits: Vec<Option<Vec<Operand>>> = vec![None; function.blocks.len()];
+    let mut map = HashMap::new();
+    for &block in &order {
+        let mut current = match entries[block.0].clone() {
+            Some(values) => values,
+            None => exits[preds[block.0][0].0].clone().unwrap(),
+        };
+        function.blocks[block.0].insts.retain(|inst| match inst {
+            Inst::Load(dst, Operand::Reg(addr)) if vars.contains_key(addr) => {
+                map.insert(*dst, current[vars[addr]].clone());
+                false
+            }
+            Inst::Store(Operand::Reg(addr), value) if vars.contains_key(addr) => {
+                let mut value = value.clone();
+                while let Operand::Reg(reg) = value {
+                    match map.get(&reg) {
+                        Some(resolved) => value = Operand::clone(resolved),
+                        None => break,
+                    }
+                }
+                current[vars[addr]] = value;
+                false
+            }
+            Inst::SlotAddr(dst, _) => !vars.contains_key(dst),
+            _ => true,
+        });
+        exits[block.0] = Some(current);
+    }
+
+    for &block in &order {
+        let exit = exits[block.0].take().unwrap();
+        for target in function.blocks[block.0].term.targets_mut() {
+            if entries[target.block.0].is_some() && target.block.0 != 0 {
+                target.args.extend(exit.iter().cloned());
+            }
+        }
+    }
+    super::substitute(function, &map);
+    super::remove_trivial_params(function);
+    super::remove_unused_slots(function);
+}
+
+/// The registers holding the address of a promotable slot, each with the
+/// index of its variable, and the type of each variable
+fn promotable(function: &Function) -> (HashMap<Reg, usize>, Vec<Ty>) {
+    let mut addrs = HashMap::new();
+    for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+        if let Inst::SlotAddr(reg, slot) = inst {
+            addrs.insert(*reg, *slot);
+        }
+    }
+
+    let mut tys: HashMap<Reg, Option<Ty>> = HashMap::new();
+    let mut access = |addr: &Reg, ty: Ty| {
+        let entry = tys.entry(*addr).or_insert(Some(ty));
+        if *entry != Some(ty) {
+            *entry = None;
+        }
+    };
+    let mut escaped = Vec::new();
+    for block in &function.blocks {
+        for inst in &block.insts {
+            match inst {
+                Inst::Load(dst, Operand::Reg(addr)) if addrs.contains_key(addr) => {
+                    access(addr, function.reg_ty(*dst));
+                }
+                Inst::Store(Operand::Reg(addr), value) if addrs.contains_key(addr) => {
+                    access(addr, function.operand_ty(value));
+                    escaped.push(value);
+                }
+                _ => escaped.extend(inst.operands()),
+            }
+        }
+        if let Terminator::Return(Some(value)) | Terminator::Branch(value, ..) = &block.term {
+            escaped.push(value);
+        }
+        for target in block.term.targets() {
+            escaped.extend(&target.args);
+        }
+    }
+    for operand in escaped {
+        if let Operand::Reg(reg) = operand {
+            tys.insert(*reg, None);
+        }
+    }
+
+    let mut vars = HashMap::new();
+    let mut var_tys = Vec::new();
+    let mut regs: Vec<_> = addrs.into_iter().collect();
+    regs.sort_by_key(|(reg, _)| *reg);
+    for (reg, slot) in regs {
+        let Some(Some(ty)) = tys.get(&reg) else {
+            continue;
+        };
+        if ty.size() == function.slots[slot.0].size {
+            vars.insert(reg, var_tys.len());
+            var_tys.push(*ty);
+        }
+    }
+    (vars, var_tys)
+}
+
+#[test]
+fn promote_slots() {
+    let ir = super::optimized(
+        r#"
+        fn count(u32 n) u32 {
+            mut u32 total = 0;
+            for (i in 0..n) {
+                if (i == 3) { continue; }
+                total += i;
+            }
+            return total;
+        }
+        fn escapes() i32 {
+            mut i32 x = 1;
+            &mut i32 p = &mut x;
+            *p = 2;
+            return x;
+        }
+        fn uninit(bool cond) i64 {
+            i64 value;
+            if (cond) { value = 1; } else { value = 2; }
+            return value;
+        }
+        "#,
+        &[super::Pass::Mem2Reg],
+    );
+    super::super::assert_snapshot("opt_mem2reg.ir", &ir);
+}
@@ -0,0 +1,341 @@
+//! Optimization passes over the IR.
+//!
+//! Every pass works on one function at a time and can be run on its own,
+//! [`OptLevel`] picks which ones the `-O` levels run.
+
+use std::{collections::HashMap, str::FromStr};
+
+use super::{BlockId, Function, Inst, Module, Operand, Reg, SlotId, Ty};
+
+mod dce;
+mod fold;
+mod mem2reg;
+mod simplify;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pass {
+    /// Folds instructions on constants and propagates the results
+    Fold,
+    /// Removes instructions and block parameters whose values are never used
+    Dce,
+    /// Removes unreachable blocks, jumps through empty blocks and merges
+    /// blocks with their only successor
+    Simplify,
+    /// Promotes stack slots that never have their address taken to registers
+    Mem2Reg,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 4] = [Pass::Fold, Pass::Dce, Pass::Simplify, Pass::Mem2Reg];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Fold => "fold",
+            Pass::Dce => "dce",
+            Pass::Simplify => "simplify",
+            Pass::Mem2Reg => "mem2reg",
+        }
+    }
+
+    pub fn run(self, function: &mut Function) {
+        match self {
+            Pass::Fold => fold::run(function),
+            Pass::Dce => dce::run(function),
+            Pass::Simplify => simplify::run(function),
+            Pass::Mem2Reg => mem2reg::run(function),
+        }
+    }
+}
+
+impl FromStr for Pass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pass::ALL
+            .into_iter()
+            .find(|pass| pass.name() == s)
+            .ok_or_else(|| format!("unknown pass `{s}`"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    /// Every pass once
+    O1,
+    /// Every pass until nothing changes anymore
+    O2,
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            _ => Err(format!("unknown optimization level `{s}`")),
+        }
+    }
+}
+
+impl OptLevel {
+    pub fn passes(self) -> &'static [Pass] {
+        match self {
+            OptLevel::O0 => &[],
+            OptLevel::O1 | OptLevel::O2 => &[
+                Pass::Simplify,
+                Pass::Mem2Reg,
+                Pass::Fold,
+                Pass::Simplify,
+                Pass::Dce,
+            ],
+        }
+    }
+}
+
+/// Gives up on reaching a fixed point at `-O2` after this many rounds
+const MAX_ROUNDS: usize = 8;
+
+pub fn optimize(module: &mut Module, level: OptLevel) {
+    for function in &mut module.functions {
+        let rounds = match level {
+            OptLevel::O2 => MAX_ROUNDS,
+            _ => 1,
+        };
+        for _ in 0..rounds {
+            let before = function.clone();
+            for pass in level.passes() {
+                pass.run(function);
+            }
+            if *function == before {
+                break;
+            }
+        }
+    }
+}
+
+/// Runs the given passes in order over every function
+pub fn run_passes(module: &mut Module, passes: &[Pass]) {
+    for function in &mut module.functions {
+        for pass in passes {
+            pass.run(function);
+        }
+    }
+}
+
+/// The source block of every edge into each block
+fn predecessors(function: &Function) -> Vec<Vec<BlockId>> {
+    let mut preds = vec![Vec::new(); function.blocks.len()];
+    for (id, block) in function.blocks.iter().enumerate() {
+        for target in block.term.targets() {
+            preds[target.block.0].push(BlockId(id));
+        }
+    }
+    preds
+}
+
+/// The blocks reachable from the entry, every block comes after its
+/// predecessors unless the edge between them closes a loop
+fn reverse_postorder(function: &Function) -> Vec<BlockId> {
+    let mut visited = vec![false; function.blocks.len()];
+    let mut order = Vec::new();
+    // blocks along with how many of their successors were visited
+    let mut stack = vec![(BlockId(0), 0)];
+    visited[0] = true;
+    while let Some((block, next)) = stack.pop() {
+        let targets = function.blocks[block.0].term.targets();
+        match targets.get(next) {
+            Some(target) => {
+                stack.push((block, next + 1));
+                if !visited[target.block.0] {
+                    visited[target.block.0] = true;
+                    stack.push((target.block, 0));
+                }
+            }
+            None => order.push(block),
+        }
+    }
+    order.reverse();
+    order
+}
+
+/// Drops the blocks not reachable from the entry, returns whether any were
+fn remove_unreachable(function: &mut Function) -> bool {
+    let mut keep = vec![false; function.blocks.len()];
+    for block in reverse_postorder(function) {
+        keep[block.0] = true;
+    }
+    if keep.iter().all(|keep| *keep) {
+        return false;
+    }
+    let mut map = Vec::new();
+    let mut next = 0;
+    for keep in &keep {
+        map.push(BlockId(next));
+        next += *keep as usize;
+    }
+    let mut id = 0;
+    function.blocks.retain(|_| {
+        id += 1;
+        keep[id - 1]
+    });
+    for block in &mut function.blocks {
+        for target in block.term.targets_mut() {
+            target.block = map[target.block.0];
+        }
+    }
+    true
+}
+
+/// Replaces every use of the registers in `map`, following chains of them
+fn substitute(function: &mut Function, map: &HashMap<Reg, Operand>) {
+    if map.is_empty() {
+        return;
+    }
+    let resolve = |operand: &mut Operand| {
+        while let Operand::Reg(reg) = operand {
+            match map.get(reg) {
+                Some(value) => *operand = value.clone(),
+                None => break,
+            }
+        }
+    };
+    for block in &mut function.blocks {
+        for inst in &mut block.insts {
+            inst.operands_mut().into_iter().for_each(resolve);
+        }
+        block.term.operands_mut().into_iter().for_each(resolve);
+    }
+}
+
+/// Removes a block parameter along with its argument on every jump to the block
+fn remove_param(function: &mut Function, block: BlockId, index: usize) {
+    function.blocks[block.0].params.remove(index);
+    for other in &mut function.blocks {
+        for target in other.term.targets_mut() {
+            if target.block == block {
+                target.args.remove(index);
+            }
+        }
+    }
+}
+
+/// Replaces block parameters which always get the same value, apart from
+/// themselves, with that value
+fn remove_trivial_params(function: &mut Function) -> bool {
+    let mut changed = false;
+    'search: loop {
+        let mut incoming = vec![Vec::new(); function.blocks.len()];
+        for block in &function.blocks {
+            for target in block.term.targets() {
+                incoming[target.block.0].push(&target.args);
+            }
+        }
+        for (id, block) in function.blocks.iter().enumerate().skip(1) {
+            for (index, param) in block.params.iter().enumerate() {
+                let this = Operand::Reg(*param);
+                let mut values = incoming[id]
+                    .iter()
+                    .map(|args| &args[index])
+                    .filter(|value| **value != this);
+                let Some(value) = values.next() else {
+                    continue;
+                };
+                if values.all(|other| other == value) {
+                    let map = HashMap::from([(*param, value.clone())]);
+                    remove_param(function, BlockId(id), index);
+                    substitute(function, &map);
+                    changed = true;
+                    continue 'search;
+                }
+            }
+        }
+        return changed;
+    }
+}
+
+/// Drops stack slots without a `SlotAddr` and renumbers the rest
+fn remove_unused_slots(function: &mut Function) {
+    let mut used = vec![false; function.slots.len()];
+    for block in &function.blocks {
+        for inst in &block.insts {
+            if let Inst::SlotAddr(_, slot) = inst {
+                used[slot.0] = true;
+            }
+        }
+    }
+    let mut map = Vec::new();
+    let mut next = 0;
+    for used in &used {
+        map.push(SlotId(next));
+        next += *used as usize;
+    }
+    let mut id = 0;
+    function.slots.retain(|_| {
+        id += 1;
+        used[id - 1]
+    });
+    for block in &mut function.blocks {
+        for inst in &mut block.insts {
+            if let Inst::SlotAddr(_, slot) = inst {
+                *slot = map[slot.0];
+            }
+        }
+    }
+}
+
+fn zero(ty: Ty) -> Operand {
+    match ty {
+        Ty::F32 => Operand::F32(0.0),
+        Ty::F64 => Operand::F64(0.0),
+        _ => Operand::Int(ty, 0),
+    }
+}
+
+#[cfg(test)]
+fn optimized(src: &str, passes: &[Pass]) -> String {
+    let mut module = super::lower::lower_source(src);
+    run_passes(&mut module, passes);
+    module.to_string()
+}
+
+#[cfg(test)]
+const PIPELINE_SOURCE: &str = r#"
+    struct Pair { i32 a, i32 b, }
+
+    fn total(&Pair pair, u32 limit) i32 {
+        mut i32 sum = 0;
+        for (i in 0..limit) {
+            if (i % 2 == 0) { continue; }
+            sum += pair.a * 2 + 1;
+        }
+        i32 unused = 4 * 5;
+        return sum + pair.b;
+    }
+
+    fn chosen() i32 {
+        bool fast = true;
+        i32 x = 'pick {
+            if (fast) { break 'pick 1; }
+            break 'pick 2;
+        };
+        if (x == 1) { return 10; }
+        return 20;
+    }
+"#;
+
+#[test]
+fn levels() {
+    for (level, name) in [
+        (OptLevel::O0, "opt_o0.ir"),
+        (OptLevel::O1, "opt_o1.ir"),
+        (OptLevel::O2, "opt_o2.ir"),
+    ] {
+        let mut module = super::lower::lower_source(PIPELINE_SOURCE);
+        optimize(&mut module, level);
+        super::assert_snapshot(name, &module.to_string());
+    }
+}
@@ -0,0 +1,113 @@
+//! Control flow graph simplification.
+//!
+//! Removes unreachable blocks, sends jumps to empty blocks straight on to
+//! where those go and merges blocks into their only predecessor.
+
+use std::collections::HashMap;
+
+use super::super::{Block, Function, Terminator};
+
+pub(super) fn run(function: &mut Function) {
+    loop {
+        let mut changed = same_targets(function);
+        changed |= thread_jumps(function);
+        changed |= merge_blocks(function);
+        changed |= super::remove_unreachable(function);
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Turns branches going to the same place either way into jumps
+fn same_targets(function: &mut Function) -> bool {
+    let mut changed = false;
+    for block in &mut function.blocks {
+        if let Terminator::Branch(_, a, b) = &block.term {
+            if a == b {
+                block.term = Terminator::Jump(a.clone());
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Skips blocks that do nothing but jump somewhere else
+fn thread_jumps(function: &mut Function) -> bool {
+    let mut changed = false;
+    for id in 1..function.blocks.len() {
+        let block = &function.blocks[id];
+        let Terminator::Jump(next) = &block.term else {
+            continue;
+        };
+        if !block.params.is_empty() || !block.insts.is_empty() || next.block.0 == id {
+            continue;
+        }
+        let next = next.clone();
+        for other in &mut function.blocks {
+            for target in other.term.targets_mut() {
+                if target.block.0 == id {
+                    *target = next.clone();
+                    changed = true;
+                }
+            }
+        }
+    }
+    changed
+}
+
+/// Appends blocks to their only predecessor when it jumps to them
+fn merge_blocks(function: &mut Function) -> bool {
+    let preds = super::predecessors(function);
+    let mut map = HashMap::new();
+    let mut changed = false;
+    for id in 0..function.blocks.len() {
+        while let Terminator::Jump(target) = &function.blocks[id].term {
+            let next = target.block;
+            if next.0 == 0 || next.0 == id || preds[next.0].len() != 1 {
+                break;
+            }
+            let args = target.args.clone();
+            let merged = std::mem::replace(
+                &mut function.blocks[next.0],
+                Block {
+                    params: Vec::new(),
+                    insts: Vec::new(),
+                    term: Terminator::Unreachable,
+                },
+            );
+            for (param, arg) in merged.params.iter().zip(args) {
+                map.insert(*param, arg);
+            }
+            let block = &mut function.blocks[id];
+            block.insts.extend(merged.insts);
+            block.term = merged.term;
+            changed = true;
+        }
+    }
+    super::substitute(function, &map);
+    changed
+}
+
+#[test]
+fn simplify_cfg() {
+    let ir = super::optimized(
+        r#"
+        fn sign(i32 value) i32 {
+            if (value < 0) { return -1; } else if (value == 0) { return 0; }
+            return 1;
+        }
+        fn pick(bool a, bool b) i32 {
+            i32 x = 'out {
+                if (a) { break 'out 1; }
+                if (b) { break 'out 2; }
+                break 'out 3;
+            };
+            return x;
+        }
+        "#,
+        &[super::Pass::Simplify],
+    );
+    super::super::assert_snapshot("opt_simplify.ir", &ir);
+}
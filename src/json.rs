@@ -0,0 +1,390 @@
+//! Just enough JSON for talking to editors and other tools.
+//!
+//! Numbers are kept as the text they're written as, so integers of any size
+//! go through without being rounded to a float.
+
+use std::fmt::{Display, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    /// Members in the order they're written in
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<'a>(members: impl IntoIterator<Item = (&'a str, Json)>) -> Self {
+        let members = members.into_iter();
+        Json::Object(
+            members
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// The member of an object with the name `key`
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(other, _)| other == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(number) => number.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn parse(src: &str) -> Result<Json, String> {
+        let mut parser = Parser { src, at: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        match parser.at == src.len() {
+            true => Ok(value),
+            false => Err(format!("unexpected `{}` after the value", parser.rest())),
+        }
+    }
+
+    /// Prints the value over several lines, with members indented by `indent`
+    pub fn pretty(&self, indent: usize) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, indent, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize, depth: usize) {
+        let pad = |out: &mut String, depth: usize| {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent * depth));
+        };
+        match self {
+            Json::Array(values) if !values.is_empty() => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    pad(out, depth + 1);
+                    value.write_pretty(out, indent, depth + 1);
+                }
+                pad(out, depth);
+                out.push(']');
+            }
+            Json::Object(members) if !members.is_empty() => {
+                out.push('{');
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    pad(out, depth + 1);
+                    write_string(out, key);
+                    out.push_str(": ");
+                    value.write_pretty(out, indent, depth + 1);
+                }
+                pad(out, depth);
+                out.push('}');
+            }
+            value => out.push_str(&value.to_string()),
+        }
+    }
+}
+
+fn write_string(out: &mut String, string: &str) {
+    out.push('"');
+    for char in string.chars() {
+        match char {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            char if (char as u32) < 0x20 => {
+                write!(out, "\\u{:04x}", char as u32).unwrap();
+            }
+            char => out.push(char),
+        }
+    }
+    out.push('"');
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(bool) => write!(f, "{bool}"),
+            Json::Number(number) => write!(f, "{number}"),
+            Json::String(string) => {
+                let mut out = String::new();
+                write_string(&mut out, string);
+                write!(f, "{out}")
+            }
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{value}", Json::String(key.clone()))?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+macro_rules! number {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for Json {
+            fn from(value: $ty) -> Self {
+                Json::Number(value.to_string())
+            }
+        }
+    )*};
+}
+
+number!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl From<f64> for Json {
+    fn from(value: f64) -> Self {
+        match value.is_finite() {
+            true => Json::Number(format!("{value:?}")),
+            false => Json::Null,
+        }
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(value: Vec<T>) -> Self {
+        Json::Array(value.into_iter().map(Into::into).collect())
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    at: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.src[self.at..]
+    }
+
+    fn whitespace(&mut self) {
+        let rest = self.rest();
+        self.at += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let eaten = self.rest().starts_with(text);
+        if eaten {
+            self.at += text.len();
+        }
+        eaten
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        self.whitespace();
+        match self.eat(text) {
+            true => Ok(()),
+            false => Err(format!("expected `{text}` at byte {}", self.at)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        let Some(next) = self.rest().chars().next() else {
+            return Err("the value ends too soon".to_string());
+        };
+        match next {
+            'n' if self.eat("null") => Ok(Json::Null),
+            't' if self.eat("true") => Ok(Json::Bool(true)),
+            'f' if self.eat("false") => Ok(Json::Bool(false)),
+            '"' => Ok(Json::String(self.string()?)),
+            '[' => {
+                self.at += 1;
+                let mut values = Vec::new();
+                self.whitespace();
+                if !self.eat("]") {
+                    loop {
+                        values.push(self.value()?);
+                        self.whitespace();
+                        if self.eat("]") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Json::Array(values))
+            }
+            '{' => {
+                self.at += 1;
+                let mut members = Vec::new();
+                self.whitespace();
+                if !self.eat("}") {
+                    loop {
+                        self.whitespace();
+                        let key = self.string()?;
+                        self.expect(":")?;
+                        members.push((key, self.value()?));
+                        self.whitespace();
+                        if self.eat("}") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Json::Object(members))
+            }
+            '-' | '0'..='9' => {
+                let rest = self.rest();
+                let len = rest
+                    .find(|char: char| !matches!(char, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+                    .unwrap_or(rest.len());
+                let number = &rest[..len];
+                if number.parse::<f64>().is_err() {
+                    return Err(format!("invalid number `{number}`"));
+                }
+                self.at += len;
+                Ok(Json::Number(number.to_string()))
+            }
+            _ => Err(format!("unexpected `{next}` at byte {}", self.at)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if !self.eat("\"") {
+            return Err(format!("expected a string at byte {}", self.at));
+        }
+        let mut string = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, char)) = chars.next() {
+            match char {
+                '"' => {
+                    self.at += i + 1;
+                    return Ok(string);
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, char)| char) {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let high = hex(&mut chars).ok_or("invalid `\\u` escape")?;
+                            let code = match high {
+                                // the first half of a pair, the second half is another escape
+                                0xD800..=0xDBFF => {
+                                    let (_, '\\') = chars.next().unwrap_or_default() else {
+                                        return Err("unpaired surrogate".to_string());
+                                    };
+                                    let (_, 'u') = chars.next().unwrap_or_default() else {
+                                        return Err("unpaired surrogate".to_string());
+                                    };
+                                    let low = hex(&mut chars).ok_or("invalid `\\u` escape")?;
+                                    0x10000
+                                        + ((high - 0xD800) << 10)
+                                        + (low.wrapping_sub(0xDC00) & 0x3FF)
+                                }
+                                code => code,
+                            };
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        Some(char @ ('"' | '\\' | '/')) => char,
+                        _ => return Err("invalid escape in string".to_string()),
+                    };
+                    string.push(escaped);
+                }
+                char => string.push(char),
+            }
+        }
+        Err("unclosed string".to_string())
+    }
+}
+
+/// The four hex digits of a `\u` escape
+fn hex(chars: &mut std::str::CharIndices) -> Option<u32> {
+    let hex: String = chars.take(4).map(|(_, char)| char).collect();
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+#[test]
+fn round_trip() {
+    let src = r#" {"id": 12, "big": 18446744073709551615, "list": [true, null, -1.5e3, []],
+        "text": "a \"quote\"\né😀", "empty": {}} "#;
+    let json = Json::parse(src).unwrap();
+    assert_eq!(json.get("id").and_then(Json::as_u64), Some(12));
+    assert_eq!(json.get("big").and_then(Json::as_u64), Some(u64::MAX));
+    assert_eq!(
+        json.get("text").and_then(Json::as_str),
+        Some("a \"quote\"\n\u{e9}\u{1f600}")
+    );
+    let printed = json.to_string();
+    assert_eq!(
+        printed,
+        r#"{"id":12,"big":18446744073709551615,"list":[true,null,-1.5e3,[]],"text":"a \"quote\"\né😀","empty":{}}"#
+    );
+    assert_eq!(Json::parse(&printed).unwrap(), json);
+    assert_eq!(Json::parse(&json.pretty(2)).unwrap(), json);
+
+    for broken in ["", "{", "[1,]", "{\"a\" 1}", "\"open", "01x", "[1] 2"] {
+        assert!(Json::parse(broken).is_err(), "{broken}");
+    }
+}
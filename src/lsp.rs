@@ -0,0 +1,655 @@
+//! A language server for bc files, talking JSON-RPC over standard input and
+//! output.
+//!
+//! Every request parses and checks the document again from scratch, files are
+//! small enough that keeping anything around isn't worth it.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{BufRead, Write},
+    ops::Range,
+    panic::AssertUnwindSafe,
+};
+
+use lalrpop_util::ParseError;
+
+use crate::{
+    json::Json,
+    parser::{
+        ast::{self, GlobalKind, Path},
+        def,
+        highlight::{self, Category},
+        outline::{self, Definition, DefinitionKind},
+    },
+    stage::{
+        error::Level, types::Type, Context, FunctionSig, Global, Program, Resolvable, UserType,
+    },
+    tokenizer::Token,
+};
+
+/// Answers messages until the client says to exit or closes the input
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> Result<(), String> {
+    let mut server = Server::default();
+    while !server.exited {
+        let Some(message) = read_message(&mut input)? else {
+            break;
+        };
+        let replies = match Json::parse(&message) {
+            Ok(message) => server.handle(&message),
+            Err(err) => vec![error(Json::Null, -32700, err)],
+        };
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+    }
+    Ok(())
+}
+
+/// The body of the next message, or `None` once the input ends
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>, String> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                let value = value.trim();
+                let value = value
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid Content-Length `{value}`"))?;
+                length = Some(value);
+            }
+        }
+    }
+    let mut body = vec![0; length.ok_or("message without a Content-Length")?];
+    input.read_exact(&mut body).map_err(|err| err.to_string())?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|_| "message is not valid UTF-8".to_string())
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> Result<(), String> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())
+        .and_then(|()| output.flush())
+        .map_err(|err| err.to_string())
+}
+
+fn response(id: Json, result: Json) -> Json {
+    Json::object([("jsonrpc", "2.0".into()), ("id", id), ("result", result)])
+}
+
+fn error(id: Json, code: i64, message: String) -> Json {
+    let error = Json::object([("code", code.into()), ("message", message.into())]);
+    Json::object([("jsonrpc", "2.0".into()), ("id", id), ("error", error)])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+#[derive(Default)]
+struct Server {
+    /// The text of each open document by its uri
+    documents: HashMap<String, String>,
+    exited: bool,
+}
+
+impl Server {
+    /// The replies and notifications to send back for a message
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        // responses to anything we sent have no method
+        let Some(method) = message.get("method").and_then(Json::as_str) else {
+            return Vec::new();
+        };
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let Some(id) = message.get("id").cloned() else {
+            return self.notify(method, params);
+        };
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => Json::Null,
+            "textDocument/hover" => self.hover(params).unwrap_or(Json::Null),
+            "textDocument/definition" => self.definition(params).unwrap_or(Json::Null),
+            "textDocument/documentSymbol" => self.symbols(params).unwrap_or(Json::Null),
+            "textDocument/semanticTokens/full" => {
+                self.semantic_tokens(params).unwrap_or(Json::Null)
+            }
+            _ => return vec![error(id, -32601, format!("unknown method `{method}`"))],
+        };
+        vec![response(id, result)]
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let text = match method {
+            "textDocument/didOpen" => params
+                .get("textDocument")
+                .and_then(|document| document.get("text")),
+            // only whole documents are synced, so the last change has all of it
+            "textDocument/didChange" => params
+                .get("contentChanges")
+                .and_then(Json::as_array)
+                .and_then(<[Json]>::last)
+                .and_then(|change| change.get("text")),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish(&uri, Vec::new())];
+            }
+            "exit" => {
+                self.exited = true;
+                return Vec::new();
+            }
+            _ => return Vec::new(),
+        };
+        let Some(text) = text.and_then(Json::as_str) else {
+            return Vec::new();
+        };
+        let lines = Lines::new(text);
+        let diagnostics = analyze(text)
+            .diagnostics
+            .into_iter()
+            .map(|(span, level, message)| {
+                let severity = match level {
+                    Level::Error => 1,
+                    Level::Warning => 2,
+                };
+                Json::object([
+                    ("range", lines.range(span)),
+                    ("severity", severity.into()),
+                    ("source", "bc".into()),
+                    ("message", message.into()),
+                ])
+            })
+            .collect();
+        self.documents.insert(uri.clone(), text.to_string());
+        vec![publish(&uri, diagnostics)]
+    }
+
+    /// The uri and text of the document a request is about, along with the
+    /// offset of its position if it has one
+    fn document<'a>(&'a self, params: &'a Json) -> Option<(&'a str, &'a str, usize)> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let src = self.documents.get(uri)?;
+        let offset = match params.get("position") {
+            Some(position) => Lines::new(src).offset(position)?,
+            None => 0,
+        };
+        Some((uri, src, offset))
+    }
+
+    fn hover(&self, params: &Json) -> Option<Json> {
+        let (_, src, offset) = self.document(params)?;
+        let tokens = outline::tokens(src);
+        let at = ident_at(&tokens, offset)?;
+        let mut analysis = analyze(src);
+        let (path, after_dot) = outline::path_at(&tokens, at, &analysis.outline);
+        let context = analysis.context.as_mut()?;
+        let text = match find(&analysis.outline, &path, after_dot) {
+            Some(definition) => describe(context, definition)?,
+            // only primitives are left that can be described without a definition
+            None => match ast::Type::new(path) {
+                ast::Type::Nammed(_) => return None,
+                ty => {
+                    let ty = context.resolve_type(&ty, &Path::new(), None).ok()?;
+                    let layout = ty.layout(context);
+                    let notes = format!("size {}, align {}", layout.size_bytes(), layout.align());
+                    markdown(&ty.to_string(), &notes)
+                }
+            },
+        };
+        let contents = Json::object([("kind", "markdown".into()), ("value", text.into())]);
+        let range = Lines::new(src).range(tokens[at].0.clone());
+        Some(Json::object([("contents", contents), ("range", range)]))
+    }
+
+    fn definition(&self, params: &Json) -> Option<Json> {
+        let (uri, src, offset) = self.document(params)?;
+        let tokens = outline::tokens(src);
+        let at = ident_at(&tokens, offset)?;
+        let outline = outline::outline(src);
+        let (path, after_dot) = outline::path_at(&tokens, at, &outline);
+        let name = match find(&outline, &path, after_dot) {
+            Some(definition) => definition.name.clone(),
+            None if path.is_single() && !after_dot => outline::local(&tokens, &outline, at)?,
+            None => return None,
+        };
+        let range = Lines::new(src).range(name);
+        Some(Json::object([("uri", uri.into()), ("range", range)]))
+    }
+
+    fn semantic_tokens(&self, params: &Json) -> Option<Json> {
+        let (_, src, _) = self.document(params)?;
+        let lines = Lines::new(src);
+        let analysis = analyze(src);
+        // each token is given relative to the one before it
+        let mut data: Vec<usize> = Vec::new();
+        let (mut last_line, mut last_character) = (0, 0);
+        for highlight in highlight::highlight(src, analysis.context.as_ref()) {
+            let Some((ty, modifiers)) = token_type(highlight.category) else {
+                continue;
+            };
+            // tokens can't go over several lines, so comments and strings
+            // that do are split up
+            let mut start = highlight.span.start;
+            for piece in src[highlight.span].split_inclusive('\n') {
+                let text = piece.trim_end_matches(['\n', '\r']);
+                let length: usize = text.chars().map(char::len_utf16).sum();
+                if length != 0 {
+                    let (line, character) = lines.line_character(start);
+                    let delta = match line == last_line {
+                        true => character - last_character,
+                        false => character,
+                    };
+                    data.extend([line - last_line, delta, length, ty, modifiers]);
+                    (last_line, last_character) = (line, character);
+                }
+                start += piece.len();
+            }
+        }
+        Some(Json::object([("data", data.into())]))
+    }
+
+    fn symbols(&self, params: &Json) -> Option<Json> {
+        let (_, src, _) = self.document(params)?;
+        let lines = Lines::new(src);
+        let symbols = outline::outline(src)
+            .iter()
+            .map(|definition| symbol(&lines, definition))
+            .collect();
+        Some(Json::Array(symbols))
+    }
+}
+
+fn capabilities() -> Json {
+    let capabilities = Json::object([
+        ("textDocumentSync", 1.into()),
+        ("hoverProvider", true.into()),
+        ("definitionProvider", true.into()),
+        ("documentSymbolProvider", true.into()),
+        ("semanticTokensProvider", semantic_tokens_legend()),
+    ]);
+    let info = Json::object([("name", "bc".into())]);
+    Json::object([("capabilities", capabilities), ("serverInfo", info)])
+}
+
+/// Semantic tokens are given as indices into these
+const TOKEN_TYPES: [&str; 13] = [
+    "keyword",
+    "type",
+    "function",
+    "method",
+    "property",
+    "enumMember",
+    "variable",
+    "label",
+    "number",
+    "string",
+    "escapeSequence",
+    "comment",
+    "operator",
+];
+const TOKEN_MODIFIERS: [&str; 2] = ["readonly", "static"];
+
+fn semantic_tokens_legend() -> Json {
+    let legend = Json::object([
+        ("tokenTypes", TOKEN_TYPES.to_vec().into()),
+        ("tokenModifiers", TOKEN_MODIFIERS.to_vec().into()),
+    ]);
+    Json::object([("legend", legend), ("full", true.into())])
+}
+
+/// The index of a category's token type and the bits of its modifiers, what
+/// editors have no type for isn't sent
+fn token_type(category: Category) -> Option<(usize, usize)> {
+    Some(match category {
+        Category::Keyword => (0, 0),
+        Category::Type | Category::NumberSuffix => (1, 0),
+        Category::Function => (2, 0),
+        Category::Method => (3, 0),
+        Category::Field => (4, 0),
+        Category::EnumVariant => (5, 0),
+        Category::Local => (6, 0),
+        Category::Constant => (6, 0b01),
+        Category::Static => (6, 0b10),
+        Category::Label => (7, 0),
+        Category::Number => (8, 0),
+        Category::String | Category::Char => (9, 0),
+        Category::Escape => (10, 0),
+        Category::Comment => (11, 0),
+        Category::Operator => (12, 0),
+        Category::Ident | Category::Punctuation | Category::Invalid => return None,
+    })
+}
+
+fn publish(uri: &str, diagnostics: Vec<Json>) -> Json {
+    let params = Json::object([
+        ("uri", uri.into()),
+        ("diagnostics", Json::Array(diagnostics)),
+    ]);
+    notification("textDocument/publishDiagnostics", params)
+}
+
+/// Converts between byte offsets and the positions editors use, which count
+/// UTF-16 code units from the start of the line
+struct Lines<'a> {
+    src: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(src: &'a str) -> Self {
+        let starts = src.match_indices('\n').map(|(i, _)| i + 1);
+        Self {
+            src,
+            starts: std::iter::once(0).chain(starts).collect(),
+        }
+    }
+
+    /// The line and character an offset is at
+    fn line_character(&self, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let before = &self.src[self.starts[line]..offset];
+        (line, before.chars().map(char::len_utf16).sum())
+    }
+
+    fn position(&self, offset: usize) -> Json {
+        let (line, character) = self.line_character(offset);
+        Json::object([("line", line.into()), ("character", character.into())])
+    }
+
+    fn range(&self, range: Range<usize>) -> Json {
+        Json::object([
+            ("start", self.position(range.start)),
+            ("end", self.position(range.end)),
+        ])
+    }
+
+    fn offset(&self, position: &Json) -> Option<usize> {
+        let line = position.get("line")?.as_u64()? as usize;
+        let character = position.get("character")?.as_u64()? as usize;
+        let Some(&start) = self.starts.get(line) else {
+            return Some(self.src.len());
+        };
+        let mut units = 0;
+        for (i, char) in self.src[start..].char_indices() {
+            if units >= character || char == '\n' {
+                return Some(start + i);
+            }
+            units += char.len_utf16();
+        }
+        Some(self.src.len())
+    }
+}
+
+/// What parsing and checking a document finds
+struct Analysis {
+    outline: Vec<Definition>,
+    /// `None` if the document doesn't parse
+    context: Option<Context>,
+    diagnostics: Vec<(Range<usize>, Level, String)>,
+}
+
+fn analyze(src: &str) -> Analysis {
+    let outline = outline::outline(src);
+    let module = match def::ModuleParser::new().parse(src) {
+        Ok(module) => module,
+        Err(err) => {
+            let (span, message) = parse_error(src, err);
+            return Analysis {
+                outline,
+                context: None,
+                diagnostics: vec![(span, Level::Error, message)],
+            };
+        }
+    };
+    // a broken program can still trip up the checker, that shouldn't take
+    // the whole server down with it
+    let checked = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut program = Program::default();
+        program.load_module(Path::new(), module);
+        program.check();
+        program.context
+    }));
+    let Ok(context) = checked else {
+        let message = "internal compiler error while checking this file".to_string();
+        return Analysis {
+            outline,
+            context: None,
+            diagnostics: vec![(0..0, Level::Error, message)],
+        };
+    };
+    // diagnostics the checker can't place on an expression go on the name of
+    // the item they're in
+    let diagnostics = context
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| {
+            if let Some(span) = diagnostic.span {
+                let message = diagnostic.kind.to_string();
+                return (span.start..span.end, diagnostic.level, message);
+            }
+            let span = diagnostic.item.as_ref().and_then(|item| {
+                let mut all = outline.iter().flat_map(Definition::all);
+                all.find(|definition| {
+                    definition.path == *item && definition.kind != DefinitionKind::Impl
+                })
+            });
+            let span = span.map_or(0..0, |definition| definition.name.clone());
+            (span, diagnostic.level, diagnostic.kind.to_string())
+        })
+        .collect();
+    Analysis {
+        outline,
+        context: Some(context),
+        diagnostics,
+    }
+}
+
+/// Where a parse error is and what it says
+fn parse_error<T: Display>(src: &str, err: ParseError<usize, T, &str>) -> (Range<usize>, String) {
+    let char_at = |at: usize| src.get(at..).and_then(|rest| rest.chars().next());
+    match err {
+        ParseError::InvalidToken { location } => {
+            let len = char_at(location).map_or(0, char::len_utf8);
+            (location..location + len, "invalid token".to_string())
+        }
+        ParseError::UnrecognizedEof { location, .. } => {
+            (location..location, "the file ends too soon".to_string())
+        }
+        ParseError::UnrecognizedToken {
+            token: (start, token, end),
+            ..
+        }
+        | ParseError::ExtraToken {
+            token: (start, token, end),
+        } => {
+            // tokens the lexer can't match come through empty
+            let mut token = token.to_string();
+            let mut end = end;
+            if token.is_empty() {
+                token = char_at(start).into_iter().collect();
+                end = start + token.len();
+            }
+            (start..end, format!("unexpected `{token}`"))
+        }
+        ParseError::User { error } => (0..0, error.to_string()),
+    }
+}
+
+/// The index of the identifier at `offset`, if there is one
+fn ident_at(tokens: &[(Range<usize>, Token)], offset: usize) -> Option<usize> {
+    tokens.iter().position(|(span, token)| {
+        matches!(token, Token::Ident(_)) && span.start <= offset && offset <= span.end
+    })
+}
+
+/// The definition a path refers to, a member reached through a value is only
+/// found if it's the only one with its name
+fn find<'a>(outline: &'a [Definition], path: &Path, after_dot: bool) -> Option<&'a Definition> {
+    let all = || {
+        let all = outline.iter().flat_map(Definition::all);
+        all.filter(|definition| definition.kind != DefinitionKind::Impl)
+    };
+    if !after_dot {
+        return all().find(|definition| definition.path == *path);
+    }
+    let mut members = all().filter(|definition| {
+        matches!(
+            definition.kind,
+            DefinitionKind::Field | DefinitionKind::Method
+        ) && definition.path.last() == path.last()
+    });
+    let member = members.next()?;
+    members.next().is_none().then_some(member)
+}
+
+/// Hover text for a definition, from what the checker worked out about it
+fn describe(context: &mut Context, definition: &Definition) -> Option<String> {
+    let path = &definition.path;
+    let name = path.last().unwrap_or_default();
+    let parent = path.parent().unwrap_or_default();
+    Some(match definition.kind {
+        DefinitionKind::Struct | DefinitionKind::Union | DefinitionKind::Enum => {
+            let (keyword, members) = match context.user_type(path)? {
+                UserType::Struct(struc) => {
+                    let members = struc.members.iter().map(|member| {
+                        let offset = member.offset;
+                        format!("    {} {}, // offset {offset}\n", member.ty, member.name)
+                    });
+                    ("struct", members.collect::<String>())
+                }
+                UserType::Union(unio) => {
+                    let members = unio.members.iter();
+                    let members =
+                        members.map(|member| format!("    {} {},\n", member.ty, member.name));
+                    ("union", members.collect())
+                }
+                UserType::Enum(enu) => {
+                    let members = enu.members.iter();
+                    let members =
+                        members.map(|member| format!("    {} = {},\n", member.name, member.value));
+                    ("enum", members.collect())
+                }
+                _ => return None,
+            };
+            // layouts are left alone if the checker couldn't work them out,
+            // a recursive type would never finish
+            let notes = match context.computed_layout(path) {
+                Some(layout) if layout.is_sized() => {
+                    format!("size {}, align {}", layout.size_bytes(), layout.align())
+                }
+                Some(layout) => format!("unsized, align {}", layout.align()),
+                None => String::new(),
+            };
+            markdown(&format!("{keyword} {path} {{\n{members}}}"), &notes)
+        }
+        DefinitionKind::Field => {
+            let (ty, notes) = match context.user_type(&parent)? {
+                UserType::Struct(struc) => {
+                    let member = struc.members.iter().find(|member| member.name == name)?;
+                    (
+                        &member.ty,
+                        format!("offset {} in `{parent}`", member.offset),
+                    )
+                }
+                UserType::Union(unio) => {
+                    let member = unio.members.iter().find(|member| member.name == name)?;
+                    (&member.ty, format!("offset 0 in `{parent}`"))
+                }
+                _ => return None,
+            };
+            markdown(&format!("{ty} {name}"), &notes)
+        }
+        DefinitionKind::Variant => {
+            let UserType::Enum(enu) = context.user_type(&parent)? else {
+                return None;
+            };
+            let variant = enu.members.iter().find(|member| member.name == name)?;
+            markdown(&format!("{path} = {}", variant.value), "")
+        }
+        DefinitionKind::Const | DefinitionKind::Static => {
+            let Global::Variable(Resolvable::Resolved(var)) = context.global(path)? else {
+                return None;
+            };
+            let keyword = match var.kind {
+                GlobalKind::Const => "const",
+                GlobalKind::Static => "static",
+                GlobalKind::Extern => "extern static",
+            };
+            let value = var.value.as_ref().map(|value| format!(" = {value}"));
+            let code = format!("{keyword} {} {path}{}", var.ty, value.unwrap_or_default());
+            markdown(&code, "")
+        }
+        DefinitionKind::Function => {
+            let Global::Function(id) = context.global(path)? else {
+                return None;
+            };
+            markdown(&signature(&context.function(*id).sig), "")
+        }
+        DefinitionKind::Method => {
+            let id = context.method(&Type::Nammed(parent), name)?;
+            markdown(&signature(&context.function(id).sig), "")
+        }
+        DefinitionKind::Impl => return None,
+    })
+}
+
+fn signature(sig: &FunctionSig) -> String {
+    let params = sig.params.iter().map(|(ty, name)| format!("{ty} {name}"));
+    let params = params.collect::<Vec<_>>().join(", ");
+    let name = sig.name.as_ref().map(Path::to_string).unwrap_or_default();
+    match sig.ret_ty {
+        Type::Void => format!("fn {name}({params})"),
+        ref ret => format!("fn {name}({params}) {ret}"),
+    }
+}
+
+fn markdown(code: &str, notes: &str) -> String {
+    match notes.is_empty() {
+        true => format!("```bc\n{code}\n```"),
+        false => format!("```bc\n{code}\n```\n{notes}"),
+    }
+}
+
+fn symbol(lines: &Lines, definition: &Definition) -> Json {
+    let name = definition.path.last().unwrap_or_default().to_string();
+    // the numbers are the editor's `SymbolKind`s
+    let (kind, name) = match definition.kind {
+        DefinitionKind::Struct | DefinitionKind::Union => (23, name),
+        DefinitionKind::Enum => (10, name),
+        DefinitionKind::Variant => (22, name),
+        DefinitionKind::Field => (8, name),
+        DefinitionKind::Const => (14, name),
+        DefinitionKind::Static => (13, name),
+        DefinitionKind::Function => (12, name),
+        DefinitionKind::Method => (6, name),
+        DefinitionKind::Impl => (3, format!("impl {}", definition.path)),
+    };
+    let children = definition.children.iter();
+    let children = children.map(|child| symbol(lines, child)).collect();
+    Json::object([
+        ("name", name.into()),
+        ("kind", kind.into()),
+        ("range", lines.range(definition.span.clone())),
+        ("selectionRange", lines.range(definition.name.clone())),
+        ("children", Json::Array(children)),
+    ])
+}
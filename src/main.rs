@@ -1,10 +1,292 @@
-#![feature(const_type_id)]
-
+pub mod backend;
 pub mod bruh;
 pub mod bruh2;
 pub mod comp;
+pub mod export;
+pub mod ir;
+pub mod json;
+pub mod lsp;
 pub mod parser;
 pub mod stage;
 pub mod tokenizer;
 
-fn main() {}
+use std::{path::PathBuf, process::ExitCode};
+
+use crate::{
+    ir::opt::{OptLevel, Pass},
+    parser::ast::{self, Path},
+    stage::Program,
+};
+
+const USAGE: &str = "\
+usage: bc build <file.bc> [-o <output>] [-O<level> | --passes <pass,...>] [--target x86_64|wasm|llvm|c] [--debug-regalloc]
+       bc build <file.bc> --target c --cc [-o <output>] [-O<level>]
+       bc build <file.bc> --emit json [-o <output>]
+       bc run <file.bc> [-O<level> | --passes <pass,...>] [--interpret]
+       bc fmt <file.bc> [--indent <n>] [--width <n>] [--check]
+       bc calc
+       bc lsp";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("build") => build(&args[1..]).map(|()| ExitCode::SUCCESS),
+        Some("run") => run(&args[1..]),
+        Some("fmt") => fmt(&args[1..]),
+        Some("calc") if args.len() == 1 => calc().map(|()| ExitCode::SUCCESS),
+        Some("lsp") if args.len() == 1 => {
+            lsp::serve(std::io::stdin().lock(), std::io::stdout().lock())
+                .map(|()| ExitCode::SUCCESS)
+        }
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parses and checks a source file, reporting what's wrong with it
+fn load(file: &str) -> Result<Program, String> {
+    let (_, module) = parse(file)?;
+    check(file, module)
+}
+
+fn parse(file: &str) -> Result<(String, ast::Module), String> {
+    let src =
+        std::fs::read_to_string(file).map_err(|err| format!("could not read {file}: {err}"))?;
+    let module = parser::def::ModuleParser::new()
+        .parse(&src)
+        .map_err(|err| format!("{file}: {err}"))?;
+    Ok((src, module))
+}
+
+fn check(file: &str, module: ast::Module) -> Result<Program, String> {
+    let mut program = Program::default();
+    program.load_module(Path::new(), module);
+    let ok = program.check();
+    for diagnostic in program.context.diagnostics() {
+        eprintln!("{file}: {diagnostic}");
+    }
+    if !ok {
+        return Err(format!("could not compile {file}"));
+    }
+    Ok(program)
+}
+
+/// Parses the `--passes` list, which are run in order instead of `-O`
+fn passes(list: &str) -> Result<Vec<Pass>, String> {
+    list.split(',').map(str::parse).collect()
+}
+
+fn optimize(module: &mut ir::Module, level: OptLevel, passes: Option<&[Pass]>) {
+    match passes {
+        Some(passes) => ir::opt::run_passes(module, passes),
+        None => ir::opt::optimize(module, level),
+    }
+}
+
+fn build(args: &[String]) -> Result<(), String> {
+    let mut file = None;
+    let mut output = None;
+    let mut level = OptLevel::default();
+    let mut pass_list = None;
+    let mut target = "x86_64";
+    let mut debug_regalloc = false;
+    let mut cc = false;
+    let mut emit = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--debug-regalloc" {
+            debug_regalloc = true;
+        } else if arg == "--cc" {
+            cc = true;
+        } else if arg == "--emit" {
+            emit = Some(args.next().ok_or(USAGE)?);
+        } else if arg == "-o" {
+            output = Some(args.next().ok_or(USAGE)?);
+        } else if arg == "--target" {
+            target = args.next().ok_or(USAGE)?;
+        } else if arg == "--passes" {
+            pass_list = Some(passes(args.next().ok_or(USAGE)?)?);
+        } else if let Some(value) = arg.strip_prefix("-O") {
+            level = value.parse()?;
+        } else if file.is_none() && !arg.starts_with('-') {
+            file = Some(arg);
+        } else {
+            return Err(USAGE.to_string());
+        }
+    }
+    let file = file.ok_or(USAGE)?;
+    if let Some(emit) = emit {
+        if emit != "json" {
+            return Err(format!("unknown emit kind `{emit}`, expected json"));
+        }
+        let output = match output {
+            Some(output) => PathBuf::from(output),
+            None => PathBuf::from(file).with_extension("json"),
+        };
+        let (src, module) = parse(file)?;
+        let program = check(file, module.clone())?;
+        let json = export::export(&src, &module, &program.context);
+        return std::fs::write(&output, json.pretty(2) + "\n")
+            .map_err(|err| format!("could not write {}: {err}", output.display()));
+    }
+    if cc && target != "c" {
+        return Err("`--cc` only goes with `--target c`".to_string());
+    }
+    if pass_list.is_some() && target == "c" {
+        return Err("`--passes` doesn't go with `--target c`, which skips the IR".to_string());
+    }
+    let extension = match target {
+        "x86_64" => "",
+        "wasm" => "wasm",
+        "llvm" => "ll",
+        "c" if cc => "",
+        "c" => "c",
+        _ => {
+            return Err(format!(
+                "unknown target `{target}`, expected x86_64, wasm, llvm or c"
+            ))
+        }
+    };
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => PathBuf::from(file).with_extension(extension),
+    };
+
+    let mut program = load(file)?;
+    // the C backend works from the checked program rather than the IR
+    if target == "c" {
+        if cc {
+            return backend::c::build(&mut program.context, &output, level);
+        }
+        let source = backend::c::emit(&mut program.context);
+        return std::fs::write(&output, source)
+            .map_err(|err| format!("could not write {}: {err}", output.display()));
+    }
+    let mut module = ir::lower(&mut program.context);
+    optimize(&mut module, level, pass_list.as_deref());
+    if target == "wasm" {
+        let bytes = backend::wasm::emit(&module)?;
+        return std::fs::write(&output, bytes)
+            .map_err(|err| format!("could not write {}: {err}", output.display()));
+    }
+    if target == "llvm" {
+        let source = backend::llvm::emit(&mut program.context, &module);
+        return std::fs::write(&output, source)
+            .map_err(|err| format!("could not write {}: {err}", output.display()));
+    }
+    if debug_regalloc {
+        eprint!("{}", backend::x86_64::allocations(&module));
+    }
+    backend::x86_64::build(&module, &output)
+}
+
+/// Runs a program's `main` on the bytecode VM, or the tree interpreter with
+/// `--interpret`, exiting with what it returns
+fn run(args: &[String]) -> Result<ExitCode, String> {
+    let mut file = None;
+    let mut level = OptLevel::default();
+    let mut pass_list = None;
+    let mut interpret = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--interpret" {
+            interpret = true;
+        } else if arg == "--passes" {
+            pass_list = Some(passes(args.next().ok_or(USAGE)?)?);
+        } else if let Some(value) = arg.strip_prefix("-O") {
+            level = value.parse()?;
+        } else if file.is_none() && !arg.starts_with('-') {
+            file = Some(arg);
+        } else {
+            return Err(USAGE.to_string());
+        }
+    }
+    let file = file.ok_or(USAGE)?;
+
+    let mut program = load(file)?;
+    if interpret {
+        let thread = std::thread::Builder::new().stack_size(stage::interpret::THREAD_STACK_SIZE);
+        let thread = thread.spawn(move || {
+            stage::interpret::Interpreter::new(&mut program.context)?.run_main()
+        });
+        let value = thread.map_err(|err| err.to_string())?.join().unwrap()?;
+        return Ok(ExitCode::from(value.as_int().unwrap_or(0) as u8));
+    }
+    let mut module = ir::lower(&mut program.context);
+    optimize(&mut module, level, pass_list.as_deref());
+    let mut program = backend::vm::compile(&module)?;
+    let code = program.call("main")?.and_then(|value| value.bits());
+    Ok(ExitCode::from(code.unwrap_or(0) as u8))
+}
+
+/// Formats a file in place, or with `--check` fails if it isn't formatted
+fn fmt(args: &[String]) -> Result<ExitCode, String> {
+    let mut file = None;
+    let mut style = parser::fmt::Style::default();
+    let mut check = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut number = || -> Result<usize, String> {
+            let value = args.next().ok_or(USAGE)?;
+            value
+                .parse()
+                .map_err(|_| format!("expected a number, found `{value}`"))
+        };
+        match arg.as_str() {
+            "--indent" => style.indent = number()?,
+            "--width" => style.width = number()?,
+            "--check" => check = true,
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let file = file.ok_or(USAGE)?;
+
+    let src =
+        std::fs::read_to_string(file).map_err(|err| format!("could not read {file}: {err}"))?;
+    let formatted = parser::fmt::format(&src, &style).map_err(|err| format!("{file}: {err}"))?;
+    if formatted == src {
+        return Ok(ExitCode::SUCCESS);
+    }
+    if check {
+        eprintln!("{file} is not formatted");
+        return Ok(ExitCode::FAILURE);
+    }
+    std::fs::write(file, formatted).map_err(|err| format!("could not write {file}: {err}"))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Reads calc a line at a time from standard input, printing what each gives
+fn calc() -> Result<(), String> {
+    use std::io::Write;
+
+    let mut repl = bruh2::repl::Repl::new();
+    let mut line = String::new();
+    loop {
+        print!("> ");
+        std::io::stdout().flush().map_err(|err| err.to_string())?;
+        line.clear();
+        if std::io::stdin()
+            .read_line(&mut line)
+            .map_err(|err| err.to_string())?
+            == 0
+        {
+            println!();
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        // errors are lined up under the prompt
+        match repl.eval(line.trim_end()) {
+            Ok(value) => println!("{value}"),
+            Err(err) => println!("  {err}"),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use crate::tokenizer::Number;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Module {
     pub use_statements: Vec<()>,
 
@@ -12,6 +12,11 @@ pub struct Module {
 
     pub function_def: Vec<FunctionDef>,
     pub function_header: Vec<FunctionHeader>,
+
+    pub impl_def: Vec<ImplDef>,
+
+    /// The kind of each definition, in the order they were written
+    pub order: Vec<DefKind>,
 }
 
 impl Module {
@@ -22,15 +27,42 @@ impl Module {
     }
 
     pub fn append(&mut self, tl: TopLevelDef) {
-        match tl{
-            TopLevelDef::FunctionDef(item) => self.function_def.push(item),
-            TopLevelDef::FunctionHeader(item) => self.function_header.push(item),
-            TopLevelDef::StructDef(item) => self.struct_def.push(item),
-            TopLevelDef::EnumDef(item) => self.enum_def.push(item),
-            TopLevelDef::UnionDef(item) => self.union_def.push(item),
-            TopLevelDef::GlobalDef(item) => self.glob_def.push(item),
-            TopLevelDef::UseStatement() => todo!(),
-        }
+        let kind = match tl {
+            TopLevelDef::FunctionDef(item) => {
+                self.function_def.push(item);
+                DefKind::FunctionDef
+            }
+            TopLevelDef::FunctionHeader(item) => {
+                self.function_header.push(item);
+                DefKind::FunctionHeader
+            }
+            TopLevelDef::StructDef(item) => {
+                self.struct_def.push(item);
+                DefKind::StructDef
+            }
+            TopLevelDef::EnumDef(item) => {
+                self.enum_def.push(item);
+                DefKind::EnumDef
+            }
+            TopLevelDef::UnionDef(item) => {
+                self.union_def.push(item);
+                DefKind::UnionDef
+            }
+            TopLevelDef::GlobalDef(item) => {
+                self.glob_def.push(item);
+                DefKind::GlobalDef
+            }
+            TopLevelDef::ImplDef(item) => {
+                self.impl_def.push(item);
+                DefKind::ImplDef
+            }
+            // a `use` has nothing in it to print yet, so it stays out of `order`
+            TopLevelDef::UseStatement() => {
+                self.use_statements.push(());
+                return;
+            }
+        };
+        self.order.push(kind);
     }
 }
 
@@ -42,13 +74,29 @@ pub enum TopLevelDef {
     EnumDef(EnumDef),
     UnionDef(UnionDef),
     GlobalDef(GlobalDef),
+    ImplDef(ImplDef),
     UseStatement(),
 }
 
+/// Which list of a [`Module`] a definition went into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefKind {
+    FunctionDef,
+    FunctionHeader,
+    StructDef,
+    EnumDef,
+    UnionDef,
+    GlobalDef,
+    ImplDef,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Block {
     Scope(Option<String>, Vec<Statement>),
     While(Option<String>, Box<Expression>, Vec<Statement>),
+    Loop(Option<String>, Vec<Statement>),
+    /// Binds the name to every value of a range or element of an array
+    For(Option<String>, String, Box<Expression>, Vec<Statement>),
     If(
         Option<String>,
         Box<Expression>,
@@ -99,15 +147,59 @@ pub enum Type {
     Str,
     FnPointer(Vec<Type>, Option<Box<Type>>),
     Nammed(Path),
-    Ptr(Box<Type>),
-    Ref(Box<Type>),
+    /// The pointee and whether it can be written through
+    Ptr(Box<Type>, bool),
+    Ref(Box<Type>, bool),
     Array(Box<Type>),
     ArrayStatic(Box<Type>, Box<Expression>),
 }
 
 
+/// Where a node was in the source, as byte offsets
+///
+/// Spans never tell nodes apart, the same code written somewhere else
+/// compares and hashes the same.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Span {}
+
+impl std::hash::Hash for Span {
+    fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+impl Expression {
+    pub fn new(start: usize, end: usize, kind: ExpressionKind) -> Self {
+        Self {
+            kind,
+            span: Span::new(start, end),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum Expression {
+pub enum ExpressionKind {
     Path(Path),
     Literal(Literal),
     Block(Block),
@@ -120,6 +212,8 @@ pub enum Expression {
     BinaryOp(Box<Expression>, BinOpKind, Box<Expression>),
 
     Assign(Box<Expression>, Box<Expression>),
+    /// `place op= value`
+    CompoundAssign(Box<Expression>, BinOpKind, Box<Expression>),
 
     SizeOf(Type),
     AlignOf(Type),
@@ -127,6 +221,9 @@ pub enum Expression {
     OffsetOf(Type, String),
     TypeName(Type),
 
+    /// `start..end`, or `start..=end` when the flag is set
+    Range(Box<Expression>, Box<Expression>, bool),
+
     StructCon(Path, Vec<(String, Expression)>),
     ArrayCon(Vec<Expression>),
 
@@ -136,9 +233,25 @@ pub enum Expression {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum Statement {
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+impl Statement {
+    pub fn new(start: usize, end: usize, kind: StatementKind) -> Self {
+        Self {
+            kind,
+            span: Span::new(start, end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StatementKind {
     Expression(Expression),
-    VariableDeclaration(Type, String, Expression),
+    /// `mut`, the type, the name and the initial value if there is one
+    VariableDeclaration(bool, Type, String, Option<Expression>),
 }
 
 #[derive(Default, Debug, Clone, Hash, PartialEq, Eq)]
@@ -162,55 +275,123 @@ impl Path {
         }
         new
     }
+
+    pub fn join(&self, other: &Path) -> Self {
+        Self {
+            path: self.path.join(&other.path),
+        }
+    }
+
+    pub fn parts(&self) -> impl Iterator<Item = &str> + '_ {
+        self.path
+            .iter()
+            .map(|part| part.to_str().unwrap_or_default())
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.path.iter().count() == 1
+    }
+
+    pub fn last(&self) -> Option<&str> {
+        self.path.file_name()?.to_str()
+    }
+
+    pub fn parent(&self) -> Option<Path> {
+        let parent = self.path.parent()?;
+        if parent.as_os_str().is_empty() {
+            return None;
+        }
+        Some(Self {
+            path: parent.to_path_buf(),
+        })
+    }
 }
 
-#[derive(Debug, Clone)]
+impl std::fmt::Display for Path {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, part) in self.parts().enumerate() {
+            if i != 0 {
+                write!(f, "::")?;
+            }
+            write!(f, "{part}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GlobalKind {
     Const,
     Static,
+    /// A static defined outside of the program, it never has a value
+    Extern,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct GlobalDef {
+    pub vis: Vis,
     pub kind: GlobalKind,
     pub ty: Type,
     pub name: String,
     pub value: Option<Expression>,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+/// Whether an item can be used from outside of the program
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Vis {
+    Pub,
+    #[default]
+    Priv,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FunctionDef {
+    pub vis: Vis,
     pub name: String,
     pub kind: Option<String>,
-    pub params: Vec<(Type, String)>,
+    /// `mut`, the type and the name of each parameter
+    pub params: Vec<(bool, Type, String)>,
     pub ret: Option<Type>,
     pub body: Vec<Statement>,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FunctionHeader {
     pub name: String,
     pub kind: Option<String>,
     pub params: Vec<(Type, String)>,
     pub ret: Option<Type>,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StructDef {
     pub name: String,
-    pub values: Vec<(Type, String)>,
+    pub values: Vec<(Type, String, Span)>,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EnumDef {
     pub name: String,
-    pub values: Vec<String>,
+    pub values: Vec<(String, Span)>,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UnionDef {
     pub name: String,
-    pub values: Vec<(Type, String)>,
+    pub values: Vec<(Type, String, Span)>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImplDef {
+    pub ty: Type,
+    pub functions: Vec<FunctionDef>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -292,11 +473,19 @@ impl Type{
     }
 
     pub fn wrap_ref(self) -> Self {
-        Self::Ref(self.into())
+        Self::Ref(self.into(), false)
+    }
+
+    pub fn wrap_ref_mut(self) -> Self {
+        Self::Ref(self.into(), true)
     }
 
     pub fn wrap_ptr(self) -> Self {
-        Self::Ptr(self.into())
+        Self::Ptr(self.into(), false)
+    }
+
+    pub fn wrap_ptr_mut(self) -> Self {
+        Self::Ptr(self.into(), true)
     }
 
     pub fn wrap_array(self) -> Self {
@@ -306,4 +495,41 @@ impl Type{
     pub fn wrap_array_sized(self, e: Expression) -> Self {
         Self::ArrayStatic(self.into(), e.into())
     }
-}
\ No newline at end of file
+}
+impl std::fmt::Display for UnaryOpKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnaryOpKind::Negate => write!(f, "-"),
+            UnaryOpKind::Deref => write!(f, "*"),
+            UnaryOpKind::Not => write!(f, "!"),
+            UnaryOpKind::Ref => write!(f, "&"),
+            UnaryOpKind::RefMut => write!(f, "&mut"),
+        }
+    }
+}
+
+impl std::fmt::Display for BinOpKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            BinOpKind::Times => "*",
+            BinOpKind::Divide => "/",
+            BinOpKind::Modulo => "%",
+            BinOpKind::Plus => "+",
+            BinOpKind::Minus => "-",
+            BinOpKind::ShiftLeft => "<<",
+            BinOpKind::ShiftRight => ">>",
+            BinOpKind::BitAnd => "&",
+            BinOpKind::BitXor => "^",
+            BinOpKind::BitOr => "|",
+            BinOpKind::Eq => "==",
+            BinOpKind::Neq => "!=",
+            BinOpKind::Gt => ">",
+            BinOpKind::Lt => "<",
+            BinOpKind::Gteq => ">=",
+            BinOpKind::Lteq => "<=",
+            BinOpKind::LogicalAnd => "&&",
+            BinOpKind::LogicalOr => "||",
+        };
+        write!(f, "{op}")
+    }
+}
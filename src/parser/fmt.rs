@@ -0,0 +1,837 @@
+//! Printing a module back out as source, in one style for every file.
+//!
+//! The printer works from the [`Module`] alone, so it puts in only the
+//! parentheses the precedence levels of `def.lalrpop` need. The grammar has
+//! nowhere to keep comments, they're put back afterwards by lining up the
+//! tokens of the source with the tokens of what was printed.
+
+use crate::tokenizer::{Token, Tokenizer};
+
+use super::{
+    ast::{
+        Block, DefKind, EnumDef, Expression, ExpressionKind, FloatType, FunctionDef,
+        FunctionHeader, GlobalDef, GlobalKind, ImplDef, IntSize, Literal, Module, Span, Statement,
+        StatementKind, Type, Vis,
+    },
+    def,
+};
+
+/// How the printed source is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    /// Spaces per level of nesting
+    pub indent: usize,
+    /// How long a line can get before lists are split over several lines
+    pub width: usize,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            indent: 4,
+            width: 100,
+        }
+    }
+}
+
+/// Formats source, keeping its comments
+pub fn format(src: &str, style: &Style) -> Result<String, String> {
+    let module = def::ModuleParser::new()
+        .parse(src)
+        .map_err(|err| err.to_string())?;
+    Ok(reattach(src, &print(&module, style), style))
+}
+
+/// Prints a module without any comments
+pub fn print(module: &Module, style: &Style) -> String {
+    let printer = Printer { style };
+    let mut functions = module.function_def.iter();
+    let mut headers = module.function_header.iter();
+    let mut structs = module.struct_def.iter();
+    let mut enums = module.enum_def.iter();
+    let mut unions = module.union_def.iter();
+    let mut globals = module.glob_def.iter();
+    let mut impls = module.impl_def.iter();
+
+    let mut items = Vec::new();
+    let mut item = |kind: DefKind| {
+        let printed = match kind {
+            DefKind::FunctionDef => printer.function(functions.next()?, 0),
+            DefKind::FunctionHeader => printer.header(headers.next()?),
+            DefKind::StructDef => {
+                let def = structs.next()?;
+                printer.fields("struct", &def.name, &def.values)
+            }
+            DefKind::UnionDef => {
+                let def = unions.next()?;
+                printer.fields("union", &def.name, &def.values)
+            }
+            DefKind::EnumDef => printer.enumeration(enums.next()?),
+            DefKind::GlobalDef => printer.global(globals.next()?),
+            DefKind::ImplDef => printer.implementation(impls.next()?),
+        };
+        items.push((kind, printed));
+        Some(())
+    };
+    for kind in &module.order {
+        item(*kind);
+    }
+    // anything that was added to the module by hand goes at the end
+    for kind in [
+        DefKind::StructDef,
+        DefKind::UnionDef,
+        DefKind::EnumDef,
+        DefKind::GlobalDef,
+        DefKind::FunctionHeader,
+        DefKind::FunctionDef,
+        DefKind::ImplDef,
+    ] {
+        while item(kind).is_some() {}
+    }
+
+    let mut out = String::new();
+    for (i, (kind, printed)) in items.iter().enumerate() {
+        // globals and headers are single lines, runs of them stay together
+        let grouped = matches!(kind, DefKind::GlobalDef | DefKind::FunctionHeader)
+            && i > 0
+            && items[i - 1].0 == *kind;
+        if i > 0 && !grouped {
+            out.push('\n');
+        }
+        out.push_str(printed);
+        out.push('\n');
+    }
+    out
+}
+
+/// Where an expression binds in the grammar, higher levels bind looser
+///
+/// These are the `precedence` levels of `ExpressionWithoutBlock`, blocks come
+/// after all of them since they need parentheses anywhere an `Expression`
+/// can't go.
+fn level(expression: &Expression) -> u8 {
+    use super::ast::BinOpKind::*;
+    match &expression.kind {
+        ExpressionKind::Path(_)
+        | ExpressionKind::Literal(_)
+        | ExpressionKind::SizeOf(_)
+        | ExpressionKind::AlignOf(_)
+        | ExpressionKind::Sized(_)
+        | ExpressionKind::OffsetOf(..)
+        | ExpressionKind::TypeName(_)
+        | ExpressionKind::ArrayCon(_) => 0,
+        ExpressionKind::StructCon(..) => 6,
+        ExpressionKind::FieldAccess(..) => 7,
+        ExpressionKind::MemberFunction(..)
+        | ExpressionKind::FunctionCall(..)
+        | ExpressionKind::ArrayAccess(..) => 8,
+        ExpressionKind::UnaryOp(..) => 9,
+        ExpressionKind::BinaryOp(_, op, _) => match op {
+            Times | Divide | Modulo => 10,
+            Plus | Minus => 11,
+            ShiftLeft | ShiftRight => 12,
+            BitAnd => 13,
+            BitXor => 14,
+            BitOr => 15,
+            Eq | Neq | Gt | Lt | Gteq | Lteq => 16,
+            LogicalAnd => 17,
+            LogicalOr => 18,
+        },
+        ExpressionKind::Range(..) => 19,
+        ExpressionKind::Assign(..) | ExpressionKind::CompoundAssign(..) => 20,
+        ExpressionKind::Break(..) | ExpressionKind::Continue(_) | ExpressionKind::Return(_) => 21,
+        ExpressionKind::Block(_) => BLOCK,
+    }
+}
+
+const BLOCK: u8 = 22;
+/// The loosest level that can go without parentheses outside of a block
+const WITHOUT_BLOCK: u8 = BLOCK - 1;
+
+struct Printer<'a> {
+    style: &'a Style,
+}
+
+impl Printer<'_> {
+    fn indent(&self, depth: usize) -> String {
+        " ".repeat(depth * self.style.indent)
+    }
+
+    /// How much of a line is left after indenting it
+    fn room(&self, depth: usize) -> usize {
+        self.style.width.saturating_sub(depth * self.style.indent)
+    }
+
+    fn global(&self, global: &GlobalDef) -> String {
+        let vis = if global.vis == Vis::Pub { "pub " } else { "" };
+        let kind = match global.kind {
+            GlobalKind::Const => "const",
+            GlobalKind::Static => "static",
+            GlobalKind::Extern => "extern static",
+        };
+        let head = format!("{vis}{kind} {} {}", self.ty(&global.ty), global.name);
+        match &global.value {
+            Some(value) => {
+                let room = self.room(0).saturating_sub(head.len() + 4);
+                format!("{head} = {};", self.expr(value, BLOCK, 0, room))
+            }
+            None => format!("{head};"),
+        }
+    }
+
+    fn header(&self, header: &FunctionHeader) -> String {
+        let kind = match &header.kind {
+            Some(kind) => format!(" \"{kind}\""),
+            None => String::new(),
+        };
+        let params = header.params.iter();
+        let params: Vec<_> = params
+            .map(|(ty, name)| format!("{} {name}", self.ty(ty)))
+            .collect();
+        let head = format!("extern{kind} fn {}", header.name);
+        let ret = header.ret.as_ref().map(|ret| format!(" {}", self.ty(ret)));
+        let ret = ret.unwrap_or_default();
+        let room = self.room(0).saturating_sub(head.len() + ret.len() + 1);
+        format!("{head}{}{ret};", self.list("(", params, ")", 0, room))
+    }
+
+    fn function(&self, function: &FunctionDef, depth: usize) -> String {
+        let vis = if function.vis == Vis::Pub { "pub " } else { "" };
+        let kind = match &function.kind {
+            Some(kind) => format!("extern \"{kind}\" "),
+            None => String::new(),
+        };
+        let params = function.params.iter().map(|(mutable, ty, name)| {
+            let mutable = if *mutable { "mut " } else { "" };
+            format!("{mutable}{} {name}", self.ty(ty))
+        });
+        let head = format!("{vis}{kind}fn {}", function.name);
+        let ret = function
+            .ret
+            .as_ref()
+            .map(|ret| format!(" {}", self.ty(ret)));
+        let ret = ret.unwrap_or_default();
+        let room = self.room(depth).saturating_sub(head.len() + ret.len() + 2);
+        let params = self.list("(", params.collect(), ")", depth, room);
+        format!("{head}{params}{ret} {}", self.body(&function.body, depth))
+    }
+
+    fn fields(&self, keyword: &str, name: &str, fields: &[(Type, String, Span)]) -> String {
+        let fields = fields.iter();
+        let fields = fields.map(|(ty, name, _)| format!("{} {name},", self.ty(ty)));
+        self.item(format!("{keyword} {name}"), fields.collect())
+    }
+
+    fn enumeration(&self, enumeration: &EnumDef) -> String {
+        let values = enumeration
+            .values
+            .iter()
+            .map(|(value, _)| format!("{value},"));
+        self.item(format!("enum {}", enumeration.name), values.collect())
+    }
+
+    /// A definition with one line for each of its parts
+    fn item(&self, head: String, lines: Vec<String>) -> String {
+        if lines.is_empty() {
+            return format!("{head} {{}}");
+        }
+        let indent = self.indent(1);
+        let lines: Vec<_> = lines
+            .iter()
+            .map(|line| format!("{indent}{line}\n"))
+            .collect();
+        format!("{head} {{\n{}}}", lines.concat())
+    }
+
+    fn implementation(&self, implementation: &ImplDef) -> String {
+        let functions = implementation.functions.iter();
+        let functions: Vec<_> = functions
+            .map(|function| self.function(function, 1))
+            .collect();
+        let head = format!("impl {}", self.ty(&implementation.ty));
+        if functions.is_empty() {
+            return format!("{head} {{}}");
+        }
+        let indent = self.indent(1);
+        let functions: Vec<_> = functions.iter().map(|f| format!("{indent}{f}\n")).collect();
+        format!("{head} {{\n{}}}", functions.join("\n"))
+    }
+
+    /// Statements between braces, with the closing brace indented to `depth`
+    fn body(&self, statements: &[Statement], depth: usize) -> String {
+        if statements.is_empty() {
+            return "{}".to_string();
+        }
+        let mut out = "{\n".to_string();
+        for statement in statements {
+            out.push_str(&self.indent(depth + 1));
+            out.push_str(&self.statement(statement, depth + 1));
+            out.push('\n');
+        }
+        out.push_str(&self.indent(depth));
+        out.push('}');
+        out
+    }
+
+    fn statement(&self, statement: &Statement, depth: usize) -> String {
+        let room = self.room(depth);
+        match &statement.kind {
+            StatementKind::Expression(Expression {
+                kind: ExpressionKind::Block(block),
+                ..
+            }) => self.block(block, depth),
+            StatementKind::Expression(expression) => {
+                let room = room.saturating_sub(1);
+                format!("{};", self.expr(expression, WITHOUT_BLOCK, depth, room))
+            }
+            StatementKind::VariableDeclaration(mutable, ty, name, value) => {
+                let mutable = if *mutable { "mut " } else { "" };
+                let head = format!("{mutable}{} {name}", self.ty(ty));
+                match value {
+                    Some(value) => {
+                        let room = room.saturating_sub(head.len() + 4);
+                        format!("{head} = {};", self.expr(value, BLOCK, depth, room))
+                    }
+                    None => format!("{head};"),
+                }
+            }
+        }
+    }
+
+    fn block(&self, block: &Block, depth: usize) -> String {
+        let label = |label: &Option<String>| match label {
+            Some(label) => format!("{label} "),
+            None => String::new(),
+        };
+        let condition =
+            |condition: &Expression| self.expr(condition, BLOCK, depth, self.room(depth));
+        match block {
+            Block::Scope(l, body) => format!("{}{}", label(l), self.body(body, depth)),
+            Block::While(l, condition_, body) => format!(
+                "{}while ({}) {}",
+                label(l),
+                condition(condition_),
+                self.body(body, depth)
+            ),
+            Block::Loop(l, body) => format!("{}loop {}", label(l), self.body(body, depth)),
+            Block::For(l, name, values, body) => format!(
+                "{}for ({name} in {}) {}",
+                label(l),
+                condition(values),
+                self.body(body, depth)
+            ),
+            Block::If(l, condition_, body, others, otherwise) => {
+                let mut out = format!(
+                    "{}if ({}) {}",
+                    label(l),
+                    condition(condition_),
+                    self.body(body, depth)
+                );
+                for (other, body) in others {
+                    let body = self.body(body, depth);
+                    out.push_str(&format!(" else if ({}) {body}", condition(other)));
+                }
+                if let Some(otherwise) = otherwise {
+                    out.push_str(&format!(" else {}", self.body(otherwise, depth)));
+                }
+                out
+            }
+        }
+    }
+
+    /// An expression that has to bind at least as tight as `max`, starting on
+    /// a line indented to `depth` with `room` left on it
+    fn expr(&self, expression: &Expression, max: u8, depth: usize, room: usize) -> String {
+        if level(expression) > max {
+            format!(
+                "({})",
+                self.unwrapped(expression, depth, room.saturating_sub(2))
+            )
+        } else {
+            self.unwrapped(expression, depth, room)
+        }
+    }
+
+    fn unwrapped(&self, expression: &Expression, depth: usize, room: usize) -> String {
+        use ExpressionKind as E;
+        let args = |args: &[Expression], room: usize| {
+            let args = args.iter().map(|arg| (arg, BLOCK));
+            self.exprs("(", args, ")", depth, room)
+        };
+        match &expression.kind {
+            E::Path(path) => path.to_string(),
+            E::Literal(Literal::String(string)) => format!("\"{string}\""),
+            E::Literal(Literal::Char(char)) => format!("'{char}'"),
+            E::Literal(Literal::Boolean(boolean)) => boolean.to_string(),
+            E::Literal(Literal::Number(number)) => number.clone(),
+            E::Block(block) => self.block(block, depth),
+            E::FieldAccess(value, field) => {
+                format!("{}.{field}", self.expr(value, 7, depth, room))
+            }
+            E::MemberFunction(value, name, arguments) => {
+                let value = format!("{}.{name}", self.expr(value, 7, depth, room));
+                let room = room.saturating_sub(last_line(&value));
+                format!("{value}{}", args(arguments, room))
+            }
+            E::FunctionCall(function, arguments) => {
+                let function = self.expr(function, 8, depth, room);
+                let room = room.saturating_sub(last_line(&function));
+                format!("{function}{}", args(arguments, room))
+            }
+            E::ArrayAccess(array, index) => {
+                let array = self.expr(array, 8, depth, room);
+                let room = room.saturating_sub(last_line(&array) + 2);
+                format!("{array}[{}]", self.expr(index, 7, depth, room))
+            }
+            E::UnaryOp(op, value) => {
+                let value = self.expr(value, 9, depth, room.saturating_sub(5));
+                let op = op.to_string();
+                // `&&` would be read as a logical and, and `--` looks like it does something
+                if op == "&mut" || value.starts_with(&op) {
+                    format!("{op} {value}")
+                } else {
+                    format!("{op}{value}")
+                }
+            }
+            E::BinaryOp(left, op, right) => {
+                let level = level(expression);
+                self.binary(
+                    left,
+                    &op.to_string(),
+                    right,
+                    (level, level - 1),
+                    depth,
+                    room,
+                )
+            }
+            E::Assign(place, value) => self.binary(place, "=", value, (19, 20), depth, room),
+            E::CompoundAssign(place, op, value) => {
+                self.binary(place, &format!("{op}="), value, (19, 20), depth, room)
+            }
+            E::Range(start, end, inclusive) => {
+                let start = self.expr(start, 18, depth, room);
+                let room = room.saturating_sub(last_line(&start) + 3);
+                let op = if *inclusive { "..=" } else { ".." };
+                format!("{start}{op}{}", self.expr(end, 18, depth, room))
+            }
+            E::SizeOf(ty) => format!("size_of({})", self.ty(ty)),
+            E::AlignOf(ty) => format!("align_of({})", self.ty(ty)),
+            E::Sized(ty) => format!("sized({})", self.ty(ty)),
+            E::OffsetOf(ty, field) => format!("offset_of({}, {field})", self.ty(ty)),
+            E::TypeName(ty) => format!("type_name({})", self.ty(ty)),
+            E::StructCon(path, fields) => {
+                if fields.is_empty() {
+                    return format!("{path} {{}}");
+                }
+                let path = format!("{path} ");
+                let room = room.saturating_sub(path.len());
+                let fields = fields.iter().map(|(name, value)| {
+                    let room = self.room(depth + 1).saturating_sub(name.len() + 4);
+                    let value = self.expr(value, BLOCK, depth + 1, room);
+                    format!("{name} = {value}")
+                });
+                format!(
+                    "{path}{}",
+                    self.list("{ ", fields.collect(), " }", depth, room)
+                )
+            }
+            E::ArrayCon(values) => {
+                let values = values.iter().map(|value| (value, BLOCK));
+                self.exprs("[", values, "]", depth, room)
+            }
+            E::Break(label, value) => {
+                let mut out = "break".to_string();
+                if let Some(label) = label {
+                    out.push_str(&format!(" {label}"));
+                }
+                if let Some(value) = value {
+                    let room = room.saturating_sub(out.len() + 1);
+                    out.push_str(&format!(
+                        " {}",
+                        self.expr(value, WITHOUT_BLOCK, depth, room)
+                    ));
+                }
+                out
+            }
+            E::Continue(Some(label)) => format!("continue {label}"),
+            E::Continue(None) => "continue".to_string(),
+            E::Return(Some(value)) => {
+                let room = room.saturating_sub(7);
+                format!("return {}", self.expr(value, WITHOUT_BLOCK, depth, room))
+            }
+            E::Return(None) => "return".to_string(),
+        }
+    }
+
+    /// Two operands around an operator, with the levels each can be
+    fn binary(
+        &self,
+        left: &Expression,
+        op: &str,
+        right: &Expression,
+        (left_max, right_max): (u8, u8),
+        depth: usize,
+        room: usize,
+    ) -> String {
+        let left = self.expr(left, left_max, depth, room);
+        let room = room.saturating_sub(last_line(&left) + op.len() + 2);
+        format!("{left} {op} {}", self.expr(right, right_max, depth, room))
+    }
+
+    /// Expressions separated by commas, see [`Printer::list`]
+    fn exprs<'e>(
+        &self,
+        open: &str,
+        values: impl Iterator<Item = (&'e Expression, u8)> + Clone,
+        close: &str,
+        depth: usize,
+        room: usize,
+    ) -> String {
+        let flat = values
+            .clone()
+            .map(|(value, max)| self.expr(value, max, depth, usize::MAX));
+        let flat: Vec<_> = flat.collect();
+        if fits(&flat, open, close, room) {
+            return format!("{open}{}{close}", flat.join(", "));
+        }
+        let room = self.room(depth + 1).saturating_sub(1);
+        let broken = values.map(|(value, max)| self.expr(value, max, depth + 1, room));
+        self.broken(open, broken.collect(), close, depth)
+    }
+
+    /// Parts separated by commas, all on the line if they fit in `room` and
+    /// one to a line otherwise
+    fn list(
+        &self,
+        open: &str,
+        parts: Vec<String>,
+        close: &str,
+        depth: usize,
+        room: usize,
+    ) -> String {
+        if fits(&parts, open, close, room) {
+            return format!("{open}{}{close}", parts.join(", "));
+        }
+        self.broken(open, parts, close, depth)
+    }
+
+    fn broken(&self, open: &str, parts: Vec<String>, close: &str, depth: usize) -> String {
+        let mut out = format!("{}\n", open.trim_end());
+        for part in parts {
+            out.push_str(&format!("{}{part},\n", self.indent(depth + 1)));
+        }
+        out.push_str(&self.indent(depth));
+        out.push_str(close.trim_start());
+        out
+    }
+
+    fn ty(&self, ty: &Type) -> String {
+        match ty {
+            Type::Int(size, signed) => {
+                let sign = if *signed { "i" } else { "u" };
+                let size = match size {
+                    IntSize::U8 => "8",
+                    IntSize::U16 => "16",
+                    IntSize::U32 => "32",
+                    IntSize::U64 => "64",
+                    IntSize::Usize => "size",
+                };
+                format!("{sign}{size}")
+            }
+            Type::Float(FloatType::F32) => "f32".to_string(),
+            Type::Float(FloatType::F64) => "f64".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Char => "char".to_string(),
+            Type::Void => "void".to_string(),
+            Type::Str => "str".to_string(),
+            Type::FnPointer(params, ret) => {
+                let params: Vec<_> = params.iter().map(|param| self.ty(param)).collect();
+                let ret = ret.as_ref().map(|ret| format!(" {}", self.ty(ret)));
+                format!("fn({}){}", params.join(", "), ret.unwrap_or_default())
+            }
+            Type::Nammed(path) => path.to_string(),
+            Type::Ptr(ty, true) => format!("*mut {}", self.ty(ty)),
+            Type::Ptr(ty, false) => format!("*{}", self.ty(ty)),
+            Type::Ref(ty, true) => format!("&mut {}", self.ty(ty)),
+            Type::Ref(ty, false) => match self.ty(ty) {
+                ty if ty.starts_with('&') => format!("& {ty}"),
+                ty => format!("&{ty}"),
+            },
+            Type::Array(ty) => format!("[{}]", self.ty(ty)),
+            Type::ArrayStatic(ty, size) => {
+                let size = self.expr(size, BLOCK, 0, usize::MAX);
+                format!("[{}; {size}]", self.ty(ty))
+            }
+        }
+    }
+}
+
+fn fits(parts: &[String], open: &str, close: &str, room: usize) -> bool {
+    let len: usize = parts.iter().map(|part| part.len() + 2).sum();
+    !parts.iter().any(|part| part.contains('\n')) && open.len() + len + close.len() <= room + 2
+}
+
+/// How long the last line of some printed source is
+fn last_line(printed: &str) -> usize {
+    printed.rsplit('\n').next().unwrap_or(printed).len()
+}
+
+/// A comment and where it was in the source
+struct Comment<'a> {
+    text: &'a str,
+    /// Whether nothing but whitespace comes before it on its line
+    own_line: bool,
+    /// Whether there's an empty line between it and what comes before
+    blank_before: bool,
+    /// The index of the token after it
+    next: usize,
+}
+
+/// The tokens a printer keeps as they were, where they start and whether
+/// there's an empty line before them, along with the comments between them
+///
+/// Parentheses and commas can come and go, so they're left out.
+fn tokens(src: &str) -> (Vec<(usize, Token<'_>, bool)>, Vec<Comment<'_>>) {
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    let mut end = 0;
+    for token in Tokenizer::new(src).include_comments() {
+        let Ok(token) = token else { continue };
+        let start = token.span.offset as usize;
+        let blank_before = src[end.min(start)..start].matches('\n').count() > 1;
+        let mut text = &src[start..start + token.span.len as usize];
+        match token.val {
+            Token::SingleLineComment(_) | Token::MultiLineComment(_) => {
+                text = text.trim_end();
+                let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+                comments.push(Comment {
+                    text,
+                    own_line: src[line_start..start].trim().is_empty(),
+                    blank_before,
+                    next: tokens.len(),
+                });
+            }
+            Token::LPar | Token::RPar | Token::Comma => {}
+            val => tokens.push((start, val, blank_before)),
+        }
+        end = start + text.len();
+    }
+    (tokens, comments)
+}
+
+/// Puts the comments of the source into what it was printed as
+///
+/// A comment on a line of its own goes on a line of its own before the line
+/// the token after it ends up on, a comment after some code goes at the end
+/// of the line the token before it ends up on. Empty lines from the source
+/// are kept where they're before the start of a line.
+fn reattach(src: &str, printed: &str, style: &Style) -> String {
+    let (from, comments) = tokens(src);
+    let (to, _) = tokens(printed);
+    // printing only leaves tokens out, so the rest are in the same order
+    let mut next = 0;
+    let mut moved = Vec::with_capacity(from.len());
+    for (_, token, _) in &from {
+        if to.get(next).is_some_and(|(_, other, _)| other == token) {
+            moved.push(Some(to[next].0));
+            next += 1;
+        } else {
+            moved.push(None);
+        }
+    }
+
+    let lines: Vec<&str> = printed.lines().collect();
+    let mut starts = vec![0];
+    starts.extend(printed.match_indices('\n').map(|(i, _)| i + 1));
+    let line_of = |offset: usize| starts.partition_point(|start| *start <= offset) - 1;
+
+    // what goes before each line and at the end of it, with one more line
+    // for the end of the file
+    let mut before: Vec<Vec<(bool, &str)>> = vec![Vec::new(); lines.len() + 1];
+    let mut after: Vec<String> = vec![String::new(); lines.len()];
+    // comments in braces that were printed empty, by where the `}` is
+    let mut inside: Vec<Vec<(usize, &str)>> = vec![Vec::new(); lines.len()];
+    let mut blank = vec![false; lines.len()];
+    for comment in &comments {
+        let previous = moved[..comment.next].iter().rev().flatten().next();
+        match previous {
+            Some(offset) if !comment.own_line => {
+                after[line_of(*offset)].push_str(&format!(" {}", comment.text));
+            }
+            _ => {
+                let following = moved[comment.next..].iter().flatten().next();
+                match following {
+                    Some(offset) if printed[..*offset].ends_with('{') => {
+                        let line = line_of(*offset);
+                        inside[line].push((offset - starts[line], comment.text));
+                    }
+                    Some(offset) => {
+                        before[line_of(*offset)].push((comment.blank_before, comment.text))
+                    }
+                    None => before[lines.len()].push((comment.blank_before, comment.text)),
+                }
+            }
+        }
+    }
+    for ((_, _, blank_before), offset) in from.iter().zip(&moved) {
+        let Some(offset) = offset else { continue };
+        let line = line_of(*offset);
+        let indent = lines[line].len() - lines[line].trim_start().len();
+        if *blank_before && starts[line] + indent == *offset {
+            blank[line] = true;
+        }
+    }
+
+    let mut out = String::new();
+    let push = |out: &mut String, line: &str| {
+        out.push_str(line.trim_end());
+        out.push('\n');
+    };
+    // a blank line after an opening brace, after another one or at the start
+    // of the file is never kept
+    let can_blank = |out: &str| !(out.is_empty() || out.ends_with("\n\n") || out.ends_with("{\n"));
+    for (i, comments) in before.iter().enumerate() {
+        let line = lines.get(i).copied().unwrap_or("");
+        let start = &line[..line.len() - line.trim_start().len()];
+        let deeper = format!("{start}{}", " ".repeat(style.indent));
+        let closing = line.trim_start().starts_with(['}', ')', ']']);
+        let indent = if closing { &deeper } else { start };
+        for (blank_before, comment) in comments {
+            if *blank_before && can_blank(&out) {
+                out.push('\n');
+            }
+            push(&mut out, &format!("{indent}{comment}"));
+        }
+        if i == lines.len() {
+            break;
+        }
+        if blank[i] && !closing && can_blank(&out) {
+            out.push('\n');
+        }
+        // empty braces with comments in them are opened up
+        let mut indent = "";
+        let mut split = 0;
+        for (column, comment) in &inside[i] {
+            if *column > split {
+                push(&mut out, &format!("{indent}{}", &line[split..*column]));
+                indent = start;
+                split = *column;
+            }
+            push(&mut out, &format!("{deeper}{comment}"));
+        }
+        push(&mut out, &format!("{indent}{}{}", &line[split..], after[i]));
+    }
+    out
+}
+
+#[cfg(test)]
+const SOURCE: &str = r#"
+// globals
+const i32 VALUE = 23;
+static isize FUNNY = -55;   // trailing
+extern static usize errno;
+
+/* a struct
+   with a comment over two lines */
+struct Point{ i32 x, i32 y }
+
+enum Color { Red, Green }
+
+impl Point {
+    fn len(&Self self) i32 { return self.x * self.x + self.y * self.y; }
+    pub fn swap(&mut Self self) {
+        i32 tmp = self.x; self.x = self.y;
+        self.y = tmp;
+    }
+}
+
+fn main(mut i32 count, [&str] args) i32 {
+    // count down
+    while (count > 0) {
+        count -= 1;
+
+        // after a gap
+        mut i32 sum = (1 + 2) * (3 - (4 - 5)) + 6;
+        sum = -(-sum) + (a[(i + 1)]).len() + *(p.x) + (*p).x;
+    }
+    'outer loop { for (i in 0..10) { if (i == 3) { break 'outer; } else if (i > 4) { continue; } else { } } }
+    i32 big = some_function_with_a_long_name(first_argument, second_argument, third_argument + 1);
+    Point p = Point { x = 1, y = if (count > 0) { 2; } else { 3; } };
+    return (if (count == 0) { 1; } else { 2; }) + size_of(Point);
+    /* the end */
+}
+
+fn empty() {
+    // nothing yet
+}
+
+extern "C" fn strlen(*u8 str) usize;
+extern "C" fn write(i32 fd, *u8 data, usize len) isize;
+"#;
+
+#[test]
+fn format_source() {
+    let formatted = format(SOURCE, &Style::default()).unwrap();
+    crate::ir::assert_snapshot("fmt_source.bc", &formatted);
+    assert_eq!(format(&formatted, &Style::default()).unwrap(), formatted);
+}
+
+#[test]
+fn keeps_meaning() {
+    let files = [SOURCE, include_str!("../../test/main.bc")];
+    let styles = [
+        Style::default(),
+        Style {
+            indent: 2,
+            width: 30,
+        },
+    ];
+    for (src, style) in files
+        .iter()
+        .flat_map(|src| styles.iter().map(move |style| (src, style)))
+    {
+        // spans are left out of comparing what was parsed
+        let parse = |src: &str| def::ModuleParser::new().parse(src).unwrap();
+        let formatted = format(src, style).unwrap();
+        assert_eq!(parse(&formatted), parse(src), "{formatted}");
+        assert_eq!(format(&formatted, style).unwrap(), formatted);
+        // nothing but whitespace and parentheses changes around comments
+        let comments = |src: &str| {
+            let (_, comments) = tokens(src);
+            comments
+                .iter()
+                .map(|comment| comment.text.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(comments(&formatted), comments(src));
+    }
+}
+
+#[test]
+fn narrow_lines() {
+    let style = Style {
+        indent: 2,
+        width: 30,
+    };
+    let src = "fn f() { call(first_argument, second, Point { x = 1, y = 2 }); short(a, b); }";
+    let formatted = format(src, &style).unwrap();
+    let expected = "\
+fn f() {
+  call(
+    first_argument,
+    second,
+    Point { x = 1, y = 2 },
+  );
+  short(a, b);
+}
+";
+    assert_eq!(formatted, expected);
+}
+
+#[test]
+fn prints_around_use() {
+    let mut module = def::ModuleParser::new().parse("fn f() {}").unwrap();
+    module.append(super::ast::TopLevelDef::UseStatement());
+    assert_eq!(module.use_statements.len(), 1);
+    assert_eq!(print(&module, &Style::default()), "fn f() {}\n");
+}
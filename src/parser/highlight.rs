@@ -0,0 +1,396 @@
+//! What each part of a source file is, for syntax highlighting.
+//!
+//! The tokens say most of it. What an identifier names comes from the
+//! outline, and from the checker's [`Context`] when the file gets that far.
+//! Anything the tokenizer can't make sense of is kept as [`Category::Invalid`]
+//! so a broken file still highlights everywhere else.
+
+use std::{collections::HashMap, ops::Range};
+
+use crate::{
+    parser::ast::{self, GlobalKind, Path},
+    stage::{types::Type, Context, Global, Resolvable, UserType},
+    tokenizer::{Token, Tokenizer, TokenizerError},
+};
+
+use super::outline::{self, Definition, DefinitionKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Category {
+    Keyword,
+    /// A primitive or user defined type
+    Type,
+    Function,
+    Method,
+    /// A field of a struct or union
+    Field,
+    EnumVariant,
+    Constant,
+    Static,
+    /// A parameter or a local variable
+    Local,
+    /// An identifier that couldn't be resolved
+    Ident,
+    Label,
+    Number,
+    /// The type at the end of a number, like the `u8` of `1u8`
+    NumberSuffix,
+    String,
+    Char,
+    /// An escape sequence in a string or character literal
+    Escape,
+    Comment,
+    Operator,
+    Punctuation,
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Highlight {
+    pub span: Range<usize>,
+    pub category: Category,
+}
+
+/// Every part of `src` except whitespace, in order
+pub fn highlight(src: &str, context: Option<&Context>) -> Vec<Highlight> {
+    let mut identifiers = identifiers(src, context);
+    let mut highlights = Vec::new();
+    let mut push = |span: Range<usize>, category| {
+        if !span.is_empty() {
+            highlights.push(Highlight { span, category })
+        }
+    };
+    for token in Tokenizer::new(src).include_comments() {
+        let (meta, token) = match token {
+            Ok(token) => (token.span, Ok(token.val)),
+            Err(err) => (err.span, Err(err.val)),
+        };
+        let start = meta.offset as usize;
+        let span = start..start + meta.len as usize;
+        let category = match token {
+            Ok(Token::Ident(_)) => identifiers.remove(&start).unwrap_or(Category::Ident),
+            Ok(Token::NumericLiteral(number)) => {
+                let suffix = number.get_suffix().map_or(0, str::len);
+                push(span.start..span.end - suffix, Category::Number);
+                push(span.end - suffix..span.end, Category::NumberSuffix);
+                continue;
+            }
+            Ok(Token::StringLiteral(_)) | Err(TokenizerError::UnclosedStringLiteral) => {
+                escapes(src, span, Category::String, &mut push);
+                continue;
+            }
+            Ok(Token::CharLiteral(_))
+            | Err(
+                TokenizerError::EmptyCharLiteral
+                | TokenizerError::UnclosedCharLiteral
+                | TokenizerError::CharLiteralTooBig,
+            ) => {
+                escapes(src, span, Category::Char, &mut push);
+                continue;
+            }
+            // the newline ending a comment isn't part of it
+            Ok(Token::SingleLineComment(_)) => {
+                let comment = src[span.clone()].trim_end_matches(['\n', '\r']);
+                push(span.start..span.start + comment.len(), Category::Comment);
+                continue;
+            }
+            Ok(Token::MultiLineComment(_)) | Err(TokenizerError::UnclosedMultiLineComment) => {
+                Category::Comment
+            }
+            // the literal they're in still comes through, and covers them
+            Err(TokenizerError::InvalidEscape(_) | TokenizerError::UnfinishedEscapeSequence(_)) => {
+                continue
+            }
+            Err(_) => Category::Invalid,
+            Ok(token) => category(&token),
+        };
+        push(span, category);
+    }
+    highlights
+}
+
+/// The category of any token that isn't an identifier or a literal
+fn category(token: &Token) -> Category {
+    match token {
+        Token::Fn
+        | Token::Return
+        | Token::If
+        | Token::Else
+        | Token::While
+        | Token::Loop
+        | Token::Let
+        | Token::For
+        | Token::Struct
+        | Token::Enum
+        | Token::Union
+        | Token::Break
+        | Token::TrueLiteral
+        | Token::FalseLiteral => Category::Keyword,
+        Token::Label(_) => Category::Label,
+        Token::LPar
+        | Token::RPar
+        | Token::LBrace
+        | Token::RBrace
+        | Token::LBracket
+        | Token::RBracket
+        | Token::Dot
+        | Token::Comma
+        | Token::Colon
+        | Token::Semicolon
+        | Token::SmallRightArrow
+        | Token::BigRightArrow => Category::Punctuation,
+        _ => Category::Operator,
+    }
+}
+
+/// Splits a string or character literal around its escape sequences
+fn escapes(
+    src: &str,
+    span: Range<usize>,
+    category: Category,
+    push: &mut impl FnMut(Range<usize>, Category),
+) {
+    let text = &src[span.clone()];
+    let mut chars = text.char_indices().peekable();
+    let mut start = 0;
+    while let Some((i, char)) = chars.next() {
+        if char != '\\' {
+            continue;
+        }
+        let escape = match chars.next() {
+            Some((_, '0' | 'n' | 'r' | 't' | '\\' | '\'' | '"')) => Category::Escape,
+            // a line continuation takes the whitespace after it as well
+            Some((_, '\n')) => {
+                while chars.next_if(|(_, char)| char.is_whitespace()).is_some() {}
+                Category::Escape
+            }
+            _ => Category::Invalid,
+        };
+        let end = chars.peek().map_or(text.len(), |&(end, _)| end);
+        push(span.start + start..span.start + i, category);
+        push(span.start + i..span.start + end, escape);
+        start = end;
+    }
+    push(span.start + start..span.end, category);
+}
+
+/// The category of each identifier that can be worked out, by where it starts
+fn identifiers(src: &str, context: Option<&Context>) -> HashMap<usize, Category> {
+    let tokens = outline::tokens(src);
+    let outline = outline::outline(src);
+    let definitions: Vec<_> = outline
+        .iter()
+        .flat_map(Definition::all)
+        .filter(|definition| definition.kind != DefinitionKind::Impl)
+        .collect();
+    // the braces opening function bodies, every other brace after the name of
+    // a type starts a struct literal
+    let bodies: Vec<usize> = definitions
+        .iter()
+        .filter(|definition| {
+            matches!(
+                definition.kind,
+                DefinitionKind::Function | DefinitionKind::Method
+            )
+        })
+        .filter_map(|definition| {
+            let name = definition.name.start;
+            tokens
+                .iter()
+                .position(|(span, token)| span.start > name && *token == Token::LBrace)
+        })
+        .collect();
+
+    let mut categories = HashMap::new();
+    let mut literals = Vec::new();
+    for (i, (span, token)) in tokens.iter().enumerate() {
+        let before = i.checked_sub(1).map(|i| &tokens[i].1);
+        let after = tokens.get(i + 1).map(|(_, token)| token);
+        let word = match token {
+            Token::LBrace => {
+                let literal =
+                    matches!(before, Some(Token::Ident(word)) if !outline::is_keyword(word));
+                literals.push(literal && !bodies.contains(&i));
+                continue;
+            }
+            Token::RBrace => {
+                literals.pop();
+                continue;
+            }
+            Token::Ident(word) => *word,
+            _ => continue,
+        };
+        let declared = definitions
+            .iter()
+            .find(|definition| definition.name == *span);
+        let (path, after_dot) = outline::path_at(&tokens, i, &outline);
+        let category = if outline::is_keyword(word) {
+            Category::Keyword
+        } else if let Some(definition) = declared {
+            kind(definition.kind)
+        } else if after_dot {
+            match after {
+                Some(Token::LPar) => Category::Method,
+                _ => Category::Field,
+            }
+        } else if literals.last() == Some(&true)
+            && matches!(before, Some(Token::LBrace | Token::Comma))
+            && after == Some(&Token::Equals)
+        {
+            Category::Field
+        } else if let Some(category) = resolve(&definitions, context, &path) {
+            category
+        } else if word == "Self" || !matches!(ast::Type::new(path), ast::Type::Nammed(_)) {
+            Category::Type
+        } else if outline::local(&tokens, &outline, i).is_some() {
+            Category::Local
+        } else {
+            continue;
+        };
+        categories.insert(span.start, category);
+    }
+    categories
+}
+
+fn kind(kind: DefinitionKind) -> Category {
+    match kind {
+        DefinitionKind::Struct | DefinitionKind::Union | DefinitionKind::Enum => Category::Type,
+        DefinitionKind::Variant => Category::EnumVariant,
+        DefinitionKind::Field => Category::Field,
+        DefinitionKind::Const => Category::Constant,
+        DefinitionKind::Static => Category::Static,
+        DefinitionKind::Function => Category::Function,
+        DefinitionKind::Method | DefinitionKind::Impl => Category::Method,
+    }
+}
+
+/// What a path names, asking the checker first since it knows what everything
+/// resolved to, and the outline if the file didn't get that far
+fn resolve(
+    definitions: &[&Definition],
+    context: Option<&Context>,
+    path: &Path,
+) -> Option<Category> {
+    if let Some(context) = context {
+        let name = path.last().unwrap_or_default();
+        let parent = path.parent().unwrap_or_default();
+        let global = match context.global(path) {
+            // functions in an `impl` without `self` aren't methods
+            Some(Global::Function(id)) => {
+                return Some(match context.function(*id).sig.is_method() {
+                    true => Category::Method,
+                    false => Category::Function,
+                })
+            }
+            Some(Global::Variable(Resolvable::Resolved(var))) => Some(&var.kind),
+            Some(Global::Variable(Resolvable::Unresolved(unresolved))) => Some(&unresolved.1.kind),
+            _ => None,
+        };
+        match global {
+            Some(GlobalKind::Const) => return Some(Category::Constant),
+            Some(GlobalKind::Static | GlobalKind::Extern) => return Some(Category::Static),
+            None => {}
+        }
+        if context.user_type(path).is_some() {
+            return Some(Category::Type);
+        }
+        if let Some(UserType::Enum(enu)) = context.user_type(&parent) {
+            if enu.members.iter().any(|member| member.name == name) {
+                return Some(Category::EnumVariant);
+            }
+        }
+        if context.method(&Type::Nammed(parent), name).is_some() {
+            return Some(Category::Method);
+        }
+    }
+    let definition = definitions
+        .iter()
+        .find(|definition| definition.path == *path)?;
+    Some(kind(definition.kind))
+}
+
+#[test]
+fn highlight_test() {
+    let src = "\
+// point
+struct Point { i32 x }
+enum Color { Red }
+const u8 MAX = 255u8;
+impl Point {
+    fn get(&Self self) i32 { return self.x; }
+}
+fn main() i32 {
+    Point p = Point { x = MAX };
+    'outer loop { break 'outer; }
+    &str s = \"a\\tb\";
+    Color c = Color::Red;
+    return p.get() + unknown;
+}
+";
+    let show = |highlights: Vec<Highlight>| {
+        let highlights = highlights.into_iter();
+        let highlights = highlights.filter(|highlight| {
+            !matches!(
+                highlight.category,
+                Category::Punctuation | Category::Operator
+            )
+        });
+        let highlights = highlights
+            .map(|highlight| format!("{:?} {:?}", &src[highlight.span], highlight.category));
+        highlights.collect::<Vec<_>>().join("\n")
+    };
+    let program = crate::stage::check::check_source(src);
+    let resolved = show(highlight(src, Some(&program.context)));
+    crate::ir::assert_snapshot("highlight.txt", &resolved);
+    // the outline alone knows as much about a file this simple
+    assert_eq!(show(highlight(src, None)), resolved);
+
+    // but only the checker knows which functions in an `impl` take `self`
+    let src = "\
+struct Point { i32 x }
+impl Point {
+    fn new() Self { return Self { x = 1 }; }
+}
+impl i32 {
+    fn double(Self self) i32 { return self * 2; }
+}
+fn main() i32 {
+    Point p = Point::new();
+    return i32::double(p.x);
+}
+";
+    let program = crate::stage::check::checked_source(src);
+    let category = |highlights: &[Highlight], name: &str| {
+        let at = src.rfind(name).unwrap();
+        let highlight = highlights
+            .iter()
+            .find(|highlight| highlight.span.start == at);
+        highlight.unwrap().category
+    };
+    let resolved = highlight(src, Some(&program.context));
+    let outlined = highlight(src, None);
+    assert_eq!(category(&resolved, "new"), Category::Function);
+    assert_eq!(category(&outlined, "new"), Category::Method);
+    assert_eq!(category(&resolved, "double"), Category::Method);
+
+    // everything around what can't be tokenized still gets a category
+    let broken = "fn f() { i32 x = 1 ¤ \"\\e\"; 'x' + '' } /* open";
+    let highlights = highlight(broken, None);
+    let categories: Vec<_> = highlights
+        .iter()
+        .map(|highlight| (&broken[highlight.span.clone()], highlight.category))
+        .collect();
+    for expected in [
+        ("x", Category::Local),
+        ("¤", Category::Invalid),
+        ("\\e", Category::Invalid),
+        ("'x'", Category::Char),
+        ("''", Category::Char),
+        ("/* open", Category::Comment),
+    ] {
+        assert!(
+            categories.contains(&expected),
+            "{expected:?} in {categories:?}"
+        );
+    }
+}
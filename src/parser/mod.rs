@@ -3,8 +3,11 @@ use lalrpop_util::lalrpop_mod;
 // use crate::tokenizer::{Span, Token, Tokenizer, TokenizerError};
 
 pub mod ast;
+pub mod fmt;
+pub mod highlight;
+pub mod outline;
 
-lalrpop_mod!(pub def, "/parser/def.rs");
+lalrpop_mod!(#[allow(clippy::empty_line_after_outer_attr)] pub def, "/parser/def.rs");
 
 // struct TokenizerIdk<'a> {
 //     tokenizer: Tokenizer<'a>,
@@ -80,3 +83,45 @@ lalrpop_mod!(pub def, "/parser/def.rs");
 //     let res = parser.parse(str);
 //     println!("{:#?}", res);
 // }
+
+#[test]
+fn char_literals() {
+    let parse = |src: &str| {
+        def::ModuleParser::new()
+            .parse(src)
+            .map_err(|err| err.to_string())
+    };
+    let module = parse(r"static char A = 'a'; static char B = '\'';").unwrap();
+    let values: Vec<_> = module
+        .glob_def
+        .iter()
+        .map(|glob| match glob.value.as_ref().map(|value| &value.kind) {
+            Some(ast::ExpressionKind::Literal(ast::Literal::Char(c))) => c.as_str(),
+            other => panic!("{other:?}"),
+        })
+        .collect();
+    assert_eq!(values, ["a", r"\'"]);
+    // a char literal ends at its closing quote so it can't run into a label
+    parse("fn f() { char c = 'c'; 'block { break 'block; } }").unwrap();
+    assert!(parse("static char C = 'character?';").is_err());
+}
+
+#[test]
+fn spans() {
+    let src = "pub fn f() { i32 x = (1 + 2) * 3; }";
+    let module = def::ModuleParser::new().parse(src).unwrap();
+    let at = |span: &ast::Span| &src[span.start..span.end];
+    let function = &module.function_def[0];
+    assert_eq!(at(&function.span), src);
+    let statement = &function.body[0];
+    assert_eq!(at(&statement.span), "i32 x = (1 + 2) * 3;");
+    let ast::StatementKind::VariableDeclaration(_, _, _, Some(value)) = &statement.kind else {
+        panic!("{statement:?}");
+    };
+    let ast::ExpressionKind::BinaryOp(left, _, right) = &value.kind else {
+        panic!("{value:?}");
+    };
+    assert_eq!(at(&value.span), "(1 + 2) * 3");
+    assert_eq!(at(&left.span), "(1 + 2)");
+    assert_eq!(at(&right.span), "3");
+}
@@ -0,0 +1,482 @@
+//! Where each definition of a source file is.
+//!
+//! The outline comes from skimming the tokens rather than from the spans in
+//! the AST, since an editor mostly has files that are halfway through being
+//! written. It only needs the definitions to be roughly well formed, anything
+//! it can't make sense of is skipped, so it works on files that don't parse.
+
+use std::ops::Range;
+
+use crate::tokenizer::{Token, Tokenizer};
+
+use super::ast::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionKind {
+    Struct,
+    Union,
+    Enum,
+    Variant,
+    Field,
+    Const,
+    Static,
+    Function,
+    Method,
+    /// An `impl` block, named after the type it's for
+    Impl,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    pub kind: DefinitionKind,
+    /// The path the definition can be found at, for an `impl` block the path
+    /// of its type
+    pub path: Path,
+    /// Where its name is
+    pub name: Range<usize>,
+    /// Where the whole definition is
+    pub span: Range<usize>,
+    pub children: Vec<Definition>,
+}
+
+impl Definition {
+    /// This definition and everything in it
+    pub fn all(&self) -> Box<dyn Iterator<Item = &Definition> + '_> {
+        Box::new(std::iter::once(self).chain(self.children.iter().flat_map(Definition::all)))
+    }
+}
+
+/// The tokens of some source and where each one is, leaving out comments and
+/// whatever couldn't be tokenized
+pub fn tokens(src: &str) -> Vec<(Range<usize>, Token<'_>)> {
+    let tokens = Tokenizer::new(src).filter_map(Result::ok);
+    let tokens = tokens.map(|token| {
+        let start = token.span.offset as usize;
+        (start..start + token.span.len as usize, token.val)
+    });
+    tokens.collect()
+}
+
+pub fn outline(src: &str) -> Vec<Definition> {
+    let tokens = tokens(src);
+    let mut skimmer = Skimmer {
+        tokens: &tokens,
+        at: 0,
+    };
+    skimmer.items(&Path::new(), false)
+}
+
+/// The path up to and including the identifier at `at`, and whether it's a
+/// member reached through a value with `.`
+pub fn path_at(
+    tokens: &[(Range<usize>, Token)],
+    at: usize,
+    outline: &[Definition],
+) -> (Path, bool) {
+    let mut start = at;
+    while start >= 3
+        && matches!(
+            tokens[start - 3..start],
+            [(_, Token::Ident(_)), (_, Token::Colon), (_, Token::Colon)]
+        )
+    {
+        start -= 3;
+    }
+    let mut path = Path::new();
+    for (_, token) in tokens[start..=at].iter().step_by(3) {
+        if let Token::Ident(part) = token {
+            path.push(part);
+        }
+    }
+    // `Self` is whichever type the surrounding `impl` is for
+    if path.parts().next() == Some("Self") {
+        let offset = tokens[at].0.start;
+        let imp = outline.iter().find(|definition| {
+            definition.kind == DefinitionKind::Impl && definition.span.contains(&offset)
+        });
+        if let Some(imp) = imp {
+            let mut resolved = imp.path.clone();
+            for part in path.parts().skip(1) {
+                resolved.push(part);
+            }
+            path = resolved;
+        }
+    }
+    let after_dot = start > 0 && tokens[start - 1].1 == Token::Dot;
+    (path, after_dot)
+}
+
+/// Where the local used at `at` is declared, looking back as far as the start
+/// of the function it's in
+pub fn local(
+    tokens: &[(Range<usize>, Token)],
+    outline: &[Definition],
+    at: usize,
+) -> Option<Range<usize>> {
+    let (span, Token::Ident(name)) = &tokens[at] else {
+        return None;
+    };
+    let function = outline
+        .iter()
+        .flat_map(Definition::all)
+        .find(|definition| {
+            matches!(
+                definition.kind,
+                DefinitionKind::Function | DefinitionKind::Method
+            ) && definition.span.contains(&span.start)
+        })?;
+    let first = tokens
+        .iter()
+        .position(|(span, _)| span.start >= function.span.start)?;
+    let declares = |i: usize| {
+        let before = |back: usize| i.checked_sub(back).map(|i| &tokens[i].1);
+        match before(1) {
+            Some(Token::Ident("mut")) => !matches!(before(2), Some(Token::Ampersand | Token::Star)),
+            // `i32 x` and `[u8; 4] x` but not `return x`
+            Some(Token::Ident(word)) => !is_keyword(word),
+            Some(Token::RBracket) => true,
+            Some(Token::LPar) => before(2) == Some(&Token::For),
+            _ => false,
+        }
+    };
+    (first..=at)
+        .rev()
+        .find(|&i| tokens[i].1 == Token::Ident(name) && declares(i))
+        .map(|i| tokens[i].0.clone())
+}
+
+/// Whether an identifier token is really a keyword, most of them aren't
+/// tokens of their own
+pub fn is_keyword(word: &str) -> bool {
+    matches!(
+        word,
+        "break"
+            | "const"
+            | "continue"
+            | "else"
+            | "enum"
+            | "extern"
+            | "impl"
+            | "in"
+            | "mut"
+            | "pub"
+            | "return"
+            | "static"
+            | "struct"
+            | "union"
+            | "size_of"
+            | "align_of"
+            | "offset_of"
+            | "type_name"
+    )
+}
+
+struct Skimmer<'a, 'b> {
+    tokens: &'a [(Range<usize>, Token<'b>)],
+    at: usize,
+}
+
+impl<'a, 'b> Skimmer<'a, 'b> {
+    fn peek(&self) -> Option<&'a Token<'b>> {
+        self.tokens.get(self.at).map(|(_, token)| token)
+    }
+
+    fn word(&self) -> Option<&'b str> {
+        match self.peek()? {
+            Token::Ident(word) => Some(word),
+            Token::Fn => Some("fn"),
+            _ => None,
+        }
+    }
+
+    /// Where the token before the current one ends
+    fn end(&self) -> usize {
+        self.at
+            .checked_sub(1)
+            .and_then(|at| self.tokens.get(at))
+            .map_or(0, |(span, _)| span.end)
+    }
+
+    fn start(&self) -> usize {
+        self.tokens
+            .get(self.at)
+            .map_or(self.end(), |(span, _)| span.start)
+    }
+
+    /// Takes an identifier, giving its text and where it is
+    fn ident(&mut self) -> Option<(String, Range<usize>)> {
+        let (span, Token::Ident(ident)) = self.tokens.get(self.at)? else {
+            return None;
+        };
+        self.at += 1;
+        Some((ident.to_string(), span.clone()))
+    }
+
+    /// Skips to after the end of an item: a `;` or the closing brace of the
+    /// first block outside of any brackets
+    fn skip_item(&mut self) {
+        let mut depth = 0usize;
+        while let Some(token) = self.peek() {
+            self.at += 1;
+            match token {
+                Token::LPar | Token::LBracket => depth += 1,
+                Token::RPar | Token::RBracket => depth = depth.saturating_sub(1),
+                Token::LBrace if depth == 0 => {
+                    self.skip_block();
+                    return;
+                }
+                Token::LBrace => depth += 1,
+                Token::RBrace => depth = depth.saturating_sub(1),
+                Token::Semicolon if depth == 0 => return,
+                _ => {}
+            }
+        }
+    }
+
+    /// Skips to after the brace closing the one just taken
+    fn skip_block(&mut self) {
+        let mut depth = 1usize;
+        while let Some(token) = self.peek() {
+            self.at += 1;
+            match token {
+                Token::LBrace => depth += 1,
+                Token::RBrace if depth == 1 => return,
+                Token::RBrace => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    /// Items up to the end of the file, or in an `impl` block up to its brace
+    fn items(&mut self, module: &Path, in_impl: bool) -> Vec<Definition> {
+        let mut items = Vec::new();
+        while let Some(token) = self.peek() {
+            if in_impl && *token == Token::RBrace {
+                self.at += 1;
+                break;
+            }
+            let start = self.start();
+            if let Some(item) = self.item(module, in_impl, start) {
+                items.push(item);
+            }
+        }
+        items
+    }
+
+    fn item(&mut self, module: &Path, in_impl: bool, start: usize) -> Option<Definition> {
+        // what can come before the keyword
+        while matches!(self.word(), Some("pub" | "extern"))
+            || matches!(self.peek(), Some(Token::StringLiteral(_)))
+        {
+            self.at += 1;
+        }
+        let path = |name: &str| module.join(&Path::new_path(name));
+        let keyword = self.word().map(str::to_string);
+        self.at += 1;
+        let mut definition = match keyword.as_deref() {
+            Some(keyword @ ("struct" | "union" | "enum")) => {
+                let (name, span) = self.ident()?;
+                let kind = match keyword {
+                    "struct" => DefinitionKind::Struct,
+                    "union" => DefinitionKind::Union,
+                    _ => DefinitionKind::Enum,
+                };
+                let children = match self.peek() {
+                    Some(Token::LBrace) => {
+                        self.at += 1;
+                        self.members(&path(&name), kind == DefinitionKind::Enum)
+                    }
+                    _ => Vec::new(),
+                };
+                Definition {
+                    kind,
+                    path: path(&name),
+                    name: span,
+                    span: start..0,
+                    children,
+                }
+            }
+            Some("fn") => {
+                let (name, span) = self.ident()?;
+                self.skip_item();
+                let kind = match in_impl {
+                    true => DefinitionKind::Method,
+                    false => DefinitionKind::Function,
+                };
+                Definition {
+                    kind,
+                    path: path(&name),
+                    name: span,
+                    span: start..0,
+                    children: Vec::new(),
+                }
+            }
+            Some("impl") if !in_impl => {
+                let mut ty = Path::new();
+                let name_start = self.start();
+                while let Some(token) = self.peek() {
+                    match token {
+                        Token::Ident(part) => ty.push(part),
+                        Token::LBrace => break,
+                        _ => {}
+                    }
+                    self.at += 1;
+                }
+                let name = name_start..self.end();
+                self.at += 1;
+                let children = self.items(&ty, true);
+                Definition {
+                    kind: DefinitionKind::Impl,
+                    path: ty,
+                    name,
+                    span: start..0,
+                    children,
+                }
+            }
+            Some(keyword @ ("static" | "const")) => {
+                // the name is the last identifier before the value
+                let mut name = None;
+                while let Some(token) = self.peek() {
+                    match token {
+                        Token::Ident(ident) => {
+                            name = Some((ident.to_string(), self.tokens[self.at].0.clone()))
+                        }
+                        Token::Equals | Token::Semicolon => break,
+                        Token::LBracket => {
+                            self.skip_brackets();
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    self.at += 1;
+                }
+                self.skip_item();
+                let (name, span) = name?;
+                let kind = match keyword {
+                    "const" => DefinitionKind::Const,
+                    _ => DefinitionKind::Static,
+                };
+                Definition {
+                    kind,
+                    path: path(&name),
+                    name: span,
+                    span: start..0,
+                    children: Vec::new(),
+                }
+            }
+            _ => return None,
+        };
+        definition.span.end = self.end();
+        Some(definition)
+    }
+
+    /// Skips an array type, which can have a `;` in it
+    fn skip_brackets(&mut self) {
+        let mut depth = 0usize;
+        while let Some(token) = self.peek() {
+            self.at += 1;
+            match token {
+                Token::LBracket => depth += 1,
+                Token::RBracket if depth == 1 => return,
+                Token::RBracket => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    /// The fields of a struct or union, or the variants of an enum, up to and
+    /// including the closing brace
+    fn members(&mut self, ty: &Path, variants: bool) -> Vec<Definition> {
+        let mut members = Vec::new();
+        let mut start = self.start();
+        let mut name = None;
+        let mut depth = 0usize;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Ident(ident) if depth == 0 => {
+                    name = Some((ident.to_string(), self.tokens[self.at].0.clone()))
+                }
+                Token::LBracket | Token::LPar => depth += 1,
+                Token::RBracket | Token::RPar => depth = depth.saturating_sub(1),
+                Token::Comma | Token::RBrace if depth == 0 => {
+                    if let Some((name, span)) = name.take() {
+                        let kind = match variants {
+                            true => DefinitionKind::Variant,
+                            false => DefinitionKind::Field,
+                        };
+                        members.push(Definition {
+                            kind,
+                            path: ty.join(&Path::new_path(&name)),
+                            name: span.clone(),
+                            span: start..span.end,
+                            children: Vec::new(),
+                        });
+                    }
+                    self.at += 1;
+                    if *token == Token::RBrace {
+                        break;
+                    }
+                    start = self.start();
+                    continue;
+                }
+                _ => {}
+            }
+            self.at += 1;
+        }
+        members
+    }
+}
+
+#[test]
+fn outline_test() {
+    let src = "
+        pub const i32 VALUE = 23;
+        static [u8; 4] BYTES = { 1 };
+        struct Point { i32 x, [u8; 2] y }
+        enum Color { Red, Green, }
+        impl Point {
+            fn len(&Self self) i32 { if (true) { return 1; } return 0; }
+            pub fn zero() Point { return Point { x = 0, y = 0 }; }
+        }
+        extern \"C\" fn strlen(*u8 str) usize;
+        fn main() {}
+        this isn't anything;
+        union Either { u8 a, u16 b }
+    ";
+    let outline = outline(src);
+    let names: Vec<_> = outline
+        .iter()
+        .flat_map(Definition::all)
+        .map(|definition| {
+            let name = &src[definition.name.clone()];
+            format!("{:?} {} {name}", definition.kind, definition.path)
+        })
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "Const VALUE VALUE",
+            "Static BYTES BYTES",
+            "Struct Point Point",
+            "Field Point::x x",
+            "Field Point::y y",
+            "Enum Color Color",
+            "Variant Color::Red Red",
+            "Variant Color::Green Green",
+            "Impl Point Point",
+            "Method Point::len len",
+            "Method Point::zero zero",
+            "Function strlen strlen",
+            "Function main main",
+            "Union Either Either",
+            "Field Either::a a",
+            "Field Either::b b",
+        ]
+    );
+    let spans: Vec<_> = outline.iter().map(|item| &src[item.span.clone()]).collect();
+    assert_eq!(spans[0], "pub const i32 VALUE = 23;");
+    assert_eq!(spans[3], "enum Color { Red, Green, }");
+    assert!(spans[4].starts_with("impl Point {") && spans[4].ends_with("}\n        }"));
+    assert_eq!(spans[5], "extern \"C\" fn strlen(*u8 str) usize;");
+    assert_eq!(&src[outline[2].children[1].span.clone()], "[u8; 2] y");
+}
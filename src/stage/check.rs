@@ -0,0 +1,1516 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::ast::{
+    self, BinOpKind, Expression, ExpressionKind, GlobalKind, Literal, Path, Statement,
+    StatementKind, UnaryOpKind,
+};
+
+use super::{
+    constant_eval::{self, Value},
+    error::{CheckError, Diagnostic, DiagnosticKind},
+    tree::{Block, Body, Coercion, Expr, ExprKind, LabelId, Local, LocalId, Stmt},
+    types::Type,
+    Context, FunctionSig, Global, UserType,
+};
+
+type CheckResult<T> = Result<T, CheckError>;
+
+struct LabelScope {
+    name: Option<String>,
+    id: LabelId,
+    is_loop: bool,
+    /// The type the surrounding code wants this block to produce
+    expected: Option<Type>,
+    /// The common type of the values `break` has produced so far
+    break_ty: Option<Type>,
+    /// Whether some `break` values need to be coerced to `break_ty`
+    coerce_breaks: bool,
+}
+
+/// Type checks expressions of a single function body or constant
+pub struct Checker<'a> {
+    context: &'a mut Context,
+    module: Path,
+    self_ty: Option<Type>,
+    ret_ty: Type,
+
+    locals: Vec<Local>,
+    /// Immutable locals declared without a value, they may be assigned once
+    /// which is left to the initialization pass to verify
+    deferred: HashSet<LocalId>,
+    scopes: Vec<HashMap<String, LocalId>>,
+    labels: Vec<LabelScope>,
+    label_count: usize,
+
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    pub fn new(context: &'a mut Context, module: Path, self_ty: Option<Type>) -> Self {
+        Self {
+            context,
+            module,
+            self_ty,
+            ret_ty: Type::Void,
+            locals: Vec::new(),
+            deferred: HashSet::new(),
+            scopes: Vec::new(),
+            labels: Vec::new(),
+            label_count: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Checks and evaluates a constant expression of type `ty`
+    pub fn constant(&mut self, expr: &Expression, ty: &Type) -> CheckResult<Value> {
+        let expr = self.expect(expr, ty)?;
+        Ok(constant_eval::const_eval(self.context, &expr)?)
+    }
+
+    pub fn body(
+        mut self,
+        sig: &FunctionSig,
+        mutable_params: &[bool],
+        stmts: &[Statement],
+    ) -> Result<Body, Vec<Diagnostic>> {
+        self.ret_ty = sig.ret_ty.clone();
+
+        self.scopes.push(HashMap::new());
+        for (index, (ty, name)) in sig.params.iter().enumerate() {
+            if !ty.is_sized(self.context) {
+                self.error(DiagnosticKind::UnsizedValue(ty.clone()));
+            }
+            let mutable = mutable_params.get(index).copied().unwrap_or(false);
+            self.declare(name, ty.clone(), mutable);
+        }
+        if !sig.ret_ty.is_sized(self.context) {
+            self.error(DiagnosticKind::UnsizedValue(sig.ret_ty.clone()));
+        }
+        let params = self.locals.len();
+        let stmts = self.stmts(stmts);
+        self.scopes.pop();
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        Ok(Body {
+            locals: self.locals,
+            params,
+            labels: self.label_count,
+            ret_ty: self.ret_ty,
+            stmts,
+        })
+    }
+
+    fn error(&mut self, err: impl Into<Diagnostic>) {
+        self.errors.push(err.into())
+    }
+
+    fn declare(&mut self, name: &str, ty: Type, mutable: bool) -> LocalId {
+        let id = LocalId(self.locals.len());
+        self.locals.push(Local {
+            name: name.to_owned(),
+            ty,
+            mutable,
+        });
+        self.scopes
+            .last_mut()
+            .expect("no scope to declare local in")
+            .insert(name.to_owned(), id);
+        id
+    }
+
+    fn lookup_local(&self, name: &str) -> Option<LocalId> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn resolve_type(&mut self, ty: &ast::Type) -> CheckResult<Type> {
+        let module = self.module.clone();
+        Ok(self
+            .context
+            .resolve_type(ty, &module, self.self_ty.as_ref())?)
+    }
+
+    fn stmts(&mut self, stmts: &[Statement]) -> Vec<Stmt> {
+        self.scopes.push(HashMap::new());
+        let stmts = stmts.iter().filter_map(|stmt| self.stmt(stmt)).collect();
+        self.scopes.pop();
+        stmts
+    }
+
+    fn stmt(&mut self, stmt: &Statement) -> Option<Stmt> {
+        let result = match &stmt.kind {
+            StatementKind::Expression(expr) => self.expr(expr, None).map(Stmt::Expr),
+            StatementKind::VariableDeclaration(mutable, ty, name, value) => {
+                let ty = match self.resolve_type(ty) {
+                    Ok(ty) => ty,
+                    Err(err) => {
+                        self.error(err.at(stmt.span));
+                        return None;
+                    }
+                };
+                // the initializer can't see the local it's initializing
+                let value = value
+                    .as_ref()
+                    .map(|value| self.expect(value, &ty))
+                    .transpose();
+                if !ty.is_sized(self.context) {
+                    let err = Diagnostic::error(DiagnosticKind::UnsizedValue(ty.clone()));
+                    self.error(err.at(stmt.span));
+                }
+                let deferred = !*mutable && value.as_ref().is_ok_and(Option::is_none);
+                let id = self.declare(name, ty, *mutable);
+                if deferred {
+                    self.deferred.insert(id);
+                }
+                value.map(|value| Stmt::Let(id, value))
+            }
+        };
+        match result {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.error(err.at(stmt.span));
+                None
+            }
+        }
+    }
+
+    /// Checks `expr` and coerces it to `ty`
+    pub fn expect(&mut self, expr: &Expression, ty: &Type) -> CheckResult<Expr> {
+        let expr = self.expr(expr, Some(ty))?;
+        self.coerce(expr, ty)
+    }
+
+    pub fn coerce(&mut self, expr: Expr, target: &Type) -> CheckResult<Expr> {
+        if expr.ty == *target {
+            return Ok(expr);
+        }
+        let mismatch = |expr: &Expr| {
+            let kind = DiagnosticKind::Mismatch {
+                expected: target.clone(),
+                found: expr.ty.clone(),
+            };
+            CheckError::from(kind).at(expr.span)
+        };
+        let (Some(from), Type::Ref(to, to_mut) | Type::Ptr(to, to_mut)) =
+            (expr.ty.pointee(), target)
+        else {
+            return Err(mismatch(&expr));
+        };
+        // mutability can be given up but never gained
+        if *to_mut && !expr.ty.is_mut_pointer() {
+            return Err(mismatch(&expr));
+        }
+
+        // fat or array pointers to a thin pointer to their first element
+        let data = match from {
+            Type::Str => Some(&Type::U8),
+            Type::Array(inner) | Type::ArrayStatic(inner, _) => Some(&**inner),
+            _ => None,
+        };
+        if matches!(target, Type::Ptr(..)) && data == Some(&**to) {
+            return Ok(Expr::coerce(target.clone(), Coercion::DataPtr, expr));
+        }
+
+        let same_kind = matches!(
+            (&expr.ty, target),
+            (Type::Ref(..), Type::Ref(..)) | (Type::Ptr(..), Type::Ptr(..))
+        );
+        match (from, &**to) {
+            (Type::ArrayStatic(a, len), Type::Array(b)) if a == b && same_kind => {
+                let len = *len;
+                Ok(Expr::coerce(target.clone(), Coercion::Unsize(len), expr))
+            }
+            (from, to) if from == to && same_kind => {
+                Ok(Expr::coerce(target.clone(), Coercion::Immutable, expr))
+            }
+            _ if matches!((&expr.ty, target), (Type::Ref(..), Type::Ptr(..))) => {
+                let found = expr.ty.clone();
+                let as_ref = self
+                    .coerce(expr, &Type::Ref(to.clone(), *to_mut))
+                    .map_err(|_| DiagnosticKind::Mismatch {
+                        expected: target.clone(),
+                        found,
+                    })?;
+                Ok(Expr::coerce(target.clone(), Coercion::RefToPtr, as_ref))
+            }
+            _ => Err(mismatch(&expr)),
+        }
+    }
+
+    /// Checks `expr`, errors in it are placed on the innermost expression
+    /// they're about
+    pub fn expr(&mut self, expr: &Expression, expected: Option<&Type>) -> CheckResult<Expr> {
+        match self.expr_kind(expr, expected) {
+            Ok(mut checked) => {
+                checked.span = expr.span;
+                Ok(checked)
+            }
+            Err(err) => Err(err.at(expr.span)),
+        }
+    }
+
+    fn expr_kind(&mut self, expr: &Expression, expected: Option<&Type>) -> CheckResult<Expr> {
+        match &expr.kind {
+            ExpressionKind::Path(path) => self.path(path),
+            ExpressionKind::Literal(lit) => self.literal(lit, expected, false),
+            ExpressionKind::Block(block) => self.block(block, expected),
+            ExpressionKind::FieldAccess(base, field) => {
+                let base = self.expr(base, None)?;
+                self.field(base, field)
+            }
+            ExpressionKind::MemberFunction(receiver, name, args) => {
+                self.method_call(receiver, name, args)
+            }
+            ExpressionKind::ArrayAccess(base, index) => {
+                let base = self.expr(base, None)?;
+                let base = self.auto_deref(base);
+                let Some(element) = base.ty.element().cloned() else {
+                    return Err(DiagnosticKind::NotIndexable(base.ty).into());
+                };
+                let index = self.expr(index, Some(&Type::USIZE))?;
+                if !index.ty.is_int() {
+                    return Err(DiagnosticKind::Mismatch {
+                        expected: Type::USIZE,
+                        found: index.ty,
+                    }
+                    .into());
+                }
+                Ok(Expr::new(
+                    element,
+                    ExprKind::Index(Box::new(base), Box::new(index)),
+                ))
+            }
+            ExpressionKind::FunctionCall(callee, args) => {
+                let callee = self.expr(callee, None)?;
+                self.call(callee, Vec::new(), args)
+            }
+            ExpressionKind::UnaryOp(op, inner) => self.unary(*op, inner, expected),
+            ExpressionKind::BinaryOp(l, op, r) => self.binary(l, *op, r, expected),
+            ExpressionKind::Assign(place, value) => {
+                let place = self.assigned_place(place)?;
+                match place.kind {
+                    ExprKind::Local(id) if self.deferred.contains(&id) => {}
+                    _ => self.check_mutable(&place)?,
+                }
+                let value = self.expect(value, &place.ty)?;
+                Ok(Expr::new(
+                    Type::Void,
+                    ExprKind::Assign(Box::new(place), Box::new(value)),
+                ))
+            }
+
+            ExpressionKind::CompoundAssign(place, op, value) => {
+                let place = self.assigned_place(place)?;
+                self.check_mutable(&place)?;
+                let value = self.expr(value, Some(&place.ty))?;
+                let (l_ty, r_ty) = (place.ty.clone(), value.ty.clone());
+                let invalid = || DiagnosticKind::InvalidBinary(l_ty.clone(), *op, r_ty.clone());
+                let valid = match op {
+                    BinOpKind::Times
+                    | BinOpKind::Divide
+                    | BinOpKind::Modulo
+                    | BinOpKind::Plus
+                    | BinOpKind::Minus => place.ty.is_numeric(),
+                    BinOpKind::BitAnd | BinOpKind::BitXor | BinOpKind::BitOr => {
+                        place.ty.is_int() || place.ty == Type::Bool
+                    }
+                    BinOpKind::ShiftLeft | BinOpKind::ShiftRight => {
+                        place.ty.is_int() && value.ty.is_int()
+                    }
+                    _ => false,
+                };
+                if !valid {
+                    return Err(invalid().into());
+                }
+                let value = match op {
+                    BinOpKind::ShiftLeft | BinOpKind::ShiftRight => value,
+                    _ => self.coerce(value, &place.ty).map_err(|_| invalid())?,
+                };
+                Ok(Expr::new(
+                    Type::Void,
+                    ExprKind::CompoundAssign(Box::new(place), *op, Box::new(value)),
+                ))
+            }
+
+            ExpressionKind::SizeOf(ty) => {
+                let ty = self.resolve_type(ty)?;
+                if !ty.is_sized(self.context) {
+                    return Err(DiagnosticKind::UnsizedValue(ty).into());
+                }
+                let size = ty.layout(self.context).size_bytes();
+                Ok(Expr::value(Type::USIZE, Value::U64(size as u64)))
+            }
+            ExpressionKind::AlignOf(ty) => {
+                let ty = self.resolve_type(ty)?;
+                let align = ty.layout(self.context).align().get();
+                Ok(Expr::value(Type::USIZE, Value::U64(align as u64)))
+            }
+            ExpressionKind::Sized(ty) => {
+                let ty = self.resolve_type(ty)?;
+                let sized = ty.is_sized(self.context);
+                Ok(Expr::value(Type::Bool, Value::Bool(sized)))
+            }
+            ExpressionKind::OffsetOf(ty, field) => {
+                let ty = self.resolve_type(ty)?;
+                let undefined = || DiagnosticKind::UndefinedField(ty.clone(), field.clone());
+                let Type::Nammed(path) = &ty else {
+                    return Err(undefined().into());
+                };
+                self.context.layout(path);
+                let offset = match self.context.user_type(path) {
+                    Some(UserType::Struct(struc)) => struc
+                        .members
+                        .iter()
+                        .find(|member| member.name == *field)
+                        .map(|member| member.offset),
+                    Some(UserType::Union(unio)) => unio
+                        .members
+                        .iter()
+                        .any(|member| member.name == *field)
+                        .then_some(0),
+                    _ => None,
+                };
+                let offset = offset.ok_or_else(undefined)?;
+                Ok(Expr::value(Type::USIZE, Value::U64(offset as u64)))
+            }
+            ExpressionKind::TypeName(ty) => {
+                let ty = self.resolve_type(ty)?;
+                Ok(Expr::value(
+                    Type::Ref(Box::new(Type::Str), false),
+                    Value::Str(ty.to_string()),
+                ))
+            }
+
+            ExpressionKind::Range(..) => Err(DiagnosticKind::RangeOutsideFor.into()),
+
+            ExpressionKind::StructCon(path, fields) => self.struct_con(path, fields),
+            ExpressionKind::ArrayCon(values) => {
+                let mut element = expected.and_then(Type::element).cloned();
+                let mut exprs = Vec::new();
+                for value in values {
+                    let expr = match &element {
+                        Some(element) => self.expect(value, element)?,
+                        None => self.expr(value, None)?,
+                    };
+                    element.get_or_insert_with(|| expr.ty.clone());
+                    exprs.push(expr);
+                }
+                let Some(element) = element else {
+                    return Err(DiagnosticKind::InvalidLiteral("[]".into()).into());
+                };
+                Ok(Expr::new(
+                    Type::ArrayStatic(Box::new(element), exprs.len()),
+                    ExprKind::ArrayCon(exprs),
+                ))
+            }
+
+            ExpressionKind::Break(label, value) => {
+                let index = self.find_label(label.as_deref(), "break")?;
+                let target = &self.labels[index];
+                let id = target.id;
+                let expected = target.break_ty.clone().or(target.expected.clone());
+                let value = match value {
+                    Some(value) => Some(self.expr(value, expected.as_ref())?),
+                    None => None,
+                };
+                let ty = value.as_ref().map_or(Type::Void, |value| value.ty.clone());
+                let common = match self.labels[index].break_ty.clone() {
+                    Some(current) => {
+                        let common = self.common_type(&current, &ty).ok_or_else(|| {
+                            DiagnosticKind::Mismatch {
+                                expected: current.clone(),
+                                found: ty.clone(),
+                            }
+                        })?;
+                        if common != current || common != ty {
+                            self.labels[index].coerce_breaks = true;
+                        }
+                        common
+                    }
+                    None => ty,
+                };
+                self.labels[index].break_ty = Some(common);
+                Ok(Expr::new(
+                    Type::Void,
+                    ExprKind::Break(id, value.map(Box::new)),
+                ))
+            }
+            ExpressionKind::Continue(label) => {
+                let index = self.find_label(label.as_deref(), "continue")?;
+                if !self.labels[index].is_loop {
+                    return Err(DiagnosticKind::NotALoop(label.clone().unwrap_or_default()).into());
+                }
+                Ok(Expr::new(
+                    Type::Void,
+                    ExprKind::Continue(self.labels[index].id),
+                ))
+            }
+            ExpressionKind::Return(value) => {
+                let ret_ty = self.ret_ty.clone();
+                let value = match value {
+                    Some(value) => Some(Box::new(self.expect(value, &ret_ty)?)),
+                    None if ret_ty == Type::Void => None,
+                    None => {
+                        return Err(DiagnosticKind::Mismatch {
+                            expected: ret_ty,
+                            found: Type::Void,
+                        }
+                        .into())
+                    }
+                };
+                Ok(Expr::new(Type::Void, ExprKind::Return(value)))
+            }
+        }
+    }
+
+    fn path(&mut self, path: &Path) -> CheckResult<Expr> {
+        if path.is_single() {
+            if let Some(id) = self.lookup_local(path.last().unwrap_or_default()) {
+                let ty = self.locals[id.0].ty.clone();
+                return Ok(Expr::new(ty, ExprKind::Local(id)));
+            }
+        }
+
+        if let Some(global) = self.context.find_global(&self.module, path) {
+            if let Some(Global::Function(id)) = self.context.global(&global) {
+                let id = *id;
+                let ty = self.context.function(id).sig.fn_type();
+                return Ok(Expr::new(ty, ExprKind::Function(id)));
+            }
+            let Some(var) = self.context.resolve_global(&global).cloned() else {
+                return Err(DiagnosticKind::UndefinedValue(path.clone()).into());
+            };
+            return match var.kind {
+                GlobalKind::Const => match var.value {
+                    Some(value) => Ok(Expr::value(var.ty, value)),
+                    None => Err(DiagnosticKind::NotConstant.into()),
+                },
+                GlobalKind::Static | GlobalKind::Extern => {
+                    Ok(Expr::new(var.ty, ExprKind::Static(global)))
+                }
+            };
+        }
+
+        // enum varients and associated functions
+        if let (Some(parent), Some(name)) = (path.parent(), path.last()) {
+            if let Ok(ty) = self.resolve_type(&ast::Type::new(parent)) {
+                if let Some(id) = self.context.method(&ty, name) {
+                    let ty = self.context.function(id).sig.fn_type();
+                    return Ok(Expr::new(ty, ExprKind::Function(id)));
+                }
+                if let Type::Nammed(ty_path) = &ty {
+                    if let Some(UserType::Enum(enu)) = self.context.user_type(ty_path) {
+                        if let Some(varient) = enu.members.iter().find(|m| m.name == name) {
+                            return Ok(Expr::value(ty.clone(), Value::Enum(varient.value)));
+                        }
+                    }
+                }
+            }
+        }
+
+        Err(DiagnosticKind::UndefinedValue(path.clone()).into())
+    }
+
+    fn literal(
+        &mut self,
+        lit: &Literal,
+        expected: Option<&Type>,
+        negate: bool,
+    ) -> CheckResult<Expr> {
+        match lit {
+            Literal::String(str) => {
+                let str =
+                    unescape(str).ok_or_else(|| DiagnosticKind::InvalidLiteral(str.clone()))?;
+                Ok(Expr::value(
+                    Type::Ref(Box::new(Type::Str), false),
+                    Value::Str(str),
+                ))
+            }
+            Literal::Char(char) => {
+                let invalid = || DiagnosticKind::InvalidLiteral(format!("'{char}'"));
+                let str = unescape(char).ok_or_else(invalid)?;
+                let mut chars = str.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c.is_ascii() => Ok(Expr::value(Type::Char, Value::Char(c))),
+                    _ => Err(invalid().into()),
+                }
+            }
+            Literal::Boolean(bool) => Ok(Expr::value(Type::Bool, Value::Bool(*bool))),
+            Literal::Number(number) => number_literal(number, expected, negate),
+        }
+    }
+
+    fn push_label(
+        &mut self,
+        name: Option<String>,
+        is_loop: bool,
+        expected: Option<Type>,
+    ) -> LabelId {
+        let id = LabelId(self.label_count);
+        self.label_count += 1;
+        self.labels.push(LabelScope {
+            name,
+            id,
+            is_loop,
+            expected,
+            break_ty: None,
+            coerce_breaks: false,
+        });
+        id
+    }
+
+    /// Finds the index of the label a `break`/`continue` refers to
+    fn find_label(&self, label: Option<&str>, keyword: &'static str) -> CheckResult<usize> {
+        let found = match label {
+            Some(label) => self
+                .labels
+                .iter()
+                .rposition(|scope| scope.name.as_deref() == Some(label)),
+            None => self.labels.iter().rposition(|scope| scope.is_loop),
+        };
+        found.ok_or_else(|| {
+            let kind = match label {
+                Some(label) => DiagnosticKind::UndefinedLabel(label.to_owned()),
+                None => DiagnosticKind::OutsideLoop(keyword),
+            };
+            kind.into()
+        })
+    }
+
+    fn block(&mut self, block: &ast::Block, expected: Option<&Type>) -> CheckResult<Expr> {
+        let (label, is_loop) = match block {
+            ast::Block::Scope(label, _) => (label, false),
+            ast::Block::While(label, _, _) => (label, true),
+            ast::Block::Loop(label, _) => (label, true),
+            ast::Block::For(label, ..) => (label, true),
+            ast::Block::If(label, ..) => (label, false),
+        };
+        // only `loop` can be left with a value, the others may end on their own
+        let expected = match block {
+            ast::Block::While(..) | ast::Block::For(..) => Some(Type::Void),
+            _ => expected.cloned(),
+        };
+        let id = self.push_label(label.clone(), is_loop, expected);
+        let block = self.block_inner(block);
+        let scope = self.labels.pop().expect("label stack underflow");
+        let ty = scope.break_ty.unwrap_or(Type::Void);
+        let mut block = block?;
+        if scope.coerce_breaks {
+            self.coerce_breaks(&mut block, id, &ty)?;
+        }
+        Ok(Expr::new(ty, ExprKind::Block(id, block)))
+    }
+
+    /// Declares the local of a `for` loop and checks the body with it in scope
+    fn for_body(&mut self, name: &str, ty: Type, stmts: &[Statement]) -> (LocalId, Vec<Stmt>) {
+        self.scopes.push(HashMap::new());
+        let local = self.declare(name, ty, false);
+        let stmts = self.stmts(stmts);
+        self.scopes.pop();
+        (local, stmts)
+    }
+
+    /// Checks the ends of a range, they have to be the same integer type
+    fn range(&mut self, start: &Expression, end: &Expression) -> CheckResult<(Expr, Expr)> {
+        let (start, end) = if untyped_literal(start) && !untyped_literal(end) {
+            let end = self.expr(end, None)?;
+            let start = self.expect(start, &end.ty)?;
+            (start, end)
+        } else {
+            let start = self.expr(start, None)?;
+            let end = self.expect(end, &start.ty)?;
+            (start, end)
+        };
+        if !start.ty.is_int() {
+            return Err(DiagnosticKind::NotIterable(start.ty).into());
+        }
+        Ok((start, end))
+    }
+
+    /// The type both `a` and `b` coerce to, if there is one
+    fn common_type(&mut self, a: &Type, b: &Type) -> Option<Type> {
+        // coercions only look at the type of the expression
+        let mut coercible = |from: &Type, to: &Type| {
+            let expr = Expr::new(from.clone(), ExprKind::Value(Value::Void(())));
+            self.coerce(expr, to).is_ok()
+        };
+        if coercible(b, a) {
+            Some(a.clone())
+        } else if coercible(a, b) {
+            Some(b.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Coerces the value of every `break` to `id` to the common type of the block
+    fn coerce_breaks(&mut self, block: &mut Block, id: LabelId, ty: &Type) -> CheckResult<()> {
+        let mut result = Ok(());
+        block.visit_mut(&mut |expr| {
+            let ExprKind::Break(label, Some(value)) = &mut expr.kind else {
+                return;
+            };
+            if *label != id || value.ty == *ty {
+                return;
+            }
+            let inner = std::mem::replace(&mut **value, Expr::void());
+            match self.coerce(inner, ty) {
+                Ok(coerced) => **value = coerced,
+                Err(err) => result = Err(err),
+            }
+        });
+        result
+    }
+
+    fn block_inner(&mut self, block: &ast::Block) -> CheckResult<Block> {
+        Ok(match block {
+            ast::Block::Scope(_, stmts) => Block::Scope(self.stmts(stmts)),
+            ast::Block::While(_, cond, stmts) => {
+                let cond = self.expect(cond, &Type::Bool)?;
+                Block::While(Box::new(cond), self.stmts(stmts))
+            }
+            ast::Block::Loop(_, stmts) => Block::Loop(self.stmts(stmts)),
+            ast::Block::For(_, name, iter, stmts) => match &iter.kind {
+                ExpressionKind::Range(start, end, inclusive) => {
+                    let (start, end) = self.range(start, end)?;
+                    let (local, stmts) = self.for_body(name, start.ty.clone(), stmts);
+                    Block::Range(local, Box::new(start), Box::new(end), *inclusive, stmts)
+                }
+                _ => {
+                    let iter = self.expr(iter, None)?;
+                    let ty = element_binding(&iter.ty)
+                        .ok_or_else(|| DiagnosticKind::NotIterable(iter.ty.clone()))?;
+                    let (local, stmts) = self.for_body(name, ty, stmts);
+                    Block::Each(local, Box::new(iter), stmts)
+                }
+            },
+            ast::Block::If(_, cond, stmts, else_ifs, els) => {
+                let mut arms = Vec::new();
+                let cond = self.expect(cond, &Type::Bool)?;
+                arms.push((cond, self.stmts(stmts)));
+                for (cond, stmts) in else_ifs {
+                    let cond = self.expect(cond, &Type::Bool)?;
+                    arms.push((cond, self.stmts(stmts)));
+                }
+                let els = els.as_ref().map(|els| self.stmts(els));
+                Block::If(arms, els)
+            }
+        })
+    }
+
+    /// Dereferences references and pointers until a value is reached
+    fn auto_deref(&mut self, mut expr: Expr) -> Expr {
+        while let Some(inner) = expr.ty.pointee() {
+            expr = Expr::new(
+                inner.clone(),
+                ExprKind::Unary(UnaryOpKind::Deref, Box::new(expr)),
+            );
+        }
+        expr
+    }
+
+    fn field(&mut self, base: Expr, field: &str) -> CheckResult<Expr> {
+        let base = self.auto_deref(base);
+        let undefined = || DiagnosticKind::UndefinedField(base.ty.clone(), field.to_owned());
+        let Type::Nammed(path) = &base.ty else {
+            return Err(undefined().into());
+        };
+        let member = match self.context.user_type(path) {
+            Some(UserType::Struct(struc)) => struc
+                .members
+                .iter()
+                .enumerate()
+                .find(|(_, member)| member.name == field)
+                .map(|(index, member)| (index, member.ty.clone())),
+            Some(UserType::Union(unio)) => unio
+                .members
+                .iter()
+                .enumerate()
+                .find(|(_, member)| member.name == field)
+                .map(|(index, member)| (index, member.ty.clone())),
+            _ => None,
+        };
+        let Some((index, ty)) = member else {
+            return Err(undefined().into());
+        };
+        Ok(Expr::new(ty, ExprKind::Field(Box::new(base), index)))
+    }
+
+    fn call(
+        &mut self,
+        callee: Expr,
+        mut args: Vec<Expr>,
+        rest: &[Expression],
+    ) -> CheckResult<Expr> {
+        let Type::FnPointer(params, ret) = &callee.ty else {
+            return Err(DiagnosticKind::NotCallable(callee.ty).into());
+        };
+        if params.len() != args.len() + rest.len() {
+            return Err(DiagnosticKind::ArgumentCount {
+                expected: params.len() - args.len(),
+                found: rest.len(),
+            }
+            .into());
+        }
+        for (param, arg) in params[args.len()..].iter().zip(rest) {
+            args.push(self.expect(arg, param)?);
+        }
+        let ret = ret.as_deref().cloned().unwrap_or(Type::Void);
+        Ok(Expr::new(ret, ExprKind::Call(Box::new(callee), args)))
+    }
+
+    fn method_call(
+        &mut self,
+        receiver: &Expression,
+        name: &str,
+        args: &[Expression],
+    ) -> CheckResult<Expr> {
+        let receiver = self.expr(receiver, None)?;
+
+        let mut candidates = vec![receiver.ty.clone()];
+        if let Some(inner) = receiver.ty.pointee() {
+            candidates.push(inner.clone());
+        }
+        for (deref, ty) in candidates.iter().enumerate() {
+            let Some(id) = self.context.method(ty, name) else {
+                continue;
+            };
+            let sig = self.context.function(id).sig.clone();
+            if !sig.is_method() {
+                return Err(DiagnosticKind::NotAMethod(sig.name.unwrap_or_default()).into());
+            }
+            let receiver = self.adjust_receiver(receiver, deref == 1, &sig.params[0].0)?;
+            let callee = Expr::new(sig.fn_type(), ExprKind::Function(id));
+            return self.call(callee, vec![receiver], args);
+        }
+
+        // calling a function pointer stored in a field
+        let ty = receiver.ty.clone();
+        if let Ok(field) = self.field(receiver, name) {
+            if matches!(field.ty, Type::FnPointer(..)) {
+                return self.call(field, Vec::new(), args);
+            }
+        }
+        Err(DiagnosticKind::NoMethod(ty, name.to_owned()).into())
+    }
+
+    fn unary(
+        &mut self,
+        op: UnaryOpKind,
+        inner: &Expression,
+        expected: Option<&Type>,
+    ) -> CheckResult<Expr> {
+        let expr = match op {
+            UnaryOpKind::Negate => {
+                if let ExpressionKind::Literal(lit @ Literal::Number(_)) = &inner.kind {
+                    return self.literal(lit, expected, true);
+                }
+                let expr = self.expr(inner, expected)?;
+                if !expr.ty.is_signed() {
+                    return Err(DiagnosticKind::InvalidUnary(op, expr.ty).into());
+                }
+                expr
+            }
+            UnaryOpKind::Not => {
+                let expr = self.expr(inner, expected)?;
+                if !expr.ty.is_int() && expr.ty != Type::Bool {
+                    return Err(DiagnosticKind::InvalidUnary(op, expr.ty).into());
+                }
+                expr
+            }
+            UnaryOpKind::Deref => {
+                let expr = self.expr(inner, None)?;
+                let Some(pointee) = expr.ty.pointee().cloned() else {
+                    return Err(DiagnosticKind::InvalidUnary(op, expr.ty).into());
+                };
+                return Ok(Expr::new(pointee, ExprKind::Unary(op, Box::new(expr))));
+            }
+            UnaryOpKind::Ref | UnaryOpKind::RefMut => {
+                let expr = self.expr(inner, expected.and_then(Type::pointee))?;
+                return self.borrow(expr, op == UnaryOpKind::RefMut);
+            }
+        };
+        Ok(Expr::new(
+            expr.ty.clone(),
+            ExprKind::Unary(op, Box::new(expr)),
+        ))
+    }
+
+    fn binary(
+        &mut self,
+        l: &Expression,
+        op: BinOpKind,
+        r: &Expression,
+        expected: Option<&Type>,
+    ) -> CheckResult<Expr> {
+        use BinOpKind::*;
+
+        if let LogicalAnd | LogicalOr = op {
+            let l = self.expect(l, &Type::Bool)?;
+            let r = self.expect(r, &Type::Bool)?;
+            return Ok(Expr::new(
+                Type::Bool,
+                ExprKind::Binary(Box::new(l), op, Box::new(r)),
+            ));
+        }
+
+        let hint = match op {
+            Times | Divide | Modulo | Plus | Minus | ShiftLeft | ShiftRight | BitAnd | BitXor
+            | BitOr => expected,
+            _ => None,
+        };
+
+        if let ShiftLeft | ShiftRight = op {
+            let l = self.expr(l, hint)?;
+            let r = self.expr(r, Some(&l.ty))?;
+            if !l.ty.is_int() || !r.ty.is_int() {
+                return Err(DiagnosticKind::InvalidBinary(l.ty, op, r.ty).into());
+            }
+            return Ok(Expr::new(
+                l.ty.clone(),
+                ExprKind::Binary(Box::new(l), op, Box::new(r)),
+            ));
+        }
+
+        // let the side with a known type decide the type of untyped literals
+        let (l, r) = if untyped_literal(l) && !untyped_literal(r) {
+            let r = self.expr(r, hint)?;
+            let l = self.expr(l, Some(&r.ty))?;
+            (l, r)
+        } else {
+            let l = self.expr(l, hint)?;
+            let r = self.expr(r, Some(&l.ty))?;
+            (l, r)
+        };
+        let (l_ty, r_ty) = (l.ty.clone(), r.ty.clone());
+        let invalid = || DiagnosticKind::InvalidBinary(l_ty.clone(), op, r_ty.clone());
+        let r = self.coerce(r, &l.ty).map_err(|_| invalid())?;
+
+        let ty = match op {
+            Times | Divide | Modulo | Plus | Minus if l.ty.is_numeric() => l.ty.clone(),
+            BitAnd | BitXor | BitOr if l.ty.is_int() || l.ty == Type::Bool => l.ty.clone(),
+            Eq | Neq if is_comparable(self.context, &l.ty) => Type::Bool,
+            Gt | Lt | Gteq | Lteq if l.ty.is_numeric() || l.ty == Type::Char => Type::Bool,
+            _ => return Err(invalid().into()),
+        };
+        Ok(Expr::new(
+            ty,
+            ExprKind::Binary(Box::new(l), op, Box::new(r)),
+        ))
+    }
+
+    /// Checks the left side of an assignment
+    fn assigned_place(&mut self, place: &Expression) -> CheckResult<Expr> {
+        let expr = self.expr(place, None)?;
+        if let (ExpressionKind::Path(path), ExprKind::Value(_)) = (&place.kind, &expr.kind) {
+            return Err(DiagnosticKind::AssignConstant(path.clone()).into());
+        }
+        Ok(expr)
+    }
+
+    /// Errors unless `place` can be assigned to or mutably borrowed
+    fn check_mutable(&self, place: &Expr) -> CheckResult<()> {
+        match &place.kind {
+            ExprKind::Local(id) => {
+                let local = &self.locals[id.0];
+                match local.mutable {
+                    true => Ok(()),
+                    false => Err(DiagnosticKind::ImmutableLocal(local.name.clone()).into()),
+                }
+            }
+            ExprKind::Static(_) => Ok(()),
+            ExprKind::Unary(UnaryOpKind::Deref, inner) => match inner.ty.is_mut_pointer() {
+                true => Ok(()),
+                false => Err(DiagnosticKind::ImmutableReference(inner.ty.clone()).into()),
+            },
+            ExprKind::Field(inner, _) | ExprKind::Index(inner, _) => self.check_mutable(inner),
+            _ => Err(DiagnosticKind::NotAPlace.into()),
+        }
+    }
+
+    /// Takes a reference to `expr`, temporaries can always be borrowed mutably
+    fn borrow(&mut self, expr: Expr, mutable: bool) -> CheckResult<Expr> {
+        if mutable && expr.is_place() {
+            self.check_mutable(&expr)?;
+        }
+        let op = if mutable {
+            UnaryOpKind::RefMut
+        } else {
+            UnaryOpKind::Ref
+        };
+        Ok(Expr::new(
+            Type::Ref(Box::new(expr.ty.clone()), mutable),
+            ExprKind::Unary(op, Box::new(expr)),
+        ))
+    }
+
+    /// Takes a reference to or dereferences a method receiver so it matches the `self` parameter
+    fn adjust_receiver(
+        &mut self,
+        receiver: Expr,
+        derefed: bool,
+        self_ty: &Type,
+    ) -> CheckResult<Expr> {
+        if derefed && matches!(receiver.ty, Type::Ref(..)) && matches!(self_ty, Type::Ref(..)) {
+            if self_ty.is_mut_pointer() && !receiver.ty.is_mut_pointer() {
+                return Err(DiagnosticKind::ImmutableReference(receiver.ty).into());
+            }
+            return self.coerce(receiver, self_ty);
+        }
+        let receiver = match (derefed, receiver.ty.pointee()) {
+            (true, Some(inner)) => Expr::new(
+                inner.clone(),
+                ExprKind::Unary(UnaryOpKind::Deref, Box::new(receiver)),
+            ),
+            _ => receiver,
+        };
+        match self_ty {
+            Type::Ref(_, mutable) => self.borrow(receiver, *mutable),
+            _ => Ok(receiver),
+        }
+    }
+
+    fn struct_con(&mut self, path: &Path, fields: &[(String, Expression)]) -> CheckResult<Expr> {
+        let ty = self.resolve_type(&ast::Type::new(path.clone()))?;
+        let Type::Nammed(ty_path) = &ty else {
+            return Err(DiagnosticKind::UndefinedType(path.clone()).into());
+        };
+        let (is_union, members): (bool, Vec<(String, Type)>) = match self.context.user_type(ty_path)
+        {
+            Some(UserType::Struct(struc)) => (
+                false,
+                struc
+                    .members
+                    .iter()
+                    .map(|member| (member.name.clone(), member.ty.clone()))
+                    .collect(),
+            ),
+            Some(UserType::Union(unio)) => (
+                true,
+                unio.members
+                    .iter()
+                    .map(|member| (member.name.clone(), member.ty.clone()))
+                    .collect(),
+            ),
+            _ => return Err(DiagnosticKind::UndefinedType(path.clone()).into()),
+        };
+
+        let mut values: Vec<Option<Expr>> = vec![None; members.len()];
+        for (name, value) in fields {
+            let Some(index) = members.iter().position(|(member, _)| member == name) else {
+                return Err(DiagnosticKind::UndefinedField(ty.clone(), name.clone()).into());
+            };
+            if values[index].is_some() {
+                return Err(DiagnosticKind::MultipleDefinitions(Path::new_path(name)).into());
+            }
+            values[index] = Some(self.expect(value, &members[index].1)?);
+        }
+
+        if is_union {
+            let mut given = values
+                .into_iter()
+                .enumerate()
+                .filter_map(|(index, value)| Some((index, value?)));
+            return match (given.next(), given.next()) {
+                (Some((index, value)), None) => {
+                    Ok(Expr::new(ty, ExprKind::UnionCon(index, Box::new(value))))
+                }
+                _ => Err(DiagnosticKind::ArgumentCount {
+                    expected: 1,
+                    found: fields.len(),
+                }
+                .into()),
+            };
+        }
+
+        let values = values
+            .into_iter()
+            .zip(members)
+            .map(|(value, (name, _))| {
+                value.ok_or_else(|| DiagnosticKind::MissingField(ty_path.clone(), name))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Expr::new(ty, ExprKind::StructCon(values)))
+    }
+}
+
+/// The type a `for` loop over a value of type `ty` binds each element as
+fn element_binding(ty: &Type) -> Option<Type> {
+    match ty {
+        Type::Array(element) | Type::ArrayStatic(element, _) => Some((**element).clone()),
+        Type::Ref(inner, mutable) => Some(Type::Ref(Box::new(inner.element()?.clone()), *mutable)),
+        Type::Ptr(inner, mutable) => Some(Type::Ptr(Box::new(inner.element()?.clone()), *mutable)),
+        _ => None,
+    }
+}
+
+/// Structs and unions have no `==`, only enums among the user types
+fn is_comparable(context: &Context, ty: &Type) -> bool {
+    match ty {
+        Type::Nammed(path) => matches!(context.user_type(path), Some(UserType::Enum(_))),
+        _ => matches!(
+            ty,
+            Type::Int(..)
+                | Type::Float(_)
+                | Type::Bool
+                | Type::Char
+                | Type::Ptr(..)
+                | Type::Ref(..)
+                | Type::FnPointer(..)
+        ),
+    }
+}
+
+/// Whether `expr` is a number literal that takes its type from its surroundings
+fn untyped_literal(expr: &Expression) -> bool {
+    match &expr.kind {
+        ExpressionKind::Literal(Literal::Number(number)) => split_suffix(number).1.is_none(),
+        ExpressionKind::UnaryOp(UnaryOpKind::Negate, inner) => untyped_literal(inner),
+        _ => false,
+    }
+}
+
+fn split_suffix(number: &str) -> (&str, Option<&str>) {
+    if number.starts_with("0x") || number.starts_with("0b") {
+        return (number, None);
+    }
+    let digits = number.trim_end_matches(|c: char| c.is_ascii_digit());
+    match digits.strip_suffix(['u', 'i', 'f']) {
+        Some(num) if digits.len() != number.len() => (num, Some(&number[num.len()..])),
+        _ => (number, None),
+    }
+}
+
+fn suffix_type(suffix: &str) -> Option<Type> {
+    use ast::{FloatType, IntSize};
+    Some(match suffix {
+        "u8" => Type::Int(IntSize::U8, false),
+        "u16" => Type::Int(IntSize::U16, false),
+        "u32" => Type::Int(IntSize::U32, false),
+        "u64" => Type::Int(IntSize::U64, false),
+        "i8" => Type::Int(IntSize::U8, true),
+        "i16" => Type::Int(IntSize::U16, true),
+        "i32" => Type::Int(IntSize::U32, true),
+        "i64" => Type::Int(IntSize::U64, true),
+        "f32" => Type::Float(FloatType::F32),
+        "f64" => Type::Float(FloatType::F64),
+        _ => return None,
+    })
+}
+
+fn number_literal(number: &str, expected: Option<&Type>, negate: bool) -> CheckResult<Expr> {
+    let invalid = || DiagnosticKind::InvalidLiteral(number.to_owned());
+    let clean: String = number.chars().filter(|c| *c != '_').collect();
+    let (digits, suffix) = split_suffix(&clean);
+    let is_float = !digits.starts_with("0x") && (digits.contains('.') || digits.contains('e'));
+
+    let ty = match suffix {
+        Some(suffix) => suffix_type(suffix).ok_or_else(invalid)?,
+        None => match expected {
+            Some(ty) if ty.is_float() => ty.clone(),
+            Some(ty) if ty.is_int() && !is_float => ty.clone(),
+            _ if is_float => Type::F64,
+            _ => Type::I32,
+        },
+    };
+
+    if ty.is_float() {
+        let value: f64 = if let Some(hex) = digits.strip_prefix("0x") {
+            u128::from_str_radix(hex, 16).map_err(|_| invalid())? as f64
+        } else if let Some(bin) = digits.strip_prefix("0b") {
+            u128::from_str_radix(bin, 2).map_err(|_| invalid())? as f64
+        } else {
+            digits.parse().map_err(|_| invalid())?
+        };
+        let value = if negate { -value } else { value };
+        return Ok(Expr::value(ty.clone(), Value::float(&ty, value).unwrap()));
+    }
+    if is_float {
+        return Err(DiagnosticKind::Mismatch {
+            expected: ty,
+            found: Type::F64,
+        }
+        .into());
+    }
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        u128::from_str_radix(hex, 16)
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        u128::from_str_radix(bin, 2)
+    } else {
+        digits.parse()
+    };
+    let value: i128 = value
+        .ok()
+        .and_then(|value| value.try_into().ok())
+        .ok_or(DiagnosticKind::ConstantOverflow)?;
+    let value = if negate { -value } else { value };
+    let value = Value::int(&ty, value).ok_or(DiagnosticKind::ConstantOverflow)?;
+    Ok(Expr::value(ty, value))
+}
+
+pub fn unescape(str: &str) -> Option<String> {
+    let mut out = String::with_capacity(str.len());
+    let mut chars = str.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '\'' => '\'',
+            '"' => '"',
+            _ => return None,
+        });
+    }
+    Some(out)
+}
+
+/// Parses and checks a test program, printing what's wrong with it
+#[cfg(test)]
+pub(crate) fn check_source(src: &str) -> super::Program {
+    let module = crate::parser::def::ModuleParser::new().parse(src).unwrap();
+    let mut program = super::Program::default();
+    program.load_module(Path::new(), module);
+    program.check();
+    for diagnostic in program.context.diagnostics() {
+        println!("{diagnostic}");
+    }
+    program
+}
+
+/// [`check_source`] for a test program that has to be right
+#[cfg(test)]
+pub(crate) fn checked_source(src: &str) -> super::Program {
+    let program = check_source(src);
+    assert!(!program.context.has_errors());
+    program
+}
+
+#[cfg(test)]
+pub(super) fn errors(program: &super::Program) -> Vec<DiagnosticKind> {
+    program
+        .context
+        .diagnostics()
+        .iter()
+        .filter(|diagnostic| diagnostic.level == super::error::Level::Error)
+        .map(|diagnostic| diagnostic.kind.clone())
+        .collect()
+}
+
+#[test]
+fn method_calls() {
+    let program = check_source(
+        r#"
+        struct Counter { i32 count, }
+
+        impl Counter {
+            fn new() Self { return Counter { count = 0 }; }
+            fn get(&Self self) i32 { return self.count; }
+            fn take(Self self) i32 { return self.count; }
+        }
+
+        impl i32 {
+            fn test(&Self self, i32 other) i32 { return *self + other; }
+        }
+
+        fn main(i32 count) {
+            Counter counter = Counter::new();
+            i32 a = counter.get();
+            &Counter by_ref = &counter;
+            i32 b = by_ref.get();
+            i32 c = by_ref.take();
+            i32 d = count.test(12);
+        }
+        "#,
+    );
+    assert_eq!(errors(&program), vec![]);
+}
+
+#[test]
+fn method_errors() {
+    let program = check_source(
+        r#"
+        struct Counter { i32 count, }
+        impl Counter {
+            fn new() Self { return Counter { count = 0 }; }
+        }
+        fn main() {
+            Counter counter = Counter::new();
+            counter.missing();
+            counter.new();
+            i32 x = 5;
+            x.test();
+        }
+        "#,
+    );
+    let counter = Type::Nammed(Path::new_path("Counter"));
+    assert_eq!(
+        errors(&program),
+        vec![
+            DiagnosticKind::NoMethod(counter, "missing".into()),
+            DiagnosticKind::NotAMethod(Path::new_path("Counter::new")),
+            DiagnosticKind::NoMethod(Type::I32, "test".into()),
+        ]
+    );
+}
+
+#[test]
+fn literals_take_expected_type() {
+    let program = check_source(
+        r#"
+        const u8 SMALL = 200;
+        const usize LEN = 100 + 20;
+        const i64 NEG = -5;
+        extern static [u8; LEN] BUF;
+        fn main() {
+            u8 a = 1 + SMALL;
+            f32 b = 1.5;
+            usize c = size_of([u8; LEN * 2]);
+            u8 d = 256;
+        }
+        "#,
+    );
+    assert_eq!(errors(&program), vec![DiagnosticKind::ConstantOverflow]);
+}
+
+#[test]
+fn mutability() {
+    let program = check_source(
+        r#"
+        struct Counter { i32 count, }
+        impl Counter {
+            fn bump(&mut Self self) { self.count = self.count + 1; }
+            fn get(&Self self) i32 { return self.count; }
+        }
+        static i32 TOTAL = 0;
+        fn main(mut i32 param) {
+            param = 3;
+            TOTAL = 4;
+            mut Counter counter = Counter { count = 0 };
+            counter.count = 1;
+            counter.bump();
+            &mut Counter by_ref = &mut counter;
+            by_ref.bump();
+            i32 value = by_ref.get();
+            &Counter shared = by_ref;
+            *i32 ptr = &mut counter.count;
+            *mut i32 ptr_mut = &mut counter.count;
+            *ptr_mut = 5;
+        }
+        "#,
+    );
+    assert_eq!(errors(&program), vec![]);
+}
+
+#[test]
+fn mutability_errors() {
+    let program = check_source(
+        r#"
+        struct Counter { i32 count, }
+        impl Counter {
+            fn bump(&mut Self self) { self.count = self.count + 1; }
+        }
+        const i32 MAX = 10;
+        fn five() i32 { return 5; }
+        fn main(i32 param, &Counter shared, *i32 ptr) {
+            param = 3;
+            MAX = 4;
+            five() = 3;
+            shared.count = 1;
+            shared.bump();
+            *ptr = 2;
+            Counter counter = Counter { count = 0 };
+            &mut Counter by_ref = &mut counter;
+            &mut Counter from_shared = shared;
+        }
+        "#,
+    );
+    let counter = Type::Nammed(Path::new_path("Counter"));
+    let shared = Type::Ref(Box::new(counter.clone()), false);
+    assert_eq!(
+        errors(&program),
+        vec![
+            DiagnosticKind::ImmutableLocal("param".into()),
+            DiagnosticKind::AssignConstant(Path::new_path("MAX")),
+            DiagnosticKind::NotAPlace,
+            DiagnosticKind::ImmutableReference(shared.clone()),
+            DiagnosticKind::ImmutableReference(shared.clone()),
+            DiagnosticKind::ImmutableReference(Type::Ptr(Box::new(Type::I32), false)),
+            DiagnosticKind::ImmutableLocal("counter".into()),
+            DiagnosticKind::Mismatch {
+                expected: Type::Ref(Box::new(counter), true),
+                found: shared,
+            },
+        ]
+    );
+}
+
+#[test]
+fn labeled_breaks() {
+    let program = check_source(
+        r#"
+        struct Counter { i32 count, }
+        fn main(bool cond) {
+            mut Counter counter = Counter { count = 0 };
+            &Counter common = 'a {
+                if (cond) { break 'a &mut counter; }
+                break 'a &counter;
+            };
+            i32 mismatch = 'b {
+                if (cond) { break 'b 1; }
+                break 'b true;
+            };
+            'c { continue 'c; }
+            while (cond) { break 'nope; }
+            break;
+        }
+        "#,
+    );
+    assert_eq!(
+        errors(&program),
+        vec![
+            DiagnosticKind::Mismatch {
+                expected: Type::I32,
+                found: Type::Bool,
+            },
+            DiagnosticKind::NotALoop("'c".into()),
+            DiagnosticKind::UndefinedLabel("'nope".into()),
+            DiagnosticKind::OutsideLoop("break"),
+        ]
+    );
+}
+
+#[test]
+fn loops() {
+    let program = check_source(
+        r#"
+        fn sum(&[i32] values, usize n) i32 {
+            mut i32 total = 0;
+            for (i in 0..n) { total = total + values[i]; }
+            for (value in values) { total = total + *value; }
+            'outer for (i in 1..=10u8) {
+                if (i == 5u8) { continue 'outer; }
+            }
+            i32 found = loop { break 5; };
+            return total + found;
+        }
+        fn by_value([i32; 4] values) i32 {
+            mut i32 total = 0;
+            for (value in values) { total = total + value; }
+            return total;
+        }
+        fn zero(&mut [i32] values) {
+            for (value in values) { *value = 0; }
+        }
+        fn errors() {
+            for (x in 5) { }
+            for (x in true..false) { }
+            for (i in 0..3) { i = 2; }
+            i32 range = 0..3;
+        }
+        "#,
+    );
+    assert_eq!(
+        errors(&program),
+        vec![
+            DiagnosticKind::NotIterable(Type::I32),
+            DiagnosticKind::NotIterable(Type::Bool),
+            DiagnosticKind::ImmutableLocal("i".into()),
+            DiagnosticKind::RangeOutsideFor,
+        ]
+    );
+}
+
+#[test]
+fn compound_assignment() {
+    let program = check_source(
+        r#"
+        const i32 MAX = 1;
+        fn index() usize { return 0; }
+        fn main(&mut [i32] values, bool flag) {
+            mut i32 a = 1;
+            a += 2; a -= 1; a *= 3; a /= 2; a %= 5;
+            a <<= 1; a >>= 1u8; a |= 4; a &= 7; a ^= 1;
+            mut f64 b = 1.0;
+            b += 0.5;
+            mut bool c = true;
+            c &= flag;
+            values[index()] += 1;
+            b <<= 1;
+            c += true;
+            MAX += 1;
+            i32 d = 0;
+            d += 1;
+        }
+        "#,
+    );
+    assert_eq!(
+        errors(&program),
+        vec![
+            DiagnosticKind::InvalidBinary(Type::F64, BinOpKind::ShiftLeft, Type::F64),
+            DiagnosticKind::InvalidBinary(Type::Bool, BinOpKind::Plus, Type::Bool),
+            DiagnosticKind::AssignConstant(Path::new_path("MAX")),
+            DiagnosticKind::ImmutableLocal("d".into()),
+        ]
+    );
+}
+
+#[test]
+fn struct_equality() {
+    let program = check_source(
+        r#"
+        struct Point { i32 x, }
+        enum Dir { Up, Down, }
+
+        fn main(Point a, Point b) bool {
+            bool same = Dir::Up == Dir::Down;
+            return a == b;
+        }
+        "#,
+    );
+    let point = Type::Nammed(Path::new_path("Point"));
+    assert_eq!(
+        errors(&program),
+        vec![DiagnosticKind::InvalidBinary(
+            point.clone(),
+            BinOpKind::Eq,
+            point
+        )]
+    );
+}
+
+#[test]
+fn error_spans() {
+    let src = r#"
+        fn take(i32 value) i32 { return value; }
+        fn main() i32 {
+            i32 later;
+            bool flag = take(true) == 1;
+            return later + missing;
+        }
+        "#;
+    let spans = |src: &str| -> Vec<String> {
+        let program = check_source(src);
+        let diagnostics = program.context.diagnostics().iter();
+        let spans = diagnostics.map(|diagnostic| diagnostic.span.unwrap());
+        spans
+            .map(|span| src[span.start..span.end].to_string())
+            .collect()
+    };
+    assert_eq!(spans(src), ["true", "missing"]);
+    // the flow checks run once everything else is right
+    let src = src
+        .replace("take(true)", "take(2)")
+        .replace(" + missing", "");
+    assert_eq!(spans(&src), ["later"]);
+}
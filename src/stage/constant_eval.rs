@@ -1,11 +1,14 @@
-use std::task::Context;
+use crate::parser::ast::{BinOpKind, FloatType, IntSize, Path, UnaryOpKind};
 
-use crate::parser::ast::{BinOpKind, Expression, Literal, Path, Type, UnaryOpKind};
+use super::{
+    error::DiagnosticKind,
+    tree::{Coercion, Expr, ExprKind},
+    types::Type,
+    Context,
+};
 
-use super::TypeMap;
-
-
-pub enum Value{
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
     U8(u8),
     U16(u16),
     U32(u32),
@@ -24,95 +27,338 @@ pub enum Value{
 
     Void(()),
 
+    Str(String),
+
     Ref(Path),
     Ptr(Path),
 
     Array(Vec<Value>),
 
-    Struct(),
-    Enum(),
-    Union()
+    Struct(Vec<Value>),
+    Enum(usize),
+    Union(usize, Box<Value>),
+}
+
+impl Value {
+    /// Builds an integer of type `ty` from `value`, failing if it doesn't fit
+    pub fn int(ty: &Type, value: i128) -> Option<Value> {
+        Some(match ty {
+            Type::Int(IntSize::U8, false) => Value::U8(value.try_into().ok()?),
+            Type::Int(IntSize::U16, false) => Value::U16(value.try_into().ok()?),
+            Type::Int(IntSize::U32, false) => Value::U32(value.try_into().ok()?),
+            Type::Int(IntSize::U64 | IntSize::Usize, false) => Value::U64(value.try_into().ok()?),
+            Type::Int(IntSize::U8, true) => Value::I8(value.try_into().ok()?),
+            Type::Int(IntSize::U16, true) => Value::I16(value.try_into().ok()?),
+            Type::Int(IntSize::U32, true) => Value::I32(value.try_into().ok()?),
+            Type::Int(IntSize::U64 | IntSize::Usize, true) => Value::I64(value.try_into().ok()?),
+            _ => return None,
+        })
+    }
+
+    /// Builds an integer of type `ty` from `value` truncating it like a two's complement cast
+    pub fn int_wrapping(ty: &Type, value: i128) -> Option<Value> {
+        Some(match ty {
+            Type::Int(IntSize::U8, false) => Value::U8(value as u8),
+            Type::Int(IntSize::U16, false) => Value::U16(value as u16),
+            Type::Int(IntSize::U32, false) => Value::U32(value as u32),
+            Type::Int(IntSize::U64 | IntSize::Usize, false) => Value::U64(value as u64),
+            Type::Int(IntSize::U8, true) => Value::I8(value as i8),
+            Type::Int(IntSize::U16, true) => Value::I16(value as i16),
+            Type::Int(IntSize::U32, true) => Value::I32(value as i32),
+            Type::Int(IntSize::U64 | IntSize::Usize, true) => Value::I64(value as i64),
+            _ => return None,
+        })
+    }
+
+    pub fn float(ty: &Type, value: f64) -> Option<Value> {
+        match ty {
+            Type::Float(FloatType::F32) => Some(Value::F32(value as f32)),
+            Type::Float(FloatType::F64) => Some(Value::F64(value)),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i128> {
+        Some(match *self {
+            Value::U8(v) => v as i128,
+            Value::U16(v) => v as i128,
+            Value::U32(v) => v as i128,
+            Value::U64(v) => v as i128,
+            Value::I8(v) => v as i128,
+            Value::I16(v) => v as i128,
+            Value::I32(v) => v as i128,
+            Value::I64(v) => v as i128,
+            Value::Char(c) => c as i128,
+            Value::Bool(b) => b as i128,
+            Value::Enum(v) => v as i128,
+            _ => return None,
+        })
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            Value::F32(v) => Some(v as f64),
+            Value::F64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Rebuilds a value of the same variant as `self` from an integer, wrapping on overflow
+    fn with_int(&self, value: i128) -> Value {
+        match self {
+            Value::U8(_) => Value::U8(value as u8),
+            Value::U16(_) => Value::U16(value as u16),
+            Value::U32(_) => Value::U32(value as u32),
+            Value::U64(_) => Value::U64(value as u64),
+            Value::I8(_) => Value::I8(value as i8),
+            Value::I16(_) => Value::I16(value as i16),
+            Value::I32(_) => Value::I32(value as i32),
+            Value::I64(_) => Value::I64(value as i64),
+            Value::Char(_) => Value::Char(value as u8 as char),
+            Value::Bool(_) => Value::Bool(value & 1 == 1),
+            _ => unreachable!("not an integer"),
+        }
+    }
+
+    fn bits(&self) -> u32 {
+        match self {
+            Value::U8(_) | Value::I8(_) | Value::Char(_) | Value::Bool(_) => 8,
+            Value::U16(_) | Value::I16(_) => 16,
+            Value::U32(_) | Value::I32(_) | Value::F32(_) => 32,
+            _ => 64,
+        }
+    }
 }
 
-pub fn const_eval(context: &mut Context, desired_type: &Type, expr: &Expression) -> Value{
-    match expr{
-        Expression::Path(_) => todo!(),
-
-        Expression::Literal(lit) => {
-            match lit{
-                Literal::String(_) => todo!(),
-                Literal::Char(_) => todo!(),
-                Literal::Boolean(value) => {
-                    assert_eq!(desired_type, &Type::Bool);
-                    Value::Bool(*value)
-                },
-                Literal::Number(_) => todo!(),
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::U8(v) => write!(f, "{v}u8"),
+            Value::U16(v) => write!(f, "{v}u16"),
+            Value::U32(v) => write!(f, "{v}u32"),
+            Value::U64(v) => write!(f, "{v}u64"),
+            Value::I8(v) => write!(f, "{v}i8"),
+            Value::I16(v) => write!(f, "{v}i16"),
+            Value::I32(v) => write!(f, "{v}i32"),
+            Value::I64(v) => write!(f, "{v}i64"),
+            Value::F32(v) => write!(f, "{v:?}f32"),
+            Value::F64(v) => write!(f, "{v:?}f64"),
+            Value::Bool(v) => write!(f, "{v}"),
+            Value::Char(v) => write!(f, "{v:?}"),
+            Value::Void(()) => write!(f, "void"),
+            Value::Str(v) => write!(f, "{v:?}"),
+            Value::Ref(path) => write!(f, "&{path}"),
+            Value::Ptr(path) => write!(f, "*{path}"),
+            Value::Array(values) | Value::Struct(values) => {
+                let (open, close) = match self {
+                    Value::Array(_) => ('[', ']'),
+                    _ => ('{', '}'),
+                };
+                write!(f, "{open}")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "{close}")
             }
+            Value::Enum(v) => write!(f, "#{v}"),
+            Value::Union(member, value) => write!(f, "{{#{member} = {value}}}"),
+        }
+    }
+}
+
+pub type EvalResult = Result<Value, DiagnosticKind>;
+
+/// Evaluates an already type checked expression at compile time
+pub fn const_eval(context: &mut Context, expr: &Expr) -> EvalResult {
+    match &expr.kind {
+        ExprKind::Value(value) => Ok(value.clone()),
+
+        ExprKind::Static(path) => match context.global_value(path) {
+            Some(value) => Ok(value.clone()),
+            None => Err(DiagnosticKind::NotConstant),
         },
-        Expression::Block(_) => todo!("Cannot evaluate block expressions in constant evaluation"),
-        Expression::FieldAccess(expr, field) => {
-            todo!()
-        },
-        Expression::MemberFunction(_, _, _) => todo!("Cannot call member functions in constant evaluation"),
-        
-        Expression::ArrayAccess(contents, index) => todo!(),
-
-        Expression::FunctionCall(_, _) => todo!("Cannot call functions in constant evaluation"),
-        Expression::UnaryOp(op, expr) => {
-            // let val = const_eval(type_map, desired_type, expr);
-            apply_unary_op(expr, *op)
+
+        ExprKind::Unary(UnaryOpKind::Ref | UnaryOpKind::RefMut, inner) => match &inner.kind {
+            ExprKind::Static(path) => Ok(Value::Ref(path.clone())),
+            _ => Err(DiagnosticKind::NotConstant),
         },
-        Expression::BinaryOp(l, op, r) => {
-            // apply_binop_op(l, op, r)
-            todo!()
+        ExprKind::Unary(op, inner) => {
+            let value = const_eval(context, inner)?;
+            apply_unary_op(value, *op)
+        }
+        ExprKind::Binary(l, op, r) => {
+            let l = const_eval(context, l)?;
+            // short circuit just like at runtime
+            match (op, &l) {
+                (BinOpKind::LogicalAnd, Value::Bool(false)) => return Ok(l),
+                (BinOpKind::LogicalOr, Value::Bool(true)) => return Ok(l),
+                _ => {}
+            }
+            let r = const_eval(context, r)?;
+            apply_binop_op(l, *op, r)
+        }
+        ExprKind::Coerce(coercion, inner) => {
+            let value = const_eval(context, inner)?;
+            match (coercion, value) {
+                (Coercion::RefToPtr, Value::Ref(path)) => Ok(Value::Ptr(path)),
+                (Coercion::Unsize(_) | Coercion::Immutable, value) => Ok(value),
+                _ => Err(DiagnosticKind::NotConstant),
+            }
+        }
+
+        ExprKind::Field(inner, index) => match const_eval(context, inner)? {
+            Value::Struct(mut values) => Ok(values.swap_remove(*index)),
+            Value::Union(member, value) if member == *index => Ok(*value),
+            _ => Err(DiagnosticKind::NotConstant),
         },
-        Expression::Assign(_, _) => todo!("Cannot perform assignment in constant evaluation"),
-        
-        Expression::StructCon(_, _) => todo!(),
-        Expression::ArrayCon(exprs) => todo!(),
-
-        Expression::Break(_, _) => todo!("No loop to break out of"),
-        Expression::Continue(_) => todo!("No loop to continue in"),
-        Expression::Return(_) => todo!("Cannot return not in a function"),
-        
-        Expression::SizeOf(_) => todo!(),
-        Expression::AlignOf(_) => todo!(),
-        Expression::Sized(_) => todo!(),
-        Expression::OffsetOf(_, _) => todo!(),
-        Expression::TypeName(_) => todo!(),
-        
+        ExprKind::Index(contents, index) => {
+            let contents = const_eval(context, contents)?;
+            let index = const_eval(context, index)?;
+            match (contents, index.as_int()) {
+                (Value::Array(mut values), Some(index))
+                    if index >= 0 && (index as usize) < values.len() =>
+                {
+                    Ok(values.swap_remove(index as usize))
+                }
+                _ => Err(DiagnosticKind::NotConstant),
+            }
+        }
+
+        ExprKind::StructCon(exprs) => {
+            let values = exprs
+                .iter()
+                .map(|expr| const_eval(context, expr))
+                .collect::<Result<_, _>>()?;
+            Ok(Value::Struct(values))
+        }
+        ExprKind::UnionCon(member, expr) => {
+            let value = const_eval(context, expr)?;
+            Ok(Value::Union(*member, Box::new(value)))
+        }
+        ExprKind::ArrayCon(exprs) => {
+            let values = exprs
+                .iter()
+                .map(|expr| const_eval(context, expr))
+                .collect::<Result<_, _>>()?;
+            Ok(Value::Array(values))
+        }
+
+        ExprKind::Local(_)
+        | ExprKind::Function(_)
+        | ExprKind::Block(..)
+        | ExprKind::Call(..)
+        | ExprKind::Assign(..)
+        | ExprKind::CompoundAssign(..)
+        | ExprKind::Break(..)
+        | ExprKind::Continue(_)
+        | ExprKind::Return(_) => Err(DiagnosticKind::NotConstant),
     }
 }
 
-fn apply_unary_op(value: &Expression, op: UnaryOpKind) -> Value{
-    match op{
-        UnaryOpKind::Negate => todo!(),
-        UnaryOpKind::Deref => todo!(),
-        UnaryOpKind::Not => todo!(),
-        UnaryOpKind::Ref => todo!(),
-        UnaryOpKind::RefMut => todo!(),
+pub fn apply_unary_op(value: Value, op: UnaryOpKind) -> EvalResult {
+    match op {
+        UnaryOpKind::Negate => match value {
+            Value::F32(v) => Ok(Value::F32(-v)),
+            Value::F64(v) => Ok(Value::F64(-v)),
+            Value::I8(_) | Value::I16(_) | Value::I32(_) | Value::I64(_) => {
+                let v = value.as_int().unwrap();
+                Ok(value.with_int(v.wrapping_neg()))
+            }
+            _ => Err(DiagnosticKind::NotConstant),
+        },
+        UnaryOpKind::Not => match value {
+            Value::Bool(v) => Ok(Value::Bool(!v)),
+            _ => match value.as_int() {
+                Some(v) => Ok(value.with_int(!v)),
+                None => Err(DiagnosticKind::NotConstant),
+            },
+        },
+        UnaryOpKind::Deref | UnaryOpKind::Ref | UnaryOpKind::RefMut => {
+            Err(DiagnosticKind::NotConstant)
+        }
     }
 }
 
-fn apply_binop_op(l: &Expression, op: BinOpKind, r: &Expression) -> Value{
-    match op{
-        BinOpKind::Times => todo!(),
-        BinOpKind::Divide => todo!(),
-        BinOpKind::Modulo => todo!(),
-        BinOpKind::Plus => todo!(),
-        BinOpKind::Minus => todo!(),
-        BinOpKind::ShiftLeft => todo!(),
-        BinOpKind::ShiftRight => todo!(),
-        BinOpKind::BitAnd => todo!(),
-        BinOpKind::BitXor => todo!(),
-        BinOpKind::BitOr => todo!(),
-        BinOpKind::Eq => todo!(),
-        BinOpKind::Neq => todo!(),
-        BinOpKind::Gt => todo!(),
-        BinOpKind::Lt => todo!(),
-        BinOpKind::Gteq => todo!(),
-        BinOpKind::Lteq => todo!(),
-        BinOpKind::LogicalAnd => todo!(),
-        BinOpKind::LogicalOr => todo!(),
+pub fn apply_binop_op(l: Value, op: BinOpKind, r: Value) -> EvalResult {
+    use std::cmp::Ordering;
+
+    if let (Some(a), Some(b)) = (l.as_float(), r.as_float()) {
+        let float = |v: f64| match l {
+            Value::F32(_) => Value::F32(v as f32),
+            _ => Value::F64(v),
+        };
+        return Ok(match op {
+            BinOpKind::Times => float(a * b),
+            BinOpKind::Divide => float(a / b),
+            BinOpKind::Modulo => float(a % b),
+            BinOpKind::Plus => float(a + b),
+            BinOpKind::Minus => float(a - b),
+            BinOpKind::Eq => Value::Bool(a == b),
+            BinOpKind::Neq => Value::Bool(a != b),
+            BinOpKind::Gt => Value::Bool(a > b),
+            BinOpKind::Lt => Value::Bool(a < b),
+            BinOpKind::Gteq => Value::Bool(a >= b),
+            BinOpKind::Lteq => Value::Bool(a <= b),
+            _ => return Err(DiagnosticKind::NotConstant),
+        });
+    }
+
+    if let (Value::Bool(a), Value::Bool(b)) = (&l, &r) {
+        let (a, b) = (*a, *b);
+        return Ok(Value::Bool(match op {
+            BinOpKind::BitAnd | BinOpKind::LogicalAnd => a & b,
+            BinOpKind::BitOr | BinOpKind::LogicalOr => a | b,
+            BinOpKind::BitXor | BinOpKind::Neq => a ^ b,
+            BinOpKind::Eq => a == b,
+            _ => return Err(DiagnosticKind::NotConstant),
+        }));
     }
-}
\ No newline at end of file
+
+    let (Some(a), Some(b)) = (l.as_int(), r.as_int()) else {
+        return match op {
+            BinOpKind::Eq => Ok(Value::Bool(l == r)),
+            BinOpKind::Neq => Ok(Value::Bool(l != r)),
+            _ => Err(DiagnosticKind::NotConstant),
+        };
+    };
+
+    let compare = |f: fn(Ordering) -> bool| Ok(Value::Bool(f(a.cmp(&b))));
+    match op {
+        BinOpKind::Times => Ok(l.with_int(a.wrapping_mul(b))),
+        BinOpKind::Divide | BinOpKind::Modulo => {
+            if b == 0 {
+                return Err(DiagnosticKind::DivideByZero);
+            }
+            let v = if op == BinOpKind::Divide {
+                a.wrapping_div(b)
+            } else {
+                a.wrapping_rem(b)
+            };
+            Ok(l.with_int(v))
+        }
+        BinOpKind::Plus => Ok(l.with_int(a.wrapping_add(b))),
+        BinOpKind::Minus => Ok(l.with_int(a.wrapping_sub(b))),
+        BinOpKind::ShiftLeft => Ok(l.with_int(a << (b as u32 % l.bits()))),
+        BinOpKind::ShiftRight => Ok(l.with_int(a >> (b as u32 % l.bits()))),
+        BinOpKind::BitAnd => Ok(l.with_int(a & b)),
+        BinOpKind::BitXor => Ok(l.with_int(a ^ b)),
+        BinOpKind::BitOr => Ok(l.with_int(a | b)),
+        BinOpKind::Eq => compare(|o| o.is_eq()),
+        BinOpKind::Neq => compare(|o| o.is_ne()),
+        BinOpKind::Gt => compare(|o| o.is_gt()),
+        BinOpKind::Lt => compare(|o| o.is_lt()),
+        BinOpKind::Gteq => compare(|o| o.is_ge()),
+        BinOpKind::Lteq => compare(|o| o.is_le()),
+        BinOpKind::LogicalAnd | BinOpKind::LogicalOr => Err(DiagnosticKind::NotConstant),
+    }
+}
@@ -0,0 +1,262 @@
+use crate::parser::ast::{Path, Span};
+
+use super::types::Type;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    UndefinedType(Path),
+    UndefinedValue(Path),
+    UndefinedField(Type, String),
+    MultipleDefinitions(Path),
+    RecursiveType(Path),
+    RecursiveConstant(Path),
+    UnsizedValue(Type),
+
+    NoMethod(Type, String),
+    NotAMethod(Path),
+    InvalidImplType(Type),
+    InvalidSelfType(Path, Type),
+
+    Mismatch {
+        expected: Type,
+        found: Type,
+    },
+    InvalidUnary(crate::parser::ast::UnaryOpKind, Type),
+    InvalidBinary(Type, crate::parser::ast::BinOpKind, Type),
+    NotCallable(Type),
+    ArgumentCount {
+        expected: usize,
+        found: usize,
+    },
+    NotIndexable(Type),
+    NotIterable(Type),
+    RangeOutsideFor,
+    InvalidLiteral(String),
+    MissingField(Path, String),
+    UndefinedLabel(String),
+    OutsideLoop(&'static str),
+    /// `continue` to a label that isn't on a loop
+    NotALoop(String),
+    MissingReturn(Type),
+    /// A block with a value from `break` whose end can be reached
+    MissingBlockValue(Type),
+    UnreachableCode,
+
+    /// Assigning to or mutably borrowing a local not declared `mut`
+    ImmutableLocal(String),
+    /// Mutating through a `&T` or `*T`
+    ImmutableReference(Type),
+    AssignConstant(Path),
+    NotAPlace,
+    UninitializedLocal(String),
+    UninitializedStatic(Path),
+
+    NotConstant,
+    ConstantOverflow,
+    DivideByZero,
+}
+
+impl std::fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticKind::UndefinedType(path) => write!(f, "cannot find type `{path}`"),
+            DiagnosticKind::UndefinedValue(path) => write!(f, "cannot find value `{path}`"),
+            DiagnosticKind::UndefinedField(ty, field) => {
+                write!(f, "no field named `{field}` on type `{ty}`")
+            }
+            DiagnosticKind::MultipleDefinitions(path) => {
+                write!(f, "`{path}` is defined multiple times")
+            }
+            DiagnosticKind::RecursiveType(path) => {
+                write!(f, "recursive type `{path}` has infinite size")
+            }
+            DiagnosticKind::RecursiveConstant(path) => {
+                write!(f, "the value of `{path}` depends on itself")
+            }
+            DiagnosticKind::UnsizedValue(ty) => {
+                write!(f, "cannot use unsized type `{ty}` as a value")
+            }
+
+            DiagnosticKind::NoMethod(ty, name) => {
+                write!(f, "no method named `{name}` on type `{ty}`")
+            }
+            DiagnosticKind::NotAMethod(path) => write!(
+                f,
+                "`{path}` is an associated function, not a method (it takes no `self` parameter)"
+            ),
+            DiagnosticKind::InvalidImplType(ty) => {
+                write!(f, "cannot define methods on type `{ty}`")
+            }
+            DiagnosticKind::InvalidSelfType(path, ty) => write!(
+                f,
+                "invalid `self` parameter type `{ty}` on `{path}`, expected `Self`, `&Self` or `&mut Self`"
+            ),
+
+            DiagnosticKind::Mismatch { expected, found } => {
+                write!(f, "mismatched types, expected `{expected}` found `{found}`")
+            }
+            DiagnosticKind::InvalidUnary(op, ty) => {
+                write!(f, "cannot apply unary `{op}` to type `{ty}`")
+            }
+            DiagnosticKind::InvalidBinary(l, op, r) => {
+                write!(f, "cannot apply `{op}` to types `{l}` and `{r}`")
+            }
+            DiagnosticKind::NotCallable(ty) => write!(f, "type `{ty}` is not callable"),
+            DiagnosticKind::ArgumentCount { expected, found } => write!(
+                f,
+                "expected {expected} argument(s) but {found} were supplied"
+            ),
+            DiagnosticKind::NotIndexable(ty) => write!(f, "cannot index into type `{ty}`"),
+            DiagnosticKind::NotIterable(ty) => write!(f, "cannot iterate over type `{ty}`"),
+            DiagnosticKind::RangeOutsideFor => {
+                write!(f, "ranges can only be used as the iterator of `for`")
+            }
+            DiagnosticKind::InvalidLiteral(lit) => write!(f, "invalid literal `{lit}`"),
+            DiagnosticKind::MissingField(path, field) => {
+                write!(f, "missing field `{field}` in initializer of `{path}`")
+            }
+            DiagnosticKind::UndefinedLabel(label) => {
+                write!(f, "use of undeclared label `{label}`")
+            }
+            DiagnosticKind::OutsideLoop(keyword) => write!(f, "`{keyword}` outside of a loop"),
+            DiagnosticKind::NotALoop(label) => {
+                write!(f, "cannot `continue` label `{label}`, it is not a loop")
+            }
+            DiagnosticKind::MissingReturn(ty) => {
+                write!(f, "function may reach its end without returning `{ty}`")
+            }
+            DiagnosticKind::MissingBlockValue(ty) => write!(
+                f,
+                "block may reach its end without a value of type `{ty}`, use `break` with a value"
+            ),
+            DiagnosticKind::UnreachableCode => write!(f, "unreachable code"),
+
+            DiagnosticKind::ImmutableLocal(name) => {
+                write!(f, "cannot mutate `{name}`, it is not declared `mut`")
+            }
+            DiagnosticKind::ImmutableReference(ty) => {
+                write!(f, "cannot mutate through `{ty}`, it is not `mut`")
+            }
+            DiagnosticKind::AssignConstant(path) => {
+                write!(f, "cannot assign to constant `{path}`")
+            }
+            DiagnosticKind::NotAPlace => {
+                write!(f, "expression cannot be assigned to or mutably borrowed")
+            }
+            DiagnosticKind::UninitializedLocal(name) => {
+                write!(f, "use of possibly uninitialized local `{name}`")
+            }
+            DiagnosticKind::UninitializedStatic(path) => write!(
+                f,
+                "static `{path}` needs an initial value unless it is declared `extern`"
+            ),
+
+            DiagnosticKind::NotConstant => write!(f, "expression is not constant"),
+            DiagnosticKind::ConstantOverflow => {
+                write!(f, "constant value does not fit in its type")
+            }
+            DiagnosticKind::DivideByZero => write!(f, "division by zero in constant"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub kind: DiagnosticKind,
+    /// The item the diagnostic was raised in
+    pub item: Option<Path>,
+    /// The innermost expression or statement it's about
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn error(kind: DiagnosticKind) -> Self {
+        Self {
+            level: Level::Error,
+            kind,
+            item: None,
+            span: None,
+        }
+    }
+
+    pub fn warning(kind: DiagnosticKind) -> Self {
+        Self {
+            level: Level::Warning,
+            kind,
+            item: None,
+            span: None,
+        }
+    }
+
+    pub fn in_item(mut self, item: &Path) -> Self {
+        if self.item.is_none() {
+            self.item = Some(item.clone());
+        }
+        self
+    }
+
+    pub fn at(mut self, span: Span) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+}
+
+impl From<DiagnosticKind> for Diagnostic {
+    fn from(value: DiagnosticKind) -> Self {
+        Self::error(value)
+    }
+}
+
+/// What checking an expression gives back when it's wrong, placed on the
+/// innermost expression or statement it's about
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckError {
+    pub kind: DiagnosticKind,
+    pub span: Option<Span>,
+}
+
+impl CheckError {
+    pub fn at(mut self, span: Span) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+}
+
+impl From<DiagnosticKind> for CheckError {
+    fn from(kind: DiagnosticKind) -> Self {
+        Self { kind, span: None }
+    }
+}
+
+impl From<CheckError> for Diagnostic {
+    fn from(err: CheckError) -> Self {
+        Self {
+            span: err.span,
+            ..Self::error(err.kind)
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.level {
+            Level::Error => write!(f, "error")?,
+            Level::Warning => write!(f, "warning")?,
+        }
+        if let Some(item) = &self.item {
+            write!(f, " in `{item}`")?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
@@ -0,0 +1,415 @@
+//! Control flow checks over function bodies.
+//!
+//! Walks a checked [`Body`] in evaluation order tracking whether the current
+//! point is reachable, which locals are initialized on every path, which reads
+//! need, and which are initialized on at least one path, which assigning an
+//! immutable local needs to rule out.
+
+use crate::parser::ast::{BinOpKind, Span};
+
+use super::{
+    constant_eval::Value,
+    error::{Diagnostic, DiagnosticKind},
+    tree::{Block, Body, Expr, ExprKind, LabelId, Local, LocalId, Stmt},
+    types::Type,
+};
+
+#[derive(Debug, Clone)]
+struct State {
+    reachable: bool,
+    /// Initialized on every path to this point
+    definite: Vec<bool>,
+    /// Initialized on at least one path to this point
+    maybe: Vec<bool>,
+}
+
+impl State {
+    fn unreachable(locals: usize) -> Self {
+        Self {
+            reachable: false,
+            definite: vec![true; locals],
+            maybe: vec![false; locals],
+        }
+    }
+
+    /// Merges the state of another path reaching the same point
+    fn join(&mut self, other: &State) {
+        if !other.reachable {
+            return;
+        }
+        if !self.reachable {
+            *self = other.clone();
+            return;
+        }
+        for (a, b) in self.definite.iter_mut().zip(&other.definite) {
+            *a &= b;
+        }
+        for (a, b) in self.maybe.iter_mut().zip(&other.maybe) {
+            *a |= b;
+        }
+    }
+
+    fn init(&mut self, id: usize) {
+        self.definite[id] = true;
+        self.maybe[id] = true;
+    }
+}
+
+struct FlowCheck<'a> {
+    locals: &'a [Local],
+    /// The joined states of every `break` to a label
+    breaks: Vec<State>,
+    /// The joined states of every `continue` to a loop
+    continues: Vec<State>,
+    errors: Vec<Diagnostic>,
+}
+
+/// Reports reads of locals that may not have been assigned yet, immutable
+/// locals that may be assigned more than once, missing returns and
+/// unreachable code
+pub fn check_body(body: &Body) -> Vec<Diagnostic> {
+    let locals = body.locals.len();
+    let mut check = FlowCheck {
+        locals: &body.locals,
+        breaks: vec![State::unreachable(locals); body.labels],
+        continues: vec![State::unreachable(locals); body.labels],
+        errors: Vec::new(),
+    };
+    let mut state = State {
+        reachable: true,
+        definite: vec![false; locals],
+        maybe: vec![false; locals],
+    };
+    for id in 0..body.params {
+        state.init(id);
+    }
+    check.stmts(&body.stmts, &mut state);
+    if state.reachable && body.ret_ty != Type::Void {
+        let err = Diagnostic::error(DiagnosticKind::MissingReturn(body.ret_ty.clone()));
+        check.errors.push(err);
+    }
+    check.errors
+}
+
+impl FlowCheck<'_> {
+    fn error(&mut self, kind: DiagnosticKind, span: Span) {
+        self.errors.push(Diagnostic::error(kind).at(span));
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt], state: &mut State) {
+        let mut warned = false;
+        for stmt in stmts {
+            if !state.reachable && !warned {
+                warned = true;
+                let mut warning = Diagnostic::warning(DiagnosticKind::UnreachableCode);
+                if let Stmt::Expr(expr) | Stmt::Let(_, Some(expr)) = stmt {
+                    warning = warning.at(expr.span);
+                }
+                self.errors.push(warning);
+            }
+            match stmt {
+                Stmt::Expr(expr) => self.expr(expr, state),
+                Stmt::Let(id, Some(value)) => {
+                    self.expr(value, state);
+                    state.init(id.0);
+                }
+                // a declaration inside a loop starts over every iteration
+                Stmt::Let(id, None) => {
+                    state.definite[id.0] = !state.reachable;
+                    state.maybe[id.0] = false;
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, state: &mut State) {
+        match &expr.kind {
+            ExprKind::Local(id) => {
+                if !state.definite[id.0] {
+                    let name = self.locals[id.0].name.clone();
+                    self.error(DiagnosticKind::UninitializedLocal(name), expr.span);
+                    // only report the first use
+                    state.init(id.0);
+                }
+            }
+            ExprKind::Static(_) | ExprKind::Function(_) | ExprKind::Value(_) => {}
+
+            ExprKind::Block(id, block) => self.block(*id, block, expr, state),
+            ExprKind::Field(inner, _)
+            | ExprKind::Unary(_, inner)
+            | ExprKind::Coerce(_, inner)
+            | ExprKind::UnionCon(_, inner) => self.expr(inner, state),
+            ExprKind::Index(base, index) => {
+                self.expr(base, state);
+                self.expr(index, state);
+            }
+            ExprKind::Call(callee, args) => {
+                self.expr(callee, state);
+                for arg in args {
+                    self.expr(arg, state);
+                }
+            }
+            ExprKind::StructCon(values) | ExprKind::ArrayCon(values) => {
+                for value in values {
+                    self.expr(value, state);
+                }
+            }
+            ExprKind::Binary(l, BinOpKind::LogicalAnd | BinOpKind::LogicalOr, r) => {
+                self.expr(l, state);
+                // the right side only runs sometimes
+                let mut right = state.clone();
+                self.expr(r, &mut right);
+                state.join(&right);
+            }
+            // the place is read before it is written
+            ExprKind::Binary(l, _, r) | ExprKind::CompoundAssign(l, _, r) => {
+                self.expr(l, state);
+                self.expr(r, state);
+            }
+            ExprKind::Assign(place, value) => {
+                let ExprKind::Local(id) = place.kind else {
+                    self.expr(place, state);
+                    self.expr(value, state);
+                    return;
+                };
+                self.expr(value, state);
+                let local = &self.locals[id.0];
+                if !local.mutable && state.maybe[id.0] {
+                    let name = local.name.clone();
+                    self.error(DiagnosticKind::ImmutableLocal(name), expr.span);
+                }
+                state.init(id.0);
+            }
+
+            ExprKind::Break(label, value) => {
+                if let Some(value) = value {
+                    self.expr(value, state);
+                }
+                self.breaks[label.0].join(state);
+                *state = State::unreachable(self.locals.len());
+            }
+            ExprKind::Continue(label) => {
+                self.continues[label.0].join(state);
+                *state = State::unreachable(self.locals.len());
+            }
+            ExprKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value, state);
+                }
+                *state = State::unreachable(self.locals.len());
+            }
+        }
+    }
+
+    /// Leaves `state` at the loop head where the condition was just checked.
+    ///
+    /// The body is walked once to find what a previous iteration may have
+    /// assigned and then again with that for the real diagnostics.
+    fn loop_body(
+        &mut self,
+        id: LabelId,
+        cond: Option<&Expr>,
+        local: Option<LocalId>,
+        stmts: &[Stmt],
+        state: &mut State,
+    ) {
+        let errors = self.errors.len();
+        let mut head = state.clone();
+        let mut body = self.iteration(cond, local, stmts, &mut head);
+        self.errors.truncate(errors);
+
+        let unreachable = State::unreachable(self.locals.len());
+        body.join(&std::mem::replace(&mut self.continues[id.0], unreachable));
+        for (maybe, assigned) in state.maybe.iter_mut().zip(&body.maybe) {
+            *maybe |= assigned;
+        }
+        self.iteration(cond, local, stmts, state);
+        self.continues[id.0] = State::unreachable(self.locals.len());
+    }
+
+    /// Returns the state at the end of the body
+    fn iteration(
+        &mut self,
+        cond: Option<&Expr>,
+        local: Option<LocalId>,
+        stmts: &[Stmt],
+        head: &mut State,
+    ) -> State {
+        if let Some(cond) = cond {
+            self.expr(cond, head);
+        }
+        let mut body = head.clone();
+        if let Some(local) = local {
+            body.init(local.0);
+        }
+        self.stmts(stmts, &mut body);
+        body
+    }
+
+    fn block(&mut self, id: LabelId, block: &Block, expr: &Expr, state: &mut State) {
+        match block {
+            Block::Scope(stmts) => self.stmts(stmts, state),
+            Block::While(cond, stmts) => {
+                self.loop_body(id, Some(cond), None, stmts, state);
+                // `while (true)` can only be left through `break`
+                if let ExprKind::Value(Value::Bool(true)) = cond.kind {
+                    *state = State::unreachable(self.locals.len());
+                }
+            }
+            Block::Loop(stmts) => {
+                self.loop_body(id, None, None, stmts, state);
+                *state = State::unreachable(self.locals.len());
+            }
+            Block::Range(local, start, end, _, stmts) => {
+                self.expr(start, state);
+                self.expr(end, state);
+                self.loop_body(id, None, Some(*local), stmts, state);
+            }
+            Block::Each(local, iter, stmts) => {
+                self.expr(iter, state);
+                self.loop_body(id, None, Some(*local), stmts, state);
+            }
+            Block::If(arms, els) => {
+                let mut out = State::unreachable(self.locals.len());
+                for (cond, stmts) in arms {
+                    self.expr(cond, state);
+                    let mut arm = state.clone();
+                    self.stmts(stmts, &mut arm);
+                    out.join(&arm);
+                }
+                // without an `else` the state where every condition was false
+                // falls through as is
+                if let Some(els) = els {
+                    self.stmts(els, state);
+                }
+                out.join(state);
+                *state = out;
+            }
+        }
+        // only `break` can give a block a value
+        if state.reachable && expr.ty != Type::Void {
+            self.error(
+                DiagnosticKind::MissingBlockValue(expr.ty.clone()),
+                expr.span,
+            );
+        }
+        let breaks = std::mem::replace(
+            &mut self.breaks[id.0],
+            State::unreachable(self.locals.len()),
+        );
+        state.join(&breaks);
+    }
+}
+
+#[cfg(test)]
+use super::check::{check_source, errors};
+
+#[test]
+fn uninitialized_reads() {
+    let program = check_source(
+        r#"
+        fn main(bool cond) i32 {
+            i32 a;
+            i32 b;
+            if (cond) { a = 1; b = 1; } else { a = 2; }
+            i32 c = a + b;
+            mut i32 d;
+            while (cond) { d = 3; }
+            i32 e = d;
+            i32 f;
+            while (true) {
+                if (cond) { f = 1; break; }
+                return 0;
+            }
+            return f;
+        }
+        "#,
+    );
+    assert_eq!(
+        errors(&program),
+        vec![
+            DiagnosticKind::UninitializedLocal("b".into()),
+            DiagnosticKind::UninitializedLocal("d".into()),
+        ]
+    );
+}
+
+#[test]
+fn immutable_assigned_once() {
+    let program = check_source(
+        r#"
+        fn main(bool cond) {
+            i32 a;
+            a = 1;
+            i32 b;
+            if (cond) { b = 1; }
+            b = 2;
+            i32 c;
+            while (cond) { c = 1; }
+            while (cond) { i32 d; d = 1; }
+            mut i32 e;
+            i32 g;
+            while (cond) {
+                e = 1;
+                if (cond) { g = e; continue; }
+            }
+        }
+        "#,
+    );
+    assert_eq!(
+        errors(&program),
+        vec![
+            DiagnosticKind::ImmutableLocal("b".into()),
+            DiagnosticKind::ImmutableLocal("c".into()),
+            DiagnosticKind::ImmutableLocal("g".into()),
+        ]
+    );
+}
+
+#[test]
+fn returns_and_reachability() {
+    let program = check_source(
+        r#"
+        fn missing(bool cond) i32 {
+            if (cond) { return 1; }
+        }
+        fn both(bool cond) i32 {
+            if (cond) { return 1; } else { return 2; }
+        }
+        fn forever() i32 {
+            while (true) { }
+        }
+        fn spin() i32 {
+            loop { }
+        }
+        fn counted(&[i32] values) i32 {
+            for (value in values) { return *value; }
+        }
+        fn dead() {
+            return;
+            i32 x = 1;
+        }
+        fn no_value(bool cond) {
+            i32 x = 'a {
+                if (cond) { break 'a 1; }
+            };
+        }
+        "#,
+    );
+    assert_eq!(
+        errors(&program),
+        vec![
+            DiagnosticKind::MissingReturn(Type::I32),
+            DiagnosticKind::MissingReturn(Type::I32),
+            DiagnosticKind::MissingBlockValue(Type::I32),
+        ]
+    );
+    let warnings: Vec<_> = program
+        .context
+        .diagnostics()
+        .iter()
+        .filter(|diagnostic| diagnostic.level == super::error::Level::Warning)
+        .map(|diagnostic| diagnostic.kind.clone())
+        .collect();
+    assert_eq!(warnings, vec![DiagnosticKind::UnreachableCode]);
+}
@@ -0,0 +1,810 @@
+//! Runs checked function bodies directly, without lowering them first.
+//!
+//! Values are [`Value`]s, except that runtime pointers are addresses: thin
+//! pointers and references are `U64`s, pointers to unsized values are a
+//! `Struct` of the address and the length and function pointers are one more
+//! than the function's id. Locals and statics live in byte addressed memory
+//! with the layout every backend gives them, so the interpreter doubles as
+//! the reference the backends are checked against.
+
+use std::{collections::HashMap, io::Write, rc::Rc};
+
+use crate::parser::ast::{BinOpKind, GlobalKind, Path, UnaryOpKind};
+
+use super::{
+    constant_eval::{apply_binop_op, apply_unary_op, Value},
+    tree::{self, Body, Coercion, Expr, ExprKind, LabelId, Stmt},
+    types::Type,
+    Context, FunctionId, FunctionKind, Resolvable, UserType,
+};
+
+/// Where statics and string literals start, nothing valid is at null
+const DATA_START: u64 = 16;
+/// Where the locals of running functions start
+const STACK_START: u64 = 1 << 32;
+const STACK_SIZE: usize = 8 * 1024 * 1024;
+/// How deep calls can nest before the interpreter gives up
+const MAX_DEPTH: usize = 4096;
+/// The native stack a thread running the interpreter needs for calls nested
+/// [`MAX_DEPTH`] deep, every level takes a few large frames
+pub const THREAD_STACK_SIZE: usize = 512 * 1024 * 1024;
+
+/// Why evaluation stopped before producing a value
+enum Unwind {
+    Break(LabelId, Value),
+    Continue(LabelId),
+    Return(Value),
+    Error(String),
+}
+
+impl From<String> for Unwind {
+    fn from(err: String) -> Self {
+        Unwind::Error(err)
+    }
+}
+
+type Eval<T = Value> = Result<T, Unwind>;
+
+/// How running the statements of a block ended
+enum Exit {
+    Done,
+    Break(Value),
+    Continue,
+}
+
+type Place = super::Place<u64>;
+
+pub struct Interpreter<'a> {
+    context: &'a mut Context,
+    bodies: HashMap<FunctionId, Rc<Body>>,
+    data: Vec<u8>,
+    stack: Vec<u8>,
+    statics: HashMap<Path, u64>,
+    strings: HashMap<String, u64>,
+    /// The address of every local of the running function
+    locals: Vec<u64>,
+    depth: usize,
+    /// Where writes to standard output go, straight through when `None`
+    pub stdout: Option<Vec<u8>>,
+}
+
+fn fat(addr: u64, len: u64) -> Value {
+    Value::Struct(vec![Value::U64(addr), Value::U64(len)])
+}
+
+fn address(value: &Value) -> u64 {
+    match value {
+        Value::U64(addr) => *addr,
+        Value::Struct(parts) => address(&parts[0]),
+        _ => unreachable!("{value} is not a pointer"),
+    }
+}
+
+/// The place a pointer points to
+fn deref(pointer: &Value) -> Place {
+    match pointer {
+        Value::Struct(parts) => Place {
+            addr: address(&parts[0]),
+            len: Some(parts[1].as_int().unwrap() as u64),
+        },
+        pointer => Place::thin(address(pointer)),
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    /// Prepares to run a program that checked without errors
+    pub fn new(context: &'a mut Context) -> Result<Self, String> {
+        let bodies = context
+            .functions()
+            .filter_map(|(id, function)| match &function.kind {
+                FunctionKind::Definition {
+                    code: Resolvable::Resolved(body),
+                    ..
+                } => Some((id, Rc::new(body.clone()))),
+                _ => None,
+            })
+            .collect();
+        let mut globals: Vec<_> = context
+            .globals()
+            .filter(|(_, var)| matches!(var.kind, GlobalKind::Static))
+            .map(|(path, var)| (path.clone(), var.clone()))
+            .collect();
+        globals.sort_by_key(|(path, _)| path.to_string());
+
+        let mut interpreter = Interpreter {
+            context,
+            bodies,
+            data: Vec::new(),
+            stack: Vec::new(),
+            statics: HashMap::new(),
+            strings: HashMap::new(),
+            locals: Vec::new(),
+            depth: 0,
+            stdout: None,
+        };
+        // every static gets its address first so they can point to each other
+        for (path, var) in &globals {
+            let layout = var.ty.layout(interpreter.context);
+            let addr = interpreter.alloc_data(layout.size_bytes(), layout.align().get());
+            interpreter.statics.insert(path.clone(), addr);
+        }
+        for (path, var) in globals {
+            if let Some(value) = &var.value {
+                let value = interpreter.runtime(value, &var.ty)?;
+                interpreter.store(interpreter.statics[&path], &var.ty, &value)?;
+            }
+        }
+        Ok(interpreter)
+    }
+
+    pub fn function(&self, path: &Path) -> Option<FunctionId> {
+        let path = Some(path.clone());
+        self.context
+            .functions()
+            .find(|(_, function)| function.sig.name == path)
+            .map(|(id, _)| id)
+    }
+
+    /// Runs the program's `main`, giving what it returns
+    pub fn run_main(&mut self) -> Result<Value, String> {
+        let id = self
+            .function(&Path::new_path("main"))
+            .ok_or("there is no function `main`")?;
+        self.call(id, Vec::new())
+    }
+
+    pub fn call(&mut self, id: FunctionId, args: Vec<Value>) -> Result<Value, String> {
+        match self.invoke(id, args) {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(err)) => Err(err),
+            Err(Unwind::Break(..) | Unwind::Continue(_)) => {
+                unreachable!("labels never leave their function")
+            }
+        }
+    }
+
+    fn alloc_data(&mut self, size: usize, align: usize) -> u64 {
+        let start = (DATA_START as usize + self.data.len()).next_multiple_of(align);
+        self.data.resize(start + size - DATA_START as usize, 0);
+        start as u64
+    }
+
+    fn alloc_stack(&mut self, ty: &Type) -> Result<u64, String> {
+        let layout = ty.layout(self.context);
+        let start = self.stack.len().next_multiple_of(layout.align().get());
+        if start + layout.size_bytes() > STACK_SIZE {
+            return Err("stack overflow".to_string());
+        }
+        self.stack.resize(start + layout.size_bytes(), 0);
+        Ok(STACK_START + start as u64)
+    }
+
+    fn bytes(&mut self, addr: u64, size: usize) -> Result<&mut [u8], String> {
+        let (memory, start) = match addr {
+            STACK_START.. => (&mut self.stack, addr - STACK_START),
+            DATA_START.. => (&mut self.data, addr - DATA_START),
+            _ => (&mut self.data, u64::MAX),
+        };
+        let start = usize::try_from(start).unwrap_or(usize::MAX);
+        memory
+            .get_mut(start..start.saturating_add(size))
+            .ok_or_else(|| format!("out of bounds memory access of {size} bytes at {addr:#x}"))
+    }
+
+    /// The address of a string literal, followed by a nul byte that isn't
+    /// part of its length for C functions
+    fn string(&mut self, value: &str) -> u64 {
+        if let Some(addr) = self.strings.get(value) {
+            return *addr;
+        }
+        let addr = self.alloc_data(value.len() + 1, 1);
+        let start = (addr - DATA_START) as usize;
+        self.data[start..start + value.len()].copy_from_slice(value.as_bytes());
+        self.strings.insert(value.to_string(), addr);
+        addr
+    }
+
+    /// Turns a compile time value into its runtime form
+    fn runtime(&mut self, value: &Value, ty: &Type) -> Result<Value, String> {
+        Ok(match value {
+            Value::Str(value) => fat(self.string(value), value.len() as u64),
+            Value::Ref(path) | Value::Ptr(path) => match self.statics.get(path) {
+                Some(addr) => Value::U64(*addr),
+                None => return Err(format!("`{path}` can't be referred to by address")),
+            },
+            Value::Array(values) => {
+                let element = ty.element().unwrap();
+                let values = values.iter().map(|value| self.runtime(value, element));
+                Value::Array(values.collect::<Result<_, _>>()?)
+            }
+            Value::Struct(values) => {
+                let members = self.context.members(ty);
+                let values = values.iter().zip(members);
+                let values = values.map(|(value, member)| self.runtime(value, &member.ty));
+                Value::Struct(values.collect::<Result<_, _>>()?)
+            }
+            Value::Union(member, value) => {
+                let ty = self.context.members(ty).swap_remove(*member).ty;
+                Value::Union(*member, Box::new(self.runtime(value, &ty)?))
+            }
+            value => value.clone(),
+        })
+    }
+
+    fn load(&mut self, addr: u64, ty: &Type) -> Result<Value, String> {
+        let size = ty.layout(self.context).size_bytes().min(8);
+        let int = |interpreter: &mut Self| -> Result<u64, String> {
+            let mut bytes = [0; 8];
+            bytes[..size].copy_from_slice(interpreter.bytes(addr, size)?);
+            Ok(u64::from_le_bytes(bytes))
+        };
+        Ok(match ty {
+            Type::Void => Value::Void(()),
+            Type::Int(..) => Value::int_wrapping(ty, int(self)? as i128).unwrap(),
+            Type::Float(_) if size == 4 => Value::F32(f32::from_bits(int(self)? as u32)),
+            Type::Float(_) => Value::F64(f64::from_bits(int(self)?)),
+            Type::Bool => Value::Bool(int(self)? != 0),
+            Type::Char => Value::Char(int(self)? as u8 as char),
+            Type::FnPointer(..) => Value::U64(int(self)?),
+            Type::Ptr(inner, _) | Type::Ref(inner, _) if inner.is_sized(self.context) => {
+                Value::U64(int(self)?)
+            }
+            Type::Ptr(..) | Type::Ref(..) => {
+                let len = self.load(addr + 8, &Type::USIZE)?;
+                fat(int(self)?, len.as_int().unwrap() as u64)
+            }
+            Type::ArrayStatic(element, len) => {
+                let size = element.layout(self.context).size_bytes() as u64;
+                let values = (0..*len as u64).map(|i| self.load(addr + i * size, element));
+                Value::Array(values.collect::<Result<_, _>>()?)
+            }
+            Type::Nammed(path) => match self.context.user_type(path) {
+                Some(UserType::Enum(_)) => Value::Enum(int(self)? as usize),
+                Some(UserType::Struct(_)) => {
+                    let members = self.context.members(ty).into_iter();
+                    let values =
+                        members.map(|member| self.load(addr + member.offset as u64, &member.ty));
+                    Value::Struct(values.collect::<Result<_, _>>()?)
+                }
+                // which member was written last isn't known, the largest
+                // one keeps all of the bytes
+                _ => {
+                    let members = self.context.members(ty);
+                    let mut largest = 0;
+                    let mut largest_size = 0;
+                    for (i, member) in members.iter().enumerate() {
+                        let size = member.ty.layout(self.context).size_bytes();
+                        if size > largest_size {
+                            (largest, largest_size) = (i, size);
+                        }
+                    }
+                    let value = self.load(addr, &members[largest].ty)?;
+                    Value::Union(largest, Box::new(value))
+                }
+            },
+            Type::Str | Type::Array(_) => unreachable!("{ty} is unsized"),
+        })
+    }
+
+    fn store(&mut self, addr: u64, ty: &Type, value: &Value) -> Result<(), String> {
+        let size = ty.layout(self.context).size_bytes();
+        match value {
+            Value::Void(()) => {}
+            Value::F32(value) => self.bytes(addr, 4)?.copy_from_slice(&value.to_le_bytes()),
+            Value::F64(value) => self.bytes(addr, 8)?.copy_from_slice(&value.to_le_bytes()),
+            Value::Struct(values) if ty.pointee().is_some() => {
+                self.store(addr, &Type::USIZE, &values[0])?;
+                self.store(addr + 8, &Type::USIZE, &values[1])?;
+            }
+            Value::Struct(values) => {
+                let members = self.context.members(ty);
+                for (value, member) in values.iter().zip(members) {
+                    self.store(addr + member.offset as u64, &member.ty, value)?;
+                }
+            }
+            Value::Union(member, value) => {
+                let ty = self.context.members(ty).swap_remove(*member).ty;
+                self.store(addr, &ty, value)?;
+            }
+            Value::Array(values) => {
+                let element = ty.element().unwrap();
+                let size = element.layout(self.context).size_bytes() as u64;
+                for (i, value) in values.iter().enumerate() {
+                    self.store(addr + i as u64 * size, element, value)?;
+                }
+            }
+            Value::Str(_) | Value::Ref(_) | Value::Ptr(_) => {
+                unreachable!("{value} is only known at compile time")
+            }
+            _ => {
+                let bits = value.as_int().unwrap() as u64;
+                self.bytes(addr, size)?
+                    .copy_from_slice(&bits.to_le_bytes()[..size]);
+            }
+        }
+        Ok(())
+    }
+
+    fn invoke(&mut self, id: FunctionId, args: Vec<Value>) -> Eval {
+        let Some(body) = self.bodies.get(&id).cloned() else {
+            return self.builtin(id, args);
+        };
+        if self.depth == MAX_DEPTH {
+            return Err(Unwind::Error("stack overflow".to_string()));
+        }
+
+        let mark = self.stack.len();
+        let mut locals = Vec::new();
+        for local in &body.locals {
+            locals.push(self.alloc_stack(&local.ty)?);
+        }
+        for ((addr, local), arg) in locals.iter().zip(&body.locals).zip(&args) {
+            self.store(*addr, &local.ty, arg)?;
+        }
+        let caller = std::mem::replace(&mut self.locals, locals);
+        self.depth += 1;
+        let result = self.stmts(&body.stmts);
+        self.depth -= 1;
+        self.locals = caller;
+        self.stack.truncate(mark);
+        match result {
+            Ok(()) => Ok(Value::Void(())),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Emulates the few C functions programs commonly declare
+    fn builtin(&mut self, id: FunctionId, args: Vec<Value>) -> Eval {
+        let name = self
+            .context
+            .function(id)
+            .sig
+            .name
+            .clone()
+            .unwrap_or_default();
+        let int = |value: &Value| value.as_int().unwrap();
+        match (name.last().unwrap_or_default(), &args[..]) {
+            ("write", [fd, data, len]) => {
+                let len = int(len) as usize;
+                let bytes = self.bytes(address(data), len)?.to_vec();
+                let written = match (int(fd), &mut self.stdout) {
+                    (1, Some(stdout)) => {
+                        stdout.extend_from_slice(&bytes);
+                        Ok(())
+                    }
+                    (1, None) => std::io::stdout().write_all(&bytes),
+                    (2, _) => std::io::stderr().write_all(&bytes),
+                    _ => Err(std::io::ErrorKind::NotFound.into()),
+                };
+                let written = match written {
+                    Ok(()) => len as i128,
+                    Err(_) => -1,
+                };
+                let ty = self.context.function(id).sig.ret_ty.clone();
+                Ok(Value::int_wrapping(&ty, written).unwrap())
+            }
+            ("strlen", [data]) => {
+                let start = address(data);
+                let mut len = 0;
+                while self.bytes(start + len, 1)?[0] != 0 {
+                    len += 1;
+                }
+                Ok(Value::U64(len))
+            }
+            _ => Err(Unwind::Error(format!(
+                "`{name}` is defined outside of the program, which the interpreter can't call"
+            ))),
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> Eval<()> {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Expr(expr) => {
+                    self.expr(expr)?;
+                }
+                Stmt::Let(id, Some(value)) => {
+                    let value_ = self.expr(value)?;
+                    self.store(self.locals[id.0], &value.ty, &value_)?;
+                }
+                Stmt::Let(_, None) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the statements of a block, catching what leaves it
+    fn body(&mut self, id: LabelId, stmts: &[Stmt]) -> Eval<Exit> {
+        match self.stmts(stmts) {
+            Ok(()) => Ok(Exit::Done),
+            Err(Unwind::Break(label, value)) if label == id => Ok(Exit::Break(value)),
+            Err(Unwind::Continue(label)) if label == id => Ok(Exit::Continue),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn cond(&mut self, expr: &Expr) -> Eval<bool> {
+        Ok(self.expr(expr)?.as_bool().unwrap())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Eval {
+        match &expr.kind {
+            ExprKind::Local(_) | ExprKind::Static(_) | ExprKind::Unary(UnaryOpKind::Deref, _) => {
+                let place = self.place(expr)?;
+                Ok(self.load(place.addr, &expr.ty)?)
+            }
+            ExprKind::Field(base, member) if !base.is_place() => {
+                match (self.expr(base)?, &base.ty) {
+                    (Value::Struct(mut values), _) => Ok(values.swap_remove(*member)),
+                    (Value::Union(written, value), _) if written == *member => Ok(*value),
+                    // reading another member reinterprets the bytes
+                    (value, ty) => {
+                        let addr = self.alloc_stack(ty)?;
+                        self.store(addr, ty, &value)?;
+                        Ok(self.load(addr, &expr.ty)?)
+                    }
+                }
+            }
+            ExprKind::Field(..) | ExprKind::Index(..) => {
+                let place = self.place(expr)?;
+                Ok(self.load(place.addr, &expr.ty)?)
+            }
+            ExprKind::Function(id) => Ok(Value::U64(id.0 as u64 + 1)),
+            ExprKind::Value(value) => Ok(self.runtime(value, &expr.ty)?),
+            ExprKind::Block(id, block) => self.block(*id, block),
+            ExprKind::Call(callee, args) => {
+                let id = match &callee.kind {
+                    ExprKind::Function(id) => *id,
+                    _ => match self.expr(callee)? {
+                        Value::U64(index) if index > 0 => FunctionId(index as usize - 1),
+                        _ => return Err(Unwind::Error("called a null function pointer".into())),
+                    },
+                };
+                let args = args
+                    .iter()
+                    .map(|arg| self.expr(arg))
+                    .collect::<Result<_, _>>()?;
+                self.invoke(id, args)
+            }
+
+            ExprKind::Unary(UnaryOpKind::Ref | UnaryOpKind::RefMut, inner) => {
+                let place = self.place(inner)?;
+                Ok(match place.len {
+                    Some(len) => fat(place.addr, len),
+                    None => Value::U64(place.addr),
+                })
+            }
+            ExprKind::Unary(op, inner) => {
+                let value = self.expr(inner)?;
+                apply_unary_op(value, *op).map_err(|err| Unwind::Error(err.to_string()))
+            }
+            ExprKind::Binary(l, op @ (BinOpKind::LogicalAnd | BinOpKind::LogicalOr), r) => {
+                let l = self.cond(l)?;
+                if l == (*op == BinOpKind::LogicalOr) {
+                    return Ok(Value::Bool(l));
+                }
+                Ok(Value::Bool(self.cond(r)?))
+            }
+            ExprKind::Binary(l, op, r) => {
+                let l = self.expr(l)?;
+                let r = self.expr(r)?;
+                self.arith(l, *op, r)
+            }
+            ExprKind::Assign(place, value) => {
+                let place_ = self.place(place)?;
+                let value = self.expr(value)?;
+                self.store(place_.addr, &place.ty, &value)?;
+                Ok(Value::Void(()))
+            }
+            ExprKind::CompoundAssign(place, op, value) => {
+                let addr = self.place(place)?.addr;
+                let value = self.expr(value)?;
+                let current = self.load(addr, &place.ty)?;
+                let result = self.arith(current, *op, value)?;
+                self.store(addr, &place.ty, &result)?;
+                Ok(Value::Void(()))
+            }
+            ExprKind::Coerce(coercion, inner) => {
+                let value = self.expr(inner)?;
+                Ok(match (coercion, value) {
+                    (Coercion::Unsize(len), value) => fat(address(&value), *len as u64),
+                    (Coercion::DataPtr, value) => Value::U64(address(&value)),
+                    (Coercion::RefToPtr | Coercion::Immutable, value) => value,
+                })
+            }
+
+            ExprKind::StructCon(values) => {
+                let values = values.iter().map(|value| self.expr(value));
+                Ok(Value::Struct(values.collect::<Result<_, _>>()?))
+            }
+            ExprKind::UnionCon(member, value) => {
+                Ok(Value::Union(*member, Box::new(self.expr(value)?)))
+            }
+            ExprKind::ArrayCon(values) => {
+                let values = values.iter().map(|value| self.expr(value));
+                Ok(Value::Array(values.collect::<Result<_, _>>()?))
+            }
+
+            ExprKind::Break(label, value) => {
+                let value = match value {
+                    Some(value) => self.expr(value)?,
+                    None => Value::Void(()),
+                };
+                Err(Unwind::Break(*label, value))
+            }
+            ExprKind::Continue(label) => Err(Unwind::Continue(*label)),
+            ExprKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expr(value)?,
+                    None => Value::Void(()),
+                };
+                Err(Unwind::Return(value))
+            }
+        }
+    }
+
+    fn arith(&mut self, l: Value, op: BinOpKind, r: Value) -> Eval {
+        apply_binop_op(l, op, r).map_err(|err| match err {
+            super::error::DiagnosticKind::DivideByZero => {
+                Unwind::Error("division by zero".to_string())
+            }
+            err => Unwind::Error(err.to_string()),
+        })
+    }
+
+    /// The memory location of an expression, values that aren't places are
+    /// stored to a temporary first
+    fn place(&mut self, expr: &Expr) -> Eval<Place> {
+        match &expr.kind {
+            ExprKind::Local(id) => Ok(Place::thin(self.locals[id.0])),
+            ExprKind::Static(path) => match self.statics.get(path) {
+                Some(addr) => Ok(Place::thin(*addr)),
+                None => Err(Unwind::Error(format!(
+                    "`{path}` is defined outside of the program, which the interpreter can't \
+                     refer to"
+                ))),
+            },
+            ExprKind::Unary(UnaryOpKind::Deref, inner) => Ok(deref(&self.expr(inner)?)),
+            ExprKind::Field(base, member) => {
+                let place = self.place(base)?;
+                let offset = self.context.members(&base.ty)[*member].offset;
+                Ok(Place {
+                    addr: place.addr + offset as u64,
+                    len: place.len,
+                })
+            }
+            ExprKind::Index(base, index) => {
+                let place = self.place(base)?;
+                let index = self.expr(index)?.as_int().unwrap();
+                let len = match &base.ty {
+                    Type::ArrayStatic(_, len) => *len as u64,
+                    _ => place.len.unwrap(),
+                };
+                if index < 0 || index >= len as i128 {
+                    return Err(Unwind::Error(format!(
+                        "index {index} is out of bounds of an array of {len} elements"
+                    )));
+                }
+                let size = expr.ty.layout(self.context).size_bytes() as u64;
+                Ok(Place::thin(place.addr + index as u64 * size))
+            }
+            _ => {
+                let value = self.expr(expr)?;
+                let addr = self.alloc_stack(&expr.ty)?;
+                self.store(addr, &expr.ty, &value)?;
+                Ok(Place::thin(addr))
+            }
+        }
+    }
+
+    fn block(&mut self, id: LabelId, block: &tree::Block) -> Eval {
+        match block {
+            tree::Block::Scope(stmts) => match self.body(id, stmts)? {
+                Exit::Break(value) => Ok(value),
+                _ => Ok(Value::Void(())),
+            },
+            tree::Block::If(arms, els) => {
+                for (cond, stmts) in arms {
+                    if self.cond(cond)? {
+                        return match self.body(id, stmts)? {
+                            Exit::Break(value) => Ok(value),
+                            _ => Ok(Value::Void(())),
+                        };
+                    }
+                }
+                match els {
+                    Some(stmts) => match self.body(id, stmts)? {
+                        Exit::Break(value) => Ok(value),
+                        _ => Ok(Value::Void(())),
+                    },
+                    None => Ok(Value::Void(())),
+                }
+            }
+            tree::Block::While(cond, stmts) => {
+                while self.cond(cond)? {
+                    if let Exit::Break(value) = self.body(id, stmts)? {
+                        return Ok(value);
+                    }
+                }
+                Ok(Value::Void(()))
+            }
+            tree::Block::Loop(stmts) => loop {
+                if let Exit::Break(value) = self.body(id, stmts)? {
+                    return Ok(value);
+                }
+            },
+            tree::Block::Range(local, start, end, inclusive, stmts) => {
+                let ty = &start.ty;
+                let counter = self.locals[local.0];
+                let start = self.expr(start)?;
+                let end = self.expr(end)?.as_int().unwrap();
+                self.store(counter, ty, &start)?;
+                let start = start.as_int().unwrap();
+                if start > end || (start == end && !inclusive) {
+                    return Ok(Value::Void(()));
+                }
+                loop {
+                    if let Exit::Break(value) = self.body(id, stmts)? {
+                        return Ok(value);
+                    }
+                    let current = self.load(counter, ty)?.as_int().unwrap();
+                    let next = Value::int_wrapping(ty, current + 1).unwrap();
+                    let last = Context::range_last(*inclusive, current, next.as_int().unwrap());
+                    if last == end {
+                        return Ok(Value::Void(()));
+                    }
+                    self.store(counter, ty, &next)?;
+                }
+            }
+            tree::Block::Each(local, iter, stmts) => {
+                let by_ref = iter.ty.pointee().is_some();
+                let (place, ty) = match &iter.ty {
+                    Type::Ref(inner, _) | Type::Ptr(inner, _) => {
+                        let pointer = self.expr(iter)?;
+                        (deref(&pointer), &**inner)
+                    }
+                    ty => (self.place(iter)?, ty),
+                };
+                let (element, len) = match ty {
+                    Type::ArrayStatic(element, len) => (&**element, *len as u64),
+                    Type::Array(element) => (&**element, place.len.unwrap()),
+                    _ => unreachable!("{ty} is not an array"),
+                };
+                let size = element.layout(self.context).size_bytes() as u64;
+                let addr = self.locals[local.0];
+                for i in 0..len {
+                    let item = place.addr + i * size;
+                    if by_ref {
+                        self.store(addr, &Type::USIZE, &Value::U64(item))?;
+                    } else {
+                        let value = self.load(item, element)?;
+                        self.store(addr, element, &value)?;
+                    }
+                    if let Exit::Break(value) = self.body(id, stmts)? {
+                        return Ok(value);
+                    }
+                }
+                Ok(Value::Void(()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn interpret(src: &str) -> (Result<Value, String>, Vec<u8>) {
+    interpret_with(src, |interpreter| interpreter.run_main())
+}
+
+#[cfg(test)]
+fn interpret_with(
+    src: &str,
+    run: impl FnOnce(&mut Interpreter) -> Result<Value, String> + Send,
+) -> (Result<Value, String>, Vec<u8>) {
+    let mut program = super::check::checked_source(src);
+    std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new().stack_size(THREAD_STACK_SIZE);
+        let thread = thread.spawn_scoped(scope, || {
+            let mut interpreter = Interpreter::new(&mut program.context).unwrap();
+            interpreter.stdout = Some(Vec::new());
+            let result = run(&mut interpreter);
+            (result, interpreter.stdout.unwrap())
+        });
+        thread.unwrap().join().unwrap()
+    })
+}
+#[test]
+fn interpret_program() {
+    let (result, stdout) = interpret(crate::backend::x86_64::PROGRAM);
+    assert_eq!(result, Ok(Value::I32(8)));
+    assert_eq!(stdout, b"hello\n");
+}
+
+#[test]
+fn matches_backends() {
+    let src = r#"
+        enum Color { Red, Green, Blue, }
+        union Bits { u32 int, f32 float, }
+        struct Pair { u8 a, u64 b, }
+
+        fn swap(Pair pair) Pair { return Pair { a = 7, b = pair.b + 1u64 }; }
+        fn twice(u64 value) u64 { return value * 2u64; }
+        fn apply(fn(u64) u64 f, u64 value) u64 { return f(value); }
+
+        fn main() u64 {
+            mut u64 total = 0;
+            for (i in 0..=4) { total += 1u64 << i; }
+            Bits bits = Bits { float = 1.0 };
+            if (bits.int == 1065353216u32) { total += 100u64; }
+            Color color = Color::Blue;
+            if (color != Color::Green) { total += 1000u64; }
+            if (-7i16 >> 1u8 == -4i16) { total += 10000u64; }
+            Pair pair = swap(Pair { a = 1, b = 41 });
+            u64 found = 'search {
+                mut u64 n = 0;
+                while (true) {
+                    n += 1u64;
+                    if (n * n > 50u64) { break 'search n; }
+                }
+                break 'search 0u64;
+            };
+            u8 byte = 250;
+            &str text = "bc";
+            if (text == "bc" && byte + 10u8 == 4u8) { total += 200000u64; }
+            if (pair.a == 7u8) { total += 7000000u64; }
+            return total + pair.b + found + apply(twice, 3u64);
+        }
+    "#;
+    let expected = 7_000_000 + 200_000 + 10_000 + 1000 + 100 + 31 + 42 + 8 + 6;
+    assert_eq!(interpret(src).0, Ok(Value::U64(expected)));
+
+    let mut module = crate::ir::lower::lower_source(src);
+    crate::ir::opt::optimize(&mut module, crate::ir::opt::OptLevel::O2);
+    let mut program = crate::backend::vm::compile(&module).unwrap();
+    let result = program.call("main").unwrap();
+    assert_eq!(result, Some(crate::backend::vm::Value::U64(expected)));
+}
+
+#[test]
+fn arrays() {
+    let src = r#"
+        fn sum(&[i16] values) i16 {
+            mut i16 total = 0;
+            for (value in values) { total += *value; }
+            return total;
+        }
+        fn total(mut [i16; 4] values) i16 {
+            values[3] = 10;
+            mut i16 copies = 0;
+            for (value in values) { copies += value; }
+            return sum(&values) * copies;
+        }
+        fn at([u8; 2] bytes, usize i) u8 { return bytes[i]; }
+        "#;
+    let values = [1, -2, 3, -4].into_iter().map(Value::I16).collect();
+    let (result, _) = interpret_with(src, |interpreter| {
+        let total = interpreter.function(&Path::new_path("total")).unwrap();
+        interpreter.call(total, vec![Value::Array(values)])
+    });
+    assert_eq!(result, Ok(Value::I16(144)));
+
+    let (result, _) = interpret_with(src, |interpreter| {
+        let at = interpreter.function(&Path::new_path("at")).unwrap();
+        let bytes = Value::Array(vec![Value::U8(1), Value::U8(2)]);
+        interpreter.call(at, vec![bytes, Value::U64(2)])
+    });
+    assert_eq!(
+        result,
+        Err("index 2 is out of bounds of an array of 2 elements".to_string())
+    );
+}
+
+#[test]
+fn runtime_errors() {
+    let (result, _) = interpret("fn main() i32 { mut i32 zero = 0; return 1 / zero; }");
+    assert_eq!(result, Err("division by zero".to_string()));
+    let (result, _) = interpret(
+        "fn down(u64 n) u64 { return down(n + 1u64); } fn main() u64 { return down(0u64); }",
+    );
+    assert_eq!(result, Err("stack overflow".to_string()));
+}
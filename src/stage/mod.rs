@@ -1,16 +1,26 @@
-use std::{collections::{HashMap, HashSet}, num::NonZeroUsize};
+use std::{collections::HashMap, num::NonZeroUsize};
 
+use constant_eval::Value;
+use error::{Diagnostic, DiagnosticKind, Level};
 use types::{Layout, Type};
 
 use super::parser::ast::Type as UnresolvedType;
 
-use crate::parser::ast::{Expression, FunctionDef, FunctionHeader, Module, Path, Statement};
+use crate::parser::ast::{
+    FunctionDef, FunctionHeader, GlobalDef, GlobalKind, ImplDef, Module, Path, Span, Statement,
+    TopLevelDef, Vis,
+};
 
+pub mod check;
+pub mod constant_eval;
+pub mod error;
+pub mod flow;
+pub mod interpret;
 pub mod scope;
+pub mod tree;
 pub mod types;
-pub mod constant_eval;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StructMember {
     pub offset: usize,
     pub name: String,
@@ -52,95 +62,214 @@ pub enum UserType {
     Struct(Struct),
     Union(Union),
     Enum(Enum),
+    /// The definition and the module it was defined in
+    Unresolved(Path, TopLevelDef),
     _Processing,
 }
 
-pub enum Resolvable<R, U>{
+pub enum Resolvable<R, U> {
     Resolved(R),
     Unresolved(U),
 }
 
+#[derive(Debug, Clone)]
+pub struct GlobalVar {
+    pub vis: Vis,
+    pub kind: GlobalKind,
+    pub ty: Type,
+    pub value: Option<Value>,
+}
+
 pub enum Global {
-    Constant(Resolvable<(Type, ConstantId), (UnresolvedType, UnresolvedConstantId)>),
-    Static(Option<Resolvable<ConstantId, UnresolvedConstantId>>),
+    Variable(Resolvable<GlobalVar, Box<(Path, GlobalDef)>>),
     Function(FunctionId),
-    
+
     Resolving,
 }
 
 #[derive(Default)]
-pub struct Context{
+pub struct Context {
     type_map: TypeMap,
     globals: HashMap<Path, Global>,
-    constants: Vec<Constant>,
-    unresolved_constants: Vec<Constant>,
-    functions: Vec<(Resolvable<FunctionSig, UnresolvedFunctionSig>, FunctionKind)>,
-}
-
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-pub struct ConstantId(usize);
-
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-pub struct UnresolvedConstantId(usize);
-
-
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-pub struct FunctionId(usize);
-
-pub enum ConstantKind{
-    Constant,
-    UnNamedArraySize,
-    StaticInitialization,
-}
-
-pub enum ConstantValue{
-    Resolved(constant_eval::Value),
-    Unresolved(Expression),
+    functions: Vec<Function>,
+    methods: HashMap<Type, HashMap<String, FunctionId>>,
+    diagnostics: Vec<Diagnostic>,
 }
 
-pub struct Constant{
-    kind: ConstantKind,
-    value: ConstantValue,
-}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FunctionId(pub usize);
 
 #[derive(Default)]
 pub struct Program {
     pub context: Context,
+    pending_functions: Vec<(Path, TopLevelDef)>,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct FunctionSig {
-    name: Option<Path>,
-    ret_ty: Type,
-    params: Vec<(Type, String)>,
+    pub name: Option<Path>,
+    pub ret_ty: Type,
+    pub params: Vec<(Type, String)>,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
-pub struct UnresolvedFunctionSig {
-    name: Option<Path>,
-    ret_ty: UnresolvedType,
-    params: Vec<(UnresolvedType, String)>,
+impl FunctionSig {
+    pub fn fn_type(&self) -> Type {
+        Type::FnPointer(
+            self.params.iter().map(|(ty, _)| ty.clone()).collect(),
+            Some(Box::new(self.ret_ty.clone())),
+        )
+    }
+
+    /// Whether this function takes a `self` parameter
+    pub fn is_method(&self) -> bool {
+        self.params.first().is_some_and(|(_, name)| name == "self")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UnresolvedBody {
+    pub module: Path,
+    pub self_ty: Option<Type>,
+    /// Which parameters were declared `mut`
+    pub mutable_params: Vec<bool>,
+    pub stmts: Vec<Statement>,
 }
 
 pub enum FunctionKind {
     Declaration(String),
     Definition {
         external: Option<String>,
-        code: Vec<Statement>,
+        code: Resolvable<tree::Body, UnresolvedBody>,
     },
 }
 
+pub struct Function {
+    pub vis: Vis,
+    pub sig: FunctionSig,
+    pub kind: FunctionKind,
+}
+
+/// A memory location, unsized places also carry their length
+pub struct Place<A> {
+    pub addr: A,
+    pub len: Option<A>,
+}
+
+impl<A> Place<A> {
+    pub fn thin(addr: A) -> Self {
+        Self { addr, len: None }
+    }
+}
+
 #[derive(Default)]
 pub struct TypeMap {
     pub types: HashMap<Path, UserType>,
 }
 
-impl Context{
+impl Context {
     pub fn get_type(&mut self, path: &Path) -> (Layout, &UserType) {
         (self.layout(path), self.type_map.types.get(path).unwrap())
     }
 
+    pub fn user_type(&self, path: &Path) -> Option<&UserType> {
+        self.type_map.types.get(path)
+    }
+
+    pub fn user_types(&self) -> impl Iterator<Item = (&Path, &UserType)> {
+        self.type_map.types.iter()
+    }
+
+    pub fn function(&self, id: FunctionId) -> &Function {
+        &self.functions[id.0]
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = (FunctionId, &Function)> {
+        self.functions
+            .iter()
+            .enumerate()
+            .map(|(id, func)| (FunctionId(id), func))
+    }
+
+    /// Every member of a struct or union, union members are all at offset 0
+    pub fn members(&mut self, ty: &Type) -> Vec<StructMember> {
+        let Type::Nammed(path) = ty else {
+            unreachable!("{ty} has no members")
+        };
+        match self.get_type(path).1 {
+            UserType::Struct(struc) => struc.members.clone(),
+            UserType::Union(unio) => unio
+                .members
+                .iter()
+                .map(|member| StructMember {
+                    offset: 0,
+                    name: member.name.clone(),
+                    ty: member.ty.clone(),
+                })
+                .collect(),
+            _ => unreachable!("{ty} has no members"),
+        }
+    }
+
+    /// What a range loop compares with its end after each iteration, given
+    /// the counter and the counter plus one. It stops after the last value
+    /// without stepping past it so inclusive ranges up to the maximum don't
+    /// overflow
+    pub fn range_last<T>(inclusive: bool, current: T, next: T) -> T {
+        match inclusive {
+            true => current,
+            false => next,
+        }
+    }
+
+    pub fn method(&self, ty: &Type, name: &str) -> Option<FunctionId> {
+        self.methods.get(ty)?.get(name).copied()
+    }
+
+    pub fn global(&self, path: &Path) -> Option<&Global> {
+        self.globals.get(path)
+    }
+
+    pub fn globals(&self) -> impl Iterator<Item = (&Path, &GlobalVar)> {
+        self.globals
+            .iter()
+            .filter_map(|(path, global)| match global {
+                Global::Variable(Resolvable::Resolved(var)) => Some((path, var)),
+                _ => None,
+            })
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.level == Level::Error)
+    }
+
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic)
+    }
+
+    /// The evaluated value of a global, resolving it first if needed
+    pub fn global_value(&mut self, path: &Path) -> Option<&Value> {
+        self.resolve_global(path)?.value.as_ref()
+    }
+
+    /// The layout of a user type if it has been worked out already, unlike
+    /// [`Context::layout`] this never resolves anything
+    pub fn computed_layout(&self, path: &Path) -> Option<Layout> {
+        match self.type_map.types.get(path)? {
+            UserType::Struct(Struct { layout, .. })
+            | UserType::Union(Union { layout, .. })
+            | UserType::Enum(Enum { layout, .. }) => *layout,
+            _ => None,
+        }
+    }
+
     pub fn layout(&mut self, path: &Path) -> Layout {
+        self.resolve_user_type(path);
         let ty = match self.type_map.types.get_mut(path) {
             Some(some) => some,
             None => panic!("type {path:?} not defined"),
@@ -176,7 +305,12 @@ impl Context{
                         sized &= ty_layout.is_sized();
                     }
 
-                    let layout = Layout::new_nonzero(size, align).unwrap();
+                    // pad the end so arrays of this struct stay aligned, like C
+                    let layout = if sized {
+                        Layout::new_nonzero(size, align).unwrap().align_size()
+                    } else {
+                        Layout::new_nonzero_unsized(size, align).unwrap()
+                    };
 
                     struc.layout = Some(layout);
                     *self.type_map.types.get_mut(path).unwrap() = UserType::Struct(struc);
@@ -223,8 +357,7 @@ impl Context{
                         0..=0xFF => Layout::new(1, 1).unwrap(),
                         0..=0xFFFF => Layout::new(2, 2).unwrap(),
                         0..=0xFFFFFFFF => Layout::new(4, 4).unwrap(),
-                        0..=0xFFFFFFFFFFFFFFFF => Layout::new(8, 8).unwrap(),
-                        _ => panic!("Enum {path:?} has too many varients!"),
+                        _ => Layout::new(8, 8).unwrap(),
                     };
 
                     enu.layout = Some(layout);
@@ -234,191 +367,610 @@ impl Context{
                 }
             }
 
-            UserType::_Processing => {
+            UserType::Unresolved(..) | UserType::_Processing => {
                 panic!("Recursive Type!")
             }
         }
     }
 
-    fn resolve_constant(&mut self, id: ConstantId){
+    /// Looks up a user type relative to `module` first and then as an absolute path
+    pub fn find_type(&self, module: &Path, path: &Path) -> Option<Path> {
+        let relative = module.join(path);
+        if self.type_map.types.contains_key(&relative) {
+            Some(relative)
+        } else if self.type_map.types.contains_key(path) {
+            Some(path.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Looks up a global relative to `module` first and then as an absolute path
+    pub fn find_global(&self, module: &Path, path: &Path) -> Option<Path> {
+        let relative = module.join(path);
+        if self.globals.contains_key(&relative) {
+            Some(relative)
+        } else if self.globals.contains_key(path) {
+            Some(path.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn resolve_type(
+        &mut self,
+        ty: &UnresolvedType,
+        module: &Path,
+        self_ty: Option<&Type>,
+    ) -> Result<Type, DiagnosticKind> {
+        Ok(match ty {
+            UnresolvedType::Int(size, signed) => Type::Int(size.clone(), *signed),
+            UnresolvedType::Float(float) => Type::Float(float.clone()),
+            UnresolvedType::Bool => Type::Bool,
+            UnresolvedType::Char => Type::Char,
+            UnresolvedType::Void => Type::Void,
+            UnresolvedType::Str => Type::Str,
+            UnresolvedType::FnPointer(params, ret) => Type::FnPointer(
+                params
+                    .iter()
+                    .map(|param| self.resolve_type(param, module, self_ty))
+                    .collect::<Result<_, _>>()?,
+                match ret {
+                    Some(ret) => Some(Box::new(self.resolve_type(ret, module, self_ty)?)),
+                    None => None,
+                },
+            ),
+            UnresolvedType::Nammed(path) => match (path.parts().eq(["Self"]), self_ty) {
+                (true, Some(self_ty)) => self_ty.clone(),
+                _ => match self.find_type(module, path) {
+                    Some(path) => Type::Nammed(path),
+                    None => return Err(DiagnosticKind::UndefinedType(path.clone())),
+                },
+            },
+            UnresolvedType::Ptr(inner, mutable) => Type::Ptr(
+                Box::new(self.resolve_type(inner, module, self_ty)?),
+                *mutable,
+            ),
+            UnresolvedType::Ref(inner, mutable) => Type::Ref(
+                Box::new(self.resolve_type(inner, module, self_ty)?),
+                *mutable,
+            ),
+            UnresolvedType::Array(inner) => {
+                Type::Array(Box::new(self.resolve_type(inner, module, self_ty)?))
+            }
+            UnresolvedType::ArrayStatic(inner, length) => {
+                let inner = self.resolve_type(inner, module, self_ty)?;
+                let length = check::Checker::new(self, module.clone(), self_ty.cloned())
+                    .constant(length, &Type::USIZE)
+                    .map_err(|err| err.kind)?;
+                let length = length.as_int().unwrap() as usize;
+                Type::ArrayStatic(Box::new(inner), length)
+            }
+        })
+    }
+
+    /// Resolves the members of a user type if that hasn't happened yet
+    pub fn resolve_user_type(&mut self, path: &Path) {
+        let Some(ty) = self.type_map.types.get_mut(path) else {
+            return;
+        };
+        if !matches!(ty, UserType::Unresolved(..)) {
+            return;
+        }
+        let (module, def) = match std::mem::replace(ty, UserType::_Processing) {
+            UserType::Unresolved(module, def) => (module, def),
+            _ => unreachable!(),
+        };
+
+        let members = |context: &mut Self, values: Vec<(UnresolvedType, String, Span)>| {
+            let mut members: Vec<(Type, String)> = Vec::new();
+            for (ty, name, _) in values {
+                if members.iter().any(|(_, other)| *other == name) {
+                    context.report(
+                        Diagnostic::error(DiagnosticKind::MultipleDefinitions(Path::new_path(
+                            &name,
+                        )))
+                        .in_item(path),
+                    );
+                    continue;
+                }
+                match context.resolve_type(&ty, &module, None) {
+                    Ok(ty) => members.push((ty, name)),
+                    Err(err) => context.report(Diagnostic::error(err).in_item(path)),
+                }
+            }
+            members
+        };
 
+        let resolved = match def {
+            TopLevelDef::StructDef(struc) => UserType::Struct(Struct {
+                layout: None,
+                members: members(self, struc.values)
+                    .into_iter()
+                    .map(|(ty, name)| StructMember {
+                        offset: 0,
+                        name,
+                        ty,
+                    })
+                    .collect(),
+            }),
+            TopLevelDef::UnionDef(unio) => UserType::Union(Union {
+                layout: None,
+                members: members(self, unio.values)
+                    .into_iter()
+                    .map(|(ty, name)| UnionMember { name, ty })
+                    .collect(),
+            }),
+            TopLevelDef::EnumDef(enu) => UserType::Enum(Enum {
+                layout: None,
+                members: enu
+                    .values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(value, (name, _))| EnumVarient { value, name })
+                    .collect(),
+            }),
+            _ => unreachable!("not a type definition"),
+        };
+        *self.type_map.types.get_mut(path).unwrap() = resolved;
     }
-    
+
+    /// Resolves the type and evaluates the initial value of a global variable
+    pub fn resolve_global(&mut self, path: &Path) -> Option<&GlobalVar> {
+        let global = self.globals.get_mut(path)?;
+        match global {
+            Global::Variable(Resolvable::Unresolved(_)) => {}
+            Global::Variable(Resolvable::Resolved(_)) => {}
+            Global::Function(_) => return None,
+            Global::Resolving => {
+                self.report(
+                    Diagnostic::error(DiagnosticKind::RecursiveConstant(path.clone()))
+                        .in_item(path),
+                );
+                return None;
+            }
+        }
+        if let Global::Variable(Resolvable::Unresolved(_)) = global {
+            let (module, def) = match std::mem::replace(global, Global::Resolving) {
+                Global::Variable(Resolvable::Unresolved(unresolved)) => *unresolved,
+                _ => unreachable!(),
+            };
+
+            let var = match self.resolve_type(&def.ty, &module, None) {
+                Ok(ty) => {
+                    let value = match &def.value {
+                        Some(value) => {
+                            match check::Checker::new(self, module, None).constant(value, &ty) {
+                                Ok(value) => Some(value),
+                                Err(err) => {
+                                    self.report(Diagnostic::from(err).in_item(path));
+                                    None
+                                }
+                            }
+                        }
+                        None if matches!(def.kind, GlobalKind::Static) => {
+                            let err = DiagnosticKind::UninitializedStatic(path.clone());
+                            self.report(Diagnostic::error(err).in_item(path));
+                            None
+                        }
+                        None => None,
+                    };
+                    GlobalVar {
+                        vis: def.vis,
+                        kind: def.kind,
+                        ty,
+                        value,
+                    }
+                }
+                Err(err) => {
+                    self.report(Diagnostic::error(err).in_item(path));
+                    return None;
+                }
+            };
+            *self.globals.get_mut(path).unwrap() = Global::Variable(Resolvable::Resolved(var));
+        }
+        match self.globals.get(path)? {
+            Global::Variable(Resolvable::Resolved(var)) => Some(var),
+            _ => None,
+        }
+    }
+
     fn add_global(&mut self, path: Path, glob: Global) {
- 
-        // let mut item = self.constant_sups.remove(&path).unwrap_or_default();
-        // item.retain(|v|{
-        //     let item = self.constant_deps.get_mut(v);
-        //     if let Some(map) = item{
-        //         map.remove(&path);
-        //         map.is_empty()
-        //     }else{
-        //         true
-        //     }
-        // });
-        // assert!(self.globals.insert(path, glob).is_none());
+        if self.globals.contains_key(&path) {
+            self.report(Diagnostic::error(DiagnosticKind::MultipleDefinitions(
+                path.clone(),
+            )));
+            return;
+        }
+        self.globals.insert(path, glob);
+    }
+
+    fn add_type(&mut self, path: Path, ty: UserType) {
+        if self.type_map.types.contains_key(&path) {
+            self.report(Diagnostic::error(DiagnosticKind::MultipleDefinitions(
+                path.clone(),
+            )));
+            return;
+        }
+        self.type_map.types.insert(path, ty);
     }
-}
 
+    fn add_function(&mut self, path: Path, function: Function) -> FunctionId {
+        let id = FunctionId(self.functions.len());
+        self.functions.push(function);
+        self.add_global(path, Global::Function(id));
+        id
+    }
 
-impl Program{
-    fn add_function_head(&mut self, mod_path: &Path, func: FunctionHeader){
-        // let mut path = mod_path.clone();
-        // path.push(&func.name);
-
-        // // self.context.add_glob_func();
-        // let sig = FunctionSig {
-        //     name: Some(path),
-        //     params: func.params,
-        //     ret_ty: func.ret.unwrap_or(Type::Void),
-        // };
-
-        // self.functions.push(
-        //     (sig, FunctionKind::Declaration(func.kind.unwrap_or_default())),
-        // );
-    }
-    
-    fn add_function_def(&mut self, mod_path: &Path, func: FunctionDef){
-        // let mut path = mod_path.clone();
-        // path.push(&func.name);
-
-        // let id = FunctionId(self.functions.len());
-        // self.context.add_global(path.clone(), Global::Function(id));
-
-        // self.functions.push(
-        //     (
-        //         FunctionSig {
-        //             name: Some(path),
-        //             params: func.params,
-        //             ret_ty: func.ret.unwrap_or(Type::Void),
-        //         },
-        //         FunctionKind::Definition {
-        //             external: func.kind,
-        //             code: func.body,
-        //         },
-        //     ),
-        // );
-    }
-
-    pub fn load_module(&mut self, mod_path: Path, module: Module){
-        for _use_smt in module.use_statements{
+    /// Finds user types which contain themselves by value
+    fn check_recursive_types(&mut self) {
+        fn contains(context: &Context, ty: &Type, target: &Path, seen: &mut Vec<Path>) -> bool {
+            match ty {
+                Type::Nammed(path) => {
+                    if path == target {
+                        return true;
+                    }
+                    if seen.contains(path) {
+                        return false;
+                    }
+                    seen.push(path.clone());
+                    match context.type_map.types.get(path) {
+                        Some(UserType::Struct(struc)) => struc
+                            .members
+                            .iter()
+                            .any(|member| contains(context, &member.ty, target, seen)),
+                        Some(UserType::Union(unio)) => unio
+                            .members
+                            .iter()
+                            .any(|member| contains(context, &member.ty, target, seen)),
+                        _ => false,
+                    }
+                }
+                Type::Array(inner) | Type::ArrayStatic(inner, _) => {
+                    contains(context, inner, target, seen)
+                }
+                _ => false,
+            }
+        }
+
+        let mut paths: Vec<_> = self.type_map.types.keys().cloned().collect();
+        paths.sort_by_key(|path| path.to_string());
+        for path in paths {
+            let mut seen = vec![path.clone()];
+            let recursive = match self.type_map.types.get(&path) {
+                Some(UserType::Struct(struc)) => struc
+                    .members
+                    .iter()
+                    .any(|member| contains(self, &member.ty, &path, &mut seen)),
+                Some(UserType::Union(unio)) => unio
+                    .members
+                    .iter()
+                    .any(|member| contains(self, &member.ty, &path, &mut seen)),
+                _ => false,
+            };
+            if recursive {
+                self.report(Diagnostic::error(DiagnosticKind::RecursiveType(
+                    path.clone(),
+                )));
+            }
+        }
+    }
 
+    /// Makes sure only the last member of a struct is unsized and union members are sized
+    fn check_invalid_unsized(&mut self) {
+        let mut errors = Vec::new();
+        for (path, ty) in &self.type_map.types {
+            let members: Vec<&Type> = match ty {
+                UserType::Struct(struc) => {
+                    let len = struc.members.len().saturating_sub(1);
+                    struc.members[..len].iter().map(|m| &m.ty).collect()
+                }
+                UserType::Union(unio) => unio.members.iter().map(|m| &m.ty).collect(),
+                _ => continue,
+            };
+            for ty in members {
+                if !ty.is_sized(self) {
+                    errors.push(
+                        Diagnostic::error(DiagnosticKind::UnsizedValue(ty.clone())).in_item(path),
+                    );
+                }
+            }
         }
-        // module.items.
-        // for item in module.items {
-        //     match item {
-        //         crate::parser::ast::TopLevelDef::StructDef(struc) => {
-        //             let mut path = mod_path.clone();
-        //             path.push(&struc.name);
-    
-        //             let def = Struct {
-        //                 layout: None,
-        //                 members: struc
-        //                     .values
-        //                     .into_iter()
-        //                     .map(|(ty, name)| StructMember {
-        //                         offset: 0,
-        //                         name,
-        //                         ty,
-        //                     })
-        //                     .collect(),
-        //             };
-    
-        //             self.context.type_map.types.insert(path, UserType::Struct(def));
-        //         }
-        //         crate::parser::ast::TopLevelDef::EnumDef(enu) => {
-        //             let mut path = mod_path.clone();
-        //             path.push(&enu.name);
-    
-        //             let def = Enum {
-        //                 layout: None,
-        //                 members: enu
-        //                     .values
-        //                     .into_iter()
-        //                     .enumerate()
-        //                     .map(|(value, name)| EnumVarient { value, name })
-        //                     .collect(),
-        //             };
-    
-        //             self.context.type_map.types.insert(path, UserType::Enum(def));
-        //         }
-        //         crate::parser::ast::TopLevelDef::UnionDef(unio) => {
-        //             let mut path = mod_path.clone();
-        //             path.push(&unio.name);
-    
-        //             let def = Union {
-        //                 layout: None,
-        //                 members: unio
-        //                     .values
-        //                     .into_iter()
-        //                     .map(|(ty, name)| UnionMember { name, ty })
-        //                     .collect(),
-        //             };
-    
-        //             self.context.type_map.types.insert(path, UserType::Union(def));
-        //         }
-    
-        //         crate::parser::ast::TopLevelDef::GlobalDef(glob) => {
-        //             // let mut path = mod_path.clone();
-        //             // path.push(&glob.name);
-        //             // self.globals.insert(
-        //             //     path,
-        //             //     Global {
-        //             //         constant: matches!(glob.kind, crate::parser::ast::GlobalKind::Const),
-        //             //         ty: glob.ty,
-        //             //         expr: glob.value,
-        //             //     },
-        //             // );
-        //         }
-    
-        //         crate::parser::ast::TopLevelDef::FunctionDef(func) => {
-        //             self.add_function_def(&mod_path, func);
-        //         }
-        //         crate::parser::ast::TopLevelDef::FunctionHeader(func) => {
-        //             self.add_function_head(&mod_path, func);
-        //         }
-    
-        //         crate::parser::ast::TopLevelDef::UseStatement() => todo!(),
-        //     }
-        // }
-    }
-
-    pub fn check_invalid_unsized(&mut self){
-        // for (sig, _ ) in &mut self.functions{
-        //     assert!(sig.ret_ty.layout(&mut self.context).is_sized(), "Function return values must be sized");
-        //     for param in sig.params.iter().map(|v|&v.0){
-        //         assert!(param.layout(&mut self.context).is_sized(), "Function parameters must be sized");
-        //     }
-        // }
-
-        // for glob in self.globals.values(){
-        //     assert!(glob.ty.layout(&mut self.context).is_sized(), "Global values must be sized");
-        // }
-    }
-
-    // pub fn generate_global_values(&mut self){
-    //     for glob in self.globals.values_mut(){
-            
-    //     }
-    // }
+        self.diagnostics.extend(errors);
+    }
 }
 
+impl Program {
+    fn add_function_head(&mut self, mod_path: &Path, func: FunctionHeader) -> Option<FunctionId> {
+        let mut path = mod_path.clone();
+        path.push(&func.name);
+
+        let sig = self.resolve_sig(mod_path, path.clone(), None, &func.params, &func.ret)?;
+        Some(self.context.add_function(
+            path,
+            Function {
+                vis: Vis::Priv,
+                sig,
+                kind: FunctionKind::Declaration(func.kind.unwrap_or_default()),
+            },
+        ))
+    }
+
+    fn add_function_def(
+        &mut self,
+        mod_path: &Path,
+        path: Path,
+        self_ty: Option<Type>,
+        func: FunctionDef,
+    ) -> Option<FunctionId> {
+        let (mutable_params, params): (Vec<_>, Vec<_>) = func
+            .params
+            .into_iter()
+            .map(|(mutable, ty, name)| (mutable, (ty, name)))
+            .unzip();
+        let sig = self.resolve_sig(mod_path, path.clone(), self_ty.as_ref(), &params, &func.ret)?;
+        Some(self.context.add_function(
+            path,
+            Function {
+                vis: func.vis,
+                sig,
+                kind: FunctionKind::Definition {
+                    external: func.kind,
+                    code: Resolvable::Unresolved(UnresolvedBody {
+                        module: mod_path.clone(),
+                        self_ty,
+                        mutable_params,
+                        stmts: func.body,
+                    }),
+                },
+            },
+        ))
+    }
+
+    fn add_impl(&mut self, mod_path: &Path, ty: &UnresolvedType, functions: Vec<FunctionDef>) {
+        let ty = match self.context.resolve_type(ty, mod_path, None) {
+            Ok(ty) => ty,
+            Err(err) => {
+                self.context.report(Diagnostic::error(err));
+                return;
+            }
+        };
+        let ty_path = match &ty {
+            Type::Nammed(path) => path.clone(),
+            Type::Int(..) | Type::Float(_) | Type::Bool | Type::Char | Type::Str => {
+                Path::new_path(&ty.to_string())
+            }
+            _ => {
+                self.context
+                    .report(Diagnostic::error(DiagnosticKind::InvalidImplType(ty)));
+                return;
+            }
+        };
+
+        for func in functions {
+            let name = func.name.clone();
+            let path = ty_path.join(&Path::new_path(&name));
+            let Some(id) = self.add_function_def(mod_path, path.clone(), Some(ty.clone()), func)
+            else {
+                continue;
+            };
+
+            let sig = &self.context.function(id).sig;
+            if sig.is_method() {
+                let self_param = &sig.params[0].0;
+                let by_ref = |mutable| Type::Ref(Box::new(ty.clone()), mutable);
+                if *self_param != ty && *self_param != by_ref(false) && *self_param != by_ref(true)
+                {
+                    let err = DiagnosticKind::InvalidSelfType(path.clone(), self_param.clone());
+                    self.context.report(Diagnostic::error(err).in_item(&path));
+                }
+            }
+            self.context
+                .methods
+                .entry(ty.clone())
+                .or_default()
+                .insert(name, id);
+        }
+    }
+
+    fn resolve_sig(
+        &mut self,
+        mod_path: &Path,
+        path: Path,
+        self_ty: Option<&Type>,
+        params: &[(UnresolvedType, String)],
+        ret: &Option<UnresolvedType>,
+    ) -> Option<FunctionSig> {
+        let mut resolve =
+            |ty: &UnresolvedType| match self.context.resolve_type(ty, mod_path, self_ty) {
+                Ok(ty) => Some(ty),
+                Err(err) => {
+                    self.context.report(Diagnostic::error(err).in_item(&path));
+                    None
+                }
+            };
+        let params: Option<Vec<_>> = params
+            .iter()
+            .map(|(ty, name)| Some((resolve(ty)?, name.clone())))
+            .collect();
+        let ret_ty = match ret {
+            Some(ret) => resolve(ret),
+            None => Some(Type::Void),
+        };
+        Some(FunctionSig {
+            name: Some(path),
+            ret_ty: ret_ty?,
+            params: params?,
+        })
+    }
+
+    pub fn load_module(&mut self, mod_path: Path, module: Module) {
+        for _use_smt in module.use_statements {}
+
+        let types = module
+            .struct_def
+            .into_iter()
+            .map(|def| (def.name.clone(), TopLevelDef::StructDef(def)))
+            .chain(
+                module
+                    .union_def
+                    .into_iter()
+                    .map(|def| (def.name.clone(), TopLevelDef::UnionDef(def))),
+            )
+            .chain(
+                module
+                    .enum_def
+                    .into_iter()
+                    .map(|def| (def.name.clone(), TopLevelDef::EnumDef(def))),
+            );
+        for (name, def) in types {
+            let mut path = mod_path.clone();
+            path.push(&name);
+            self.context
+                .add_type(path, UserType::Unresolved(mod_path.clone(), def));
+        }
+
+        for glob in module.glob_def {
+            let mut path = mod_path.clone();
+            path.push(&glob.name);
+            self.context.add_global(
+                path,
+                Global::Variable(Resolvable::Unresolved(Box::new((mod_path.clone(), glob)))),
+            );
+        }
+
+        for func in module.function_header {
+            self.pending_functions
+                .push((mod_path.clone(), TopLevelDef::FunctionHeader(func)));
+        }
+        for func in module.function_def {
+            self.pending_functions
+                .push((mod_path.clone(), TopLevelDef::FunctionDef(func)));
+        }
+        for imp in module.impl_def {
+            self.pending_functions
+                .push((mod_path.clone(), TopLevelDef::ImplDef(imp)));
+        }
+    }
+
+    /// Resolves and type checks everything loaded so far, the results are
+    /// reported through [`Context::diagnostics`]
+    pub fn check(&mut self) -> bool {
+        let mut paths: Vec<_> = self.context.type_map.types.keys().cloned().collect();
+        paths.sort_by_key(|path| path.to_string());
+        for path in &paths {
+            self.context.resolve_user_type(path);
+        }
+        self.context.check_recursive_types();
+        if self.context.has_errors() {
+            return false;
+        }
+        self.context.check_invalid_unsized();
+        if self.context.has_errors() {
+            return false;
+        }
+        for path in &paths {
+            self.context.layout(path);
+        }
+
+        for (mod_path, def) in std::mem::take(&mut self.pending_functions) {
+            match def {
+                TopLevelDef::FunctionHeader(func) => {
+                    self.add_function_head(&mod_path, func);
+                }
+                TopLevelDef::FunctionDef(func) => {
+                    let path = mod_path.join(&Path::new_path(&func.name));
+                    self.add_function_def(&mod_path, path, None, func);
+                }
+                TopLevelDef::ImplDef(ImplDef { ty, functions, .. }) => {
+                    self.add_impl(&mod_path, &ty, functions);
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        let mut globals: Vec<_> = self.context.globals.keys().cloned().collect();
+        globals.sort_by_key(|path| path.to_string());
+        for path in globals {
+            self.context.resolve_global(&path);
+        }
+
+        for id in 0..self.context.functions.len() {
+            self.check_function(FunctionId(id));
+        }
+
+        !self.context.has_errors()
+    }
+
+    fn check_function(&mut self, id: FunctionId) {
+        let function = &mut self.context.functions[id.0];
+        let FunctionKind::Definition { code, .. } = &mut function.kind else {
+            return;
+        };
+        let Resolvable::Unresolved(unresolved) = code else {
+            return;
+        };
+        let unresolved = unresolved.clone();
+        let sig = function.sig.clone();
+        let path = sig.name.clone().unwrap_or_default();
+
+        let checker = check::Checker::new(&mut self.context, unresolved.module, unresolved.self_ty);
+        let body = checker.body(&sig, &unresolved.mutable_params, &unresolved.stmts);
+        match body {
+            Ok(body) => {
+                for error in flow::check_body(&body) {
+                    self.context.report(error.in_item(&path));
+                }
+                if let FunctionKind::Definition { code, .. } =
+                    &mut self.context.functions[id.0].kind
+                {
+                    *code = Resolvable::Resolved(body);
+                }
+            }
+            Err(errors) => {
+                for error in errors {
+                    self.context.report(error.in_item(&path));
+                }
+            }
+        }
+    }
+}
 
 #[test]
 fn test() {
     let mut program = Program::default();
 
     let parser = crate::parser::def::ModuleParser::new();
-    let str = include_str!("../../test/main.bc");    
+    let str = include_str!("../../test/main.bc");
 
     let res = parser.parse(str).unwrap();
 
     let module = Path::new();
 
     program.load_module(module, res);
-    program.check_invalid_unsized();
-    program.generate_global_values();
+    program.check();
 
-    println!("{:#?}", program.type_map.get_type(&Path::new_path("Other")));
-    println!("{:#?}", program.type_map.get_type(&Path::new_path("Thing")));
+    for diagnostic in program.context.diagnostics() {
+        println!("{diagnostic}");
+    }
+
+    println!("{:#?}", program.context.get_type(&Path::new_path("Other")));
+    println!("{:#?}", program.context.get_type(&Path::new_path("Thing")));
+}
+
+#[test]
+fn static_needs_value() {
+    let program = check::check_source(
+        r#"
+        static i32 MISSING;
+        extern static i32 errno;
+        static i32 GIVEN = 3;
+        "#,
+    );
+    assert_eq!(
+        check::errors(&program),
+        vec![DiagnosticKind::UninitializedStatic(Path::new_path(
+            "MISSING"
+        ))]
+    );
 }
@@ -1,10 +1,6 @@
 use std::borrow::Cow;
 
-#[derive(Clone, Copy, Debug, Hash)]
-pub enum Vis {
-    Pub,
-    Priv,
-}
+pub use crate::parser::ast::Vis;
 
 #[derive(Clone, Debug, Hash)]
 pub struct Scope<'a> {
@@ -0,0 +1,217 @@
+//! The type checked form of function bodies.
+//!
+//! Every expression carries its resolved [`Type`], names are resolved to
+//! locals, globals or functions and method calls are lowered to plain calls
+//! with their receiver adjusted.
+
+use crate::parser::ast::{BinOpKind, Path, Span, UnaryOpKind};
+
+use super::{constant_eval::Value, types::Type, FunctionId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LocalId(pub usize);
+
+/// Identifies a block expression so `break`/`continue` can refer to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LabelId(pub usize);
+
+#[derive(Debug, Clone)]
+pub struct Local {
+    pub name: String,
+    pub ty: Type,
+    pub mutable: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Body {
+    /// All locals of the function, the first `params` of them are the parameters
+    pub locals: Vec<Local>,
+    pub params: usize,
+    pub labels: usize,
+    pub ret_ty: Type,
+    pub stmts: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Expr(Expr),
+    /// Declares a local, without a value it starts out uninitialized
+    Let(LocalId, Option<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub ty: Type,
+    pub kind: ExprKind,
+    /// Where the expression it was checked from is
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Local(LocalId),
+    Static(Path),
+    Function(FunctionId),
+    /// Literals, enum varients, constants and anything else known at compile time
+    Value(Value),
+
+    Block(LabelId, Block),
+    /// Member access by index into the struct or union members
+    Field(Box<Expr>, usize),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Unary(UnaryOpKind, Box<Expr>),
+    Binary(Box<Expr>, BinOpKind, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+    /// `place op= value`, the place is only evaluated once
+    CompoundAssign(Box<Expr>, BinOpKind, Box<Expr>),
+    Coerce(Coercion, Box<Expr>),
+
+    /// Member values in declaration order
+    StructCon(Vec<Expr>),
+    /// The initialized member and its value
+    UnionCon(usize, Box<Expr>),
+    ArrayCon(Vec<Expr>),
+
+    Break(LabelId, Option<Box<Expr>>),
+    Continue(LabelId),
+    Return(Option<Box<Expr>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coercion {
+    /// `&T` to `*T`
+    RefToPtr,
+    /// `&mut T` to `&T`, `*mut T` to `*T`
+    Immutable,
+    /// `&[T; N]` to `&[T]`, `*[T; N]` to `*[T]`
+    Unsize(usize),
+    /// `&str`/`&[T]` to a thin `*u8`/`*T` data pointer
+    DataPtr,
+}
+
+#[derive(Debug, Clone)]
+pub enum Block {
+    Scope(Vec<Stmt>),
+    While(Box<Expr>, Vec<Stmt>),
+    /// Runs until a `break`
+    Loop(Vec<Stmt>),
+    /// Counts the local from the start to the end, the end is included when
+    /// the flag is set and both ends are evaluated once before the loop
+    Range(LocalId, Box<Expr>, Box<Expr>, bool, Vec<Stmt>),
+    /// Runs once for every element of an array, if the expression is a
+    /// reference or pointer to the array the local is one to the element
+    Each(LocalId, Box<Expr>, Vec<Stmt>),
+    /// `if`/`else if` arms in order followed by the optional `else`
+    If(Vec<(Expr, Vec<Stmt>)>, Option<Vec<Stmt>>),
+}
+
+impl Expr {
+    pub fn new(ty: Type, kind: ExprKind) -> Self {
+        Self {
+            ty,
+            kind,
+            span: Span::default(),
+        }
+    }
+
+    pub fn value(ty: Type, value: Value) -> Self {
+        Self::new(ty, ExprKind::Value(value))
+    }
+
+    /// `inner` converted to `ty`, at the same place in the source
+    pub fn coerce(ty: Type, coercion: Coercion, inner: Expr) -> Self {
+        Self {
+            ty,
+            span: inner.span,
+            kind: ExprKind::Coerce(coercion, Box::new(inner)),
+        }
+    }
+
+    pub fn void() -> Self {
+        Self::value(Type::Void, Value::Void(()))
+    }
+
+    /// Whether this expression refers to a memory location
+    pub fn is_place(&self) -> bool {
+        match &self.kind {
+            ExprKind::Local(_) | ExprKind::Static(_) => true,
+            ExprKind::Unary(UnaryOpKind::Deref, _) => true,
+            ExprKind::Field(inner, _) | ExprKind::Index(inner, _) => inner.is_place(),
+            _ => false,
+        }
+    }
+}
+
+impl Expr {
+    /// Calls `f` on this expression and then every expression nested in it
+    pub fn visit_mut(&mut self, f: &mut impl FnMut(&mut Expr)) {
+        f(self);
+        match &mut self.kind {
+            ExprKind::Local(_)
+            | ExprKind::Static(_)
+            | ExprKind::Function(_)
+            | ExprKind::Value(_)
+            | ExprKind::Continue(_)
+            | ExprKind::Break(_, None)
+            | ExprKind::Return(None) => {}
+            ExprKind::Block(_, block) => block.visit_mut(f),
+            ExprKind::Field(inner, _)
+            | ExprKind::Unary(_, inner)
+            | ExprKind::Coerce(_, inner)
+            | ExprKind::UnionCon(_, inner)
+            | ExprKind::Break(_, Some(inner))
+            | ExprKind::Return(Some(inner)) => inner.visit_mut(f),
+            ExprKind::Index(l, r)
+            | ExprKind::Binary(l, _, r)
+            | ExprKind::Assign(l, r)
+            | ExprKind::CompoundAssign(l, _, r) => {
+                l.visit_mut(f);
+                r.visit_mut(f);
+            }
+            ExprKind::Call(callee, args) => {
+                callee.visit_mut(f);
+                args.iter_mut().for_each(|arg| arg.visit_mut(f));
+            }
+            ExprKind::StructCon(values) | ExprKind::ArrayCon(values) => {
+                values.iter_mut().for_each(|value| value.visit_mut(f));
+            }
+        }
+    }
+}
+
+impl Stmt {
+    pub fn visit_mut(&mut self, f: &mut impl FnMut(&mut Expr)) {
+        match self {
+            Stmt::Expr(expr) | Stmt::Let(_, Some(expr)) => expr.visit_mut(f),
+            Stmt::Let(_, None) => {}
+        }
+    }
+}
+
+impl Block {
+    pub fn visit_mut(&mut self, f: &mut impl FnMut(&mut Expr)) {
+        match self {
+            Block::Scope(stmts) => stmts.iter_mut().for_each(|stmt| stmt.visit_mut(f)),
+            Block::While(cond, stmts) | Block::Each(_, cond, stmts) => {
+                cond.visit_mut(f);
+                stmts.iter_mut().for_each(|stmt| stmt.visit_mut(f));
+            }
+            Block::Loop(stmts) => stmts.iter_mut().for_each(|stmt| stmt.visit_mut(f)),
+            Block::Range(_, start, end, _, stmts) => {
+                start.visit_mut(f);
+                end.visit_mut(f);
+                stmts.iter_mut().for_each(|stmt| stmt.visit_mut(f));
+            }
+            Block::If(arms, els) => {
+                for (cond, stmts) in arms {
+                    cond.visit_mut(f);
+                    stmts.iter_mut().for_each(|stmt| stmt.visit_mut(f));
+                }
+                for stmt in els.iter_mut().flatten() {
+                    stmt.visit_mut(f);
+                }
+            }
+        }
+    }
+}
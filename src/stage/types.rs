@@ -1,8 +1,8 @@
 use std::num::NonZeroUsize;
 
-use crate::parser::ast::{Expression, FloatType, IntSize, Path};
+use crate::parser::ast::{FloatType, IntSize, Path};
 
-use super::{ConstantId, Context, TypeMap};
+use super::{Context, UserType};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
@@ -14,13 +14,19 @@ pub enum Type {
     Str,
     FnPointer(Vec<Type>, Option<Box<Type>>),
     Nammed(Path),
-    Ptr(Box<Type>),
-    Ref(Box<Type>),
+    /// The pointee and whether it can be written through
+    Ptr(Box<Type>, bool),
+    Ref(Box<Type>, bool),
     Array(Box<Type>),
     ArrayStatic(Box<Type>, usize),
 }
 
 impl Type {
+    pub const USIZE: Type = Type::Int(IntSize::Usize, false);
+    pub const ISIZE: Type = Type::Int(IntSize::Usize, true);
+    pub const U8: Type = Type::Int(IntSize::U8, false);
+    pub const I32: Type = Type::Int(IntSize::U32, true);
+    pub const F64: Type = Type::Float(FloatType::F64);
 
     pub fn layout(&self, context: &mut Context) -> Layout {
         match self {
@@ -43,16 +49,10 @@ impl Type {
 
             Type::Str => Layout::ZERO_SIZE_UNSIZED,
 
-            Type::Ptr(inner) => {
-                if inner.layout(context).is_sized() {
-                    Layout::new(8, 8).unwrap()
-                } else {
-                    Layout::new(16, 8).unwrap()
-                }
-            }
-
-            Type::Ref(inner) => {
-                if inner.layout(context).is_sized() {
+            // only the sizedness of the pointee matters here, computing its
+            // full layout would recurse forever on self referential types
+            Type::Ptr(inner, _) | Type::Ref(inner, _) => {
+                if inner.is_sized(context) {
                     Layout::new(8, 8).unwrap()
                 } else {
                     Layout::new(16, 8).unwrap()
@@ -60,30 +60,122 @@ impl Type {
             }
 
             Type::Array(inner) => {
-                let mut inner = inner.layout(context);
+                let inner = inner.layout(context);
                 assert!(inner.is_sized());
-                inner.sized = false;
-                inner.size = 0;
-                inner
+                inner.unsize()
             }
 
             Type::ArrayStatic(item, length) => {
                 let mut layout = item.layout(context);
-                // layout.size *= *length;
+                layout.size *= *length;
                 layout
             }
 
             Type::Nammed(user) => context.layout(user),
         }
     }
+
+    pub fn is_sized(&self, context: &Context) -> bool {
+        match self {
+            Type::Str | Type::Array(_) => false,
+            Type::Nammed(path) => match context.type_map.types.get(path) {
+                Some(UserType::Struct(struc)) => struc
+                    .members
+                    .last()
+                    .is_none_or(|member| member.ty.is_sized(context)),
+                Some(UserType::Union(unio)) => unio
+                    .members
+                    .iter()
+                    .all(|member| member.ty.is_sized(context)),
+                _ => true,
+            },
+            _ => true,
+        }
+    }
+
+    pub fn is_int(&self) -> bool {
+        matches!(self, Type::Int(..))
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::Float(..))
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.is_int() || self.is_float()
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::Int(_, true) | Type::Float(_))
+    }
+
+    /// The type behind a reference or pointer
+    pub fn pointee(&self) -> Option<&Type> {
+        match self {
+            Type::Ref(inner, _) | Type::Ptr(inner, _) => Some(inner),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a `&mut T` or `*mut T`
+    pub fn is_mut_pointer(&self) -> bool {
+        matches!(self, Type::Ref(_, true) | Type::Ptr(_, true))
+    }
+
+    /// The element type of anything that can be indexed
+    pub fn element(&self) -> Option<&Type> {
+        match self {
+            Type::Array(inner) | Type::ArrayStatic(inner, _) => Some(inner),
+            _ => None,
+        }
+    }
 }
 
-const VAL: usize = 34-2;
-struct Test{
-    vals: [u8; VAL],
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int(size, signed) => {
+                let prefix = if *signed { 'i' } else { 'u' };
+                match size {
+                    IntSize::U8 => write!(f, "{prefix}8"),
+                    IntSize::U16 => write!(f, "{prefix}16"),
+                    IntSize::U32 => write!(f, "{prefix}32"),
+                    IntSize::U64 => write!(f, "{prefix}64"),
+                    IntSize::Usize => write!(f, "{prefix}size"),
+                }
+            }
+            Type::Float(FloatType::F32) => write!(f, "f32"),
+            Type::Float(FloatType::F64) => write!(f, "f64"),
+            Type::Bool => write!(f, "bool"),
+            Type::Char => write!(f, "char"),
+            Type::Void => write!(f, "void"),
+            Type::Str => write!(f, "str"),
+            Type::FnPointer(params, ret) => {
+                write!(f, "fn(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{param}")?;
+                }
+                write!(f, ")")?;
+                if let Some(ret) = ret {
+                    write!(f, " {ret}")?;
+                }
+                Ok(())
+            }
+            Type::Nammed(path) => write!(f, "{path}"),
+            Type::Ptr(inner, false) => write!(f, "*{inner}"),
+            Type::Ptr(inner, true) => write!(f, "*mut {inner}"),
+            Type::Ref(inner, false) => write!(f, "&{inner}"),
+            Type::Ref(inner, true) => write!(f, "&mut {inner}"),
+            Type::Array(inner) => write!(f, "[{inner}]"),
+            Type::ArrayStatic(inner, len) => write!(f, "[{inner}; {len}]"),
+        }
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Layout {
     size: usize,
     align: NonZeroUsize,
@@ -147,7 +239,7 @@ impl Layout {
         })
     }
 
-    fn align_size(mut self) -> Self {
+    pub(super) fn align_size(mut self) -> Self {
         self.size += self.align.get() - 1;
         self.size &= !(self.align.get() - 1);
         self
@@ -180,3 +272,16 @@ impl Layout {
         self
     }
 }
+
+#[test]
+fn static_array_layout() {
+    let mut program = super::check::check_source("struct Buf { u8 tag, [i32; 3] values, }");
+    assert_eq!(super::check::errors(&program), vec![]);
+    let array = Type::ArrayStatic(Box::new(Type::I32), 3);
+    assert_eq!(
+        array.layout(&mut program.context),
+        Layout::new(12, 4).unwrap()
+    );
+    let (layout, _) = program.context.get_type(&Path::new_path("Buf"));
+    assert_eq!(layout, Layout::new(16, 4).unwrap());
+}
@@ -15,14 +15,12 @@ impl<'input> Iterator for Lexer<'input> {
     fn next(&mut self) -> Option<Self::Item> {
         let next = self.0.next()?;
         match next {
-            Ok(ok) => {
-                return Some(Ok((
-                    ok.span.offset as usize,
-                    ok.val,
-                    ok.span.offset as usize + ok.span.len as usize,
-                )));
-            }
-            Err(err) => return Some(Err(err)),
+            Ok(ok) => Some(Ok((
+                ok.span.offset as usize,
+                ok.val,
+                ok.span.offset as usize + ok.span.len as usize,
+            ))),
+            Err(err) => Some(Err(err)),
         }
     }
 }
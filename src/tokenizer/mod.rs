@@ -56,6 +56,7 @@ enum State {
     CharLiteral,
     CharLiteralEnd,
     CharLiteralLarge,
+    Label,
 
     String,
 
@@ -110,7 +111,11 @@ impl<'a> SSBuilder<'a> {
     }
 }
 
-fn ident(ident: &str) -> Token {
+fn is_label(char: char) -> bool {
+    char.is_alphabetic() || char == '_'
+}
+
+fn ident(ident: &str) -> Token<'_> {
     match ident {
         "true" => Token::TrueLiteral,
         "false" => Token::FalseLiteral,
@@ -120,7 +125,7 @@ fn ident(ident: &str) -> Token {
         "while" => Token::While,
         "loop" => Token::Loop,
         "if" => Token::If,
-        o => Token::Ident(o.into()),
+        o => Token::Ident(o),
     }
 }
 
@@ -248,7 +253,7 @@ impl<'a> Tokenizer<'a> {
         let mut clone = self.chars.clone();
         clone.next();
         match clone.next() {
-            Some(c) if c == '_' || c.is_alphabetic() => DisambiguateDot::Dot,
+            Some(c) if c == '_' || c == '.' || c.is_alphabetic() => DisambiguateDot::Dot,
             _ => DisambiguateDot::Numeric,
         }
     }
@@ -448,9 +453,28 @@ impl<'a> Iterator for Tokenizer<'a> {
                 },
                 State::CharLiteralEnd => match c {
                     Some('\'') => ret = Some(Ok(Token::CharLiteral(char_lit))),
+                    // an unclosed literal that starts like an identifier is a label
+                    Some(c) if is_label(char_lit) && (c.is_alphanumeric() || c == '_') => {
+                        self.state = State::Label
+                    }
+                    _ if is_label(char_lit) => unconsume_ret!(
+                        self,
+                        Ok(Token::Label(
+                            &self.str[self.start.offset..self.current.offset]
+                        ))
+                    ),
                     None | Some('\n') => ret = Some(Err(TokenizerError::UnclosedCharLiteral)),
                     Some(_) => self.state = State::CharLiteralLarge,
                 },
+                State::Label => match c {
+                    Some(c) if c.is_alphanumeric() || c == '_' => {}
+                    _ => unconsume_ret!(
+                        self,
+                        Ok(Token::Label(
+                            &self.str[self.start.offset..self.current.offset]
+                        ))
+                    ),
+                },
                 State::CharLiteralLarge => match c {
                     Some('\'') => ret = Some(Err(TokenizerError::CharLiteralTooBig)),
                     None | Some('\n') => ret = Some(Err(TokenizerError::UnclosedCharLiteral)),
@@ -979,3 +1003,29 @@ fn test_errors() {
         println!();
     }
 }
+
+#[test]
+fn labels_and_ranges() {
+    let tokens: Vec<_> = Tokenizer::new("'outer loop { break 'a; } 'c' 0..10 1.5")
+        .map(|token| token.unwrap().val)
+        .collect();
+    assert_eq!(
+        tokens[..7],
+        [
+            Token::Label("'outer"),
+            Token::Loop,
+            Token::LBrace,
+            Token::Ident("break"),
+            Token::Label("'a"),
+            Token::Semicolon,
+            Token::RBrace,
+        ]
+    );
+    assert_eq!(tokens[7], Token::CharLiteral('c'));
+    assert!(matches!(tokens[8], Token::NumericLiteral(_)));
+    assert_eq!(tokens[9], Token::RangeExclusive);
+    assert!(matches!(
+        tokens[10..],
+        [Token::NumericLiteral(_), Token::NumericLiteral(_)]
+    ));
+}
@@ -0,0 +1,105 @@
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/* pointers to strings and slices along with their length */
+typedef struct { void *ptr; size_t len; } bc_slice;
+
+double fmod(double, double);
+float fmodf(float, float);
+
+typedef union Bits Bits;
+typedef uint8_t Color;
+typedef struct Point Point;
+union Bits {
+    uint32_t int_;
+    float float_;
+};
+_Static_assert(sizeof(Bits) == 4 && _Alignof(Bits) == 4 && offsetof(Bits, int_) == 0 && offsetof(Bits, float_) == 0, "layout of Bits");
+struct Point {
+    int32_t x;
+    int64_t y;
+};
+_Static_assert(sizeof(Point) == 16 && _Alignof(Point) == 8 && offsetof(Point, x) == 0 && offsetof(Point, y) == 8, "layout of Point");
+
+extern uint8_t COUNTER;
+extern bc_slice GREETING;
+extern Point ORIGIN;
+
+uint8_t COUNTER = 0u;
+bc_slice GREETING = { (void *)"hello\012", 6 };
+Point ORIGIN = { .x = 0, .y = 0 };
+
+ptrdiff_t write(int32_t, uint8_t *, size_t);
+size_t strlen(uint8_t *);
+int32_t first_even(bc_slice);
+int32_t main(void);
+int32_t Point__scaled(Point *, int32_t);
+
+int32_t first_even(bc_slice values_0) {
+    int32_t *value_1;
+    int32_t found_2;
+    int32_t bc_t0;
+    int32_t *bc_t1;
+    size_t bc_t2;
+    size_t bc_t3;
+
+    {
+        bc_t1 = (int32_t *)values_0.ptr;
+        bc_t2 = values_0.len;
+        for (bc_t3 = 0; bc_t3 < bc_t2; bc_t3++) {
+            value_1 = &bc_t1[bc_t3];
+            if (((int32_t)((*value_1) % 2)) == 0) {
+                bc_t0 = *value_1;
+                goto bc_break_0;
+            }
+        }
+        bc_t0 = -1;
+        goto bc_break_0;
+    }
+bc_break_0: ;
+    found_2 = bc_t0;
+    return found_2;
+}
+
+int32_t main(void) {
+    Point p_0;
+    int32_t total_1;
+    int32_t i_2;
+    Bits bits_3;
+    Color color_4;
+    uint8_t *bc_t0;
+
+    bc_t0 = (uint8_t *)GREETING.ptr;
+    write(1, bc_t0, strlen(((uint8_t *)GREETING.ptr)));
+    p_0 = (Point){ .x = 3, .y = (-4) };
+    p_0.x = (int32_t)((uint32_t)p_0.x + (uint32_t)10);
+    total_1 = 0;
+    i_2 = 0;
+    if (i_2 <= 4) {
+        for (;;) {
+            if (i_2 == 2) {
+                goto bc_continue_0;
+            }
+            total_1 = (int32_t)((uint32_t)total_1 + (uint32_t)i_2);
+        bc_continue_0: ;
+            if (i_2 == 4) break;
+            i_2 = (int32_t)((uint32_t)i_2 + (uint32_t)1);
+        }
+    }
+    COUNTER = (uint8_t)((uint32_t)COUNTER + (uint32_t)200u);
+    COUNTER = (uint8_t)((uint32_t)COUNTER + (uint32_t)100u);
+    bits_3 = (Bits){ .float_ = 1.0f };
+    color_4 = 2;
+    if ((color_4 != 2) || (bits_3.int_ != 1065353216u)) {
+        return 1;
+    }
+    if (COUNTER != 44u) {
+        return 2;
+    }
+    return (int32_t)((uint32_t)total_1 + (uint32_t)Point__scaled(&p_0, 0));
+}
+
+int32_t Point__scaled(Point *self_0, int32_t by_1) {
+    return (int32_t)((uint32_t)(*self_0).x * (uint32_t)by_1);
+}
@@ -0,0 +1,70 @@
+%union.Bits = type <{ [4 x i8] }>
+%struct.Line = type <{ %struct.Point, %struct.Point }>
+%struct.Point = type <{ i8, [7 x i8], i64, i16, [6 x i8] }>
+
+@BITS = internal global [4 x i8] c"\00\00\80?", align 4
+@CURRENT = internal global ptr @LINE, align 8
+@FLAG = external global i32
+@LINE = internal global %struct.Line <{ %struct.Point <{ i8 1, [7 x i8] zeroinitializer, i64 -1, i16 2, [6 x i8] zeroinitializer }>, %struct.Point <{ i8 2, [7 x i8] zeroinitializer, i64 5, i16 -3, [6 x i8] zeroinitializer }> }>, align 8
+@__str_0 = internal constant [6 x i8] c"line\0A\00", align 1
+@NAME = internal global { ptr, i64 } { ptr @__str_0, i64 5 }, align 8
+@SCALE = internal global double 0x4004000000000000, align 8
+
+declare i64 @write(i32, ptr, i64)
+declare i64 @strlen(ptr)
+
+define internal i64 @length(ptr %r0) {
+b0:
+  %r3 = getelementptr i8, ptr %r0, i64 24
+  %r4 = getelementptr i8, ptr %r3, i64 8
+  %r5 = load i64, ptr %r4
+  %r7 = getelementptr i8, ptr %r0, i64 8
+  %r8 = load i64, ptr %r7
+  %r9 = sub i64 %r5, %r8
+  ret i64 %r9
+}
+
+define i32 @main() {
+b0:
+  %r2 = load ptr, ptr @NAME
+  %r3 = load ptr, ptr @NAME
+  %r4 = call i64 @strlen(ptr %r3)
+  %r5 = call i64 @write(i32 1, ptr %r2, i64 %r4)
+  br label %b1
+b1:
+  %r24 = phi i64 [ 0, %b0 ], [ %r13, %b1.1 ]
+  %r25 = phi i32 [ 0, %b0 ], [ %r15, %b1.1 ]
+  %r7 = load ptr, ptr @CURRENT
+  %r8 = call i64 @length(ptr %r7)
+  %r10 = zext i32 %r25 to i64
+  %t1 = and i64 %r10, 63
+  %r11 = shl i64 %r8, %t1
+  %r13 = add i64 %r24, %r11
+  %r15 = add i32 %r25, 1
+  %r16 = icmp eq i32 %r15, 10
+  br i1 %r16, label %b2, label %b1.1
+b1.1:
+  br label %b1
+b2:
+  %r17 = load i32, ptr @BITS
+  %r18 = icmp ne i32 %r17, 1065353216
+  br i1 %r18, label %b2.0, label %b3
+b2.0:
+  br label %b4
+b3:
+  %r20 = load i32, ptr @FLAG
+  %r21 = icmp eq i32 %r20, 0
+  br label %b4
+b4:
+  %r19 = phi i1 [ true, %b2.0 ], [ %r21, %b3 ]
+  br i1 %r19, label %b5, label %b6
+b5:
+  ret i32 1
+b6:
+  %r23 = icmp ne i64 %r13, 4092
+  br i1 %r23, label %b7, label %b8
+b7:
+  ret i32 2
+b8:
+  ret i32 0
+}
@@ -0,0 +1,74 @@
+    .text
+    .type sum, @function
+sum:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $32, %rsp
+    movq %rbx, -8(%rbp)
+    movq %r12, -16(%rbp)
+    movl %edi, %r10d
+.Lsum_0:
+    movq $0, %rax
+    movl %r10d, %ecx
+    cmpq %rcx, %rax
+    setb %al
+    movq %rax, %r8
+    movzbq %r8b, %rax
+    testb %al, %al
+    jz .Lsum_l1
+    movq $0, %r9
+    movq $0, %rsi
+    jmp .Lsum_1
+.Lsum_l1:
+    movq $0, %rdi
+    jmp .Lsum_2
+.Lsum_1:
+    movl %r9d, %eax
+    movl %esi, %ecx
+    addq %rcx, %rax
+    movq %rax, %r8
+    movl %esi, %eax
+    movq $1, %rcx
+    addq %rcx, %rax
+    movq %rax, %rbx
+    movl %ebx, %eax
+    movl %r10d, %ecx
+    cmpq %rcx, %rax
+    sete %al
+    movq %rax, %r12
+    movzbq %r12b, %rax
+    testb %al, %al
+    jz .Lsum_l2
+    movl %r8d, %edi
+    jmp .Lsum_2
+.Lsum_l2:
+    movl %r8d, %r9d
+    movl %ebx, %esi
+    jmp .Lsum_1
+.Lsum_2:
+    movl %edi, %eax
+    movq -8(%rbp), %rbx
+    movq -16(%rbp), %r12
+    leave
+    ret
+    .size sum, .-sum
+    .type half, @function
+half:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $16, %rsp
+    movq %xmm0, %rax
+    movq %rax, %xmm8
+.Lhalf_0:
+    movq %xmm8, %rax
+    movq %rax, %xmm0
+    movabsq $4611686018427387904, %rax
+    movq %rax, %xmm1
+    divsd %xmm1, %xmm0
+    movq %xmm0, %rax
+    movq %rax, %xmm9
+    movq %xmm9, %rax
+    movq %rax, %xmm0
+    leave
+    ret
+    .size half, .-half
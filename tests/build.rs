@@ -0,0 +1,74 @@
+//! Builds programs with `bc build` and runs them with `bc run`.
+
+use std::process::Command;
+
+const SOURCE: &str = r#"
+extern "C" fn write(i32 fd, *u8 data, usize len) isize;
+extern "C" fn strlen(*u8 str) usize;
+
+fn main() i32 {
+    &str greeting = "hello\n";
+    write(1, greeting, strlen(greeting));
+    mut i32 total = 0;
+    for (i in 0..5) { total += i; }
+    return total;
+}
+"#;
+
+fn bc(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_bc"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn target_c() {
+    let dir = std::env::temp_dir().join(format!("bc_build_c_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("main.bc");
+    std::fs::write(&file, SOURCE).unwrap();
+    let file = file.to_str().unwrap();
+
+    // the source goes next to the file unless there's somewhere else for it
+    let output = bc(&["build", file, "--target", "c"]);
+    assert!(output.status.success(), "{output:?}");
+    let c = std::fs::read_to_string(dir.join("main.c")).unwrap();
+    assert!(c.contains("int32_t main(void) {"), "{c}");
+
+    let output = bc(&["build", file, "--cc"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--target c"));
+
+    if Command::new("cc").arg("--version").output().is_ok() {
+        let binary = dir.join("main");
+        let binary = binary.to_str().unwrap();
+        let output = bc(&["build", file, "--target", "c", "--cc", "-O2", "-o", binary]);
+        assert!(output.status.success(), "{output:?}");
+        let run = Command::new(binary).output().unwrap();
+        assert_eq!(run.stdout, b"hello\n");
+        assert_eq!(run.status.code(), Some(10));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn passes() {
+    let dir = std::env::temp_dir().join(format!("bc_passes_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("main.bc");
+    std::fs::write(&file, SOURCE).unwrap();
+    let file = file.to_str().unwrap();
+
+    let output = bc(&["run", file, "--passes", "mem2reg,fold,simplify,dce"]);
+    assert_eq!(output.stdout, b"hello\n");
+    assert_eq!(output.status.code(), Some(10), "{output:?}");
+
+    let output = bc(&["run", file, "--passes", "fold,inline"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown pass `inline`"));
+
+    let output = bc(&["build", file, "--target", "c", "--passes", "dce"]);
+    assert!(!output.status.success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,223 @@
+//! Scripts a session with `bc lsp` the way an editor would.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{ChildStdin, ChildStdout, Command, Stdio},
+};
+
+const SOURCE: &str = "\
+struct Point {
+    u8 tag,
+    i32 x,
+}
+
+enum Color { Red, Green }
+
+const i32 LIMIT = 4 * 8;
+
+impl Point {
+    fn len(&Self self) i32 {
+        return self.x;
+    }
+}
+
+fn main() i32 {
+    Point p = Point { tag = 1, x = LIMIT };
+    return p.len();
+}
+";
+
+struct Client {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: usize,
+}
+
+impl Client {
+    fn send(&mut self, message: &str) {
+        write!(
+            self.stdin,
+            "Content-Length: {}\r\n\r\n{message}",
+            message.len()
+        )
+        .unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> String {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.stdout.read_line(&mut line).unwrap();
+            match line.trim_end().strip_prefix("Content-Length: ") {
+                Some(value) => length = value.parse().unwrap(),
+                None if line.trim_end().is_empty() => break,
+                None => {}
+            }
+        }
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    /// Sends a request and waits for its response, skipping notifications
+    fn request(&mut self, method: &str, params: &str) -> String {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(&format!(
+            r#"{{"jsonrpc":"2.0","id":{id},"method":"{method}","params":{params}}}"#
+        ));
+        loop {
+            let message = self.receive();
+            if message.contains(&format!(r#""id":{id},"#)) {
+                return message;
+            }
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &str) {
+        self.send(&format!(
+            r#"{{"jsonrpc":"2.0","method":"{method}","params":{params}}}"#
+        ));
+    }
+
+    fn at(&mut self, method: &str, line: usize, character: usize) -> String {
+        self.request(
+            method,
+            &format!(
+                r#"{{"textDocument":{{"uri":"file:///main.bc"}},"position":{{"line":{line},"character":{character}}}}}"#
+            ),
+        )
+    }
+}
+
+fn escape(src: &str) -> String {
+    src.replace('\n', "\\n")
+}
+
+#[test]
+fn session() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bc"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut client = Client {
+        stdin: child.stdin.take().unwrap(),
+        stdout: BufReader::new(child.stdout.take().unwrap()),
+        next_id: 0,
+    };
+
+    let init = client.request("initialize", r#"{"capabilities":{}}"#);
+    assert!(init.contains(r#""hoverProvider":true"#), "{init}");
+    client.notify("initialized", "{}");
+
+    client.notify(
+        "textDocument/didOpen",
+        &format!(
+            r#"{{"textDocument":{{"uri":"file:///main.bc","languageId":"bc","version":1,"text":"{}"}}}}"#,
+            escape(SOURCE)
+        ),
+    );
+    let diagnostics = client.receive();
+    assert!(diagnostics.contains(r#""diagnostics":[]"#), "{diagnostics}");
+
+    // the struct with its layout and field offsets
+    let hover = client.at("textDocument/hover", 16, 5);
+    assert!(
+        hover.contains("struct Point {\\n    u8 tag, // offset 0\\n    i32 x, // offset 4\\n}"),
+        "{hover}"
+    );
+    assert!(hover.contains("size 8, align 4"), "{hover}");
+    let hover = client.at("textDocument/hover", 16, 37);
+    assert!(hover.contains("const i32 LIMIT = 32i32"), "{hover}");
+    let hover = client.at("textDocument/hover", 17, 14);
+    assert!(hover.contains("fn Point::len(&Point self) i32"), "{hover}");
+    let hover = client.at("textDocument/hover", 15, 11);
+    assert!(hover.contains("size 4, align 4"), "{hover}");
+
+    // a path, a method reached through a value, and a local
+    let definition = client.at("textDocument/definition", 16, 37);
+    assert!(
+        definition.contains(r#""start":{"line":7,"character":10}"#),
+        "{definition}"
+    );
+    let definition = client.at("textDocument/definition", 17, 14);
+    assert!(
+        definition.contains(r#""start":{"line":10,"character":7}"#),
+        "{definition}"
+    );
+    let definition = client.at("textDocument/definition", 17, 11);
+    assert!(
+        definition.contains(r#""start":{"line":16,"character":10}"#),
+        "{definition}"
+    );
+
+    let tokens = client.request(
+        "textDocument/semanticTokens/full",
+        r#"{"textDocument":{"uri":"file:///main.bc"}}"#,
+    );
+    // `struct Point {` and then `const i32 LIMIT` with `readonly`
+    assert!(
+        tokens.contains(r#""data":[0,0,6,0,0,0,7,5,1,0,"#),
+        "{tokens}"
+    );
+    assert!(tokens.contains(",0,6,3,1,0,0,4,5,6,1,"), "{tokens}");
+
+    let symbols = client.request(
+        "textDocument/documentSymbol",
+        r#"{"textDocument":{"uri":"file:///main.bc"}}"#,
+    );
+    for symbol in [
+        r#""name":"Point","kind":23"#,
+        r#""name":"x","kind":8"#,
+        r#""name":"Red","kind":22"#,
+        r#""name":"LIMIT","kind":14"#,
+        r#""name":"impl Point","kind":3"#,
+        r#""name":"len","kind":6"#,
+        r#""name":"main","kind":12"#,
+    ] {
+        assert!(symbols.contains(symbol), "{symbols}");
+    }
+
+    // errors go on the expression they're about, or where the parser stopped
+    let broken = SOURCE.replace("return self.x;", "return self.y;");
+    client.notify(
+        "textDocument/didChange",
+        &format!(
+            r#"{{"textDocument":{{"uri":"file:///main.bc","version":2}},"contentChanges":[{{"text":"{}"}}]}}"#,
+            escape(&broken)
+        ),
+    );
+    let diagnostics = client.receive();
+    assert!(
+        diagnostics.contains("no field named `y` on type `Point`"),
+        "{diagnostics}"
+    );
+    assert!(
+        diagnostics.contains(
+            r#""range":{"start":{"line":11,"character":15},"end":{"line":11,"character":21}}"#
+        ),
+        "{diagnostics}"
+    );
+    client.notify(
+        "textDocument/didChange",
+        r#"{"textDocument":{"uri":"file:///main.bc","version":3},"contentChanges":[{"text":"fn main() {\n    return 1 +;\n}\n"}]}"#,
+    );
+    let diagnostics = client.receive();
+    assert!(
+        diagnostics.contains(
+            r#""range":{"start":{"line":1,"character":14},"end":{"line":1,"character":15}}"#
+        ),
+        "{diagnostics}"
+    );
+    assert!(diagnostics.contains("unexpected `;`"), "{diagnostics}");
+
+    let unknown = client.request("workspace/symbol", "{}");
+    assert!(unknown.contains(r#""code":-32601"#), "{unknown}");
+    let shutdown = client.request("shutdown", "null");
+    assert!(shutdown.contains(r#""result":null"#), "{shutdown}");
+    client.notify("exit", "null");
+    assert!(child.wait().unwrap().success());
+}